@@ -0,0 +1,65 @@
+//! Benchmarks for lexing representative documents.
+//! The corpora are shared with the benchmarks of the converter. The inputs
+//! span many pages of tokens, so that the lexer bumps its peek cache page by
+//! page, and the prose is mostly long runs of words and spaces.
+
+use divan::{AllocProfiler, Bencher};
+use mitex_lexer::{Lexer, MacroEngine, TokenStream};
+use mitex_spec_gen::DEFAULT_SPEC;
+
+#[global_allocator]
+static ALLOC: AllocProfiler = AllocProfiler::system();
+
+fn main() {
+    // Run registered benchmarks.
+    divan::main();
+}
+
+const MATRIX: &str = include_str!("../../mitex/benches/data/matrix.tex");
+const ALIGN: &str = include_str!("../../mitex/benches/data/align.tex");
+const PROSE: &str = include_str!("../../mitex/benches/data/prose.tex");
+
+// eat all tokens of the input
+fn eat_all<'a>(input: &'a str, bumper: impl TokenStream<'a>) -> usize {
+    let mut lexer = Lexer::new_with_bumper(input, DEFAULT_SPEC.clone(), bumper);
+    std::iter::from_fn(|| lexer.eat()).count()
+}
+
+fn bench<const WITH_MACRO: bool>(bencher: Bencher, corpus: &str) {
+    let input = corpus.repeat(100);
+    if WITH_MACRO {
+        bencher.bench(|| eat_all(&input, MacroEngine::new(DEFAULT_SPEC.clone())));
+    } else {
+        bencher.bench(|| eat_all(&input, ()));
+    }
+}
+
+#[divan::bench]
+fn matrix(bencher: Bencher) {
+    bench::<false>(bencher, MATRIX);
+}
+
+#[divan::bench]
+fn matrix_macro(bencher: Bencher) {
+    bench::<true>(bencher, MATRIX);
+}
+
+#[divan::bench]
+fn align(bencher: Bencher) {
+    bench::<false>(bencher, ALIGN);
+}
+
+#[divan::bench]
+fn align_macro(bencher: Bencher) {
+    bench::<true>(bencher, ALIGN);
+}
+
+#[divan::bench]
+fn prose(bencher: Bencher) {
+    bench::<false>(bencher, PROSE);
+}
+
+#[divan::bench]
+fn prose_macro(bencher: Bencher) {
+    bench::<true>(bencher, PROSE);
+}
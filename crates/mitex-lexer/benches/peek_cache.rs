@@ -0,0 +1,58 @@
+//! Benchmarks for the peek cache of the lexer.
+//! The inputs span many pages of tokens, so the allocation counts reported by
+//! the profiler show whether the cache is reallocated across pages.
+
+use divan::{AllocProfiler, Bencher};
+use mitex_lexer::{Lexer, MacroEngine, TokenStream};
+use mitex_spec_gen::DEFAULT_SPEC;
+
+#[global_allocator]
+static ALLOC: AllocProfiler = AllocProfiler::system();
+
+fn main() {
+    // Run registered benchmarks.
+    divan::main();
+}
+
+static FRAC_20000: once_cell::sync::Lazy<String> =
+    once_cell::sync::Lazy::new(|| "\\frac{a}{b} + c ".repeat(20000));
+
+// eat all tokens of the input
+fn eat_all<'a>(input: &'a str, bumper: impl TokenStream<'a>) -> usize {
+    let mut lexer = Lexer::new_with_bumper(input, DEFAULT_SPEC.clone(), bumper);
+    std::iter::from_fn(|| lexer.eat()).count()
+}
+
+// look ahead far across pages before eating each token
+fn peek_ahead<'a>(input: &'a str, bumper: impl TokenStream<'a>) -> usize {
+    let mut lexer = Lexer::new_with_bumper(input, DEFAULT_SPEC.clone(), bumper);
+    let mut count = 0;
+    while lexer.peek().is_some() {
+        if count % 256 == 0 {
+            lexer.peek_nth(512);
+        }
+        lexer.eat();
+        count += 1;
+    }
+    count
+}
+
+#[divan::bench]
+fn eat_no_macro(bencher: Bencher) {
+    bencher.bench(|| eat_all(&FRAC_20000, ()));
+}
+
+#[divan::bench]
+fn eat_macro(bencher: Bencher) {
+    bencher.bench(|| eat_all(&FRAC_20000, MacroEngine::new(DEFAULT_SPEC.clone())));
+}
+
+#[divan::bench]
+fn peek_ahead_no_macro(bencher: Bencher) {
+    bencher.bench(|| peek_ahead(&FRAC_20000, ()));
+}
+
+#[divan::bench]
+fn peek_ahead_macro(bencher: Bencher) {
+    bencher.bench(|| peek_ahead(&FRAC_20000, MacroEngine::new(DEFAULT_SPEC.clone())));
+}
@@ -9,18 +9,41 @@
 //!   - See [`TokenStream`] for implementation
 //! - [`Lexer<MacroEngine>`]: provides tokens with macro expansion
 //!   - See [`MacroEngine`] for implementation
+//!
+//! ## Features
+//!
+//! - `std` (default): enables the [`MacroEngine`] and [`snapshot_map`].
+//!   Without it, the crate is `no_std` and only needs `alloc`, along with
+//!   `mitex-spec` without its default features, so the plain [`Lexer<()>`]
+//!   can be used in environments without the standard library. Note that the
+//!   parser still requires `std`, since its syntax tree is built by `rowan`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
+mod line_index;
+#[cfg(feature = "std")]
 mod macro_engine;
+#[cfg(feature = "std")]
 pub mod snapshot_map;
 mod stream;
 mod token;
 
+pub use line_index::LineIndex;
+#[cfg(feature = "std")]
 pub use macro_engine::MacroEngine;
-pub use token::{BraceKind, CommandName, IfCommandName, Token};
+pub use token::{
+    is_verbatim_arg_cmd, is_verbatim_env, verb_body, BraceKind, CommandName, IfCommandName,
+    LigatureKind, Token, VerbatimNext,
+};
+
+use core::ops::Range;
 
 use logos::Logos;
 use mitex_spec::CommandSpec;
 
+#[cfg(feature = "std")]
 use macro_engine::Macro;
 use stream::{LexCache, StreamContext};
 
@@ -34,10 +57,16 @@ pub trait TokenStream<'a>: MacroifyStream<'a> {
     /// Bump the token stream with at least one token if possible
     ///
     /// By default, it fills the peek cache with a page of tokens at the same
-    /// time
+    /// time. The new tokens are appended after the ones pending in
+    /// `ctx.peek_outer`, whose first token is then peeked.
     fn bump(&mut self, ctx: &mut StreamContext<'a>) {
-        ctx.peek_outer.bump(std::iter::from_fn(|| {
-            StreamContext::lex_one(&mut ctx.inner)
+        ctx.peek_outer.bump(core::iter::from_fn(|| {
+            let tok = StreamContext::lex_one(&mut ctx.inner)?;
+            // no macro is expanded, so every `\makeatletter` is used
+            if let Some(at_letter) = StreamContext::at_letter(tok) {
+                ctx.inner.extras.3 = at_letter;
+            }
+            Some(tok)
         }));
     }
 }
@@ -45,6 +74,7 @@ pub trait TokenStream<'a>: MacroifyStream<'a> {
 /// Trait for querying macro state of a stream
 pub trait MacroifyStream<'a> {
     /// Get a macro by name (if meeted in the stream)
+    #[cfg(feature = "std")]
     fn get_macro(&self, _name: &str) -> Option<Macro<'a>> {
         None
     }
@@ -58,6 +88,15 @@ impl TokenStream<'_> for () {}
 /// The default implementation of [`MacroifyStream`]
 impl MacroifyStream<'_> for () {}
 
+/// A saved position of a [`Lexer`], see [`Lexer::checkpoint`]
+#[derive(Debug, Clone)]
+pub struct Checkpoint<'a, S> {
+    /// The saved stream context, including the peek cache
+    ctx: StreamContext<'a>,
+    /// The saved bumper
+    bumper: S,
+}
+
 /// Small memory-efficient lexer for TeX
 ///
 /// It gets improved performance on x86_64 but not wasm through
@@ -85,13 +124,22 @@ impl<'a, S: TokenStream<'a>> Lexer<'a, S> {
     ///
     /// Note that since we have a bumper, the returning string is not always
     /// sliced from the input
+    ///
+    /// A leading UTF-8 BOM is skipped, but spans are still relative to the
+    /// original input.
     pub fn new_with_bumper(input: &'a str, spec: CommandSpec, bumper: S) -> Self {
-        let inner = Token::lexer_with_extras(input, (spec, 0..0));
+        let mut inner = Token::lexer_with_extras(input, (spec, 0..0, VerbatimNext::None, false));
+        if input.starts_with('\u{FEFF}') {
+            inner.bump('\u{FEFF}'.len_utf8());
+        }
         let mut n = Self {
             ctx: StreamContext {
                 inner,
                 peek_outer: LexCache::default(),
                 peek_inner: LexCache::default(),
+                eaten: 0,
+                pushed: 0,
+                use_sites: Default::default(),
             },
             bumper,
         };
@@ -103,7 +151,7 @@ impl<'a, S: TokenStream<'a>> Lexer<'a, S> {
     /// Private method to advance the lexer by one token
     #[inline]
     fn next(&mut self) {
-        if let Some(peeked) = self.ctx.peek_outer.buf.pop() {
+        if let Some(peeked) = self.ctx.peek_outer.buf.pop_front() {
             self.ctx.peek_outer.peeked = Some(peeked);
             return;
         }
@@ -117,22 +165,99 @@ impl<'a, S: TokenStream<'a>> Lexer<'a, S> {
         self.ctx.peek_outer.peeked.map(|(kind, _)| kind)
     }
 
+    /// Peek the n-th upcoming token without consuming anything
+    ///
+    /// `peek_nth(0)` is the same as [`Self::peek`]. Looking ahead within the
+    /// peek cache costs `O(1)`, otherwise the bumper is asked to lex more
+    /// pages of tokens until the n-th token is reached, which are kept in the
+    /// cache for following peeks and eats.
+    pub fn peek_nth(&mut self, n: usize) -> Option<Token> {
+        self.peek_nth_tok(n).map(|(kind, _)| kind)
+    }
+
+    /// Peek the n-th upcoming token and its text
+    fn peek_nth_tok(&mut self, n: usize) -> Option<Tok<'a>> {
+        let Some(nth) = n.checked_sub(1) else {
+            return self.ctx.peek_outer.peeked;
+        };
+        self.ctx.peek_outer.peeked?;
+
+        while self.ctx.peek_outer.buf.len() <= nth {
+            // Put the peeked token back to the front of the cache, so that
+            // the bumper appends a new page after the pending tokens and
+            // peeks it again
+            let cache = &mut self.ctx.peek_outer;
+            let pending = cache.buf.len();
+            if let Some(peeked) = cache.peeked.take() {
+                cache.buf.push_front(peeked);
+            }
+            self.bumper.bump(&mut self.ctx);
+            if self.ctx.peek_outer.buf.len() <= pending {
+                return None;
+            }
+        }
+
+        Some(self.ctx.peek_outer.buf[nth])
+    }
+
+    /// Peek the n-th upcoming token's text, see [`Self::peek_nth`]
+    pub fn peek_nth_text(&mut self, n: usize) -> Option<&'a str> {
+        self.peek_nth_tok(n).map(|(_, text)| text)
+    }
+
     /// Peek the next token's text
     pub fn peek_text(&self) -> Option<&'a str> {
         self.ctx.peek_outer.peeked.map(|(_, text)| text)
     }
 
+    /// Peek the byte range of the next token in the main input source
+    ///
+    /// A token expanded from a macro definition is reported at the macro call,
+    /// i.e. the command with its arguments, while a token of an argument keeps
+    /// its own range.
+    ///
+    /// Returns `None` at the end of the stream, or if the token is not sliced
+    /// from the input, e.g. a token synthesized by the bumper
+    pub fn peek_span(&self) -> Option<Range<usize>> {
+        let span = self.ctx.span_of(self.peek_text()?);
+        match self.ctx.use_site() {
+            Some(call)
+                if !span
+                    .as_ref()
+                    .is_some_and(|span| call.start <= span.start && span.end <= call.end) =>
+            {
+                Some(call)
+            }
+            _ => span,
+        }
+    }
+
     /// Peek the next token's first char
     pub fn peek_char(&self) -> Option<char> {
         self.peek_text().map(str::chars).and_then(|mut e| e.next())
     }
 
-    /// Update the text part of the peeked token
+    /// Update the text part of the peeked token by consuming its first `cnt`
+    /// bytes, or advance to the next token if all of them are consumed
+    ///
+    /// The count is in bytes rather than chars. If it lands inside a multibyte
+    /// char, the whole char is consumed, e.g. consuming 1 byte of `éa` leaves
+    /// `a`.
     pub fn consume_utf8_bytes(&mut self, cnt: usize) {
         let Some(peek_mut) = &mut self.ctx.peek_outer.peeked else {
             return;
         };
         if peek_mut.1.len() <= cnt {
+            self.ctx.advance();
+            self.next();
+            return;
+        }
+        let mut cnt = cnt;
+        while !peek_mut.1.is_char_boundary(cnt) {
+            cnt += 1;
+        }
+        if peek_mut.1.len() == cnt {
+            self.ctx.advance();
             self.next();
         } else {
             peek_mut.1 = &peek_mut.1[cnt..];
@@ -142,11 +267,76 @@ impl<'a, S: TokenStream<'a>> Lexer<'a, S> {
     /// Update the peeked token and return the old one
     pub fn eat(&mut self) -> Option<(Token, &'a str)> {
         let peeked = self.ctx.peek_outer.peeked.take()?;
+        self.ctx.advance();
         self.next();
         Some(peeked)
     }
 
+    /// Update the peeked token and return the old one with its byte range in
+    /// the main input source
+    ///
+    /// The range is `None` if the token is not sliced from the input. See
+    /// [`Self::peek_span`] for details.
+    pub fn eat_spanned(&mut self) -> Option<(Token, &'a str, Option<Range<usize>>)> {
+        let span = self.peek_span();
+        let (kind, text) = self.eat()?;
+        Some((kind, text, span))
+    }
+
+    /// Iterate over the remaining tokens, eating them one by one
+    ///
+    /// It yields the same tokens as repeatedly calling [`Self::eat`], which
+    /// means that a peeked token partially consumed by
+    /// [`Self::consume_utf8_bytes`] is yielded with its remaining text.
+    ///
+    /// ```
+    /// use mitex_lexer::{BraceKind, CommandName, Lexer, Token};
+    /// use mitex_spec::CommandSpec;
+    ///
+    /// let mut lexer = Lexer::<()>::new(r"\frac{1}{2}", CommandSpec::new(Default::default()));
+    /// let kinds: Vec<_> = lexer.tokens().collect();
+    /// assert_eq!(
+    ///     kinds,
+    ///     vec![
+    ///         (Token::CommandName(CommandName::Generic), r"\frac"),
+    ///         (Token::Left(BraceKind::Curly), "{"),
+    ///         (Token::Word, "1"),
+    ///         (Token::Right(BraceKind::Curly), "}"),
+    ///         (Token::Left(BraceKind::Curly), "{"),
+    ///         (Token::Word, "2"),
+    ///         (Token::Right(BraceKind::Curly), "}"),
+    ///     ]
+    /// );
+    /// ```
+    pub fn tokens(&mut self) -> impl Iterator<Item = (Token, &'a str)> + '_ {
+        core::iter::from_fn(move || self.eat())
+    }
+
+    /// Save the current position of the lexer
+    ///
+    /// The checkpoint captures the inner lexer position, the peeked token, the
+    /// remaining tokens in the peek cache and the state of the bumper, so the
+    /// lexer resumes identically after [`Self::restore`]. It costs a copy of
+    /// the peek cache, which is at most a page of tokens, and of the bumper,
+    /// e.g. the macros defined so far in a [`MacroEngine`].
+    pub fn checkpoint(&self) -> Checkpoint<'a, S>
+    where
+        S: Clone,
+    {
+        Checkpoint {
+            ctx: self.ctx.clone(),
+            bumper: self.bumper.clone(),
+        }
+    }
+
+    /// Rewind the lexer to a position saved by [`Self::checkpoint`]
+    pub fn restore(&mut self, cp: Checkpoint<'a, S>) {
+        self.ctx = cp.ctx;
+        self.bumper = cp.bumper;
+    }
+
     /// Find a **currently** defined macro by name
+    #[cfg(feature = "std")]
     pub fn get_macro(&mut self, name: &str) -> Option<Macro<'a>> {
         self.bumper.get_macro(name)
     }
@@ -0,0 +1,57 @@
+//! Line and column positions of a source string
+
+use alloc::{vec, vec::Vec};
+
+/// A precomputed table of line starts in a source string, which converts
+/// byte offsets (e.g. token spans) to human-readable positions
+///
+/// A `\r\n` is a single line break, and so are a single `\r` or `\n`.
+#[derive(Debug, Clone)]
+pub struct LineIndex<'a> {
+    /// The source string
+    text: &'a str,
+    /// Byte offsets of the start of each line
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    /// Create a line index on a source string
+    pub fn new(text: &'a str) -> Self {
+        let bytes = text.as_bytes();
+        let mut line_starts = vec![0];
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\r' if bytes.get(i + 1) == Some(&b'\n') => {
+                    i += 1;
+                    line_starts.push(i + 1);
+                }
+                b'\r' | b'\n' => line_starts.push(i + 1),
+                _ => {}
+            }
+            i += 1;
+        }
+
+        Self { text, line_starts }
+    }
+
+    /// Get the number of lines
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Convert a byte offset to a 1-based `(line, column)` position, where
+    /// the column counts chars rather than bytes
+    ///
+    /// The offset at the end of the source is valid. Returns `None` if the
+    /// offset is out of bounds or not at a char boundary.
+    ///
+    /// It costs `O(log n)` to find the line, besides counting the chars
+    /// before the offset in the line.
+    pub fn line_col(&self, offset: usize) -> Option<(usize, usize)> {
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let prefix = self.text.get(self.line_starts[line]..offset)?;
+
+        Some((line + 1, prefix.chars().count() + 1))
+    }
+}
@@ -12,8 +12,11 @@
 //! - \renewcommand*
 //! - \DeclareRobustCommand
 //! - \DeclareRobustCommand*
+//! - \DeclareMathOperator
+//! - \DeclareMathOperator*
 //! - \providecommand
 //! - \providecommand*
+//! - \def
 //!
 //! - \newenvironment
 //! - \newenvironment*
@@ -57,7 +60,6 @@
 //! - \ignorespaces
 //! - \ignorespacesafterend
 //!
-//! - \def
 //! - \gdef needs: globals: MacroMap<'a>,
 //!
 //! - ifdim
@@ -100,9 +102,13 @@ use mitex_spec::CommandSpec;
 
 pub type Checkpoint = (snapshot_map::Snapshot,);
 
+/// The maximum number of nested macro expansions, which stops a recursive
+/// macro like `\newcommand{\f}{\f}` from expanding forever
+const MAX_MACRO_DEPTH: usize = 1024;
+
 type MacroMap<'a> = SnapshotMap<&'a str, Macro<'a>>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CmdMacro<'a> {
     pub name: String,
     pub num_args: u8,
@@ -110,7 +116,21 @@ pub struct CmdMacro<'a> {
     pub def: Vec<Tok<'a>>,
 }
 
-#[derive(Debug)]
+/// Command macro defined by `\def`, whose arguments are delimited by the
+/// parameter text
+#[derive(Debug, Clone)]
+pub struct DefMacro<'a> {
+    pub name: String,
+    /// The tokens between the command name and the first parameter, which
+    /// must be matched at the call site
+    pub prefix: Vec<Tok<'a>>,
+    /// The delimiter after each parameter, which is empty for an undelimited
+    /// parameter
+    pub delimiters: Vec<Vec<Tok<'a>>>,
+    pub def: Vec<Tok<'a>>,
+}
+
+#[derive(Debug, Clone)]
 pub struct EnvMacro<'a> {
     pub name: String,
     pub num_args: u8,
@@ -142,6 +162,13 @@ pub enum DeclareCmdOrEnv {
     /// \DeclareRobustCommand{*}{\cmd}[nargs]{defn}
     /// \DeclareRobustCommand{*}{\cmd}[nargs][optargdefault]{defn}
     DeclareRobustCommand { star: bool },
+    /// Command macro for DeclareMathOperator{*}
+    /// Synopsis:
+    ///
+    /// \DeclareMathOperator{*}{\cmd}{text}
+    ///
+    /// The command expands to `\operatorname{*}{text}`.
+    DeclareMathOperator { star: bool },
     /// Command macro for NewEnvironment/RenewEnvironment{*}
     /// Synopsis, one of:
     ///
@@ -154,6 +181,13 @@ pub enum DeclareCmdOrEnv {
 #[derive(Debug, Clone)]
 pub enum DeclareMacro {
     CmdOrEnv(DeclareCmdOrEnv),
+    /// Command macro for Def
+    /// Synopsis,
+    /// \def\cmd<parameter text>{defn}
+    ///
+    /// The parameter text is a sequence of `#1` to `#9` and delimiter tokens,
+    /// e.g. `\def\pair#1;#2{(#1,#2)}`
+    Def,
     /// Command macro for DeclareTextCommand
     /// Synopsis, one of:
     ///
@@ -240,6 +274,15 @@ fn define_declarative_macros(macros: &mut MacroMap) {
             "DeclareRobustCommand*",
             DeclareMacro::CmdOrEnv(DeclareCmdOrEnv::DeclareRobustCommand { star: true }),
         ),
+        (
+            "DeclareMathOperator",
+            DeclareMacro::CmdOrEnv(DeclareCmdOrEnv::DeclareMathOperator { star: false }),
+        ),
+        (
+            "DeclareMathOperator*",
+            DeclareMacro::CmdOrEnv(DeclareCmdOrEnv::DeclareMathOperator { star: true }),
+        ),
+        ("def", DeclareMacro::Def),
         ("DeclareTextCommand", DeclareMacro::DeclareTextCommand),
         (
             "DeclareTextCommandDefault",
@@ -302,11 +345,13 @@ pub enum Macro<'a> {
     Declare(DeclareMacro),
     /// Command macro
     Cmd(Arc<CmdMacro<'a>>),
+    /// Command macro with delimited parameters
+    Def(Arc<DefMacro<'a>>),
     /// Environment macro
     Env(Arc<EnvMacro<'a>>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MacroState<T> {
     pub reading: Arc<T>,
     /// The real num of arguments read by engine
@@ -315,7 +360,7 @@ pub struct MacroState<T> {
     pub has_read_tokens: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum MacroNode<'a> {
     Cmd(MacroState<CmdMacro<'a>>),
     EnvBegin(MacroState<EnvMacro<'a>>),
@@ -343,6 +388,11 @@ enum IfState {
 ///
 /// When it meets a macro in token stream, It evaluates a macro into expanded
 /// tokens.
+///
+/// The engine is cloned by [`crate::Lexer::checkpoint`]. The macros are
+/// shared with the default macros until the first definition, after which a
+/// clone copies the defined macros.
+#[derive(Clone)]
 pub struct MacroEngine<'a> {
     /// Command specification
     pub spec: CommandSpec,
@@ -359,6 +409,16 @@ pub struct MacroEngine<'a> {
     reading_if: Vec<Option<IfState>>,
     /// Toekns used by macro stack
     pub scanned_tokens: Vec<Tok<'a>>,
+    /// Stack of nested macro expansions
+    ///
+    /// Each item is the number of tokens in the inner peek cache after the
+    /// expanded tokens, so the expansion is finished once the cache shrinks
+    /// to it.
+    expanding: Vec<usize>,
+    /// The number of tokens in the inner peek cache after the tokens expanded
+    /// by the outermost macro call, like [`Self::expanding`], while the
+    /// expanded tokens are reported at the call
+    use_site: Option<usize>,
 }
 
 impl<'a> TokenStream<'a> for MacroEngine<'a> {
@@ -383,6 +443,8 @@ impl<'a> MacroEngine<'a> {
             reading_macro: Vec::new(),
             reading_if: Vec::new(),
             scanned_tokens: Vec::new(),
+            expanding: Vec::new(),
+            use_site: None,
         }
     }
 
@@ -396,8 +458,11 @@ impl<'a> MacroEngine<'a> {
         /// Reserve one item for the peeked token
         const PEEK_CACHE_SIZE_M1: usize = PEEK_CACHE_SIZE - 1;
 
+        // The page is appended after the tokens pending in the cache
+        let limit = ctx.peek_outer.buf.len() + PEEK_CACHE_SIZE_M1;
         ctx.next_token();
-        while ctx.peek_outer.buf.len() < PEEK_CACHE_SIZE_M1 {
+        while ctx.peek_outer.buf.len() < limit {
+            self.finish_use_site(ctx);
             let Some(token) = ctx.peek_full() else {
                 break;
             };
@@ -420,6 +485,13 @@ impl<'a> MacroEngine<'a> {
                 // If it is a real macro, it will be expanded into tokens so parser is unaware of
                 // the macro.
                 Token::CommandName(CommandName::Generic) => {
+                    // `\makeatletter` applies to the input lexed after it, unless
+                    // it is commented out by `\iffalse`
+                    if let Some(at_letter) = StreamContext::at_letter(token) {
+                        if !self.reading_if.contains(&Some(IfState::LitFalse)) {
+                            ctx.inner.extras.3 = at_letter;
+                        }
+                    }
                     self.trapped_by_macro(ctx, token, &token.1[1..], false);
                 }
                 // a begin environment token traps stream into a macro checking
@@ -434,16 +506,14 @@ impl<'a> MacroEngine<'a> {
             }
         }
 
+        self.finish_use_site(ctx);
         // Push the remaining token in inner stream to outer stream
         if let Some(e) = ctx.peek_full() {
             ctx.push_outer(e);
         }
 
-        // Reverse the peek cache to make it a stack
-        ctx.peek_outer.buf.reverse();
-
         // Pop the first token again
-        ctx.peek_outer.peeked = ctx.peek_outer.buf.pop();
+        ctx.peek_outer.peeked = ctx.peek_outer.buf.pop_front();
     }
 
     /// Skip tokens until a balanced \fi
@@ -496,35 +566,23 @@ impl<'a> MacroEngine<'a> {
     #[inline]
     fn trapped_by_else(&mut self, ctx: &mut StreamContext<'a>, token: Tok<'a>) {
         ctx.next_token();
-        let last_if = self.reading_if.last().cloned().unwrap_or(None);
-        match last_if {
-            Some(IfState::TypstTrue) => {
-                self.reading_if
-                    .last_mut()
-                    .unwrap()
-                    .replace(IfState::TypstFalse);
-                self.skip_false_tokens(ctx);
-            }
-            Some(IfState::True) => {
-                self.reading_if.last_mut().unwrap().replace(IfState::False);
-                self.skip_false_tokens(ctx);
-            }
-            Some(IfState::False) => {
-                self.reading_if.last_mut().unwrap().replace(IfState::True);
-            }
-            Some(IfState::TypstFalse) => {
-                self.reading_if
-                    .last_mut()
-                    .unwrap()
-                    .replace(IfState::TypstTrue);
-            }
-            Some(IfState::LitFalse) => {
-                self.reading_if.last_mut().unwrap().replace(IfState::True);
+        let Some(Some(last_if)) = self.reading_if.last_mut() else {
+            ctx.push_outer(token);
+            return;
+        };
+        let state = *last_if;
+        *last_if = match state {
+            IfState::TypstTrue => IfState::TypstFalse,
+            IfState::True => IfState::False,
+            IfState::False | IfState::LitFalse => IfState::True,
+            IfState::TypstFalse => IfState::TypstTrue,
+        };
+        match state {
+            IfState::TypstTrue | IfState::True => self.skip_false_tokens(ctx),
+            IfState::LitFalse => {
                 ctx.push_outer((Token::CommandName(CommandName::EndIf), "\\fi"));
             }
-            None => {
-                ctx.push_outer(token);
-            }
+            IfState::False | IfState::TypstFalse => {}
         }
     }
 
@@ -565,6 +623,16 @@ impl<'a> MacroEngine<'a> {
             return None;
         }
 
+        // the call starts at the command, or at the `\begin` of an environment
+        // whose name is the text of the token
+        let start = ctx.span_of(token.1).and_then(|span| {
+            if is_env {
+                ctx.inner.source()[..span.start].rfind("\\begin")
+            } else {
+                Some(span.start)
+            }
+        });
+
         use DeclareMacro::*;
         match m {
             Macro::Declare(CmdOrEnv(c)) => {
@@ -595,6 +663,12 @@ impl<'a> MacroEngine<'a> {
 
                 None
             }
+            Macro::Declare(Def) => {
+                let (name, m) = Self::identify_def(ctx)?;
+                self.add_macro(name, m);
+
+                None
+            }
             Macro::Declare(
                 DeclareTextCommand
                 | ProvideTextCommand
@@ -613,6 +687,11 @@ impl<'a> MacroEngine<'a> {
                 None
             }
             Macro::Cmd(cmd) => {
+                if !Self::enter_expansion(&mut self.expanding, ctx) {
+                    ctx.push_outer((Token::Error, token.1));
+                    ctx.next_token();
+                    return None;
+                }
                 ctx.next_token();
 
                 // Read arguments according to the macro definition
@@ -621,6 +700,8 @@ impl<'a> MacroEngine<'a> {
                 let expanded = Self::expand_tokens(&args, &cmd.def);
 
                 // Push the reversed tokens to inner stream
+                Self::start_use_site(&mut self.use_site, ctx, start);
+                self.expanding.push(Self::pending_tokens(ctx));
                 ctx.extend_inner(expanded.into_iter().rev());
                 // We may consumed the last token in inner stream before, so we need to reload
                 // it after extending
@@ -630,7 +711,33 @@ impl<'a> MacroEngine<'a> {
 
                 None
             }
+            Macro::Def(def) => {
+                if !Self::enter_expansion(&mut self.expanding, ctx) {
+                    ctx.push_outer((Token::Error, token.1));
+                    ctx.next_token();
+                    return None;
+                }
+                ctx.next_token();
+
+                // Read arguments by matching the parameter text
+                let args = Self::read_def_args(ctx, def)?;
+                let expanded = Self::expand_tokens(&args, &def.def);
+
+                Self::start_use_site(&mut self.use_site, ctx, start);
+                self.expanding.push(Self::pending_tokens(ctx));
+                ctx.extend_inner(expanded.into_iter().rev());
+                if ctx.peek_inner.peeked.is_none() {
+                    ctx.next_token();
+                }
+
+                None
+            }
             Macro::Env(env) => {
+                if !Self::enter_expansion(&mut self.expanding, ctx) {
+                    ctx.push_outer((Token::Error, token.1));
+                    ctx.next_token();
+                    return None;
+                }
                 ctx.next_token();
 
                 // Read arguments according to the macro definition
@@ -639,6 +746,8 @@ impl<'a> MacroEngine<'a> {
                 let expanded_begin = Self::expand_tokens(&args, &env.begin_def);
                 let expanded_end = Self::expand_tokens(&args, &env.end_def);
 
+                Self::start_use_site(&mut self.use_site, ctx, start);
+                self.expanding.push(Self::pending_tokens(ctx));
                 ctx.extend_inner(
                     expanded_end
                         .into_iter()
@@ -658,6 +767,61 @@ impl<'a> MacroEngine<'a> {
         }
     }
 
+    /// Report the tokens expanded by a macro call starting at `start` in the
+    /// input at the call, unless it is inside another expansion
+    ///
+    /// It is called after the arguments are read, so the call ends at the
+    /// peeked token.
+    fn start_use_site(
+        use_site: &mut Option<usize>,
+        ctx: &mut StreamContext<'a>,
+        start: Option<usize>,
+    ) {
+        let Some(start) = start.filter(|_| use_site.is_none()) else {
+            return;
+        };
+        let end = match ctx.peek_inner.peeked {
+            Some((_, text)) => ctx.span_of(text).map_or(start, |span| span.start),
+            None => ctx.inner.source().len(),
+        };
+        *use_site = Some(Self::pending_tokens(ctx));
+        ctx.use_sites
+            .push_back((ctx.pushed..usize::MAX, start..end));
+    }
+
+    /// Stop reporting tokens at the macro call once the tokens expanded by
+    /// the outermost call are all read
+    fn finish_use_site(&mut self, ctx: &mut StreamContext<'a>) {
+        if self
+            .use_site
+            .is_some_and(|rest| Self::pending_tokens(ctx) <= rest)
+        {
+            self.use_site = None;
+            if let Some((tokens, _)) = ctx.use_sites.back_mut() {
+                tokens.end = ctx.pushed;
+            }
+        }
+    }
+
+    /// Check whether a macro at the peeked token can be expanded without
+    /// exceeding [`MAX_MACRO_DEPTH`]
+    ///
+    /// The peeked token is still in the middle of the expansions that produced
+    /// it, so a macro expanding to itself is nested more deeply at each step.
+    fn enter_expansion(expanding: &mut Vec<usize>, ctx: &StreamContext<'a>) -> bool {
+        let pending = Self::pending_tokens(ctx);
+        while expanding.last().is_some_and(|&rest| rest >= pending) {
+            expanding.pop();
+        }
+
+        expanding.len() < MAX_MACRO_DEPTH
+    }
+
+    /// The number of tokens in the inner peek cache, including the peeked one
+    fn pending_tokens(ctx: &StreamContext<'a>) -> usize {
+        ctx.peek_inner.buf.len() + usize::from(ctx.peek_inner.peeked.is_some())
+    }
+
     fn identify_macro_update(
         ctx: &mut StreamContext<'a>,
         c: &DeclareCmdOrEnv,
@@ -671,10 +835,8 @@ impl<'a> MacroEngine<'a> {
         let name = if matches!(c, DeclareCmdOrEnv::NewEnvironment { .. }) {
             ctx.peek_word_opt(BraceKind::Curly)?.1
         } else {
-            ctx.peek_cmd_name_opt(BraceKind::Curly)?
-                .1
-                .strip_prefix('\\')
-                .unwrap()
+            let name = ctx.peek_cmd_name_opt(BraceKind::Curly)?.1;
+            name.strip_prefix('\\').unwrap_or(name)
         };
 
         #[derive(Clone, Copy, PartialEq)]
@@ -709,7 +871,7 @@ impl<'a> MacroEngine<'a> {
                     break 'match_loop;
                 }
                 _ => {
-                    def = vec![ctx.peek_full().unwrap()];
+                    def = vec![ctx.peek_full()?];
                     ctx.next_token();
                     break 'match_loop;
                 }
@@ -718,6 +880,7 @@ impl<'a> MacroEngine<'a> {
 
         let mut is_env = false;
         let mut end_def = None;
+        let mut def = def;
         let action = match c {
             DeclareCmdOrEnv::NewCommand { renew, star: _ } => {
                 if *renew {
@@ -728,6 +891,20 @@ impl<'a> MacroEngine<'a> {
             }
             DeclareCmdOrEnv::DeclareRobustCommand { star: _ } => UpdateAction::New,
             DeclareCmdOrEnv::ProvideCommand { star: _ } => UpdateAction::Provide,
+            DeclareCmdOrEnv::DeclareMathOperator { star } => {
+                let operator = if *star {
+                    "\\operatorname*"
+                } else {
+                    "\\operatorname"
+                };
+                let text = std::mem::take(&mut def);
+                def.push((Token::CommandName(CommandName::Generic), operator));
+                def.push((Token::Left(BraceKind::Curly), "{"));
+                def.extend(text);
+                def.push((Token::Right(BraceKind::Curly), "}"));
+
+                UpdateAction::New
+            }
             DeclareCmdOrEnv::NewEnvironment { renew, star: _ } => {
                 is_env = true;
 
@@ -767,6 +944,61 @@ impl<'a> MacroEngine<'a> {
         Some((name, action, m))
     }
 
+    fn identify_def(ctx: &mut StreamContext<'a>) -> Option<(&'a str, Macro<'a>)> {
+        // \cmd<parameter text>{defn}
+
+        ctx.next_not_trivia()
+            .filter(|nx| *nx == Token::CommandName(CommandName::Generic))?;
+        let name = ctx.peek_full()?.1;
+        let name = name.strip_prefix('\\').unwrap_or(name);
+        ctx.next_token();
+
+        let mut prefix = vec![];
+        let mut delimiters: Vec<Vec<Tok<'a>>> = vec![];
+        loop {
+            let tok = ctx.peek_full()?;
+            match tok.0 {
+                Token::Left(BraceKind::Curly) => break,
+                // `#n` must be numbered in order, and `#{` is not supported
+                Token::Hash => {
+                    ctx.next_token();
+                    let word = ctx.peek_full().filter(|e| e.0 == Token::Word).map(|e| e.1);
+                    let num = word.and_then(|e| e.chars().next()?.to_digit(10));
+                    let Some(word) = word.filter(|_| num == Some(delimiters.len() as u32 + 1))
+                    else {
+                        ctx.push_outer((Token::Error, "unsupported parameter text"));
+                        return None;
+                    };
+
+                    // e.g. `#1.` is lexed as a hash and a word `1.`
+                    let rest = &word[1..];
+                    delimiters.push(if rest.is_empty() {
+                        vec![]
+                    } else {
+                        vec![(Token::Word, rest)]
+                    });
+                    ctx.next_token();
+                }
+                _ => {
+                    delimiters.last_mut().unwrap_or(&mut prefix).push(tok);
+                    ctx.next_token();
+                }
+            }
+        }
+
+        ctx.next_token();
+        let def = Self::process_macro_def(ctx.read_until_balanced(BraceKind::Curly));
+
+        let m = Macro::Def(Arc::new(DefMacro {
+            name: name.to_owned(),
+            prefix,
+            delimiters,
+            def,
+        }));
+
+        Some((name, m))
+    }
+
     // todo: insufficient macro arguments
     fn read_macro_args(
         ctx: &mut StreamContext<'a>,
@@ -780,6 +1012,22 @@ impl<'a> MacroEngine<'a> {
         }
 
         let mut num_of_read: u8 = 0;
+        // The first argument is optional if it has a default value, e.g.
+        // `\f[x]{y}` or `\f{y}` for `\newcommand{\f}[2][default]{#1#2}`
+        if let Some(opt) = opt {
+            if ctx.peek_not_trivia() == Some(Token::Left(BraceKind::Bracket)) {
+                ctx.next_token();
+                args.push(ctx.read_until_balanced(BraceKind::Bracket));
+            } else {
+                args.push(opt);
+            }
+
+            num_of_read += 1;
+            if num_of_read == num_args {
+                return Some(args);
+            }
+        }
+
         loop {
             match ctx.peek_not_trivia() {
                 Some(Token::Left(BraceKind::Curly)) => {
@@ -787,7 +1035,7 @@ impl<'a> MacroEngine<'a> {
                     args.push(ctx.read_until_balanced(BraceKind::Curly));
                 }
                 Some(Token::Word) => {
-                    let t = ctx.peek_full().unwrap().1;
+                    let t = ctx.peek_full()?.1;
                     let mut split_cnt = 0;
                     for c in t.chars() {
                         args.push(vec![(Token::Word, &t[split_cnt..split_cnt + c.len_utf8()])]);
@@ -798,7 +1046,7 @@ impl<'a> MacroEngine<'a> {
                         }
                     }
                     if split_cnt < t.len() {
-                        ctx.peek_inner.peeked.as_mut().unwrap().1 = &t[split_cnt..];
+                        ctx.set_peeked_text(&t[split_cnt..]);
                     } else {
                         ctx.next_token();
                     }
@@ -807,7 +1055,7 @@ impl<'a> MacroEngine<'a> {
                     }
                 }
                 Some(_) => {
-                    args.push(vec![ctx.peek_full().unwrap()]);
+                    args.push(vec![ctx.peek_full()?]);
                     ctx.next_token();
                 }
                 None => {
@@ -822,21 +1070,159 @@ impl<'a> MacroEngine<'a> {
         }
 
         if num_of_read != num_args {
-            let mut ok = false;
-            if num_args - num_of_read == 1 {
-                if let Some(opt) = opt {
-                    args.push(opt);
-                    ok = true;
+            ctx.push_outer((Token::Error, "invalid number of arguments"));
+            return None;
+        }
+
+        Some(args)
+    }
+
+    fn read_def_args(ctx: &mut StreamContext<'a>, def: &DefMacro<'a>) -> Option<Vec<Vec<Tok<'a>>>> {
+        let mut args = Vec::with_capacity(def.delimiters.len());
+        if def.prefix.is_empty() && def.delimiters.is_empty() {
+            return Some(args);
+        }
+
+        // Spaces after a command name are skipped
+        ctx.peek_not_trivia();
+        let mut matched = Self::eat_delimiter(ctx, &def.prefix);
+        for delimiter in def.delimiters.iter() {
+            if !matched {
+                break;
+            }
+
+            let arg = if delimiter.is_empty() {
+                Self::read_undelimited_arg(ctx)
+            } else {
+                Self::read_delimited_arg(ctx, delimiter)
+            };
+            matched = arg.is_some();
+            args.extend(arg);
+        }
+
+        if !matched {
+            ctx.push_outer((Token::Error, "use of macro doesn't match its definition"));
+            return None;
+        }
+
+        Some(args)
+    }
+
+    /// Read an undelimited argument, which is a curly group or a single token
+    fn read_undelimited_arg(ctx: &mut StreamContext<'a>) -> Option<Vec<Tok<'a>>> {
+        match ctx.peek_not_trivia()? {
+            Token::Left(BraceKind::Curly) => {
+                ctx.next_token();
+                Some(ctx.read_until_balanced(BraceKind::Curly))
+            }
+            Token::Right(BraceKind::Curly) => None,
+            Token::Word => {
+                let t = ctx.peek_full()?.1;
+                let len = t.chars().next()?.len_utf8();
+                if len < t.len() {
+                    ctx.set_peeked_text(&t[len..]);
+                } else {
+                    ctx.next_token();
+                }
+                Some(vec![(Token::Word, &t[..len])])
+            }
+            _ => {
+                let tok = ctx.peek_full()?;
+                ctx.next_token();
+                Some(vec![tok])
+            }
+        }
+    }
+
+    /// Read tokens until the delimiter is met outside of curly groups
+    fn read_delimited_arg(
+        ctx: &mut StreamContext<'a>,
+        delimiter: &[Tok<'a>],
+    ) -> Option<Vec<Tok<'a>>> {
+        let mut arg = vec![];
+        let mut curly_level = 0usize;
+        loop {
+            if curly_level == 0 && Self::eat_delimiter(ctx, delimiter) {
+                break;
+            }
+
+            let tok = ctx.peek_full()?;
+            match tok.0 {
+                Token::Left(BraceKind::Curly) => curly_level += 1,
+                Token::Right(BraceKind::Curly) => curly_level = curly_level.checked_sub(1)?,
+                // split a word before the delimiter in it, e.g. `b.` for `#1.`
+                Token::Word if curly_level == 0 => {
+                    if let Some((Token::Word, text)) = delimiter.first() {
+                        let mut split = tok.1.char_indices().skip(1).map(|(i, _)| i);
+                        if let Some(i) = split.find(|i| tok.1[*i..].starts_with(text)) {
+                            arg.push((Token::Word, &tok.1[..i]));
+                            ctx.set_peeked_text(&tok.1[i..]);
+                            continue;
+                        }
+                    }
                 }
+                _ => {}
             }
+            arg.push(tok);
+            ctx.next_token();
+        }
 
-            if !ok {
-                ctx.push_outer((Token::Error, "invalid number of arguments"));
-                return None;
+        // Strip a curly group enclosing the whole argument, e.g. `{a;b}` for `#1;`
+        let mut curly_level = 0;
+        let is_group = matches!(arg.first(), Some((Token::Left(BraceKind::Curly), _)))
+            && arg.iter().enumerate().all(|(i, e)| {
+                match e.0 {
+                    Token::Left(BraceKind::Curly) => curly_level += 1,
+                    Token::Right(BraceKind::Curly) => curly_level -= 1,
+                    _ => {}
+                }
+                curly_level > 0 || i + 1 == arg.len()
+            });
+        if is_group {
+            arg.pop();
+            arg.remove(0);
+        }
+
+        Some(arg)
+    }
+
+    /// Eat the delimiter tokens if they are at the start of the stream
+    ///
+    /// The last delimiter word may be a prefix of a word in the stream, e.g.
+    /// `.` of `.b`, and the partially matched tokens are put back on failure.
+    fn eat_delimiter(ctx: &mut StreamContext<'a>, delimiter: &[Tok<'a>]) -> bool {
+        let mut eaten = Vec::with_capacity(delimiter.len());
+        for (i, expected) in delimiter.iter().enumerate() {
+            let Some(tok) = ctx.peek_full() else {
+                break;
+            };
+            if tok == *expected {
+                eaten.push(tok);
+                ctx.next_token();
+                continue;
             }
+
+            let is_last = i + 1 == delimiter.len();
+            if is_last && tok.0 == Token::Word && expected.0 == Token::Word {
+                if let Some(rest) = tok.1.strip_prefix(expected.1) {
+                    ctx.set_peeked_text(rest);
+                    return true;
+                }
+            }
+            break;
         }
 
-        Some(args)
+        if eaten.len() == delimiter.len() {
+            return true;
+        }
+
+        if !eaten.is_empty() {
+            ctx.extend_inner(eaten.into_iter().rev());
+            if ctx.peek_inner.peeked.is_none() {
+                ctx.next_token();
+            }
+        }
+        false
     }
 
     fn read_env_body(ctx: &mut StreamContext<'a>, name: &str) -> Option<Vec<Tok<'a>>> {
@@ -892,7 +1278,7 @@ impl<'a> MacroEngine<'a> {
                     }
                 }
                 Token::CommandName(CommandName::Generic) => {
-                    let name = e.1.strip_prefix('\\').unwrap();
+                    let name = e.1.strip_prefix('\\').unwrap_or(e.1);
                     match name {
                         "mitexrecurse" => loop {
                             i += 1;
@@ -950,31 +1336,48 @@ impl<'a> MacroEngine<'a> {
         self.macros.to_mut().insert(name, value);
     }
 
+    /// Process parameters in a macro definition
+    ///
+    /// - `#1` to `#9` are converted to [`Token::MacroArg`]
+    /// - `##` is converted to a literal `#`, which is a parameter of nested
+    ///   macro definitions after expansion
+    /// - `#0`, or `#` followed by a non-digit is kept as is
     fn process_macro_def(mut def: Vec<(Token, &str)>) -> Vec<(Token, &str)> {
         // process hash, it will grab the next token
         let mut empty_texts = false;
-        for i in 0..def.len() {
-            if def[i].0 == Token::Hash {
-                let next = def.get_mut(i + 1).unwrap();
-                if next.0 == Token::Word {
-                    let Some(first_char) = next.1.chars().next() else {
+        let mut i = 0;
+        while i < def.len() {
+            let is_hash = def[i].0 == Token::Hash;
+            i += 1;
+            if !is_hash {
+                continue;
+            }
+            let Some(next) = def.get_mut(i) else {
+                break;
+            };
+
+            match next.0 {
+                Token::Hash => {
+                    // drop the second hash and skip the first one
+                    next.0 = Token::Word;
+                    next.1 = "";
+                    empty_texts = true;
+                    i += 1;
+                }
+                Token::Word => {
+                    let Some(num) = next.1.chars().next().and_then(|c| c.to_digit(10)) else {
                         continue;
                     };
-
-                    if first_char.is_ascii_digit() {
-                        let Some(num) = first_char.to_digit(10) else {
-                            continue;
-                        };
-                        if num == 0 || num > 9 {
-                            continue;
-                        }
-                        next.1 = &next.1[1..];
-                        if next.1.is_empty() {
-                            empty_texts = true;
-                        }
-                        def[i].0 = Token::MacroArg(num as u8);
+                    if num == 0 {
+                        continue;
+                    }
+                    next.1 = &next.1[1..];
+                    if next.1.is_empty() {
+                        empty_texts = true;
                     }
+                    def[i - 1].0 = Token::MacroArg(num as u8);
                 }
+                _ => {}
             }
         }
 
@@ -1,21 +1,32 @@
-use logos::Source;
+use alloc::{collections::VecDeque, vec::Vec};
+use core::ops::Range;
 
+use logos::{Logos, Source};
+
+use crate::token::{
+    is_verbatim_arg_cmd, is_verbatim_env, lex_verb_body, lex_verbatim_arg_body,
+    lex_verbatim_env_body, VerbatimNext,
+};
 use crate::{BraceKind, CommandName, Tok, Token};
 
 /// Lex Cache for bundling (bumping) lexing operations for CPU locality
+///
+/// The cache is a queue whose front is the token right after the peeked one.
+/// Tokens are popped from the front and a new page is appended to the back,
+/// so the buffer is neither reversed nor reallocated across pages.
 #[derive(Debug, Clone)]
 pub struct LexCache<'a> {
     /// The last peeked token
     pub peeked: Option<Tok<'a>>,
-    /// A reversed sequence of peeked tokens
-    pub buf: Vec<Tok<'a>>,
+    /// The sequence of tokens after the peeked one
+    pub buf: VecDeque<Tok<'a>>,
 }
 
 impl Default for LexCache<'_> {
     fn default() -> Self {
         Self {
             peeked: None,
-            buf: Vec::with_capacity(8),
+            buf: VecDeque::with_capacity(8),
         }
     }
 }
@@ -23,43 +34,42 @@ impl Default for LexCache<'_> {
 impl<'a> LexCache<'a> {
     /// Extend the peek cache with a sequence of tokens
     ///
-    /// Note: the tokens in the cache are reversed
+    /// Note: the tokens are given in reversed order, i.e. the last one is
+    /// peeked first
     fn extend(&mut self, peeked: impl Iterator<Item = Tok<'a>>) {
         // Push the peeked token back to the peek cache
         let peeking = if let Some(peeked) = self.peeked {
-            self.buf.push(peeked);
+            self.buf.push_front(peeked);
             true
         } else {
             false
         };
 
-        self.buf.extend(peeked);
+        for tok in peeked {
+            self.buf.push_front(tok);
+        }
 
         // Pop the first token again
         if peeking {
-            self.peeked = self.buf.pop();
+            self.peeked = self.buf.pop_front();
         }
     }
 
     /// Fill the peek cache with a page of tokens at the same time
+    ///
+    /// The page is appended after the tokens pending in the cache, and the
+    /// first token of the cache is peeked.
     pub fn bump(&mut self, peeked: impl Iterator<Item = Tok<'a>>) {
-        assert!(
-            self.buf.is_empty(),
-            "all of tokens in the peek cache should be consumed before bumping",
-        );
-
         /// The size of a page, in some architectures it is 16384B but that
         /// doesn't matter, we only need a sensible value
         const PAGE_SIZE: usize = 4096;
         /// The item size of the peek cache
-        const PEEK_CACHE_SIZE: usize = (PAGE_SIZE - 16) / std::mem::size_of::<Tok<'static>>();
+        const PEEK_CACHE_SIZE: usize = (PAGE_SIZE - 16) / core::mem::size_of::<Tok<'static>>();
 
         // Fill the peek cache with a page of tokens
         self.buf.extend(peeked.take(PEEK_CACHE_SIZE));
-        // Reverse the peek cache to make it a stack
-        self.buf.reverse();
         // Pop the first token again
-        self.peeked = self.buf.pop();
+        self.peeked = self.buf.pop_front();
     }
 }
 
@@ -74,16 +84,77 @@ pub struct StreamContext<'a> {
     pub peek_outer: LexCache<'a>,
     /// Inner peek
     pub peek_inner: LexCache<'a>,
+
+    /// The number of tokens the lexer has advanced past, i.e. the index of
+    /// the peeked token among the tokens pushed by [`Self::push_outer`]
+    pub eaten: usize,
+    /// The number of tokens pushed by [`Self::push_outer`]
+    pub pushed: usize,
+    /// The indices of pushed tokens expanded by a macro call, with the byte
+    /// range of the call in the input, where these tokens are reported
+    /// instead of the macro definition
+    pub use_sites: VecDeque<(Range<usize>, Range<usize>)>,
 }
 
 impl<'a> StreamContext<'a> {
     #[inline]
     pub fn lex_one(l: &mut logos::Lexer<'a, Token>) -> Option<Tok<'a>> {
-        let tok = l.next()?.unwrap();
+        match core::mem::take(&mut l.extras.2) {
+            VerbatimNext::None => {}
+            // the body of a verbatim environment follows its `\begin`
+            VerbatimNext::EnvBody => {
+                if let Some(body) = lex_verbatim_env_body(l) {
+                    return Some((Token::Verbatim(false), body));
+                }
+            }
+            // the whitespace before the group is lexed as usual
+            VerbatimNext::ArgGroup => {
+                let rest = &l.source()[l.span().end..];
+                if rest.starts_with([' ', '\t']) {
+                    l.extras.2 = VerbatimNext::ArgGroup;
+                } else if rest.starts_with('{') {
+                    l.extras.2 = VerbatimNext::ArgBody;
+                }
+            }
+            VerbatimNext::ArgBody => {
+                if let Some(body) = lex_verbatim_arg_body(l) {
+                    return Some((Token::Word, body));
+                }
+            }
+        }
+
+        // an input that no rule matches is kept in an error token
+        let tok = l.next()?.unwrap_or(Token::Error);
+
+        let comment_char = l.extras.0.comment_char();
+        if comment_char != '%' {
+            if let Some(tok) = Self::reclassify_comment(l, tok, comment_char) {
+                return Some(tok);
+            }
+        }
 
         let source_text = match tok {
-            Token::CommandName(CommandName::BeginEnvironment | CommandName::EndEnvironment) => {
-                l.source().slice(l.extras.1.clone()).unwrap()
+            Token::CommandName(CommandName::BeginEnvironment) => {
+                let name = l.source().slice(l.extras.1.clone()).unwrap_or_default();
+                if is_verbatim_env(name) {
+                    l.extras.2 = VerbatimNext::EnvBody;
+                }
+                name
+            }
+            Token::CommandName(CommandName::EndEnvironment) => {
+                l.source().slice(l.extras.1.clone()).unwrap_or_default()
+            }
+            Token::CommandName(CommandName::Generic)
+                if matches!(l.slice(), "\\verb" | "\\verb*") =>
+            {
+                if let Some(starred) = lex_verb_body(l) {
+                    return Some((Token::Verbatim(starred), l.slice()));
+                }
+                l.slice()
+            }
+            Token::CommandName(CommandName::Generic) if is_verbatim_arg_cmd(&l.slice()[1..]) => {
+                l.extras.2 = VerbatimNext::ArgGroup;
+                l.slice()
             }
             _ => l.slice(),
         };
@@ -91,13 +162,105 @@ impl<'a> StreamContext<'a> {
         Some((tok, source_text))
     }
 
+    /// Get whether `@` is a letter in command names after a token, i.e.
+    /// whether it is `\makeatletter` or `\makeatother`
+    ///
+    /// The bumper applies it to the inner lexer when the command is used,
+    /// so that it doesn't take effect inside macro bodies or comments.
+    pub fn at_letter(tok: Tok<'a>) -> Option<bool> {
+        match tok {
+            (Token::CommandName(CommandName::Generic), "\\makeatletter") => Some(true),
+            (Token::CommandName(CommandName::Generic), "\\makeatother") => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Reclassify a token if the comment char is not `%`
+    ///
+    /// Since the logos rules only know `%`, the inner lexer is re-seeded right
+    /// after the `%` of a non-comment, or after a comment starting with the
+    /// custom comment char.
+    #[cold]
+    fn reclassify_comment(
+        l: &mut logos::Lexer<'a, Token>,
+        tok: Token,
+        comment_char: char,
+    ) -> Option<Tok<'a>> {
+        let source = l.source();
+        let start = l.span().start;
+
+        let comment_start = match tok {
+            // `%` is an ordinary char now
+            Token::LineComment => {
+                let end = start + '%'.len_utf8();
+                Self::reseed(l, end);
+                return Some((Token::Word, &source[start..end]));
+            }
+            // the comment char may be part of a word
+            Token::Word => start + l.slice().find(comment_char)?,
+            _ if l.slice().starts_with(comment_char) => start,
+            _ => return None,
+        };
+
+        // The rest of the word before the comment char
+        if comment_start > start {
+            Self::reseed(l, comment_start);
+            return Some((Token::Word, &source[start..comment_start]));
+        }
+
+        let comment_end = source[start..]
+            .find(['\r', '\n'])
+            .map_or(source.len(), |len| start + len);
+        Self::reseed(l, comment_end);
+        Some((Token::LineComment, &source[start..comment_end]))
+    }
+
+    /// Restart the inner lexer at a byte offset of the source
+    fn reseed(l: &mut logos::Lexer<'a, Token>, offset: usize) {
+        let mut reseeded = Token::lexer_with_extras(l.source(), l.extras.clone());
+        reseeded.bump(offset);
+        *l = reseeded;
+    }
+
+    /// Get the byte range of a text slice in the main input source
+    ///
+    /// Tokens lexed from the input borrow their text from it, so the range is
+    /// computed from the slice's address. This keeps the range accurate after
+    /// a token is partially consumed without storing offsets in the peek
+    /// cache.
+    pub fn span_of(&self, text: &str) -> Option<Range<usize>> {
+        let source = self.inner.source();
+        let start = (text.as_ptr() as usize).checked_sub(source.as_ptr() as usize)?;
+        let end = start + text.len();
+        (end <= source.len()).then_some(start..end)
+    }
+
+    /// Get the byte range of the macro call that the peeked token is
+    /// expanded from
+    pub fn use_site(&self) -> Option<Range<usize>> {
+        let (tokens, span) = self.use_sites.front()?;
+        tokens.contains(&self.eaten).then(|| span.clone())
+    }
+
+    /// Advance past the peeked token, forgetting the use sites before it
+    pub fn advance(&mut self) {
+        self.eaten += 1;
+        while self
+            .use_sites
+            .front()
+            .is_some_and(|(tokens, _)| tokens.end <= self.eaten)
+        {
+            self.use_sites.pop_front();
+        }
+    }
+
     // Inner bumping is not cached
     #[inline]
     pub fn next_token(&mut self) {
         let peeked = self
             .peek_inner
             .buf
-            .pop()
+            .pop_front()
             .or_else(|| Self::lex_one(&mut self.inner));
         self.peek_inner.peeked = peeked;
     }
@@ -117,9 +280,17 @@ impl<'a> StreamContext<'a> {
         self.peek_inner.peeked.map(|(kind, _)| kind)
     }
 
+    /// Replace the text of the peeked token, e.g. by the rest of a word whose
+    /// first chars are read
+    pub fn set_peeked_text(&mut self, text: &'a str) {
+        if let Some(peeked) = &mut self.peek_inner.peeked {
+            peeked.1 = text;
+        }
+    }
+
     #[inline]
     pub fn next_stream(&mut self) -> impl Iterator<Item = Tok<'a>> + '_ {
-        std::iter::from_fn(|| self.next_full())
+        core::iter::from_fn(|| self.next_full())
     }
 
     #[inline]
@@ -142,7 +313,8 @@ impl<'a> StreamContext<'a> {
     }
 
     pub fn push_outer(&mut self, peeked: Tok<'a>) {
-        self.peek_outer.buf.push(peeked);
+        self.peek_outer.buf.push_back(peeked);
+        self.pushed += 1;
     }
 
     pub fn extend_inner(&mut self, peeked: impl Iterator<Item = Tok<'a>>) {
@@ -12,13 +12,47 @@ pub enum BraceKind {
     Paren,
 }
 
+/// The text lexed verbatim after the last token, as the logos rules don't
+/// know where TeX stops interpreting the input
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum VerbatimNext {
+    /// Nothing follows verbatim
+    #[default]
+    None,
+    /// The body of a verbatim environment follows its `\begin`
+    EnvBody,
+    /// The first curly group of a command follows, e.g. the url of
+    /// `\url{...}`, whose body is lexed as a [`Token::Word`]
+    ArgGroup,
+    /// The body of the curly group follows its `{`
+    ArgBody,
+}
+
+/// Ligature kinds in TeX, used by defining [`Token`]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub enum LigatureKind {
+    /// En dash: `--`
+    EnDash,
+    /// Em dash: `---`
+    EmDash,
+    /// Left double quotation mark: ``` `` ```
+    LeftQuote,
+    /// Right double quotation mark: `''`
+    RightQuote,
+}
+
 /// The token types defined in logos
 ///
 /// For naming of marks, see <https://en.wikipedia.org/wiki/List_of_typographical_symbols_and_punctuation_marks>
 ///
 /// It also specifies how logos would lex the token
+///
+/// The extras of the logos lexer are the command specification, the span of
+/// the last lexed environment name, what follows verbatim, and whether `@` is
+/// a letter in command names, i.e. whether the lexer is between
+/// `\makeatletter` and `\makeatother`.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Logos)]
-#[logos(extras = (CommandSpec, logos::Span))]
+#[logos(extras = (CommandSpec, logos::Span, VerbatimNext, bool))]
 pub enum Token {
     /// A line break
     /// Typically a `\r\n` or `\n`
@@ -75,6 +109,13 @@ pub enum Token {
     #[token("^")]
     Caret,
 
+    /// A char in caret notation, e.g. `^^M`, `^^@` or `^^7f`
+    /// `^^X` resolves to the char whose code is that of `X` xor 64, and `^^hh`
+    /// resolves to the char whose code is the lowercase hex number `hh`. A
+    /// `^^` that is not followed by an ascii char is lexed as two carets.
+    #[regex(r"\^\^([0-9a-f][0-9a-f]|[\x00-\x7F])", lex_caret_notation)]
+    CaretNotation(char),
+
     /// An ascii apostrophe
     #[token("'")]
     Apostrophe,
@@ -103,14 +144,33 @@ pub enum Token {
     #[token("_", priority = 2)]
     Underscore,
 
+    /// A ligature in text mode, which is still lexed in math mode
+    /// E.g. `--`, `---`, ``` `` ```, `''`, etc.
+    /// See [`LigatureKind`] for ligatures.
+    #[token("--", le)]
+    #[token("---", lm)]
+    #[token("``", ll)]
+    #[token("''", lr)]
+    Ligature(LigatureKind),
+
     /// A character sequence that doesn't contain any above tokens
-    #[regex(r#"[^\s\\%\{\},\$\[\]\(\)\~/_\*@'";&^#]+"#, priority = 1)]
+    /// Note: a hyphen or a backquote is lexed as a single char word, so that
+    /// ligatures are recognized inside words
+    #[regex(r#"[^\s\\%\{\},\$\[\]\(\)\~/_\*@'";&^#\-`]+"#, priority = 1)]
+    #[token("-")]
+    #[token("`")]
     Word,
 
-    /// Special dollar signs
-    #[regex(r"\$\$?")]
+    /// A dollar sign that toggles inline math: `$`
+    #[token("$")]
     Dollar,
 
+    /// Double dollar signs that toggle display math: `$$`
+    /// Note: in inline math, it is also possible to be a closing `$` followed
+    /// by an opening `$`, which is decided by the parser.
+    #[token("$$")]
+    DoubleDollar,
+
     /// Though newline is also a valid command, whose name is `\`, we lex it
     /// independently so to help later AST consumers. This also means that user
     /// cannot redefine `\` as a command.
@@ -123,10 +183,27 @@ pub enum Token {
     #[regex(r"\\", lex_command_name, priority = 3)]
     CommandName(CommandName),
 
+    /// A verbatim span of `\verb` or `\verb*`, e.g. `\verb|a_b|`
+    /// The token text is the whole command as is, see [`verb_body`] for the
+    /// raw text between the delimiters, and the field tells whether the
+    /// command is starred, which visualizes spaces.
+    ///
+    /// It is also the body of a verbatim environment, e.g. `verbatim` or
+    /// `lstlisting`, which spans until the first `\end` of the environment.
+    ///
+    /// It is produced by the stream context rather than by the logos rules,
+    /// since the delimiter is an arbitrary char.
+    Verbatim(bool),
+
     /// Macro error
     Error,
 
-    /// A macro argument
+    /// A macro parameter `#1` to `#9` in a macro definition
+    ///
+    /// It is produced by the macro engine from a [`Token::Hash`] when it reads
+    /// the body of a definition, so a `#` is only a parameter there. A `##`
+    /// is resolved to a literal `#` at the same time, hence there is no token
+    /// for it.
     MacroArg(u8),
 }
 
@@ -226,10 +303,49 @@ fn bp(_: &mut logos::Lexer<Token>) -> BraceKind {
     BraceKind::Paren
 }
 
+/// Mark the ligature kind of a token as en dash
+#[inline(always)]
+fn le(_: &mut logos::Lexer<Token>) -> LigatureKind {
+    LigatureKind::EnDash
+}
+
+/// Mark the ligature kind of a token as em dash
+#[inline(always)]
+fn lm(_: &mut logos::Lexer<Token>) -> LigatureKind {
+    LigatureKind::EmDash
+}
+
+/// Mark the ligature kind of a token as left quotation mark
+#[inline(always)]
+fn ll(_: &mut logos::Lexer<Token>) -> LigatureKind {
+    LigatureKind::LeftQuote
+}
+
+/// Mark the ligature kind of a token as right quotation mark
+#[inline(always)]
+fn lr(_: &mut logos::Lexer<Token>) -> LigatureKind {
+    LigatureKind::RightQuote
+}
+
+/// Resolve the char of a caret notation
+fn lex_caret_notation(lexer: &mut logos::Lexer<Token>) -> char {
+    let code = &lexer.slice()[2..];
+    let code = match u8::from_str_radix(code, 16) {
+        Ok(hex) if code.len() == 2 => hex,
+        _ => code.as_bytes()[0] ^ 64,
+    };
+    char::from(code)
+}
+
 /// The utf8 length of ascii chars
 const LEN_ASCII: usize = 1;
 
 /// Lex a valid command name
+///
+/// As in TeX, `@` is a letter in command names only after `\makeatletter`,
+/// until `\makeatother`, e.g. `\section@foo` is `\section` followed by `@foo`
+/// outside the scope. The scope is set by the bumper when the commands are
+/// used rather than lexed, e.g. not inside a macro body.
 // todo: handle commands with underscores, whcih would require command names
 // todo: from specification
 fn lex_command_name(lexer: &mut logos::Lexer<Token>) -> CommandName {
@@ -257,6 +373,7 @@ fn lex_command_name(lexer: &mut logos::Lexer<Token>) -> CommandName {
     match c {
         '(' | '[' => return CommandName::BeginMath,
         ')' | ']' => return CommandName::EndMath,
+        '@' if !lexer.extras.3 => return CommandName::Generic,
         '@' => {}
         _ if !c.is_ascii_alphabetic() => return CommandName::Generic,
         _ => {}
@@ -298,6 +415,130 @@ fn lex_command_name(lexer: &mut logos::Lexer<Token>) -> CommandName {
     }
 }
 
+/// Lex the rest of a `\verb` command, whose name has just been lexed
+///
+/// The delimiter is the first char following `\verb` or `\verb*`, and the
+/// body spans until the next occurrence of the delimiter. Special chars such
+/// as `{`, `$` and `%` lose their meaning inside the body, and are also
+/// allowed as delimiters.
+///
+/// Returns whether the command is starred, or `None` without modifying the
+/// lexer if the body is not terminated in the same line.
+pub(crate) fn lex_verb_body(lexer: &mut logos::Lexer<Token>) -> Option<bool> {
+    let source = lexer.source();
+    let name_end = lexer.span().end;
+
+    // The star is lexed as part of the command name only if `verb*` is
+    // specified, so we check the source as well
+    let mut starred = lexer.slice().ends_with('*');
+    let mut delim_start = name_end;
+    if !starred && source[name_end..].starts_with('*') {
+        starred = true;
+        delim_start += LEN_ASCII;
+    }
+
+    let delim = source[delim_start..].chars().next()?;
+    if delim.is_whitespace() {
+        return None;
+    }
+
+    let body_start = delim_start + delim.len_utf8();
+    let body_len = source[body_start..].find([delim, '\r', '\n'])?;
+    let body_end = body_start + body_len;
+    if !source[body_end..].starts_with(delim) {
+        return None;
+    }
+
+    lexer.bump(body_end + delim.len_utf8() - name_end);
+    Some(starred)
+}
+
+/// Get the raw text between the delimiters of a `\verb` token, e.g. `a b` of
+/// `\verb*|a b|`
+pub fn verb_body(text: &str) -> &str {
+    let rest = text.strip_prefix("\\verb").unwrap_or(text);
+    // the star is taken before the delimiter, as in the lexer
+    let rest = rest.strip_prefix('*').unwrap_or(rest);
+    let mut chars = rest.chars();
+    match chars.next() {
+        Some(delim) => chars.as_str().strip_suffix(delim).unwrap_or(chars.as_str()),
+        None => "",
+    }
+}
+
+/// Check whether the body of an environment is lexed as a
+/// [`Token::Verbatim`]
+pub fn is_verbatim_env(name: &str) -> bool {
+    matches!(name, "verbatim" | "verbatim*" | "Verbatim" | "lstlisting")
+}
+
+/// Check whether the first curly group of a command is lexed verbatim, e.g.
+/// the url of `\href{...}{text}`, where `%`, `#` and `~` are common
+pub fn is_verbatim_arg_cmd(name: &str) -> bool {
+    matches!(name, "url" | "href")
+}
+
+/// Lex the body of a curly group right after its `{`, which spans until the
+/// matching `}`, where an escaped char, e.g. `\}`, is kept in the body
+///
+/// Returns `None` if the body is empty.
+pub(crate) fn lex_verbatim_arg_body<'s>(lexer: &mut logos::Lexer<'s, Token>) -> Option<&'s str> {
+    let source: &'s str = lexer.source();
+    let body_start = lexer.span().end;
+
+    let mut depth = 0usize;
+    let mut body_len = source.len() - body_start;
+    let mut bytes = source.as_bytes()[body_start..].iter().enumerate();
+    while let Some((pos, c)) = bytes.next() {
+        match c {
+            b'\\' => {
+                bytes.next();
+            }
+            b'{' => depth += 1,
+            b'}' if depth == 0 => {
+                body_len = pos;
+                break;
+            }
+            b'}' => depth -= 1,
+            _ => {}
+        }
+    }
+    if body_len == 0 {
+        return None;
+    }
+
+    lexer.bump(body_len);
+    Some(&source[body_start..body_start + body_len])
+}
+
+/// Lex the body of a verbatim environment right after its `\begin`, whose
+/// name is the last lexed environment name
+///
+/// The body spans until the first `\end` of the environment, as TeX doesn't
+/// interpret anything inside, or until the end of the input if it is not
+/// terminated. Returns `None` if the body is empty.
+pub(crate) fn lex_verbatim_env_body<'s>(lexer: &mut logos::Lexer<'s, Token>) -> Option<&'s str> {
+    let source: &'s str = lexer.source();
+    let body_start = lexer.span().end;
+    let name = &source[lexer.extras.1.clone()];
+
+    let body_len = source[body_start..]
+        .match_indices("\\end")
+        .find(|(pos, end)| {
+            let rest = source[body_start + pos + end.len()..].trim_start();
+            rest.strip_prefix('{')
+                .and_then(|rest| rest.trim_start().strip_prefix(name))
+                .map_or(false, |rest| rest.trim_start().starts_with('}'))
+        })
+        .map_or(source.len() - body_start, |(pos, _)| pos);
+    if body_len == 0 {
+        return None;
+    }
+
+    lexer.bump(body_len);
+    Some(&source[body_start..body_start + body_len])
+}
+
 fn advance_ascii_name(
     lexer: &mut logos::Lexer<Token>,
     ascii_str: &[u8],
@@ -335,7 +576,8 @@ fn advance_ascii_name(
             c if c.is_ascii_alphabetic() => bump_size += LEN_ASCII,
             // todo: math mode don't want :
             // b'@' | b':' => bump_size += LEN_ASCII,
-            b'@' => bump_size += LEN_ASCII,
+            // an environment name may contain `@` regardless of the scope
+            b'@' if !lex_slash_command || lexer.extras.3 => bump_size += LEN_ASCII,
             _ => break,
         };
     }
@@ -346,7 +588,7 @@ fn advance_ascii_name(
 fn lex_begin_end(lexer: &mut logos::Lexer<Token>, is_begin: bool) -> CommandName {
     struct LexTask<'a, 'b> {
         lexer: &'a mut logos::Lexer<'b, Token>,
-        chars: std::str::Chars<'b>,
+        chars: core::str::Chars<'b>,
         collected: usize,
     }
 
@@ -1,3 +1,5 @@
+#![cfg(feature = "std")]
+
 mod common;
 
 use insta::assert_snapshot;
@@ -169,6 +171,69 @@ fn declare_macro() {
     "###);
 }
 
+#[test]
+fn macro_params() {
+    assert_snapshot!(get_macro(r#"\newcommand{\x}[1]{#1+#1}"#, "x"), @r###"
+    Cmd(
+        CmdMacro {
+            name: "x",
+            num_args: 1,
+            opt: None,
+            def: [
+                (
+                    MacroArg(
+                        1,
+                    ),
+                    "#",
+                ),
+                (
+                    Word,
+                    "+",
+                ),
+                (
+                    MacroArg(
+                        1,
+                    ),
+                    "#",
+                ),
+            ],
+        },
+    )
+    "###);
+    // Description: `##` is a literal hash, `#0` and a trailing `#` are kept as is
+    assert_snapshot!(get_macro(r#"\newcommand{\x}{##1#0#}"#, "x"), @r###"
+    Cmd(
+        CmdMacro {
+            name: "x",
+            num_args: 0,
+            opt: None,
+            def: [
+                (
+                    Hash,
+                    "#",
+                ),
+                (
+                    Word,
+                    "1",
+                ),
+                (
+                    Hash,
+                    "#",
+                ),
+                (
+                    Word,
+                    "0",
+                ),
+                (
+                    Hash,
+                    "#",
+                ),
+            ],
+        },
+    )
+    "###);
+}
+
 #[test]
 fn subst_macro() {
     // Description: zero arguments
@@ -188,6 +253,95 @@ fn subst_macro() {
     Word("test")
     Word("end")
     "###);
+    // Description: two arguments
+    assert_snapshot!(tokens(r#"\newcommand{\ab}[2]{#1+#2}\ab{x}{\R}"#), @r###"
+    Word("x")
+    Word("+")
+    CommandName(Generic)("\\R")
+    "###);
+}
+
+#[test]
+fn subst_macro_opt() {
+    // Description: the default value of the optional first argument
+    assert_snapshot!(tokens(r#"\newcommand{\f}[2][a]{#1+#2}\f{y}"#), @r###"
+    Word("a")
+    Word("+")
+    Word("y")
+    "###);
+    // Description: the optional first argument is given
+    assert_snapshot!(tokens(r#"\newcommand{\f}[2][a]{#1+#2}\f[x]{y}"#), @r###"
+    Word("x")
+    Word("+")
+    Word("y")
+    "###);
+    // Description: only an optional argument
+    assert_snapshot!(tokens(r#"\newcommand{\f}[1][a]{(#1)}\f\f[b]"#), @r###"
+    Left(Paren)("(")
+    Word("a")
+    Right(Paren)(")")
+    Left(Paren)("(")
+    Word("b")
+    Right(Paren)(")")
+    "###);
+}
+
+#[test]
+fn subst_def() {
+    // Description: delimited parameters
+    assert_snapshot!(tokens(r#"\def\pair#1;#2{(#1,#2)}\pair a;b"#), @r###"
+    Left(Paren)("(")
+    Word("a")
+    Comma(",")
+    Word("b")
+    Right(Paren)(")")
+    "###);
+    // Description: a delimiter inside a word, and braces around an argument
+    assert_snapshot!(tokens(r#"\def\f#1.{[#1]}\f ab.c\f{a.b}."#), @r###"
+    Left(Bracket)("[")
+    Word("ab")
+    Right(Bracket)("]")
+    Word("c")
+    Left(Bracket)("[")
+    Word("a.b")
+    Right(Bracket)("]")
+    "###);
+    // Description: a prefix and an undelimited parameter
+    assert_snapshot!(tokens(r#"\def\f(#1#2){#2#1}\f(xy)"#), @r###"
+    Word("y")
+    Word("x")
+    "###);
+    // Description: the prefix is not matched
+    assert_snapshot!(tokens(r#"\def\f(#1){#1}\f x"#), @r###"
+    Error("use of macro doesn't match its definition")
+    Word("x")
+    "###);
+    // Description: unsupported parameter text
+    assert_snapshot!(tokens(r#"\def\f#2{#2}"#), @r###"
+    Error("unsupported parameter text")
+    Word("2")
+    Left(Curly)("{")
+    Hash("#")
+    Word("2")
+    Right(Curly)("}")
+    "###);
+}
+
+#[test]
+fn subst_macro_recursive() {
+    // Description: a recursive macro is stopped by the depth limit
+    assert_snapshot!(tokens(r#"\newcommand{\f}{\f}\f"#), @r###"Error("\\f")"###);
+    assert_snapshot!(tokens(r#"\newcommand{\f}{\g}\newcommand{\g}{\f}\f x"#), @r###"
+    Error("\\f")
+    Whitespace(" ")
+    Word("x")
+    "###);
+    // Description: sequential expansions are not nested
+    let input = format!(
+        r#"\newcommand{{\f}}{{\g}}\newcommand{{\g}}{{x}}{}"#,
+        r"\f".repeat(2000)
+    );
+    assert_eq!(tokens(&input), vec![r#"Word("x")"#; 2000].join("\n"));
 }
 
 #[test]
@@ -319,6 +473,26 @@ fn subst_if() {
     "###);
 }
 
+#[test]
+fn at_letter_on_use() {
+    let at_letter = r#"CommandName(Generic)("\\a@b")"#;
+    let at_other = r#"CommandName(Generic)("\\a")
+AtSign("@")
+Word("b")"#;
+    // Description: `@` is a letter after `\makeatletter` is used
+    let lexed = tokens(r#"\makeatletter\a@b"#);
+    assert!(lexed.contains(at_letter), "{lexed}");
+    // Description: `\makeatletter` in a macro body applies when the macro is
+    // expanded, but not when it is defined
+    let lexed = tokens(r#"\newcommand{\f}{\makeatletter}\a@b\f \a@b"#);
+    assert!(lexed.contains(at_other), "{lexed}");
+    assert!(lexed.contains(at_letter), "{lexed}");
+    // Description: `\makeatletter` in a block comment is not used
+    let lexed = tokens(r#"\iffalse\makeatletter\fi\a@b"#);
+    assert!(lexed.contains(at_other), "{lexed}");
+    assert!(!lexed.contains(at_letter), "{lexed}");
+}
+
 #[test]
 fn newcommand_recursive() {
     assert_snapshot!(tokens(r#"\newcommand{\DeclareMathDelimit}[2]{\newcommand{#1}[1]{\left#2\mitexrecurse{#1}\right#2}}\DeclareMathDelimit{\abs}{\vert}\abs{abc}"#), @r###"
@@ -329,3 +503,76 @@ fn newcommand_recursive() {
     CommandName(Generic)("\\vert")
     "###);
 }
+
+// collect the text of all tokens with the input text they are reported at
+fn spanned_tokens(input: &str) -> Vec<(&str, &str)> {
+    let mut lexer = Lexer::new_with_bumper(
+        input,
+        DEFAULT_SPEC.clone(),
+        MacroEngine::new(DEFAULT_SPEC.clone()),
+    );
+
+    std::iter::from_fn(|| {
+        let span = lexer.peek_span()?;
+        lexer.eat().map(|tok| (tok.1, &input[span]))
+    })
+    .collect()
+}
+
+#[test]
+fn use_site_spans() {
+    // Description: the expanded tokens are reported at the macro call, but the
+    // tokens of the arguments keep their own ranges
+    assert_eq!(
+        spanned_tokens(r#"\newcommand{\pair}[2]{(#1,#2)}\pair{a}{b} c"#),
+        vec![
+            ("(", r"\pair{a}{b}"),
+            ("a", "a"),
+            (",", r"\pair{a}{b}"),
+            ("b", "b"),
+            (")", r"\pair{a}{b}"),
+            (" ", " "),
+            ("c", "c"),
+        ]
+    );
+    // Description: a nested call is reported at the outermost call
+    assert_eq!(
+        spanned_tokens(r#"\newcommand{\x}{y}\newcommand{\xx}{\x\x}\xx z"#),
+        vec![("y", r"\xx"), ("y", r"\xx"), (" ", " "), ("z", "z")]
+    );
+    // Description: an environment is reported from its beginning to its end
+    assert_eq!(
+        spanned_tokens(r#"\newenvironment{f}{<}{>}\begin{f}t\end{f}"#),
+        vec![
+            ("<", r"\begin{f}t\end{f}"),
+            ("t", "t"),
+            (">", r"\begin{f}t\end{f}"),
+        ]
+    );
+}
+
+#[test]
+fn checkpoint_restore() {
+    // Description: the macros redefined after the checkpoint are rewound,
+    // where the redefinition is lexed in a later page of the peek cache
+    let input = format!(
+        r#"\newcommand{{\f}}{{x}}{}\renewcommand{{\f}}{{y}}{}"#,
+        r"\f;".repeat(200),
+        r"\f;".repeat(200)
+    );
+    let mut lexer = Lexer::new_with_bumper(
+        &input,
+        DEFAULT_SPEC.clone(),
+        MacroEngine::new(DEFAULT_SPEC.clone()),
+    );
+    for _ in 0..100 {
+        lexer.eat();
+    }
+    let cp = lexer.checkpoint();
+    let ahead = lexer.tokens().collect::<Vec<_>>();
+    lexer.restore(cp);
+    let again = lexer.tokens().collect::<Vec<_>>();
+    assert_eq!(ahead, again);
+    assert_eq!(again.iter().filter(|tok| tok.1 == "x").count(), 150);
+    assert_eq!(again.iter().filter(|tok| tok.1 == "y").count(), 200);
+}
@@ -0,0 +1,387 @@
+use insta::assert_snapshot;
+
+use mitex_lexer::{Lexer, Token};
+use mitex_spec_gen::DEFAULT_SPEC;
+
+// format all remaining tokens of a lexer
+fn dump(lexer: &mut Lexer<'_>) -> String {
+    lexer
+        .tokens()
+        .map(|tok| format!("{:?}({:?})", tok.0, tok.1))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn tokens_iter() {
+    let mut lexer = Lexer::<()>::new(r"\frac{1}{2}", DEFAULT_SPEC.clone());
+    assert_snapshot!(dump(&mut lexer), @r###"
+    CommandName(Generic)("\\frac")
+    Left(Curly)("{")
+    Word("1")
+    Right(Curly)("}")
+    Left(Curly)("{")
+    Word("2")
+    Right(Curly)("}")
+    "###);
+    assert_eq!(lexer.peek(), None);
+}
+
+#[test]
+fn tokens_iter_after_consume() {
+    let mut lexer = Lexer::<()>::new("abc d", DEFAULT_SPEC.clone());
+    assert_eq!(lexer.peek(), Some(Token::Word));
+    lexer.consume_utf8_bytes(1);
+    assert_eq!(lexer.peek_text(), Some("bc"));
+    assert_snapshot!(dump(&mut lexer), @r###"
+    Word("bc")
+    Whitespace(" ")
+    Word("d")
+    "###);
+}
+
+#[test]
+fn consume_inside_char() {
+    let mut lexer = Lexer::<()>::new("éa 中文", DEFAULT_SPEC.clone());
+    // the rest of a partially consumed char is consumed as well
+    lexer.consume_utf8_bytes(1);
+    assert_eq!(lexer.peek_text(), Some("a"));
+    lexer.consume_utf8_bytes(1);
+    lexer.consume_utf8_bytes(1);
+    assert_eq!(lexer.peek_text(), Some("中文"));
+    lexer.consume_utf8_bytes(1);
+    assert_eq!(lexer.peek_text(), Some("文"));
+    // consuming the rest of the last char advances to the next token
+    lexer.consume_utf8_bytes(2);
+    assert_eq!(lexer.peek(), None);
+}
+
+#[test]
+fn spans_round_trip() {
+    // enough tokens to cross the boundary of a page in the peek cache
+    let input = r"\alpha_{x} + \frac{1}{2}, ".repeat(64);
+    let mut lexer = Lexer::<()>::new(&input, DEFAULT_SPEC.clone());
+    let mut end = 0;
+    while let Some((_, text, span)) = lexer.eat_spanned() {
+        let span = span.unwrap();
+        assert_eq!(span.start, end);
+        assert_eq!(&input[span.clone()], text);
+        end = span.end;
+    }
+    assert_eq!(end, input.len());
+}
+
+#[test]
+fn spans_after_consume() {
+    let input = "abc d";
+    let mut lexer = Lexer::<()>::new(input, DEFAULT_SPEC.clone());
+    assert_eq!(lexer.peek_span(), Some(0..3));
+    lexer.consume_utf8_bytes(1);
+    assert_eq!(lexer.peek_span(), Some(1..3));
+    assert_eq!(&input[lexer.peek_span().unwrap()], "bc");
+    lexer.consume_utf8_bytes(2);
+    assert_eq!(lexer.peek_span(), Some(3..4));
+}
+
+#[test]
+fn verbatim() {
+    let lex = |input| dump(&mut Lexer::<()>::new(input, DEFAULT_SPEC.clone()));
+    assert_snapshot!(lex(r"\verb#a_b^c#"), @r###"Verbatim(false)("\\verb#a_b^c#")"###);
+    assert_snapshot!(lex(r"\verb*| a b |x"), @r###"
+    Verbatim(true)("\\verb*| a b |")
+    Word("x")
+    "###);
+    // special chars as delimiters or inside the body
+    assert_snapshot!(lex(r"\verb{a}%{ \verb$x%y$"), @r###"
+    Verbatim(false)("\\verb{a}%{")
+    Whitespace(" ")
+    Verbatim(false)("\\verb$x%y$")
+    "###);
+    // not terminated in the same line
+    assert_snapshot!(lex("\\verb|a\n|"), @r###"
+    CommandName(Generic)("\\verb")
+    Word("|a")
+    LineBreak("\n")
+    Word("|")
+    "###);
+}
+
+#[test]
+fn verbatim_env() {
+    let lex = |input| dump(&mut Lexer::<()>::new(input, DEFAULT_SPEC.clone()));
+    assert_snapshot!(lex("\\begin{verbatim}\n$a_b \\end{x}\n\\end{verbatim}b"), @r###"
+    CommandName(BeginEnvironment)("verbatim")
+    Verbatim(false)("\n$a_b \\end{x}\n")
+    CommandName(EndEnvironment)("verbatim")
+    Word("b")
+    "###);
+    assert_snapshot!(lex(r"\begin{lstlisting}[language=C]{\end{lstlisting}"), @r###"
+    CommandName(BeginEnvironment)("lstlisting")
+    Verbatim(false)("[language=C]{")
+    CommandName(EndEnvironment)("lstlisting")
+    "###);
+    // not terminated
+    assert_snapshot!(lex(r"\begin{verbatim}a%"), @r###"
+    CommandName(BeginEnvironment)("verbatim")
+    Verbatim(false)("a%")
+    "###);
+}
+
+#[test]
+fn verbatim_arg() {
+    let lex = |input| dump(&mut Lexer::<()>::new(input, DEFAULT_SPEC.clone()));
+    assert_snapshot!(lex(r"\url{a%b#c{d}\}}e%f"), @r###"
+    CommandName(Generic)("\\url")
+    Left(Curly)("{")
+    Word("a%b#c{d}\\}")
+    Right(Curly)("}")
+    Word("e")
+    LineComment("%f")
+    "###);
+    // only the first group is verbatim
+    assert_snapshot!(lex(r"\href {%}{%}"), @r###"
+    CommandName(Generic)("\\href")
+    Whitespace(" ")
+    Left(Curly)("{")
+    Word("%")
+    Right(Curly)("}")
+    Left(Curly)("{")
+    LineComment("%}")
+    "###);
+    assert_snapshot!(lex(r"\url{}x"), @r###"
+    CommandName(Generic)("\\url")
+    Left(Curly)("{")
+    Right(Curly)("}")
+    Word("x")
+    "###);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn peek_nth() {
+    use mitex_lexer::{BraceKind, CommandName, MacroEngine};
+
+    let mut lexer = Lexer::<()>::new(r"\left.\right)", DEFAULT_SPEC.clone());
+    assert_eq!(lexer.peek_nth(0), lexer.peek());
+    assert_eq!(lexer.peek_nth(1), Some(Token::Word));
+    assert_eq!(lexer.peek_nth(3), Some(Token::Right(BraceKind::Paren)));
+    assert_eq!(lexer.peek_nth(4), None);
+
+    let input = r"\left . \right )";
+    let mut lexer = Lexer::new_with_bumper(
+        input,
+        DEFAULT_SPEC.clone(),
+        MacroEngine::new(DEFAULT_SPEC.clone()),
+    );
+    assert_eq!(lexer.peek_nth(2), Some(Token::Word));
+    assert_eq!(
+        lexer.peek_nth(4),
+        Some(Token::CommandName(CommandName::Right))
+    );
+    // nothing is consumed
+    assert_eq!(
+        lexer.eat(),
+        Some((Token::CommandName(CommandName::Left), r"\left"))
+    );
+    assert_eq!(lexer.peek_nth(1), Some(Token::Word));
+}
+
+#[test]
+fn peek_nth_across_pages() {
+    let input = r"\alpha_{x} + \frac{1}{2}, ".repeat(64);
+    let mut lexer = Lexer::<()>::new(&input, DEFAULT_SPEC.clone());
+    let far = lexer.peek_nth(1000);
+    let near = lexer.peek_nth(3);
+    let mut eaten = Lexer::<()>::new(&input, DEFAULT_SPEC.clone());
+    let eaten = eaten.tokens().map(|tok| tok.0).collect::<Vec<_>>();
+    assert_eq!(far, Some(eaten[1000]));
+    assert_eq!(near, Some(eaten[3]));
+    assert_eq!(lexer.tokens().map(|tok| tok.0).collect::<Vec<_>>(), eaten);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn peek_nth_across_pages_macro() {
+    use mitex_lexer::MacroEngine;
+
+    let input = r"\newcommand{\f}{\frac{1}{2}}\f + \alpha_{x}, ".repeat(64);
+    let lexer = || {
+        Lexer::new_with_bumper(
+            input.as_str(),
+            DEFAULT_SPEC.clone(),
+            MacroEngine::new(DEFAULT_SPEC.clone()),
+        )
+    };
+    let mut peeking = lexer();
+    let far = peeking.peek_nth(1000);
+    let near = peeking.peek_nth(3);
+    let eaten = lexer().tokens().map(|tok| tok.0).collect::<Vec<_>>();
+    assert_eq!(far, Some(eaten[1000]));
+    assert_eq!(near, Some(eaten[3]));
+    assert_eq!(peeking.tokens().map(|tok| tok.0).collect::<Vec<_>>(), eaten);
+}
+
+#[test]
+fn checkpoint_restore() {
+    let input = r"\frac{a}{b} + c";
+    let mut lexer = Lexer::<()>::new(input, DEFAULT_SPEC.clone());
+    lexer.eat();
+    let cp = lexer.checkpoint();
+    let ahead = (0..5).map(|_| lexer.eat().unwrap()).collect::<Vec<_>>();
+    lexer.restore(cp);
+    let again = (0..5).map(|_| lexer.eat().unwrap()).collect::<Vec<_>>();
+    assert_eq!(ahead, again);
+    assert_snapshot!(dump(&mut lexer), @r###"
+    Right(Curly)("}")
+    Whitespace(" ")
+    Word("+")
+    Whitespace(" ")
+    Word("c")
+    "###);
+}
+
+#[test]
+fn ligatures() {
+    let mut lexer = Lexer::<()>::new(r"a---b -- c ``q'' - ----", DEFAULT_SPEC.clone());
+    assert_snapshot!(dump(&mut lexer), @r###"
+    Word("a")
+    Ligature(EmDash)("---")
+    Word("b")
+    Whitespace(" ")
+    Ligature(EnDash)("--")
+    Whitespace(" ")
+    Word("c")
+    Whitespace(" ")
+    Ligature(LeftQuote)("``")
+    Word("q")
+    Ligature(RightQuote)("''")
+    Whitespace(" ")
+    Word("-")
+    Whitespace(" ")
+    Ligature(EmDash)("---")
+    Word("-")
+    "###);
+}
+
+#[test]
+fn caret_notation() {
+    let lex = |input| dump(&mut Lexer::<()>::new(input, DEFAULT_SPEC.clone()));
+    assert_snapshot!(lex(r"^^M^^@^^41^^7f"), @r###"
+    CaretNotation('\r')("^^M")
+    CaretNotation('\0')("^^@")
+    CaretNotation('A')("^^41")
+    CaretNotation('\u{7f}')("^^7f")
+    "###);
+    // a lone caret is still a superscript, and so are carets at the end
+    assert_snapshot!(lex(r"x^2 ^^"), @r###"
+    Word("x")
+    Caret("^")
+    Word("2")
+    Whitespace(" ")
+    Caret("^")
+    Caret("^")
+    "###);
+}
+
+#[test]
+fn custom_comment_char() {
+    let spec = DEFAULT_SPEC.clone().with_comment_char('#');
+    let mut lexer = Lexer::<()>::new("% not a comment\na#b c\n", spec);
+    assert_snapshot!(dump(&mut lexer), @r###"
+    Word("%")
+    Whitespace(" ")
+    Word("not")
+    Whitespace(" ")
+    Word("a")
+    Whitespace(" ")
+    Word("comment")
+    LineBreak("\n")
+    Word("a")
+    LineComment("#b c")
+    LineBreak("\n")
+    "###);
+}
+
+#[test]
+fn dollars() {
+    let lex = |input| dump(&mut Lexer::<()>::new(input, DEFAULT_SPEC.clone()));
+    assert_snapshot!(lex(r"$$x$$ $x$"), @r###"
+    DoubleDollar("$$")
+    Word("x")
+    DoubleDollar("$$")
+    Whitespace(" ")
+    Dollar("$")
+    Word("x")
+    Dollar("$")
+    "###);
+}
+
+#[test]
+fn at_letter_scope() {
+    let lex = |input| dump(&mut Lexer::<()>::new(input, DEFAULT_SPEC.clone()));
+    // `@` is a letter only between `\makeatletter` and `\makeatother`
+    assert_snapshot!(lex(r"\section@foo\makeatletter\section@foo\@ifstar\makeatother\section@foo\@ifstar"), @r###"
+    CommandName(Generic)("\\section")
+    AtSign("@")
+    Word("foo")
+    CommandName(Generic)("\\makeatletter")
+    CommandName(Generic)("\\section@foo")
+    CommandName(If(IfStar))("\\@ifstar")
+    CommandName(Generic)("\\makeatother")
+    CommandName(Generic)("\\section")
+    AtSign("@")
+    Word("foo")
+    CommandName(Generic)("\\@")
+    Word("ifstar")
+    "###);
+    // the name of an environment may always contain `@`
+    assert_snapshot!(lex(r"\begin{a@b}"), @r###"CommandName(BeginEnvironment)("a@b")"###);
+}
+
+#[test]
+fn skip_bom() {
+    let input = "\u{FEFF}\\alpha";
+    let mut lexer = Lexer::<()>::new(input, DEFAULT_SPEC.clone());
+    assert_eq!(lexer.peek_text(), Some("\\alpha"));
+    assert_eq!(lexer.peek_span(), Some(3..9));
+    assert_snapshot!(dump(&mut lexer), @r###"CommandName(Generic)("\\alpha")"###);
+}
+
+#[test]
+fn line_index() {
+    use mitex_lexer::LineIndex;
+
+    let input = "a\r\n\\beta γ\rδ\n\nε";
+    let index = LineIndex::new(input);
+    assert_eq!(index.line_count(), 5);
+    assert_eq!(index.line_col(0), Some((1, 1)));
+    // inside and after `\r\n`
+    assert_eq!(index.line_col(1), Some((1, 2)));
+    assert_eq!(index.line_col(2), Some((1, 3)));
+    assert_eq!(index.line_col(3), Some((2, 1)));
+    // columns count chars
+    let gamma = input.find('γ').unwrap();
+    assert_eq!(index.line_col(gamma), Some((2, 7)));
+    assert_eq!(index.line_col(gamma + 1), None);
+    assert_eq!(index.line_col(input.find('δ').unwrap()), Some((3, 1)));
+    assert_eq!(index.line_col(input.find('ε').unwrap()), Some((5, 1)));
+    assert_eq!(index.line_col(input.len()), Some((5, 2)));
+    assert_eq!(index.line_col(input.len() + 1), None);
+
+    // positions of tokens
+    let mut lexer = Lexer::<()>::new(input, DEFAULT_SPEC.clone());
+    let positions = std::iter::from_fn(|| lexer.eat_spanned())
+        .filter(|(kind, ..)| !kind.is_trivia())
+        .map(|(_, text, span)| (text, index.line_col(span.unwrap().start).unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        positions,
+        vec![
+            ("a", (1, 1)),
+            ("\\beta", (2, 1)),
+            ("γ", (2, 7)),
+            ("δ", (3, 1)),
+            ("ε", (5, 1)),
+        ]
+    );
+}
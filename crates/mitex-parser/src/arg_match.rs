@@ -1,7 +1,10 @@
 use core::fmt;
 use std::sync::Arc;
 
-use crate::{argument_kind::ARGUMENT_KIND_TERM, ArgPattern};
+use crate::{
+    argument_kind::{ARGUMENT_KIND_STAR, ARGUMENT_KIND_TERM},
+    ArgPattern,
+};
 use mitex_glob::glob_match_prefix;
 
 /// A matcher for arguments of a TeX command
@@ -53,6 +56,19 @@ impl ArgMatcher {
         }
     }
 
+    /// Check if the matcher is ending match with a star, which is matched as
+    /// a term if the glob doesn't expect a star there
+    pub fn match_star(&mut self) -> bool {
+        if let Self::Glob { re, prefix } = self {
+            prefix.push(ARGUMENT_KIND_STAR);
+            if glob_match_prefix(re, prefix) {
+                return true;
+            }
+            prefix.pop();
+        }
+        self.try_match(ARGUMENT_KIND_TERM)
+    }
+
     /// Check if the matcher is ending match with that char
     pub fn try_match(&mut self, text: char) -> bool {
         match self {
@@ -146,4 +162,14 @@ mod tests {
         assert!(glob_match_prefix("{,b}t", "bt"));
         assert!(!glob_match_prefix("{,b}t", "tt"));
     }
+
+    #[test]
+    fn glob_star() {
+        assert!(glob_match_prefix("{,s}{,b}t", ""));
+        assert!(glob_match_prefix("{,s}{,b}t", "s"));
+        assert!(glob_match_prefix("{,s}{,b}t", "sbt"));
+        assert!(glob_match_prefix("{,s}{,b}t", "bt"));
+        assert!(glob_match_prefix("{,s}{,b}t", "t"));
+        assert!(!glob_match_prefix("{,s}{,b}t", "bs"));
+    }
 }
@@ -0,0 +1,48 @@
+//! Diagnostics reported while parsing
+
+use std::ops::Range;
+
+/// The severity of a [`Diagnostic`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The input is still converted as expected, e.g. an unknown command is
+    /// kept as is
+    Warning,
+    /// The input is malformed, and the parser recovered from it by guessing,
+    /// e.g. an unclosed curly group is closed at the end of its scope
+    Error,
+}
+
+/// A message about a range of the input
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Diagnostic {
+    /// The human-readable message
+    pub message: String,
+    /// The byte range in the main input source
+    ///
+    /// It is empty at the start of the input if the reported token is not
+    /// sliced from the input, see [`mitex_lexer::Lexer::peek_span`].
+    pub span: Range<usize>,
+    /// The severity of the diagnostic
+    pub severity: Severity,
+}
+
+impl Diagnostic {
+    /// Create an error diagnostic
+    pub fn error(message: impl Into<String>, span: Range<usize>) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            severity: Severity::Error,
+        }
+    }
+
+    /// Create a warning diagnostic
+    pub fn warning(message: impl Into<String>, span: Range<usize>) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            severity: Severity::Warning,
+        }
+    }
+}
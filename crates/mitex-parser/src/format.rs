@@ -0,0 +1,125 @@
+//! A pretty-printer regenerating normalized LaTeX from a syntax tree
+
+use rowan::NodeOrToken;
+
+use crate::syntax::{SyntaxElement, SyntaxKind::*, SyntaxNode};
+
+/// Print a syntax tree back to LaTeX with canonical whitespace
+///
+/// The printed LaTeX has the same meaning as the input:
+/// - a run of spaces is printed as one space, and the spaces at the start or
+///   the end of a line are dropped
+/// - more than one empty line is printed as one empty line, i.e. a paragraph
+///   break
+/// - a bare argument of a command is wrapped in braces, e.g. `\frac 1 2` is
+///   printed as `\frac{1}{2}`, since the spaces between the arguments are not
+///   kept in the tree
+///
+/// Comments, block comments and verbatim text are printed as is. Note that a
+/// tree returned by [`crate::parse`] has its macros expanded, and that the
+/// comments between the arguments of a command are not kept in the tree.
+///
+/// ```
+/// use mitex_parser::{format_latex, parse, CommandSpecBuilder};
+///
+/// let mut spec = CommandSpecBuilder::default();
+/// spec.command("frac", "{}{}", "frac");
+/// let tree = parse(r#"\frac 1  2"#, spec.build().unwrap());
+/// assert_eq!(format_latex(&tree), r#"\frac{1}{2}"#);
+/// ```
+pub fn format_latex(tree: &SyntaxNode) -> String {
+    let mut printer = Printer::default();
+    printer.node(tree);
+    printer.out
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    // a space is printed before the next text, unless a line ends in between
+    space: bool,
+    // the number of line breaks printed before the next text
+    breaks: usize,
+}
+
+impl Printer {
+    /// Print text after the pending whitespace
+    fn write(&mut self, text: &str) {
+        if self.out.is_empty() {
+            // drop the whitespace at the start of the input
+        } else if self.breaks > 0 {
+            for _ in 0..self.breaks.min(2) {
+                self.out.push('\n');
+            }
+        } else if self.space {
+            self.out.push(' ');
+        }
+        self.space = false;
+        self.breaks = 0;
+        self.out.push_str(text);
+    }
+
+    fn elem(&mut self, elem: SyntaxElement) {
+        match elem {
+            NodeOrToken::Node(node) => self.node(&node),
+            NodeOrToken::Token(token) => match token.kind() {
+                TokenWhiteSpace => self.space = true,
+                TokenLineBreak => self.breaks += token.text().matches('\n').count().max(1),
+                _ => self.write(token.text()),
+            },
+        }
+    }
+
+    fn node(&mut self, node: &SyntaxNode) {
+        match node.kind() {
+            ItemBegin | ItemEnd => {
+                // the name is kept without the braces in the tree
+                let mut children = node.children_with_tokens().peekable();
+                let name = children.next_if(|child| child.kind() == TokenCommandSym);
+                let name = name.as_ref().and_then(|name| name.as_token());
+                let name = name.map_or("", |name| name.text().trim());
+                if node.kind() == ItemBegin {
+                    self.write(&format!("\\begin{{{name}}}"));
+                } else {
+                    self.write(&format!("\\end{{{name}}}"));
+                }
+                children.for_each(|child| self.elem(child));
+            }
+            // the body of a block comment is printed as is
+            ItemBlockComment | ItemTypstCode => {
+                if node.kind() == ItemBlockComment {
+                    self.write("\\iffalse");
+                } else {
+                    self.write("\\iftypst");
+                }
+                self.out.push_str(&node.text().to_string());
+                self.out.push_str("\\fi");
+            }
+            ClauseArgument if is_bare_argument(node) => {
+                self.write("{");
+                node.children_with_tokens()
+                    .for_each(|child| self.elem(child));
+                self.write("}");
+            }
+            _ => node
+                .children_with_tokens()
+                .for_each(|child| self.elem(child)),
+        }
+    }
+}
+
+/// Check whether an argument of a command is neither a group nor separated by
+/// spaces, e.g. `1` in `\frac 1 2`
+fn is_bare_argument(arg: &SyntaxNode) -> bool {
+    if !matches!(
+        arg.parent().map(|cmd| cmd.kind()),
+        Some(ItemCmd | ItemBegin)
+    ) {
+        return false;
+    }
+    let mut children = arg.children_with_tokens();
+    match (children.next(), children.next()) {
+        (Some(child), None) => matches!(child.kind(), TokenWord | TokenCommandSym | ItemCmd),
+        _ => false,
+    }
+}
@@ -0,0 +1,108 @@
+//! A JSON dump of a syntax tree
+
+use std::ops::Range;
+
+use rowan::{NodeOrToken, SyntaxElementChildren};
+use serde_json::{json, Value};
+
+use crate::source_span::SourceSpans;
+use crate::syntax::{SyntaxNode, TexLang};
+
+/// Convert a syntax tree to JSON, e.g. for tooling not written in Rust
+///
+/// Each node and token is an object with the fields:
+/// - `kind`: the name of its [`crate::syntax::SyntaxKind`], e.g. `"ItemCmd"`
+/// - `span`: the `[start, end)` byte range in the input, mapped by `spans`,
+///   i.e. the range of a token, or the range covering the tokens of a node. It
+///   is `null` if it has no token in the input, see [`SourceSpans`]
+/// - `children`: the array of its children, only for a node
+/// - `text`: its text, only for a token
+///
+/// The tree is walked without recursion, so a deeply nested tree does not
+/// overflow the stack.
+///
+/// ```
+/// use mitex_parser::{parse_with_source_spans, tree_to_json, CommandSpecBuilder};
+///
+/// let spec = CommandSpecBuilder::default().build().unwrap();
+/// let (tree, _, spans) = parse_with_source_spans(r#"a"#, spec);
+/// let json = tree_to_json(&tree, &spans);
+/// assert_eq!(json["kind"], "ScopeRoot");
+/// assert_eq!(json["span"], serde_json::json!([0, 1]));
+/// let word = &json["children"][0]["children"][0];
+/// assert_eq!(word["kind"], "TokenWord");
+/// assert_eq!(word["text"], "a");
+/// ```
+pub fn tree_to_json(tree: &SyntaxNode, spans: &SourceSpans) -> Value {
+    let mut stack = vec![NodeFrame::new(tree)];
+    while let Some(frame) = stack.last_mut() {
+        match frame.children.next() {
+            Some(NodeOrToken::Node(node)) => stack.push(NodeFrame::new(&node)),
+            Some(NodeOrToken::Token(token)) => {
+                let span = spans.get(token.text_range().start());
+                frame.cover(span.clone());
+                frame.values.push(json!({
+                    "kind": format!("{:?}", token.kind()),
+                    "span": span_to_json(span),
+                    "text": token.text(),
+                }));
+            }
+            None => {
+                let Some(frame) = stack.pop() else {
+                    break;
+                };
+                let span = frame.span.clone();
+                let value = json!({
+                    "kind": frame.kind,
+                    "span": span_to_json(span.clone()),
+                    "children": frame.values,
+                });
+                match stack.last_mut() {
+                    Some(parent) => {
+                        parent.cover(span);
+                        parent.values.push(value);
+                    }
+                    None => return value,
+                }
+            }
+        }
+    }
+    Value::Null
+}
+
+/// A node whose children are being converted
+struct NodeFrame {
+    kind: String,
+    children: SyntaxElementChildren<TexLang>,
+    values: Vec<Value>,
+    // the range covering the input spans of the converted children
+    span: Option<Range<usize>>,
+}
+
+impl NodeFrame {
+    fn new(node: &SyntaxNode) -> Self {
+        Self {
+            kind: format!("{:?}", node.kind()),
+            children: node.children_with_tokens(),
+            values: Vec::new(),
+            span: None,
+        }
+    }
+
+    fn cover(&mut self, span: Option<Range<usize>>) {
+        let Some(span) = span else {
+            return;
+        };
+        self.span = Some(match self.span.take() {
+            Some(prev) => prev.start.min(span.start)..prev.end.max(span.end),
+            None => span,
+        });
+    }
+}
+
+fn span_to_json(span: Option<Range<usize>>) -> Value {
+    match span {
+        Some(span) => json!([span.start, span.end]),
+        None => Value::Null,
+    }
+}
@@ -15,10 +15,56 @@
 //!
 //! The untyped syntax node can also convert to [`rowan::cursor::SyntaxNode`] to
 //! modify the AST syntactically.
+//!
+//! ## Produce: Diagnostics
+//! Besides the error nodes in the AST, [`parse_with_diagnostics`] also reports
+//! [`Diagnostic`]s with byte ranges in the input, e.g. for an unclosed curly
+//! group or an unknown command. For untrusted input, [`parse_resilient`]
+//! does the same, which is checked not to panic by the fuzz target.
+//!
+//! ## Produce: Source Spans
+//! The text of the AST is not always the input, e.g. after macro expansion.
+//! [`parse_with_source_spans`] also returns [`SourceSpans`] mapping the tokens
+//! of the AST back to byte ranges in the input.
+//!
+//! ## Reparse: Incremental
+//! [`IncrementalTree`] keeps the AST of an input along with its diagnostics
+//! and source spans, and reparses only the edited curly group on an edit
+//! when possible, e.g. for a live editor.
+//!
+//! ## Produce: JSON
+//! With the `serde` feature, [`tree_to_json`] dumps the AST as JSON for
+//! tooling written in other languages.
+//!
+//! ## Produce: LaTeX
+//! [`format_latex`] prints the AST back to LaTeX with canonical whitespace and
+//! braces, e.g. for a formatter.
+//!
+//! ## Traverse: Visitor
+//! [`walk`] calls a [`Visitor`] on every node and token of the AST in
+//! pre-order, e.g. to collect the keys of all `\cite` commands.
 
 mod arg_match;
+mod diagnostic;
+mod format;
+#[cfg(feature = "serde")]
+mod json;
 mod parser;
+mod reparse;
+mod source_span;
 pub mod syntax;
+mod visit;
+
+pub use diagnostic::{Diagnostic, Severity};
+pub use format::format_latex;
+#[cfg(feature = "serde")]
+pub use json::tree_to_json;
+pub use parser::ParseMode;
+pub use reparse::IncrementalTree;
+pub use source_span::SourceSpans;
+pub use visit::{walk, Visitor};
+
+pub use mitex_lexer::{is_verbatim_arg_cmd, is_verbatim_env, verb_body};
 
 pub use mitex_spec as spec;
 pub use spec::preludes::command as command_preludes;
@@ -30,11 +76,58 @@ use parser::Parser;
 /// Parse the input text with the given command specification
 /// and return the untyped syntax tree
 ///
-/// The error nodes are attached to the tree
+/// The parser starts in math mode, see [`parse_in_mode`] for text. The error
+/// nodes are attached to the tree
 pub fn parse(input: &str, spec: CommandSpec) -> SyntaxNode {
     SyntaxNode::new_root(Parser::new_macro(input, spec).parse())
 }
 
+/// Parse the input text like [`parse`] and also return the diagnostics
+/// reported during parsing
+pub fn parse_with_diagnostics(input: &str, spec: CommandSpec) -> (SyntaxNode, Vec<Diagnostic>) {
+    let (green, diagnostics) = Parser::new_macro(input, spec).parse_with_diagnostics();
+    (SyntaxNode::new_root(green), diagnostics)
+}
+
+/// Parse the input text like [`parse_with_diagnostics`] and also return the
+/// byte ranges in the input of the tokens in the syntax tree
+pub fn parse_with_source_spans(
+    input: &str,
+    spec: CommandSpec,
+) -> (SyntaxNode, Vec<Diagnostic>, SourceSpans) {
+    parse_in_mode(input, spec, ParseMode::Math)
+}
+
+/// Parse the input text like [`parse_with_source_spans`], but start in the
+/// given mode
+///
+/// In text mode, `_`, `^` and the primes outside of formulas and math
+/// environments are plain chars, e.g. `a_b` is parsed as two words around an
+/// underscore instead of an attachment.
+pub fn parse_in_mode(
+    input: &str,
+    spec: CommandSpec,
+    mode: ParseMode,
+) -> (SyntaxNode, Vec<Diagnostic>, SourceSpans) {
+    let (green, diagnostics, spans) = Parser::new_macro(input, spec)
+        .with_mode(mode)
+        .parse_with_source_spans();
+    (SyntaxNode::new_root(green), diagnostics, spans)
+}
+
+/// Parse the input text like [`parse_with_diagnostics`], and never panic
+///
+/// It is meant for untrusted input, where a malformed document should not
+/// take down the process. The parser recovers from malformed input with error
+/// nodes and diagnostics instead of panicking, which is checked by the fuzz
+/// target under `fuzz/`.
+///
+/// Note that no panic is caught, since the release profile of this workspace
+/// aborts on a panic, as the wasm plugin can't unwind anyway.
+pub fn parse_resilient(input: &str, spec: CommandSpec) -> (SyntaxNode, Vec<Diagnostic>) {
+    parse_with_diagnostics(input, spec)
+}
+
 /// It is only for internal testing
 pub fn parse_without_macro(input: &str, spec: CommandSpec) -> SyntaxNode {
     SyntaxNode::new_root(Parser::new(input, spec).parse())
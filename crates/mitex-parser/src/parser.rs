@@ -1,18 +1,54 @@
-use rowan::{Checkpoint, GreenNode, GreenNodeBuilder};
+use std::ops::Range;
+
+use rowan::{Checkpoint, GreenNode, GreenNodeBuilder, TextSize};
 
 use crate::arg_match::{ArgMatcher, ArgMatcherBuilder};
+use crate::diagnostic::Diagnostic;
+use crate::source_span::SourceSpans;
 use crate::spec::argument_kind::*;
 use crate::syntax::SyntaxKind::{self, *};
-use crate::{ArgPattern, ArgShape, CommandSpec};
-use mitex_lexer::{BraceKind, CommandName, IfCommandName, Lexer, MacroEngine, Token, TokenStream};
+use crate::{ArgPattern, ArgShape, CommandSpec, ContextFeature};
+use mitex_lexer::{
+    BraceKind, CommandName, IfCommandName, Lexer, LigatureKind, MacroEngine, Token, TokenStream,
+};
+
+/// The maximum depth of nested items, beyond which the rest of a list is
+/// kept flatly in an item reported as too deeply nested
+///
+/// It bounds the depth of the syntax tree, since parsing, dropping or
+/// converting a tree recurses into its nodes. Each command and each group is
+/// a level, and the limit keeps them on a stack of 1 MiB, e.g. of a wasm
+/// module.
+pub(crate) const MAX_NESTING_DEPTH: usize = 256;
+
+/// A right bracket or parenthesis at the end of a list, with the checkpoint
+/// before it, its range and its text, which may be a mismatched closer of a
+/// curly group
+type Closer<'a> = (Checkpoint, Range<usize>, &'a str);
+
+/// The mode in which the parser starts
+///
+/// In math mode, `_` and `^` attach scripts and the primes are attached to
+/// the previous item. In text mode, they are plain chars, except in formulas
+/// and math environments, which are always parsed in math mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// The text mode, e.g. for a paragraph of a document
+    Text,
+    /// The math mode, e.g. for the content of `$...$`
+    #[default]
+    Math,
+}
 
 /// Stacked scope for parsing
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum ParseScope {
     /// The root scope, this is set when the parser enters the entry point
     Root,
-    /// The scope of a formula, i.e. `$...$` or `$$...$$`
+    /// The scope of an inline formula, i.e. `$...$`
     DollarFormula,
+    /// The scope of a display formula, i.e. `$$...$$`
+    DisplayFormula,
     /// The scope of a formula, i.e. `\(..\)` or `\[..\]`
     CmdFormula,
     /// The scope of an environment, i.e. `\begin{...}...\end{...}`
@@ -42,6 +78,9 @@ mod list_state {
         last: Option<Checkpoint>,
         /// The current scope
         pub scope: ParseScope,
+        /// Whether the arguments of an infix command are being parsed, e.g.
+        /// `b` in `a \over b`
+        pub infix: bool,
     }
 
     impl Default for ListState {
@@ -50,6 +89,7 @@ mod list_state {
                 start: None,
                 last: None,
                 scope: ParseScope::Root,
+                infix: false,
             }
         }
     }
@@ -117,11 +157,25 @@ pub struct Parser<'a, S: TokenStream<'a> = ()> {
     /// Argument matcher builder containing cached regexes
     arg_matchers: ArgMatcherBuilder,
     /// trivia buffer
-    trivia_buffer: Vec<(Token, &'a str)>,
+    trivia_buffer: Vec<(Token, &'a str, Option<Range<usize>>)>,
 
     /// State used by item_list/argument_list parser
     /// The current state
     list_state: ListState,
+    /// The current mode
+    mode: ParseMode,
+    /// The number of currently open curly groups, a right curly brace is
+    /// unmatched if there is no open one
+    curly_depth: usize,
+    /// The number of items currently being parsed, including the curly
+    /// groups parsed by [`Self::item_list`]
+    depth: usize,
+    /// Diagnostics reported so far
+    diagnostics: Vec<Diagnostic>,
+    /// The length of the tree text built so far
+    tree_len: TextSize,
+    /// The input ranges of the tokens attached so far
+    source_spans: SourceSpans,
 }
 
 impl<'a> Parser<'a> {
@@ -134,7 +188,13 @@ impl<'a> Parser<'a> {
             spec,
             arg_matchers: ArgMatcherBuilder::default(),
             list_state: Default::default(),
+            mode: ParseMode::default(),
             trivia_buffer: Vec::new(),
+            curly_depth: 0,
+            depth: 0,
+            diagnostics: Vec::new(),
+            tree_len: TextSize::default(),
+            source_spans: SourceSpans::default(),
         }
     }
 
@@ -147,12 +207,24 @@ impl<'a> Parser<'a> {
             spec,
             arg_matchers: ArgMatcherBuilder::default(),
             list_state: Default::default(),
+            mode: ParseMode::default(),
             trivia_buffer: Vec::new(),
+            curly_depth: 0,
+            depth: 0,
+            diagnostics: Vec::new(),
+            tree_len: TextSize::default(),
+            source_spans: SourceSpans::default(),
         }
     }
 }
 
 impl<'a, S: TokenStream<'a>> Parser<'a, S> {
+    /// Start parsing in the given mode, which is math mode by default
+    pub fn with_mode(mut self, mode: ParseMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
     /// List State
     /// The start position of the list
     #[inline]
@@ -187,12 +259,60 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
         self.lexer.peek()
     }
 
+    /// Lexer Interface
+    /// Whether a blank line starts at the next token, i.e. line breaks with
+    /// only spaces in between, which are not at the end of the input
+    fn peek_par_break(&mut self) -> bool {
+        let mut breaks = 0;
+        for n in 0.. {
+            match self.lexer.peek_nth(n) {
+                Some(Token::LineBreak) => {
+                    // a line break token is a run of `\r` and `\n`
+                    let text = self.lexer.peek_nth_text(n).unwrap_or_default();
+                    breaks += text.len() - text.matches("\r\n").count();
+                }
+                Some(Token::Whitespace) if breaks > 0 => {}
+                // trailing blank lines are kept as trivia
+                None => return false,
+                Some(_) => break,
+            }
+        }
+        breaks >= 2
+    }
+
+    /// Lexer Interface
+    /// The byte range of the next token in the main input source, which is
+    /// empty if the token is not sliced from the input
+    fn peek_span(&self) -> Range<usize> {
+        self.lexer.peek_span().unwrap_or_default()
+    }
+
+    /// Lexer Interface
+    /// The byte range of the first `len` bytes of the next token in the main
+    /// input source
+    fn peek_prefix_span(&self, len: usize) -> Option<Range<usize>> {
+        self.lexer
+            .peek_span()
+            .map(|span| span.start..span.start + len)
+    }
+
+    /// Tree Building
+    /// Attach a token to the syntax tree, recording its range in the input
+    fn token(&mut self, kind: SyntaxKind, text: &str, span: Option<Range<usize>>) {
+        if let Some(span) = span {
+            self.source_spans.push(self.tree_len, span);
+        }
+        self.tree_len += TextSize::of(text);
+        self.builder.token(kind.into(), text);
+    }
+
     /// Lexer Interface
     /// Consume the next token and attach it to the syntax tree
     fn eat(&mut self) {
-        let (kind, text) = self.lexer.eat().unwrap();
-        let kind: SyntaxKind = kind.into();
-        self.builder.token(kind.into(), text);
+        // nothing is eaten at the end of the input
+        if let Some((kind, text, span)) = self.lexer.eat_spanned() {
+            self.token(kind.into(), text, span);
+        }
     }
 
     /// Lexer Interface
@@ -205,8 +325,9 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
     /// Consume the next token and attach it to the syntax tree with another
     /// syntax kind
     fn eat_as(&mut self, kind: SyntaxKind) {
-        let (_, text) = self.lexer.eat().unwrap();
-        self.builder.token(kind.into(), text);
+        if let Some((_, text, span)) = self.lexer.eat_spanned() {
+            self.token(kind, text, span);
+        }
     }
 
     /// Lexer Interface
@@ -220,7 +341,7 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
     /// Lexer Interface
     /// Hold the next trivia token
     fn hold_trivia(&mut self) {
-        self.trivia_buffer.push(self.lexer.eat().unwrap());
+        self.trivia_buffer.extend(self.lexer.eat_spanned());
     }
 
     /// Lexer Interface
@@ -230,17 +351,19 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
 
     /// Lexer Interface
     fn extract_holding_trivia(&mut self) {
-        for (kind, text) in self.trivia_buffer.drain(..) {
-            let kind: SyntaxKind = kind.into();
-            self.builder.token(kind.into(), text);
+        // keeps the capacity of the buffer
+        let mut trivia = std::mem::take(&mut self.trivia_buffer);
+        for (kind, text, span) in trivia.drain(..) {
+            self.token(kind.into(), text, span);
         }
+        self.trivia_buffer = trivia;
     }
 
     /// Lexer Interface
     fn single_char(&mut self) -> Option<()> {
         let first_char = self.lexer.peek_char()?;
-        self.builder
-            .token(TokenWord.into(), &first_char.to_string());
+        let span = self.peek_prefix_span(first_char.len_utf8());
+        self.token(TokenWord, &first_char.to_string(), span);
         self.lexer.consume_utf8_bytes(first_char.len_utf8());
 
         Some(())
@@ -256,24 +379,44 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
 
     /// Entry point
     /// The main entry point of the parser
-    pub fn parse(mut self) -> GreenNode {
+    pub fn parse(self) -> GreenNode {
+        self.parse_with_diagnostics().0
+    }
+
+    /// Entry point
+    /// Parse and also return the diagnostics reported during parsing
+    pub fn parse_with_diagnostics(self) -> (GreenNode, Vec<Diagnostic>) {
+        let (green, diagnostics, _) = self.parse_with_source_spans();
+        (green, diagnostics)
+    }
+
+    /// Entry point
+    /// Parse and also return the diagnostics and the input ranges of the
+    /// tokens in the tree
+    pub fn parse_with_source_spans(mut self) -> (GreenNode, Vec<Diagnostic>, SourceSpans) {
         self.builder.start_node(ScopeRoot.into());
         self.item_list(ParseScope::Root);
         self.builder.finish_node();
-        self.builder.finish()
+        (self.builder.finish(), self.diagnostics, self.source_spans)
     }
 
     /// Parsing Helper
     /// Check if the parser should stop parsing the current item
     #[inline]
     fn stop_by_scope(&mut self, kind: Token) -> bool {
+        // An unmatched right curly brace doesn't close any scope, and it is
+        // reported as an error item by [`Self::content`]
+        if kind == Token::Right(BraceKind::Curly) && self.curly_depth == 0 {
+            return false;
+        }
         match self.scope() {
             ParseScope::Root => false,
-            ParseScope::DollarFormula => matches!(
+            ParseScope::DollarFormula | ParseScope::DisplayFormula => matches!(
                 kind,
                 Token::Right(BraceKind::Curly)
                     | Token::CommandName(CommandName::EndEnvironment | CommandName::Right)
                     | Token::Dollar
+                    | Token::DoubleDollar
             ),
             ParseScope::CmdFormula => matches!(
                 kind,
@@ -308,55 +451,171 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
     /// Parsing Helper
     /// Parse a list of items which also maintains the `list_start` and
     /// `list_last` state for inner item parsers
+    ///
+    /// Curly groups directly nested in the list are parsed with a work stack
+    /// instead of recursion, so deeply nested groups like `{{{...}}}` don't
+    /// overflow the stack.
+    ///
+    /// Returns the right bracket or parenthesis at the end of the list, if
+    /// any, see [`Self::unclosed_curly`]
     #[inline]
-    fn item_list(&mut self, scope: ParseScope) {
+    fn item_list(&mut self, scope: ParseScope) -> Option<Closer<'a>> {
         let parent_state = self.list_state;
+        // The list states and checkpoints of the lists enclosing each group,
+        // and the span of the left curly brace starting the group
+        let mut groups: Vec<(ListState, Checkpoint, Range<usize>)> = vec![];
+        // The closer at the end of the current list
+        let mut closer = None;
 
         let mut current = self.builder.checkpoint();
         self.list_state = ListState::new(scope);
         self.list_state.store_start(current);
 
-        while self.peek().map_or(false, |kind| !self.stop_by_scope(kind)) {
-            let attachable = self.content(true);
+        loop {
+            match self.peek() {
+                // Enter a curly group, which is left to [`Self::content`] if
+                // it is nested too deeply
+                Some(Token::Left(BraceKind::Curly))
+                    if !self.stop_by_scope(Token::Left(BraceKind::Curly))
+                        && self.depth < MAX_NESTING_DEPTH =>
+                {
+                    groups.push((self.list_state, current, self.peek_span()));
+                    closer = None;
+                    self.builder.start_node(ItemCurly.into());
+                    self.eat();
+                    self.curly_depth += 1;
+                    self.depth += 1;
+
+                    current = self.builder.checkpoint();
+                    self.list_state = ListState::new(ParseScope::CurlyItem);
+                    self.list_state.store_start(current);
+                }
+                Some(kind) if !self.stop_by_scope(kind) => {
+                    match kind {
+                        Token::Right(BraceKind::Bracket | BraceKind::Paren) => {
+                            let text = self.lexer.peek_text().unwrap_or_default();
+                            closer = Some((current, self.peek_span(), text));
+                        }
+                        Token::Whitespace | Token::LineBreak | Token::LineComment => {}
+                        _ => closer = None,
+                    }
+                    let attachable = self.content(true);
+
+                    // If the item is not attachable, then we should
+                    // not update the `list_last` state
+                    if attachable {
+                        self.list_state.store_last(current);
+                    }
+                    current = self.builder.checkpoint();
+                }
+                // Leave a curly group, which is attachable
+                _ => {
+                    let Some((state, group_start, span)) = groups.pop() else {
+                        break;
+                    };
+                    self.curly_depth -= 1;
+                    self.depth -= 1;
+                    if self.peek() == Some(Token::Right(BraceKind::Curly)) {
+                        self.eat();
+                    } else {
+                        self.unclosed_curly(span, closer);
+                    }
+                    self.builder.finish_node();
+                    closer = None;
 
-            // If the item is not attachable, then we should
-            // not update the `list_last` state
-            if attachable {
-                self.list_state.store_last(current);
+                    self.list_state = state;
+                    self.list_state.store_last(group_start);
+                    current = self.builder.checkpoint();
+                }
             }
-            current = self.builder.checkpoint();
         }
 
         self.list_state = parent_state;
+        closer
+    }
+
+    /// Parsing Helper
+    /// Report a curly group starting at `span` that is not closed
+    ///
+    /// If the group ends with a right bracket or parenthesis, e.g. `{ ]`, it is
+    /// reported as a mismatched closer and wrapped in an error item instead.
+    fn unclosed_curly(&mut self, span: Range<usize>, closer: Option<Closer<'a>>) {
+        match closer {
+            Some((at, span, text)) => {
+                let message = format!("mismatched `{text}`, expected `}}`");
+                self.diagnostics.push(Diagnostic::error(message, span));
+                self.builder.start_node_at(at, TokenError.into());
+                self.builder.finish_node();
+            }
+            None => {
+                self.diagnostics
+                    .push(Diagnostic::error("unclosed `{`", span));
+            }
+        }
     }
 
     /// Parsing Helper
     /// Parse a group of items which is enclosed by a pair of tokens
     #[inline]
     fn item_group(&mut self, scope: ParseScope) {
-        assert!(matches!(
-            scope,
-            ParseScope::CurlyItem
-                | ParseScope::BracketItem
-                | ParseScope::ParenItem
-                | ParseScope::DollarFormula
-                | ParseScope::CmdFormula,
-        ));
-        // Get the corresponding closing token
+        // Get the corresponding closing token, where the other scopes are not
+        // groups
         let (end_token, group_kind) = match scope {
             ParseScope::CurlyItem => (Token::Right(BraceKind::Curly), ItemCurly),
             ParseScope::BracketItem => (Token::Right(BraceKind::Bracket), ItemBracket),
             ParseScope::ParenItem => (Token::Right(BraceKind::Paren), ItemParen),
             ParseScope::DollarFormula => (Token::Dollar, ItemFormula),
+            ParseScope::DisplayFormula => (Token::DoubleDollar, ItemFormula),
             ParseScope::CmdFormula => (Token::CommandName(CommandName::EndMath), ItemFormula),
-            _ => unreachable!(),
+            ParseScope::Root | ParseScope::Environment | ParseScope::LR => return,
         };
 
+        let span = self.peek_span();
         self.builder.start_node(group_kind.into());
         self.eat();
-        self.item_list(scope);
+        let is_curly = scope == ParseScope::CurlyItem;
+        self.curly_depth += usize::from(is_curly);
+        let prev_mode = self.mode;
+        if group_kind == ItemFormula {
+            self.mode = ParseMode::Math;
+        }
+        let closer = self.item_list(scope);
+        self.mode = prev_mode;
+        self.curly_depth -= usize::from(is_curly);
 
-        self.eat_if(end_token);
+        let is_formula = matches!(
+            scope,
+            ParseScope::DollarFormula | ParseScope::DisplayFormula
+        );
+        match self.peek() {
+            // `$$` in an inline formula closes it and opens another inline
+            // formula, e.g. `$a$$b$`
+            Some(Token::DoubleDollar)
+                if scope == ParseScope::DollarFormula && self.lexer.peek_text() == Some("$$") =>
+            {
+                let span = self.peek_prefix_span(1);
+                self.token(TokenDollar, "$", span);
+                self.lexer.consume_utf8_bytes(1);
+            }
+            // a mismatched dollar also closes the formula, which is invalid
+            Some(kind @ (Token::Dollar | Token::DoubleDollar)) if is_formula => {
+                if kind != end_token {
+                    let span = self.peek_span();
+                    self.diagnostics
+                        .push(Diagnostic::error("mismatched math delimiter", span));
+                }
+                self.eat();
+            }
+            Some(kind) if kind == end_token => self.eat(),
+            // unclosed bracket and parenthesis groups are not reported, as
+            // they are also used for optional arguments
+            _ if is_curly => self.unclosed_curly(span, closer),
+            _ if group_kind == ItemFormula => {
+                self.diagnostics
+                    .push(Diagnostic::error("unterminated math", span));
+            }
+            _ => {}
+        }
         self.builder.finish_node();
     }
 
@@ -368,12 +627,68 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
     ///
     /// Returns whether the item is attachable
     fn content(&mut self, not_prefer_single_char: bool) -> bool {
+        if self.peek().is_none() {
+            return true;
+        }
+        if self.depth >= MAX_NESTING_DEPTH {
+            self.nested_too_deeply();
+            return false;
+        }
+
+        self.depth += 1;
+        let attachable = self.nested_content(not_prefer_single_char);
+        self.depth -= 1;
+        attachable
+    }
+
+    /// Parsing Helper
+    /// Eat the next token and the rest of the list flatly into an item, as
+    /// they are nested too deeply to be parsed
+    ///
+    /// The curly braces are still balanced, so the list doesn't end inside a
+    /// curly group.
+    fn nested_too_deeply(&mut self) {
+        let span = self.peek_span();
+        self.diagnostics
+            .push(Diagnostic::error("too deeply nested", span));
+
+        self.builder.start_node(ItemTooDeep.into());
+        let mut balance = 0usize;
+        let mut first = true;
+        while let Some(kind) = self.peek() {
+            match kind {
+                Token::Left(BraceKind::Curly) => balance += 1,
+                Token::Right(BraceKind::Curly) if balance > 0 => balance -= 1,
+                _ if balance == 0 && !first && self.stop_by_scope(kind) => break,
+                _ => {}
+            }
+            self.eat();
+            first = false;
+        }
+        self.builder.finish_node();
+    }
+
+    /// Item parsers
+    /// Parse any syntax item at a depth below [`MAX_NESTING_DEPTH`], see
+    /// [`Self::content`]
+    fn nested_content(&mut self, not_prefer_single_char: bool) -> bool {
         let Some(c) = self.peek() else {
             return true;
         };
         match c {
+            Token::NewLine => {
+                self.new_line();
+                return false;
+            }
+            Token::LineBreak if self.peek_par_break() => {
+                self.par_break();
+                return false;
+            }
+            Token::CommandName(CommandName::Generic) if self.lexer.peek_text() == Some("\\par") => {
+                self.par_break();
+                return false;
+            }
             Token::Ampersand
-            | Token::NewLine
             | Token::LineBreak
             | Token::Whitespace
             | Token::LineComment
@@ -384,16 +699,31 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
                 self.eat();
                 return false;
             }
-            Token::Apostrophe => {
+            Token::Apostrophe | Token::Ligature(LigatureKind::RightQuote)
+                if self.mode == ParseMode::Math =>
+            {
                 self.attach_component(false);
                 return false;
             }
-            Token::Underscore | Token::Caret => {
+            Token::Underscore | Token::Caret if self.mode == ParseMode::Math => {
                 self.attach_component(true);
                 return false;
             }
+            // the scripts and primes are plain chars in text mode
+            Token::Apostrophe | Token::Underscore | Token::Caret => {
+                self.eat();
+                return false;
+            }
             Token::Left(BraceKind::Curly) => self.item_group(ParseScope::CurlyItem),
+            // The error item spans the unmatched right curly brace
             Token::Right(BraceKind::Curly) | Token::MacroArg(_) => {
+                let message = if c == Token::Right(BraceKind::Curly) {
+                    "unmatched `}`"
+                } else {
+                    "macro parameter outside of a macro definition"
+                };
+                let span = self.peek_span();
+                self.diagnostics.push(Diagnostic::error(message, span));
                 self.builder.start_node(TokenError.into());
                 self.eat();
                 self.builder.finish_node();
@@ -403,7 +733,9 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
             | Token::Tilde
             | Token::Slash
             | Token::Ditto
-            | Token::Semicolon => self.eat(),
+            | Token::Semicolon
+            | Token::Verbatim(_)
+            | Token::Ligature(_) => self.eat(),
             Token::Word => {
                 if not_prefer_single_char {
                     self.text()
@@ -412,8 +744,27 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
                 }
             }
             Token::Comma => self.text(),
-            Token::Dollar => {
-                self.item_group(ParseScope::DollarFormula);
+            // a control char isn't printable in the output, so it is dropped
+            Token::CaretNotation(c) if c.is_control() => {
+                let span = self.peek_span();
+                let message = format!("control char {c:?} in caret notation is dropped");
+                self.diagnostics.push(Diagnostic::warning(message, span));
+                self.drop();
+            }
+            // the resolved char is put into the tree
+            Token::CaretNotation(c) => {
+                let span = self.lexer.peek_span();
+                self.drop();
+                self.token(TokenWord, c.encode_utf8(&mut [0; 4]), span);
+            }
+            Token::Dollar | Token::DoubleDollar => {
+                // the rest of a split `$$` opens an inline formula
+                let scope = if self.lexer.peek_text() == Some("$$") {
+                    ParseScope::DisplayFormula
+                } else {
+                    ParseScope::DollarFormula
+                };
+                self.item_group(scope);
                 return false;
             }
             Token::CommandName(name) => match name {
@@ -431,8 +782,6 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
                 CommandName::Left => self.item_lr(),
                 CommandName::Right => return self.command(),
                 CommandName::ErrorBeginEnvironment | CommandName::ErrorEndEnvironment => self.eat(),
-                // todo: raise error end environment
-                //
                 // See:
                 //
                 // ```plain
@@ -442,6 +791,14 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
                 // "###);
                 // ```
                 CommandName::EndEnvironment | CommandName::EndMath => {
+                    let text = self.lexer.peek_text().unwrap_or_default();
+                    let message = if name == CommandName::EndEnvironment {
+                        format!("unexpected `\\end{{{text}}}`")
+                    } else {
+                        format!("unexpected `{text}`")
+                    };
+                    let span = self.peek_span();
+                    self.diagnostics.push(Diagnostic::error(message, span));
                     self.builder.start_node(TokenError.into());
                     self.eat();
                     self.builder.finish_node();
@@ -463,6 +820,9 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
         self.builder.start_node(ItemText.into());
         self.eat();
         while self.peek().map_or(false, is_text_component) {
+            if self.peek() == Some(Token::LineBreak) && self.peek_par_break() {
+                break;
+            }
             self.eat();
         }
         self.builder.finish_node();
@@ -480,13 +840,65 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
         }
     }
 
+    /// Item parsers
+    /// Parse a paragraph break, i.e. a blank line with the following spaces,
+    /// or `\par`
+    fn par_break(&mut self) {
+        self.builder.start_node(ItemParBreak.into());
+        if self.peek() == Some(Token::LineBreak) {
+            while matches!(self.peek(), Some(Token::LineBreak | Token::Whitespace)) {
+                self.eat();
+            }
+        } else {
+            self.eat_as(ClauseCommandName);
+        }
+        self.builder.finish_node();
+    }
+
+    /// Item parsers
+    /// Parse a row break, i.e. `\\`, with an optional star and spacing
+    /// argument directly following it, e.g. `\\*[2ex]`
+    ///
+    /// A bracket group after any whitespace is kept as the content of the
+    /// next row instead, e.g. `\\ [a, b]`.
+    fn new_line(&mut self) {
+        if !matches!(
+            self.lexer.peek_nth(1),
+            Some(Token::Asterisk | Token::Left(BraceKind::Bracket))
+        ) {
+            self.eat();
+            return;
+        }
+
+        self.builder.start_node(ItemNewLine.into());
+        self.eat_as(ClauseCommandName);
+        if self.peek() == Some(Token::Asterisk) {
+            self.eat();
+        }
+        if self.peek() == Some(Token::Left(BraceKind::Bracket)) {
+            self.builder.start_node(ClauseArgument.into());
+            self.item_group(ParseScope::BracketItem);
+            self.builder.finish_node();
+        }
+        self.builder.finish_node();
+    }
+
     /// Item parsers
     /// Parse a command
     fn command(&mut self) -> bool {
         // Process a command by corresponding command specification
         // Prepare the argument matcher for succeeding parsers
-        let cmd_name = self.lexer.peek_text().unwrap().strip_prefix('\\').unwrap();
-        let arg_shape = self.spec.get_cmd(cmd_name).map(|cmd| &cmd.args);
+        let cmd_name = self.lexer.peek_text().unwrap_or_default();
+        let cmd_name = cmd_name.strip_prefix('\\').unwrap_or(cmd_name);
+        let cmd_shape = self.spec.get_cmd(cmd_name);
+        let arg_shape = cmd_shape.map(|cmd| &cmd.args);
+        let math_args = cmd_shape.is_some_and(|cmd| cmd.math_args);
+        if arg_shape.is_none() && self.peek() == Some(Token::CommandName(CommandName::Generic)) {
+            let span = self.peek_span();
+            let message = format!("unknown command `\\{cmd_name}`");
+            self.diagnostics.push(Diagnostic::warning(message, span));
+        }
+        let is_infix = matches!(arg_shape, Some(ArgShape::InfixGreedy));
         let right_pat = match arg_shape {
             None | Some(ArgShape::Right(ArgPattern::None | ArgPattern::FixedLenTerm(0))) => {
                 self.builder.start_node(ItemCmd.into());
@@ -506,6 +918,15 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
                 pattern
             }
             Some(ArgShape::InfixGreedy) => {
+                // TeX rejects another infix command in the operands, e.g.
+                // `a \over b \over c`, which is parsed as `a \over {b \over c}`
+                if self.list_state.infix {
+                    let span = self.peek_span();
+                    let message = format!(
+                        "ambiguous infix command `\\{cmd_name}`, group its operands by curly braces"
+                    );
+                    self.diagnostics.push(Diagnostic::error(message, span));
+                }
                 // Wrap all previous items in the scope of list
                 let pos = self.list_state.take_start();
                 self.start_command_at(pos);
@@ -514,16 +935,26 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
         };
         let searcher = self.arg_matchers.start_match(right_pat);
         let is_greedy = searcher.is_greedy();
+        // the arguments are math, which the converter also puts in a formula
+        // in text mode
+        let prev_mode = self.mode;
+        if math_args {
+            self.mode = ParseMode::Math;
+        }
 
         self.eat();
 
         if is_greedy {
+            let infix = self.list_state.infix;
+            self.list_state.infix |= is_infix;
             self.builder.start_node(ClauseArgument.into());
             self.match_arguments::<true>(searcher);
             self.builder.finish_node();
+            self.list_state.infix = infix;
         } else {
             self.match_arguments::<false>(searcher);
         }
+        self.mode = prev_mode;
 
         self.builder.finish_node();
 
@@ -537,14 +968,22 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
     fn environment(&mut self) {
         self.builder.start_node(ItemEnv.into());
 
+        let env_name = self.lexer.peek_text().unwrap_or_default();
+        let span = self.peek_span();
+
         // environment begin
-        {
+        let is_math = {
             self.builder.start_node(ItemBegin.into());
 
-            let env_name = self.lexer.peek_text().unwrap();
             self.eat();
 
             let arg_shape = self.spec.get_env(env_name);
+            let is_math = arg_shape.is_some_and(|env| {
+                matches!(
+                    env.ctx_feature,
+                    ContextFeature::IsMath | ContextFeature::IsMatrix | ContextFeature::IsCases
+                )
+            });
             let right_pat = match arg_shape.map(|cmd| &cmd.args) {
                 None | Some(ArgPattern::None | ArgPattern::FixedLenTerm(0)) => None,
                 Some(pattern) => Some(pattern),
@@ -558,14 +997,35 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
             self.builder.finish_node();
 
             self.extract_holding_trivia();
-        }
 
+            is_math
+        };
+
+        let prev_mode = self.mode;
+        if is_math {
+            self.mode = ParseMode::Math;
+        }
         self.item_list(ParseScope::Environment);
+        self.mode = prev_mode;
 
         if self.peek() == Some(Token::CommandName(CommandName::EndEnvironment)) {
+            let end_name = self.lexer.peek_text().unwrap_or_default();
+            if end_name.trim() != env_name.trim() {
+                let message = format!(
+                    "expected `\\end{{{}}}`, found `\\end{{{}}}`",
+                    env_name.trim(),
+                    end_name.trim()
+                );
+                let span = self.peek_span();
+                self.diagnostics.push(Diagnostic::error(message, span));
+            }
+
             self.builder.start_node(ItemEnd.into());
             self.eat();
             self.builder.finish_node();
+        } else {
+            let message = format!("unterminated environment `{}`", env_name.trim());
+            self.diagnostics.push(Diagnostic::error(message, span));
         }
 
         self.builder.finish_node();
@@ -637,7 +1097,7 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
     /// - Term/t: any rest of terms, typically {} or single char
     #[inline]
     fn match_arguments_<const GREEDY: bool>(&mut self, mut searcher: ArgMatcher) {
-        assert!((GREEDY == searcher.is_greedy()), "GREEDY mismatched");
+        debug_assert!((GREEDY == searcher.is_greedy()), "GREEDY mismatched");
 
         // const WRAP_ARGS = !GREEDY
         macro_rules! k_wrap_args {
@@ -698,20 +1158,29 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
                     return;
                 }
                 // prefer rob characters from words as arguments
-                Token::Word if !GREEDY => {
+                // ligatures are also split, e.g. `\frac''` takes two primes
+                Token::Word | Token::Ligature(..) if !GREEDY => {
                     // Split the word into single characters for term matching
                     let mut split_cnt = 0usize;
-                    for c in self.lexer.peek_text().unwrap().chars() {
+                    let word_start = self.lexer.peek_span().map(|span| span.start);
+                    for c in self.lexer.peek_text().unwrap_or_default().chars() {
                         if !searcher.try_match(ARGUMENT_KIND_TERM) {
                             if split_cnt > 0 {
                                 self.lexer.consume_utf8_bytes(split_cnt);
                             }
                             return;
                         }
+                        let span = word_start
+                            .map(|start| start + split_cnt..start + split_cnt + c.len_utf8());
                         split_cnt += c.len_utf8();
 
-                        arg::<GREEDY, _, _>(self, |this| {
-                            this.builder.token(TokenWord.into(), &c.to_string())
+                        let kind = if c == '\'' {
+                            TokenApostrophe
+                        } else {
+                            TokenWord
+                        };
+                        arg::<GREEDY, _, _>(self, move |this| {
+                            this.token(kind, &c.to_string(), span)
                         });
                     }
 
@@ -758,7 +1227,15 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
                 }
                 // rest of any item
                 kind => {
-                    if self.stop_by_scope(kind) || !searcher.try_match(ARGUMENT_KIND_TERM) {
+                    if self.stop_by_scope(kind) {
+                        return;
+                    }
+                    let matched = if kind == Token::Asterisk {
+                        searcher.match_star()
+                    } else {
+                        searcher.try_match(ARGUMENT_KIND_TERM)
+                    };
+                    if !matched {
                         return;
                     }
 
@@ -799,13 +1276,17 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
             self.builder.start_node_at(list_last, ClauseArgument.into());
             self.builder.finish_node();
         } else if !has_script {
-            self.eat();
+            self.eat_prime();
             return;
         } else {
             self.builder.start_node(ItemAttachComponent.into());
         }
 
-        self.eat();
+        if has_script {
+            self.eat();
+        } else {
+            self.eat_prime();
+        }
 
         if has_script {
             self.trivia();
@@ -815,6 +1296,18 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
         self.builder.finish_node();
     }
 
+    /// Consume a prime, where a `''` ligature is split into two primes as
+    /// they are attached one by one
+    fn eat_prime(&mut self) {
+        if self.peek() == Some(Token::Ligature(LigatureKind::RightQuote)) {
+            let span = self.peek_prefix_span(1);
+            self.token(TokenApostrophe, "'", span);
+            self.lexer.consume_utf8_bytes(1);
+        } else {
+            self.eat();
+        }
+    }
+
     /// Clause parsers
     /// Parse a left or right delimiter
     fn clause_lr(&mut self) {
@@ -827,7 +1320,13 @@ impl<'a, S: TokenStream<'a>> Parser<'a, S> {
                 self.eat_as(TokenCommandSym);
             }
             // invalid syntax
-            Some(Token::CommandName(..) | Token::Dollar) | None => {}
+            Some(Token::CommandName(..) | Token::Dollar | Token::DoubleDollar) | None => {}
+            // the null delimiter, which is invisible
+            Some(Token::Word) if self.lexer.peek_char() == Some('.') => {
+                let span = self.peek_prefix_span(1);
+                self.token(TokenEmptyDelimiter, ".", span);
+                self.lexer.consume_utf8_bytes(1);
+            }
             Some(Token::Word) => {
                 self.single_char();
             }
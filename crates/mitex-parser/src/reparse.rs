@@ -0,0 +1,287 @@
+//! Incremental reparsing of a syntax tree after edits of its input
+
+use std::ops::Range;
+
+use rowan::{ast::AstNode, WalkEvent};
+
+use crate::{
+    is_verbatim_arg_cmd,
+    parser::{Parser, MAX_NESTING_DEPTH},
+    syntax::{CmdItem, SyntaxKind, SyntaxNode},
+    CommandSpec, Diagnostic, SourceSpans,
+};
+
+/// A parsed input, which is reparsed incrementally when it is edited
+///
+/// An edit inside a curly group only reparses the innermost group that still
+/// parses on its own, e.g. when typing in the argument of a command, and the
+/// new group is spliced into the tree, so that the rest of the tree is shared
+/// with the previous one. Otherwise, the whole input is reparsed.
+///
+/// The tree, diagnostics and source spans are always the same as those of
+/// [`crate::parse_with_source_spans`] on the edited input. To keep them so,
+/// an input with macro definitions, conditionals like `\iftrue`, or
+/// `\makeatletter` is always reparsed as a whole, since they change how the
+/// rest of the input is lexed. Neither are the url of `\href` nor groups
+/// nested near [`MAX_NESTING_DEPTH`] reparsed on their own, since they are
+/// lexed verbatim or cut by the depth of the whole input.
+///
+/// ```
+/// use mitex_parser::{CommandSpecBuilder, IncrementalTree};
+///
+/// let mut spec = CommandSpecBuilder::default();
+/// spec.command("frac", "{}{}", "frac");
+/// let mut tree = IncrementalTree::new(r#"\frac{a}{b}"#, spec.build().unwrap());
+/// assert!(tree.edit(6..7, "a + 1"));
+/// assert_eq!(tree.input(), r#"\frac{a + 1}{b}"#);
+/// ```
+#[derive(Debug, Clone)]
+pub struct IncrementalTree {
+    input: String,
+    spec: CommandSpec,
+    tree: SyntaxNode,
+    diagnostics: Vec<Diagnostic>,
+    spans: SourceSpans,
+}
+
+impl IncrementalTree {
+    /// Parse the input text with the given command specification
+    pub fn new(input: impl Into<String>, spec: CommandSpec) -> Self {
+        let input = input.into();
+        let (tree, diagnostics, spans) = crate::parse_with_source_spans(&input, spec.clone());
+        Self {
+            input,
+            spec,
+            tree,
+            diagnostics,
+            spans,
+        }
+    }
+
+    /// Get the current input text
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// Get the syntax tree of the current input
+    pub fn tree(&self) -> &SyntaxNode {
+        &self.tree
+    }
+
+    /// Get the diagnostics of the current input
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Get the byte ranges in the current input of the tokens in the tree
+    pub fn source_spans(&self) -> &SourceSpans {
+        &self.spans
+    }
+
+    /// Replace the bytes in `range` of the input by `text` and reparse it
+    ///
+    /// Returns whether it is reparsed incrementally, rather than as a whole.
+    ///
+    /// # Panics
+    /// Panics if the range is out of bounds or not on char boundaries.
+    pub fn edit(&mut self, range: Range<usize>, text: &str) -> bool {
+        let mut input = String::with_capacity(self.input.len() - range.len() + text.len());
+        input.push_str(&self.input[..range.start]);
+        input.push_str(text);
+        input.push_str(&self.input[range.end..]);
+
+        // the old input is context free if both the new input and the
+        // removed text are
+        let reparsed = if is_context_free(&input) && is_context_free(&self.input[range.clone()]) {
+            self.reparse_group(&range, text.len(), &input)
+        } else {
+            None
+        };
+        self.input = input;
+        let incremental = reparsed.is_some();
+        (self.tree, self.diagnostics, self.spans) = reparsed
+            .unwrap_or_else(|| crate::parse_with_source_spans(&self.input, self.spec.clone()));
+        incremental
+    }
+
+    /// Reparse the innermost curly group around an edit that parses on its
+    /// own, where `input` is the edited input and `len` is the length of the
+    /// replacing text
+    fn reparse_group(
+        &self,
+        range: &Range<usize>,
+        len: usize,
+        input: &str,
+    ) -> Option<(SyntaxNode, Vec<Diagnostic>, SourceSpans)> {
+        let offset = self.spans.find_input(range.start)?;
+        let token = self.tree.token_at_offset(offset).right_biased()?;
+        let groups = token.parent_ancestors();
+        for group in groups.filter(|node| node.kind() == SyntaxKind::ItemCurly) {
+            let (Some(left), Some(right)) = (group.first_token(), group.last_token()) else {
+                continue;
+            };
+            if left.kind() != SyntaxKind::TokenLBrace
+                || right.kind() != SyntaxKind::TokenRBrace
+                || is_verbatim_arg(&group)
+            {
+                continue;
+            }
+            let start = self.spans.get(left.text_range().start())?.start;
+            let end = self.spans.get(right.text_range().start())?.end;
+            // the braces of the group are kept
+            if range.start <= start || range.end >= end {
+                continue;
+            }
+            let new_end = end - range.end + range.start + len;
+            if let Some(reparsed) = self.splice(&group, start..end, &input[start..new_end]) {
+                return Some(reparsed);
+            }
+        }
+        None
+    }
+
+    /// Replace a curly group spanning `old` in the input by the group parsed
+    /// from `text`, or return `None` if `text` is not exactly a closed group,
+    /// or if the new group would be nested near [`MAX_NESTING_DEPTH`]
+    fn splice(
+        &self,
+        group: &SyntaxNode,
+        old: Range<usize>,
+        text: &str,
+    ) -> Option<(SyntaxNode, Vec<Diagnostic>, SourceSpans)> {
+        let (green, diagnostics, spans) =
+            Parser::new_macro(text, self.spec.clone()).parse_with_source_spans();
+        let root = SyntaxNode::new_root(green);
+        let mut children = root.children_with_tokens();
+        let new_group = children.next()?.into_node()?;
+        if children.next().is_some()
+            || new_group.kind() != SyntaxKind::ItemCurly
+            || new_group.last_token()?.kind() != SyntaxKind::TokenRBrace
+        {
+            return None;
+        }
+        // Each level of the parser is at least a node, so the new group is
+        // parsed the same as in the whole input if it stays below the limit
+        // with its ancestors, which the standalone parse doesn't count
+        if group.ancestors().count() + tree_depth(&new_group) > MAX_NESTING_DEPTH {
+            return None;
+        }
+
+        // The diagnostics of the old group are the ones inside it, which are
+        // reported in a row. Without them, we don't know where the new ones
+        // would be reported among the others, so we give up if there are any.
+        let inside = |span: &Range<usize>| old.start <= span.start && span.end <= old.end;
+        let first = self.diagnostics.iter().position(|diag| inside(&diag.span));
+        let count = self
+            .diagnostics
+            .iter()
+            .filter(|diag| inside(&diag.span))
+            .count();
+        let first = match first {
+            Some(first)
+                if self.diagnostics[first..first + count]
+                    .iter()
+                    .all(|diag| inside(&diag.span)) =>
+            {
+                first
+            }
+            None if diagnostics.is_empty() => 0,
+            _ => return None,
+        };
+        let new_end = old.start + text.len();
+        let shift = |span: &Range<usize>| {
+            if span.start >= old.end {
+                span.start - old.end + new_end..span.end - old.end + new_end
+            } else {
+                span.clone()
+            }
+        };
+        let old_diagnostics = |diags: &[Diagnostic]| {
+            diags.iter().map(|diag| Diagnostic {
+                span: shift(&diag.span),
+                ..diag.clone()
+            })
+        };
+        let mut new_diagnostics = old_diagnostics(&self.diagnostics[..first]).collect::<Vec<_>>();
+        new_diagnostics.extend(diagnostics.into_iter().map(|diag| Diagnostic {
+            span: diag.span.start + old.start..diag.span.end + old.start,
+            ..diag
+        }));
+        new_diagnostics.extend(old_diagnostics(&self.diagnostics[first + count..]));
+
+        let range = group.text_range();
+        let mut new_spans = SourceSpans::default();
+        for (offset, span) in self.spans.iter() {
+            if offset < range.start() {
+                new_spans.push(offset, span.clone());
+            }
+        }
+        for (offset, span) in spans.iter() {
+            let span = span.start + old.start..span.end + old.start;
+            new_spans.push(offset + range.start(), span);
+        }
+        let new_len = new_group.text_range().len();
+        for (offset, span) in self.spans.iter() {
+            if offset >= range.end() {
+                new_spans.push(offset - range.len() + new_len, shift(span));
+            }
+        }
+
+        let tree = SyntaxNode::new_root(group.replace_with(new_group.green().into_owned()));
+        Some((tree, new_diagnostics, new_spans))
+    }
+}
+
+/// Check whether a curly group is an argument of a command taking it
+/// verbatim, e.g. the url of `\href`, which is lexed differently on its own
+fn is_verbatim_arg(group: &SyntaxNode) -> bool {
+    group
+        .parent()
+        .filter(|parent| parent.kind() == SyntaxKind::ClauseArgument)
+        .and_then(|parent| parent.parent())
+        .and_then(CmdItem::cast)
+        .and_then(|cmd| cmd.name_tok())
+        .is_some_and(|name| is_verbatim_arg_cmd(name.text().trim_start_matches('\\')))
+}
+
+/// Get the number of nested nodes on the longest path down from a node,
+/// including the node itself
+fn tree_depth(node: &SyntaxNode) -> usize {
+    let (mut depth, mut max) = (0, 0);
+    for event in node.preorder() {
+        match event {
+            WalkEvent::Enter(_) => {
+                depth += 1;
+                max = max.max(depth);
+            }
+            WalkEvent::Leave(_) => depth -= 1,
+        }
+    }
+    max
+}
+
+/// Check whether a text has no commands changing how the rest of the input is
+/// lexed or expanded, i.e. macro definitions, conditionals, and the commands
+/// toggling `@` as a letter
+fn is_context_free(text: &str) -> bool {
+    text.split('\\').skip(1).all(|rest| {
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphabetic() || c == '@'))
+            .unwrap_or(rest.len());
+        let name = &rest[..len];
+        !(name.starts_with("if")
+            || name.ends_with("command")
+            || name.ends_with("Command")
+            || name.ends_with("environment")
+            || matches!(
+                name,
+                "else"
+                    | "fi"
+                    | "or"
+                    | "def"
+                    | "DeclareMathOperator"
+                    | "makeatletter"
+                    | "makeatother"
+            ))
+    })
+}
@@ -0,0 +1,63 @@
+//! Byte ranges in the input of the tokens in a syntax tree
+
+use std::ops::Range;
+
+use rowan::TextSize;
+
+/// The byte ranges in the main input source of the tokens in a syntax tree
+///
+/// The text of a syntax tree is not always the input, e.g. a macro is
+/// expanded and the name of an environment is stored without its braces, so
+/// the tree offsets of tokens are mapped back to the input here. A token
+/// expanded from a macro definition is mapped to the macro call, and tokens
+/// that are not sliced from the input have no range, see
+/// [`mitex_lexer::Lexer::peek_span`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceSpans {
+    /// Pairs of a token's start offset in the tree and its range in the
+    /// input, sorted by the tree offset
+    spans: Vec<(TextSize, Range<usize>)>,
+}
+
+impl SourceSpans {
+    /// Record the range of a token starting at `offset` in the tree
+    pub(crate) fn push(&mut self, offset: TextSize, span: Range<usize>) {
+        debug_assert!(self.spans.last().map_or(true, |(last, _)| *last <= offset));
+        self.spans.push((offset, span));
+    }
+
+    /// Get the range in the input of the token starting at `offset` in the
+    /// tree
+    pub fn get(&self, offset: TextSize) -> Option<Range<usize>> {
+        let index = self.spans.partition_point(|(start, _)| *start < offset);
+        match self.spans.get(index) {
+            Some((start, span)) if *start == offset => Some(span.clone()),
+            _ => None,
+        }
+    }
+
+    /// Get the tree offset of the last token starting at or before `offset`
+    /// in the input
+    ///
+    /// It assumes that the tokens are also sorted by their input ranges, i.e.
+    /// that no macro is expanded.
+    pub(crate) fn find_input(&self, offset: usize) -> Option<TextSize> {
+        let index = self.spans.partition_point(|(_, span)| span.start <= offset);
+        Some(self.spans.get(index.checked_sub(1)?)?.0)
+    }
+
+    /// Iterate over the tree offsets and input ranges of the recorded tokens
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (TextSize, &Range<usize>)> {
+        self.spans.iter().map(|(offset, span)| (*offset, span))
+    }
+
+    /// Get the number of recorded tokens
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    /// Check whether no token is recorded
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+}
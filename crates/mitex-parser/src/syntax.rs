@@ -77,6 +77,9 @@ pub enum SyntaxKind {
     TokenDitto,
     TokenSemicolon,
     TokenCommandSym,
+    TokenVerbatim,
+    TokenLigature,
+    TokenEmptyDelimiter,
 
     // Clauses
     ClauseCommandName,
@@ -98,6 +101,8 @@ pub enum SyntaxKind {
     ItemTypstCode,
     ItemAttachComponent,
     ItemFormula,
+    ItemParBreak,
+    ItemTooDeep,
 
     // Scopes
     ScopeRoot,
@@ -125,13 +130,15 @@ impl From<Token> for SyntaxKind {
             Token::Semicolon => SyntaxKind::TokenSemicolon,
             Token::Caret => SyntaxKind::TokenCaret,
             Token::Word => SyntaxKind::TokenWord,
-            Token::Dollar => SyntaxKind::TokenDollar,
+            Token::Dollar | Token::DoubleDollar => SyntaxKind::TokenDollar,
             Token::Ampersand => SyntaxKind::TokenAmpersand,
             Token::Hash => SyntaxKind::TokenHash,
             Token::Asterisk => SyntaxKind::TokenAsterisk,
             Token::AtSign => SyntaxKind::TokenAtSign,
             Token::NewLine => SyntaxKind::ItemNewLine,
-            Token::MacroArg(_) => SyntaxKind::TokenWord,
+            Token::MacroArg(_) | Token::CaretNotation(_) => SyntaxKind::TokenWord,
+            Token::Verbatim(_) => SyntaxKind::TokenVerbatim,
+            Token::Ligature(_) => SyntaxKind::TokenLigature,
             Token::CommandName(
                 CommandName::ErrorBeginEnvironment | CommandName::ErrorEndEnvironment,
             )
@@ -409,6 +416,12 @@ impl LRClause {
             .last_token()
             .filter(|node| !matches!(node.kind(), ClauseCommandName))
     }
+
+    /// Whether the clause has the null delimiter `.`, e.g. `\left.`
+    pub fn is_empty_delimiter(&self) -> bool {
+        self.sym()
+            .map_or(false, |node| node.kind() == TokenEmptyDelimiter)
+    }
 }
 
 syntax_tree_node!(
@@ -0,0 +1,58 @@
+//! A visitor walking over a syntax tree
+
+use rowan::{NodeOrToken, WalkEvent};
+
+use crate::syntax::{SyntaxKind, SyntaxNode, SyntaxToken};
+
+/// Callbacks of [`walk`], which are all no-op by default
+///
+/// The kind of an element is passed along with it to pattern-match on, and
+/// the text of it is available by [`SyntaxNode::text`] or
+/// [`SyntaxToken::text`], e.g. a command is an [`SyntaxKind::ItemCmd`] node
+/// whose name is a [`SyntaxKind::ClauseCommandName`] token like `\frac`.
+pub trait Visitor {
+    /// Called on a node before its children
+    fn visit_node(&mut self, _kind: SyntaxKind, _node: &SyntaxNode) {}
+
+    /// Called on a node after its children
+    fn leave_node(&mut self, _kind: SyntaxKind, _node: &SyntaxNode) {}
+
+    /// Called on a token
+    fn visit_token(&mut self, _kind: SyntaxKind, _token: &SyntaxToken) {}
+}
+
+/// Walk over a syntax tree in pre-order, including the root node, and call
+/// the visitor on each node and token
+///
+/// ```
+/// use mitex_parser::syntax::{SyntaxKind, SyntaxToken};
+/// use mitex_parser::{parse, walk, CommandSpecBuilder, Visitor};
+///
+/// #[derive(Default)]
+/// struct Words(Vec<String>);
+///
+/// impl Visitor for Words {
+///     fn visit_token(&mut self, kind: SyntaxKind, token: &SyntaxToken) {
+///         if kind == SyntaxKind::TokenWord {
+///             self.0.push(token.text().to_owned());
+///         }
+///     }
+/// }
+///
+/// let tree = parse(r#"a {b} c"#, CommandSpecBuilder::default().build().unwrap());
+/// let mut words = Words::default();
+/// walk(&tree, &mut words);
+/// assert_eq!(words.0, ["a", "b", "c"]);
+/// ```
+pub fn walk(tree: &SyntaxNode, visitor: &mut impl Visitor) {
+    for event in tree.preorder_with_tokens() {
+        match event {
+            WalkEvent::Enter(NodeOrToken::Node(node)) => visitor.visit_node(node.kind(), &node),
+            WalkEvent::Leave(NodeOrToken::Node(node)) => visitor.leave_node(node.kind(), &node),
+            WalkEvent::Enter(NodeOrToken::Token(token)) => {
+                visitor.visit_token(token.kind(), &token)
+            }
+            WalkEvent::Leave(NodeOrToken::Token(_)) => {}
+        }
+    }
+}
@@ -20,24 +20,42 @@ mod ast {
     #[cfg(test)]
     mod block_comment;
 
+    #[cfg(test)]
+    mod format;
+
     #[cfg(test)]
     mod formula;
 
     #[cfg(test)]
     mod fuzzing;
 
+    #[cfg(all(test, feature = "serde"))]
+    mod json;
+
     #[cfg(test)]
     mod command;
 
+    #[cfg(test)]
+    mod diagnostic;
+
     #[cfg(test)]
     mod environment;
 
     #[cfg(test)]
     mod left_right;
 
+    #[cfg(test)]
+    mod reparse;
+
+    #[cfg(test)]
+    mod source_span;
+
     #[cfg(test)]
     mod trivia;
 
+    #[cfg(test)]
+    mod visit;
+
     /// Convenient function to launch/debug a test case
     #[test]
     fn bug_playground() {}
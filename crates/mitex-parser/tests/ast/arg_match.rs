@@ -285,7 +285,7 @@ fn special_marks_in_env() {
     |br'("\n")
     |space'("    ")
     |lr
-    ||clause-lr(cmd-name("\\left"),word'("."))
+    ||clause-lr(cmd-name("\\left"),empty-delim'("."))
     ||space'(" ")
     ||cmd
     |||cmd-name("\\displaystyle")
@@ -319,7 +319,7 @@ fn special_marks_in_env() {
     |||||||text(word'("2"))
     |||||||rbrace'("}")
     ||||space'(" ")
-    ||clause-lr(cmd-name("\\right"),word'("."))
+    ||clause-lr(cmd-name("\\right"),empty-delim'("."))
     |br'("\n")
     |space'("    ")
     "###);
@@ -459,3 +459,147 @@ fn sqrt_pattern() {
     |text(word'("3"))
     "###);
 }
+
+#[test]
+fn spec_patterns() {
+    let mut builder = mitex_spec::CommandSpecBuilder::from_spec(&mitex_spec_gen::DEFAULT_SPEC);
+    builder
+        .command("opt", "[]{}", "opt")
+        .command("two", "{}{}", "two")
+        .command("st", "*[]{}", "st");
+    let spec = builder.build().unwrap();
+    let parse = |input| crate::common::SnapNode(mitex_parser::parse(input, spec.clone()));
+
+    assert_debug_snapshot!(parse(r#"\opt[a]{b}"#), @r###"
+    root
+    |cmd
+    ||cmd-name("\\opt")
+    ||args
+    |||bracket
+    ||||lbracket'("[")
+    ||||text(word'("a"))
+    ||||rbracket'("]")
+    ||args
+    |||curly
+    ||||lbrace'("{")
+    ||||text(word'("b"))
+    ||||rbrace'("}")
+    "###);
+    // the optional argument is absent
+    assert_debug_snapshot!(parse(r#"\opt{b}c"#), @r###"
+    root
+    |cmd
+    ||cmd-name("\\opt")
+    ||args
+    |||curly
+    ||||lbrace'("{")
+    ||||text(word'("b"))
+    ||||rbrace'("}")
+    |text(word'("c"))
+    "###);
+    // whitespace between arguments
+    assert_debug_snapshot!(parse(r#"\opt [a] {b}"#), @r###"
+    root
+    |cmd
+    ||cmd-name("\\opt")
+    ||args
+    |||bracket
+    ||||lbracket'("[")
+    ||||text(word'("a"))
+    ||||rbracket'("]")
+    ||args
+    |||curly
+    ||||lbrace'("{")
+    ||||text(word'("b"))
+    ||||rbrace'("}")
+    "###);
+    assert_debug_snapshot!(parse(r#"\two{a} {b}"#), @r###"
+    root
+    |cmd
+    ||cmd-name("\\two")
+    ||args
+    |||curly
+    ||||lbrace'("{")
+    ||||text(word'("a"))
+    ||||rbrace'("}")
+    ||args
+    |||curly
+    ||||lbrace'("{")
+    ||||text(word'("b"))
+    ||||rbrace'("}")
+    "###);
+    assert_debug_snapshot!(parse(r#"\st*[a]{b}"#), @r###"
+    root
+    |cmd
+    ||cmd-name("\\st")
+    ||args(asterisk'("*"))
+    ||args
+    |||bracket
+    ||||lbracket'("[")
+    ||||text(word'("a"))
+    ||||rbracket'("]")
+    ||args
+    |||curly
+    ||||lbrace'("{")
+    ||||text(word'("b"))
+    ||||rbrace'("}")
+    "###);
+    // the star is optional, and is not matched after the other arguments
+    assert_debug_snapshot!(parse(r#"\st{b}*"#), @r###"
+    root
+    |cmd
+    ||cmd-name("\\st")
+    ||args
+    |||curly
+    ||||lbrace'("{")
+    ||||text(word'("b"))
+    ||||rbrace'("}")
+    |asterisk'("*")
+    "###);
+}
+
+#[test]
+fn new_line_spacing() {
+    assert_debug_snapshot!(parse(r#"a\\[1em]b"#), @r###"
+    root
+    |text(word'("a"))
+    |newline
+    ||cmd-name("\\\\")
+    ||args
+    |||bracket
+    ||||lbracket'("[")
+    ||||text(word'("1em"))
+    ||||rbracket'("]")
+    |text(word'("b"))
+    "###);
+    assert_debug_snapshot!(parse(r#"a\\*b"#), @r###"
+    root
+    |text(word'("a"))
+    |newline(cmd-name("\\\\"),asterisk'("*"))
+    |text(word'("b"))
+    "###);
+    assert_debug_snapshot!(parse(r#"a\\*[1em]b"#), @r###"
+    root
+    |text(word'("a"))
+    |newline
+    ||cmd-name("\\\\")
+    ||asterisk'("*")
+    ||args
+    |||bracket
+    ||||lbracket'("[")
+    ||||text(word'("1em"))
+    ||||rbracket'("]")
+    |text(word'("b"))
+    "###);
+    // a bracket group after a space is the content of the next row
+    assert_debug_snapshot!(parse(r#"a\\ [b]"#), @r###"
+    root
+    |text(word'("a"))
+    |newline("\\\\")
+    |space'(" ")
+    |bracket
+    ||lbracket'("[")
+    ||text(word'("b"))
+    ||rbracket'("]")
+    "###);
+}
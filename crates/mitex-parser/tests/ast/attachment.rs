@@ -187,6 +187,19 @@ fn base() {
     ||underscore'("_")
     ||word'("1")
     "###);
+    assert_debug_snapshot!(parse(r#"x'''"#), @r###"
+    root
+    |attach-comp
+    ||args
+    |||attach-comp
+    ||||args
+    |||||attach-comp
+    ||||||args
+    |||||||text(word'("x"))
+    ||||||apostrophe'("'")
+    ||||apostrophe'("'")
+    ||apostrophe'("'")
+    "###);
     assert_debug_snapshot!(parse(r#"''"#), @r###"
     root(apostrophe'("'"),apostrophe'("'"))
     "###);
@@ -240,3 +253,27 @@ fn test_attachment_may_weird() {
     ||args(word'("b"))
     "###);
 }
+
+#[test]
+fn text_mode() {
+    let parse = |input| {
+        let spec = mitex_spec_gen::DEFAULT_SPEC.clone();
+        let (node, _, _) = mitex_parser::parse_in_mode(input, spec, mitex_parser::ParseMode::Text);
+        crate::common::SnapNode(node)
+    };
+    // the scripts are only attached in formulas
+    assert_debug_snapshot!(parse(r#"a_b$x_1$"#), @r###"
+    root
+    |text(word'("a"))
+    |underscore'("_")
+    |text(word'("b"))
+    |formula
+    ||dollar'("$")
+    ||attach-comp
+    |||args
+    ||||text(word'("x"))
+    |||underscore'("_")
+    |||word'("1")
+    ||dollar'("$")
+    "###);
+}
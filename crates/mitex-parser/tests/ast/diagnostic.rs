@@ -0,0 +1,122 @@
+use mitex_parser::{parse_with_diagnostics, Diagnostic};
+use mitex_spec_gen::DEFAULT_SPEC;
+
+fn diagnostics(input: &str) -> Vec<Diagnostic> {
+    parse_with_diagnostics(input, DEFAULT_SPEC.clone()).1
+}
+
+#[test]
+fn no_diagnostics() {
+    assert_eq!(
+        diagnostics(r#"\frac{a}{b} $x$ \begin{matrix}a\end{matrix}"#),
+        vec![]
+    );
+}
+
+#[test]
+fn environment() {
+    assert_eq!(
+        diagnostics(r#"\begin{foo} x"#),
+        vec![Diagnostic::error("unterminated environment `foo`", 7..10)]
+    );
+    assert_eq!(
+        diagnostics(r#"\end{foo}"#),
+        vec![Diagnostic::error("unexpected `\\end{foo}`", 5..8)]
+    );
+    assert_eq!(
+        diagnostics(r#"\begin{a}\end{b}"#),
+        vec![Diagnostic::error(
+            "expected `\\end{a}`, found `\\end{b}`",
+            14..15
+        )]
+    );
+}
+
+#[test]
+fn braces() {
+    assert_eq!(
+        diagnostics(r#"{a"#),
+        vec![Diagnostic::error("unclosed `{`", 0..1)]
+    );
+    assert_eq!(
+        diagnostics(r#"\frac{a"#),
+        vec![Diagnostic::error("unclosed `{`", 5..6)]
+    );
+    assert_eq!(
+        diagnostics(r#"a}"#),
+        vec![Diagnostic::error("unmatched `}`", 1..2)]
+    );
+}
+
+#[test]
+fn formula() {
+    assert_eq!(
+        diagnostics(r#"$a"#),
+        vec![Diagnostic::error("unterminated math", 0..1)]
+    );
+    assert_eq!(
+        diagnostics(r#"\(a"#),
+        vec![Diagnostic::error("unterminated math", 0..2)]
+    );
+    assert_eq!(
+        diagnostics(r#"$$a$"#),
+        vec![Diagnostic::error("mismatched math delimiter", 3..4)]
+    );
+}
+
+#[test]
+fn unknown_command() {
+    assert_eq!(
+        diagnostics(r#"\alpha \foo"#),
+        vec![Diagnostic::warning("unknown command `\\foo`", 7..11)]
+    );
+}
+
+#[test]
+fn infix_command() {
+    assert_eq!(
+        diagnostics(r#"a \over b \over c"#),
+        vec![Diagnostic::error(
+            "ambiguous infix command `\\over`, group its operands by curly braces",
+            10..15
+        )]
+    );
+    assert_eq!(diagnostics(r#"{a \over b} \over c"#), vec![]);
+    assert_eq!(
+        diagnostics(r#"\begin{matrix}a \over b \\ c \over d\end{matrix}"#),
+        vec![]
+    );
+}
+
+#[test]
+fn unterminated_at_end_of_input() {
+    // Description: the parser stops at the end of the input instead of
+    // waiting for the closing delimiter
+    assert_eq!(
+        diagnostics(r#"$"#),
+        vec![Diagnostic::error("unterminated math", 0..1)]
+    );
+    assert_eq!(
+        diagnostics(r#"$$x"#),
+        vec![Diagnostic::error("unterminated math", 0..2)]
+    );
+    assert_eq!(
+        diagnostics(r#"\begin{equation} x"#),
+        vec![Diagnostic::error(
+            "unterminated environment `equation`",
+            7..15
+        )]
+    );
+}
+
+#[test]
+fn caret_notation() {
+    // Description: a control char is dropped, while a printable one is kept
+    assert_eq!(
+        diagnostics(r#"a^^Mb^^41"#),
+        vec![Diagnostic::warning(
+            "control char '\\r' in caret notation is dropped",
+            1..4
+        )]
+    );
+}
@@ -0,0 +1,42 @@
+use mitex_parser::format_latex;
+
+use crate::common::parse;
+
+fn format(input: &str) -> String {
+    format_latex(&parse(input))
+}
+
+#[test]
+fn round_trip() {
+    assert_eq!(format(r#"\frac{1}{2}"#), r#"\frac{1}{2}"#);
+    assert_eq!(format(r#"\frac 1  2"#), r#"\frac{1}{2}"#);
+}
+
+#[test]
+fn whitespace() {
+    assert_eq!(format("  a   b  \n  c\n\n\n\nd  "), "a b\nc\n\nd");
+}
+
+#[test]
+fn as_is() {
+    assert_eq!(format("a  % b  c\nd"), "a % b  c\nd");
+    assert_eq!(format(r#"\verb|a  b|"#), r#"\verb|a  b|"#);
+    assert_eq!(format(r#"\verb+a|b+"#), r#"\verb+a|b+"#);
+    assert_eq!(format(r#"\verb!a|b!"#), r#"\verb!a|b!"#);
+    assert_eq!(format(r#"\verb*|a b|"#), r#"\verb*|a b|"#);
+    assert_eq!(
+        format("\\begin{verbatim}\n  a   b\n\\end{verbatim}"),
+        "\\begin{verbatim}\n  a   b\n\\end{verbatim}"
+    );
+}
+
+#[test]
+fn idempotent() {
+    let input = r#"\begin{align*}  a &= \frac 1  2 \\ % note
+   b  &=  \sqrt{x}
+
+
+\end{align*} $x_1$  \verb|a b| \iffalse  x  \fi"#;
+    let formatted = format(input);
+    assert_eq!(format(&formatted), formatted);
+}
@@ -31,6 +31,29 @@ fn cmd_formula() {
     "###);
 }
 
+#[test]
+fn dollar_formula() {
+    assert_debug_snapshot!(parse(r#"$$x$$"#), @r###"
+    root
+    |formula
+    ||dollar'("$$")
+    ||text(word'("x"))
+    ||dollar'("$$")
+    "###);
+    // `$$` inside an inline formula is a closing and an opening dollar
+    assert_debug_snapshot!(parse(r#"$a$$b$"#), @r###"
+    root
+    |formula
+    ||dollar'("$")
+    ||text(word'("a"))
+    ||dollar'("$")
+    |formula
+    ||dollar'("$")
+    ||text(word'("b"))
+    ||dollar'("$")
+    "###);
+}
+
 #[test]
 fn formula_scope() {
     assert_debug_snapshot!(parse(r#"$[)$ test"#), @r###"
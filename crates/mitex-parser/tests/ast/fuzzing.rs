@@ -12,3 +12,145 @@ fn test_fuzzing() {
     |error'(sym'(""))
     "###);
 }
+
+#[test]
+fn test_deep_nested_groups() {
+    use mitex_parser::parse_with_diagnostics;
+    use mitex_parser::syntax::SyntaxKind;
+    use mitex_parser::Diagnostic;
+    use mitex_spec_gen::DEFAULT_SPEC;
+
+    // The groups are parsed up to hundreds of levels
+    let input = "{".repeat(200) + "x" + &"}".repeat(200);
+    let (node, diagnostics) = parse_with_diagnostics(&input, DEFAULT_SPEC.clone());
+    let curly_count = node
+        .descendants()
+        .filter(|n| n.kind() == SyntaxKind::ItemCurly)
+        .count();
+    assert_eq!(curly_count, 200);
+    assert_eq!(diagnostics, vec![]);
+
+    // The groups nested too deeply are kept flatly in an item, so the tree is
+    // dropped on the default stack
+    const DEPTH: usize = 50_000;
+    let input = "{".repeat(DEPTH) + &"}".repeat(DEPTH);
+    let (node, diagnostics) = parse_with_diagnostics(&input, DEFAULT_SPEC.clone());
+    assert_eq!(node.text().to_string(), input);
+    let curly_count = node
+        .descendants()
+        .filter(|n| n.kind() == SyntaxKind::ItemCurly)
+        .count();
+    assert_eq!(curly_count, 256);
+    let too_deep = node
+        .descendants()
+        .filter(|n| n.kind() == SyntaxKind::ItemTooDeep)
+        .count();
+    assert_eq!(too_deep, 1);
+    assert_eq!(
+        diagnostics,
+        vec![Diagnostic::error("too deeply nested", 256..257)]
+    );
+
+    // So are the commands with nested arguments
+    let input = r"\sqrt".repeat(DEPTH) + "x";
+    let (node, diagnostics) = parse_with_diagnostics(&input, DEFAULT_SPEC.clone());
+    assert_eq!(node.text().to_string(), input);
+    assert_eq!(
+        diagnostics
+            .iter()
+            .filter(|d| d.message == "too deeply nested")
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn test_mismatched_group() {
+    // The curly group is closed by a mismatched bracket
+    assert_debug_snapshot!(parse(r#"{ ]"#), @r###"
+    root
+    |curly
+    ||lbrace'("{")
+    ||space'(" ")
+    ||error'(rbracket'("]"))
+    "###);
+    // A bracket inside a closed curly group is not mismatched
+    assert_debug_snapshot!(parse(r#"{[0, 1)}"#), @r###"
+    root
+    |curly
+    ||lbrace'("{")
+    ||lbracket'("[")
+    ||text(word'("0"),comma'(","),space'(" "),word'("1"))
+    ||rparen'(")")
+    ||rbrace'("}")
+    "###);
+}
+
+#[test]
+fn test_mismatched_group_diagnostics() {
+    use mitex_parser::{parse_with_diagnostics, Diagnostic};
+    use mitex_spec_gen::DEFAULT_SPEC;
+
+    assert_eq!(
+        parse_with_diagnostics(r#"{ ]"#, DEFAULT_SPEC.clone()).1,
+        vec![Diagnostic::error("mismatched `]`, expected `}`", 2..3)]
+    );
+    assert_eq!(
+        parse_with_diagnostics(r#"\frac{a)}{b"#, DEFAULT_SPEC.clone()).1,
+        vec![Diagnostic::error("unclosed `{`", 9..10)]
+    );
+}
+
+#[test]
+fn test_unmatched_closer() {
+    assert_debug_snapshot!(parse(r#"a } b"#), @r###"
+    root
+    |text(word'("a"),space'(" "))
+    |error'(rbrace'("}"))
+    |space'(" ")
+    |text(word'("b"))
+    "###);
+    // The formula is not closed by the unmatched brace
+    assert_debug_snapshot!(parse(r#"$a } b$"#), @r###"
+    root
+    |formula
+    ||dollar'("$")
+    ||text(word'("a"),space'(" "))
+    ||error'(rbrace'("}"))
+    ||space'(" ")
+    ||text(word'("b"))
+    ||dollar'("$")
+    "###);
+    // But a matched one still closes it
+    assert_debug_snapshot!(parse(r#"{$a}"#), @r###"
+    root
+    |curly
+    ||lbrace'("{")
+    ||formula
+    |||dollar'("$")
+    |||text(word'("a"))
+    ||rbrace'("}")
+    "###);
+}
+
+#[test]
+fn test_resilient() {
+    use mitex_parser::parse_resilient;
+    use mitex_spec_gen::DEFAULT_SPEC;
+
+    for input in [
+        // a backslash at the end of the input
+        r#"\"#,
+        r#"a \"#,
+        // a multibyte char right after a backslash
+        r#"\é"#,
+        r#"\😀{}"#,
+        // a word of multibyte chars split into term arguments
+        r#"\frac{}éà"#,
+        r#"\frac😀😀"#,
+    ] {
+        // a panic is not caught, so it fails the test
+        let (node, _) = parse_resilient(input, DEFAULT_SPEC.clone());
+        assert_eq!(node.text().to_string(), input);
+    }
+}
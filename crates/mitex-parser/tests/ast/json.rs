@@ -0,0 +1,50 @@
+use mitex_parser::{parse_with_source_spans, tree_to_json};
+use mitex_spec_gen::DEFAULT_SPEC;
+use serde_json::{json, Value};
+
+fn kind(value: &Value) -> &str {
+    value["kind"].as_str().unwrap()
+}
+
+fn to_json(input: &str) -> Value {
+    let (node, _, spans) = parse_with_source_spans(input, DEFAULT_SPEC.clone());
+    tree_to_json(&node, &spans)
+}
+
+#[test]
+fn frac() {
+    let json = to_json(r#"\frac{1}{2}"#);
+    assert_eq!(kind(&json), "ScopeRoot");
+    assert_eq!(json["span"], json!([0, 11]));
+
+    let frac = &json["children"][0];
+    assert_eq!(kind(frac), "ItemCmd");
+    let children = frac["children"].as_array().unwrap();
+    assert_eq!(kind(&children[0]), "ClauseCommandName");
+    assert_eq!(children[0]["text"], "\\frac");
+    let args = children
+        .iter()
+        .filter(|child| kind(child) == "ClauseArgument")
+        .collect::<Vec<_>>();
+    assert_eq!(args.len(), 2);
+    assert_eq!(args[0]["span"], json!([5, 8]));
+    assert_eq!(args[1]["span"], json!([8, 11]));
+}
+
+#[test]
+fn macro_expansion() {
+    // the expanded `y` is mapped to the macro call, not to the definition
+    let input = r#"\newcommand{\f}{y}x\f"#;
+    let json = to_json(input);
+    let mut words = vec![];
+    let mut stack = vec![&json];
+    while let Some(value) = stack.pop() {
+        if value["text"] == "y" {
+            words.push(value["span"].clone());
+        }
+        if let Some(children) = value["children"].as_array() {
+            stack.extend(children.iter().rev());
+        }
+    }
+    assert_eq!(words.last(), Some(&json!([19, 21])));
+}
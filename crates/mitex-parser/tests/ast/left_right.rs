@@ -5,20 +5,36 @@ fn base() {
     assert_debug_snapshot!(parse(r#"\left.\right."#), @r###"
     root
     |lr
-    ||clause-lr(cmd-name("\\left"),word'("."))
-    ||clause-lr(cmd-name("\\right"),word'("."))
+    ||clause-lr(cmd-name("\\left"),empty-delim'("."))
+    ||clause-lr(cmd-name("\\right"),empty-delim'("."))
     "###);
     assert_debug_snapshot!(parse(r#"\left.a\right."#), @r###"
     root
     |lr
-    ||clause-lr(cmd-name("\\left"),word'("."))
+    ||clause-lr(cmd-name("\\left"),empty-delim'("."))
     ||text(word'("a"))
-    ||clause-lr(cmd-name("\\right"),word'("."))
+    ||clause-lr(cmd-name("\\right"),empty-delim'("."))
+    "###);
+    assert_debug_snapshot!(parse(r#"\left( a \right."#), @r###"
+    root
+    |lr
+    ||clause-lr(cmd-name("\\left"),lparen'("("))
+    ||space'(" ")
+    ||text(word'("a"),space'(" "))
+    ||clause-lr(cmd-name("\\right"),empty-delim'("."))
+    "###);
+    assert_debug_snapshot!(parse(r#"\left( a \right)"#), @r###"
+    root
+    |lr
+    ||clause-lr(cmd-name("\\left"),lparen'("("))
+    ||space'(" ")
+    ||text(word'("a"),space'(" "))
+    ||clause-lr(cmd-name("\\right"),rparen'(")"))
     "###);
     assert_debug_snapshot!(parse(r#"\left.    \right] ,"#), @r###"
     root
     |lr
-    ||clause-lr(cmd-name("\\left"),word'("."))
+    ||clause-lr(cmd-name("\\left"),empty-delim'("."))
     ||space'("    ")
     ||clause-lr(cmd-name("\\right"),rbracket'("]"))
     |space'(" ")
@@ -27,7 +43,7 @@ fn base() {
     assert_debug_snapshot!(parse(r#"\left  . a \right    \|"#), @r###"
     root
     |lr
-    ||clause-lr(cmd-name("\\left"),space'("  "),word'("."))
+    ||clause-lr(cmd-name("\\left"),space'("  "),empty-delim'("."))
     ||space'(" ")
     ||text(word'("a"),space'(" "))
     ||clause-lr(cmd-name("\\right"),space'("    "),sym'("\\|"))
@@ -42,18 +58,18 @@ fn base() {
     "###);
     // Note: this is an invalid expression
     // Error handling
+    // The unmatched `}` doesn't close the lr, like any other scope, so the
+    // `\right` is still paired with the `\left`
     assert_debug_snapshot!(parse(r#"\left{.}a\right{.}"#), @r###"
     root
     |lr
     ||clause-lr(cmd-name("\\left"),lbrace'("{"))
     ||text(word'("."))
+    ||error'(rbrace'("}"))
+    ||text(word'("a"))
+    ||clause-lr(cmd-name("\\right"),lbrace'("{"))
+    |text(word'("."))
     |error'(rbrace'("}"))
-    |text(word'("a"))
-    |cmd(cmd-name("\\right"))
-    |curly
-    ||lbrace'("{")
-    ||text(word'("."))
-    ||rbrace'("}")
     "###);
     // Note: this is an invalid expression
     // Error handling
@@ -62,7 +78,7 @@ fn base() {
     |env
     ||begin(sym'("equation"))
     ||lr
-    |||clause-lr(cmd-name("\\left"),word'("."))
+    |||clause-lr(cmd-name("\\left"),empty-delim'("."))
     |||clause-lr(cmd-name("\\right"))
     ||end(sym'("equation"))
     "###);
@@ -78,3 +94,31 @@ fn base() {
     ||end(sym'("equation"))
     "###);
 }
+
+#[test]
+fn command_delimiter() {
+    assert_debug_snapshot!(parse(r#"\left\lfloor x \right\rfloor"#), @r###"
+    root
+    |lr
+    ||clause-lr(cmd-name("\\left"),sym'("\\lfloor"))
+    ||space'(" ")
+    ||text(word'("x"),space'(" "))
+    ||clause-lr(cmd-name("\\right"),sym'("\\rfloor"))
+    "###);
+    assert_debug_snapshot!(parse(r#"\left\|x\right\|"#), @r###"
+    root
+    |lr
+    ||clause-lr(cmd-name("\\left"),sym'("\\|"))
+    ||text(word'("x"))
+    ||clause-lr(cmd-name("\\right"),sym'("\\|"))
+    "###);
+    // mixed delimiters
+    assert_debug_snapshot!(parse(r#"\left\uparrow x\right]"#), @r###"
+    root
+    |lr
+    ||clause-lr(cmd-name("\\left"),sym'("\\uparrow"))
+    ||space'(" ")
+    ||text(word'("x"))
+    ||clause-lr(cmd-name("\\right"),rbracket'("]"))
+    "###);
+}
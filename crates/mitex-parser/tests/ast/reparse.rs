@@ -0,0 +1,82 @@
+use mitex_parser::{parse_with_source_spans, IncrementalTree};
+use mitex_spec_gen::DEFAULT_SPEC;
+
+/// Replace the first occurrence of `pat` in the input by `text`, check that
+/// the result is the same as a full reparse, and return whether it is
+/// reparsed incrementally
+fn edit(tree: &mut IncrementalTree, pat: &str, text: &str) -> bool {
+    let start = tree.input().find(pat).unwrap();
+    let incremental = tree.edit(start..start + pat.len(), text);
+    let (full, diagnostics, spans) = parse_with_source_spans(tree.input(), DEFAULT_SPEC.clone());
+    assert_eq!(*tree.tree().green(), *full.green(), "{}", tree.input());
+    assert_eq!(tree.diagnostics(), diagnostics, "{}", tree.input());
+    assert_eq!(tree.source_spans(), &spans, "{}", tree.input());
+    incremental
+}
+
+#[test]
+fn edits() {
+    let input = r#"\frac{alpha}{beta} + \sqrt{x_1} \text{hello} \begin{matrix} a \end{matrix}"#;
+    let mut tree = IncrementalTree::new(input, DEFAULT_SPEC.clone());
+    assert!(edit(&mut tree, "alpha", "alpha+1"));
+    assert!(edit(&mut tree, "hello", "hello world"));
+    // a new group in a group
+    assert!(edit(&mut tree, "x_1", "x_{12}"));
+    assert!(edit(&mut tree, "12", "123"));
+    assert_eq!(
+        tree.input(),
+        r#"\frac{alpha+1}{beta} + \sqrt{x_{123}} \text{hello world} \begin{matrix} a \end{matrix}"#
+    );
+    // outside of any group
+    assert!(!edit(&mut tree, " + ", " - "));
+    assert!(!edit(&mut tree, " a ", " b "));
+    // the group is not closed as before
+    assert!(!edit(&mut tree, "beta", "beta}"));
+    assert!(!edit(&mut tree, "123", "{123"));
+    // the diagnostics after the group are shifted
+    assert!(edit(&mut tree, "alpha+1", "alpha"));
+    assert_eq!(tree.diagnostics().len(), 2);
+    // a new diagnostic in a group without any
+    assert!(!edit(&mut tree, "hello world", r"\unknown"));
+    assert!(edit(&mut tree, r"\unknown", r"\unknownn"));
+    assert!(edit(&mut tree, r"\unknownn", "hello"));
+}
+
+#[test]
+fn context_sensitive() {
+    // the macro may be used in the edited group
+    let input = r"\newcommand{\x}{y} {foo} {bar}";
+    let mut tree = IncrementalTree::new(input, DEFAULT_SPEC.clone());
+    assert!(!edit(&mut tree, "foo", r"\x"));
+    let mut tree = IncrementalTree::new(r"{foo} {bar}", DEFAULT_SPEC.clone());
+    assert!(!edit(&mut tree, "foo", r"\def\x{y}"));
+    assert!(!edit(&mut tree, r"\def\x{y}", "foo"));
+    assert!(edit(&mut tree, "foo", r"\alpha"));
+    assert!(!edit(&mut tree, "bar", r"\makeatletter"));
+}
+
+#[test]
+fn verbatim_url() {
+    // the url is lexed verbatim only as an argument of `\href`
+    let mut tree = IncrementalTree::new(r"\href{a_b}{x}", DEFAULT_SPEC.clone());
+    assert!(!edit(&mut tree, "a_b", "a_{b}"));
+    assert!(edit(&mut tree, "x", "x_1"));
+    let mut tree = IncrementalTree::new(r"{\url{a_b}}", DEFAULT_SPEC.clone());
+    assert!(edit(&mut tree, "a_b", "a_{b}"));
+}
+
+#[test]
+fn nested_deeply() {
+    // the new group would be cut by the depth of the whole input
+    let input = "{".repeat(250) + "x" + &"}".repeat(250);
+    let mut tree = IncrementalTree::new(input, DEFAULT_SPEC.clone());
+    let text = "{".repeat(10) + "y" + &"}".repeat(10);
+    assert!(!edit(&mut tree, "x", &text));
+    assert!(tree
+        .diagnostics()
+        .iter()
+        .any(|diag| diag.message == "too deeply nested"));
+    // and the shallow groups are still reparsed on their own
+    let mut tree = IncrementalTree::new("{{x}}", DEFAULT_SPEC.clone());
+    assert!(edit(&mut tree, "x", "{{y}}"));
+}
@@ -0,0 +1,60 @@
+use mitex_parser::parse_with_source_spans;
+use mitex_parser::syntax::SyntaxNode;
+use mitex_spec_gen::DEFAULT_SPEC;
+
+/// Pairs of the tree text and the input text of every spanned token
+fn spanned_tokens(input: &str) -> Vec<(String, String)> {
+    let (node, _, spans): (SyntaxNode, _, _) = parse_with_source_spans(input, DEFAULT_SPEC.clone());
+    node.descendants_with_tokens()
+        .filter_map(|elem| elem.into_token())
+        .filter_map(|token| {
+            let span = spans.get(token.text_range().start())?;
+            Some((token.text().to_owned(), input[span].to_owned()))
+        })
+        .collect()
+}
+
+#[test]
+fn same_text() {
+    let tokens = spanned_tokens(r#"\begin{matrix} a & b \\ c \end{matrix} $x$"#);
+    assert!(!tokens.is_empty());
+    for (tree, input) in tokens {
+        assert_eq!(tree, input);
+    }
+}
+
+#[test]
+fn split_word() {
+    // the word `ab` is split into two arguments
+    assert_eq!(
+        spanned_tokens(r#"\frac ab"#),
+        vec![
+            (r"\frac".to_owned(), r"\frac".to_owned()),
+            (" ".to_owned(), " ".to_owned()),
+            ("a".to_owned(), "a".to_owned()),
+            ("b".to_owned(), "b".to_owned()),
+        ]
+    );
+}
+
+#[test]
+fn environment_name() {
+    let input = r#"\begin{pmatrix}\end{pmatrix}"#;
+    let (node, _, spans) = parse_with_source_spans(input, DEFAULT_SPEC.clone());
+    let name = node
+        .descendants_with_tokens()
+        .filter_map(|elem| elem.into_token())
+        .find(|token| token.text() == "pmatrix")
+        .unwrap();
+    assert_eq!(spans.get(name.text_range().start()), Some(7..14));
+}
+
+#[test]
+fn macro_expansion() {
+    // the expanded tokens are mapped to the macro call
+    let tokens = spanned_tokens(r#"\newcommand{\x}{y}\x"#);
+    assert!(tokens.contains(&("y".to_owned(), r"\x".to_owned())));
+    for (tree, input) in tokens.into_iter().filter(|(tree, _)| tree != "y") {
+        assert_eq!(tree, input);
+    }
+}
@@ -93,3 +93,36 @@ fn greedy_trivia() {
     |||space'(" ")
     "###);
 }
+
+#[test]
+fn par_break() {
+    // a single line break is a space
+    assert_debug_snapshot!(parse("a\nb"), @r###"
+    root
+    |text(word'("a"),br'("\n"),word'("b"))
+    "###);
+    assert_debug_snapshot!(parse("a \n  \nb"), @r###"
+    root
+    |text(word'("a"),space'(" "))
+    |par-break(br'("\n"),space'("  "),br'("\n"))
+    |text(word'("b"))
+    "###);
+    assert_debug_snapshot!(parse("a\r\n\r\n\n b"), @r###"
+    root
+    |text(word'("a"))
+    |par-break(br'("\r\n\r\n\n"),space'(" "))
+    |text(word'("b"))
+    "###);
+    assert_debug_snapshot!(parse(r#"a\par b"#), @r###"
+    root
+    |text(word'("a"))
+    |par-break(cmd-name("\\par"))
+    |space'(" ")
+    |text(word'("b"))
+    "###);
+    // trailing blank lines are trivia
+    assert_debug_snapshot!(parse("a\n\n"), @r###"
+    root
+    |text(word'("a"),br'("\n\n"))
+    "###);
+}
@@ -0,0 +1,73 @@
+use mitex_parser::syntax::{SyntaxKind, SyntaxNode, SyntaxToken};
+use mitex_parser::{walk, Visitor};
+
+use crate::common::parse;
+
+/// Counts the `\frac` commands and the depth of the deepest one
+#[derive(Default)]
+struct FracCounter {
+    count: usize,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Visitor for FracCounter {
+    fn visit_node(&mut self, kind: SyntaxKind, _node: &SyntaxNode) {
+        if kind == SyntaxKind::ItemCmd {
+            self.depth += 1;
+        }
+    }
+
+    fn leave_node(&mut self, kind: SyntaxKind, _node: &SyntaxNode) {
+        if kind == SyntaxKind::ItemCmd {
+            self.depth -= 1;
+        }
+    }
+
+    fn visit_token(&mut self, kind: SyntaxKind, token: &SyntaxToken) {
+        if kind == SyntaxKind::ClauseCommandName && token.text() == "\\frac" {
+            self.count += 1;
+            self.max_depth = self.max_depth.max(self.depth);
+        }
+    }
+}
+
+#[test]
+fn count_frac() {
+    let tree = parse(r#"\frac{1}{\frac{a}{b}} + \sqrt{\frac 12} + \dfrac{c}{d}"#);
+    let mut counter = FracCounter::default();
+    walk(&tree, &mut counter);
+    assert_eq!(counter.count, 3);
+    assert_eq!(counter.max_depth, 2);
+    assert_eq!(counter.depth, 0);
+}
+
+#[test]
+fn pre_order() {
+    #[derive(Default)]
+    struct Kinds(Vec<SyntaxKind>);
+
+    impl Visitor for Kinds {
+        fn visit_node(&mut self, kind: SyntaxKind, _node: &SyntaxNode) {
+            self.0.push(kind);
+        }
+
+        fn visit_token(&mut self, kind: SyntaxKind, _token: &SyntaxToken) {
+            self.0.push(kind);
+        }
+    }
+
+    let mut kinds = Kinds::default();
+    walk(&parse(r#"{a}"#), &mut kinds);
+    assert_eq!(
+        kinds.0,
+        [
+            SyntaxKind::ScopeRoot,
+            SyntaxKind::ItemCurly,
+            SyntaxKind::TokenLBrace,
+            SyntaxKind::ItemText,
+            SyntaxKind::TokenWord,
+            SyntaxKind::TokenRBrace,
+        ]
+    );
+}
@@ -82,6 +82,9 @@ pub mod ast_snapshot {
                 SyntaxKind::TokenDitto => "ditto'",
                 SyntaxKind::TokenSemicolon => "semicolon'",
                 SyntaxKind::TokenCommandSym => "sym'",
+                SyntaxKind::TokenVerbatim => "verb'",
+                SyntaxKind::TokenLigature => "ligature'",
+                SyntaxKind::TokenEmptyDelimiter => "empty-delim'",
                 SyntaxKind::ClauseCommandName => "cmd-name",
                 SyntaxKind::ClauseArgument => "args",
                 SyntaxKind::ClauseLR => "clause-lr",
@@ -99,6 +102,8 @@ pub mod ast_snapshot {
                 SyntaxKind::ItemTypstCode => "embedded-code",
                 SyntaxKind::ItemAttachComponent => "attach-comp",
                 SyntaxKind::ItemFormula => "formula",
+                SyntaxKind::ItemParBreak => "par-break",
+                SyntaxKind::ItemTooDeep => "too-deep",
                 SyntaxKind::ScopeRoot => "root",
             };
 
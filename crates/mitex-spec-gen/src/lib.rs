@@ -8,3 +8,40 @@ pub static DEFAULT_SPEC: once_cell::sync::Lazy<CommandSpec> = once_cell::sync::L
         "../../../target/mitex-artifacts/spec/default.rkyv"
     ))
 });
+
+#[cfg(test)]
+mod tests {
+    use super::DEFAULT_SPEC;
+    use mitex_spec::{CommandSpec, CommandSpecBuilder};
+
+    #[test]
+    fn json_round_trip() {
+        let json = DEFAULT_SPEC.to_json_string();
+        let spec = CommandSpec::from_json_str(&json).unwrap();
+        assert_eq!(spec, *DEFAULT_SPEC);
+    }
+
+    #[test]
+    fn json_unknown_field() {
+        let json = r#"{"commands":{"abs":{"kind":"cmd","args":{"kind":"left1"},"alais":"abs"}}}"#;
+        let err = CommandSpec::from_json_str(json).unwrap_err();
+        assert!(err.to_string().contains("unknown field `alais`"), "{err}");
+    }
+
+    #[test]
+    fn merge_overrides() {
+        let mut builder = CommandSpecBuilder::default();
+        builder.command("vec", "{}", "bold");
+        let spec = DEFAULT_SPEC.clone().merge(builder.build().unwrap());
+
+        let vec = spec.get_cmd("vec").unwrap();
+        assert_eq!(vec.alias.as_deref(), Some("bold"));
+        assert!(spec.get_cmd("alpha").is_some());
+        assert!(spec.get_env("matrix").is_some());
+        // the base specification is untouched
+        assert_ne!(
+            DEFAULT_SPEC.get_cmd("vec").unwrap().alias.as_deref(),
+            Some("bold")
+        );
+    }
+}
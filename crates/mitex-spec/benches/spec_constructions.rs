@@ -51,6 +51,8 @@ fn bench_json_deserialize(bencher: Bencher, n: i32) {
             pattern: query::ArgPattern::None,
         },
         alias: None,
+        template: None,
+        math_args: false,
     });
 
     let mut spec = query::CommandSpecRepr::default();
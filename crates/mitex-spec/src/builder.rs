@@ -0,0 +1,167 @@
+//! A builder to register commands and environments at runtime
+
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+
+use crate::{
+    ArgPattern, ArgShape, CmdShape, CommandMap, CommandSpec, CommandSpecItem, ContextFeature,
+    EnvShape, Template,
+};
+
+/// Builds a [`CommandSpec`] from commands and environments registered at
+/// runtime.
+///
+/// The arguments of an item are described by a short pattern string, see
+/// [`ArgPattern::parse`]. For example, `\frac` has a pattern of `{}{}` and `\sqrt` has a pattern of
+/// `[]{}`.
+///
+/// An invalid pattern or template doesn't stop the registration, but the
+/// first error is returned by [`Self::build`].
+///
+/// ```
+/// use mitex_spec::CommandSpecBuilder;
+///
+/// let spec = CommandSpecBuilder::default()
+///     .command("RR", "", "RR")
+///     .command("abs", "{}", "abs")
+///     .environment("proof", "", "proof")
+///     .build()
+///     .unwrap();
+/// assert!(spec.get_cmd("abs").is_some());
+/// assert!(spec.get_env("proof").is_some());
+///
+/// let err = CommandSpecBuilder::default().command("bad", "{", "bad").build();
+/// assert!(err.is_err());
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct CommandSpecBuilder {
+    commands: CommandMap,
+    /// The char that starts a line comment, or the default one
+    comment_char: Option<char>,
+    /// The first error in the registered items
+    error: Option<String>,
+}
+
+impl CommandSpecBuilder {
+    /// Start with all items of an existing specification, e.g. the default
+    /// one, so that registered items extend or override them. The comment
+    /// char of the specification is also kept.
+    ///
+    /// ```
+    /// use mitex_spec::{CommandSpec, CommandSpecBuilder};
+    ///
+    /// let spec = CommandSpec::new(Default::default()).with_comment_char('#');
+    /// let spec = CommandSpecBuilder::from_spec(&spec).build().unwrap();
+    /// assert_eq!(spec.comment_char(), '#');
+    /// ```
+    pub fn from_spec(spec: &CommandSpec) -> Self {
+        Self {
+            commands: spec
+                .items()
+                .map(|(name, item)| (name.to_owned(), item.clone()))
+                .collect(),
+            comment_char: Some(spec.comment_char()),
+            error: None,
+        }
+    }
+
+    /// Register a command `\name` converted to a call of the Typst handler
+    /// `alias`, e.g. `command("abs", "{}", "abs")` converts `\abs{x}` to
+    /// `abs(x)`.
+    ///
+    /// The command is skipped if the argument pattern is invalid.
+    pub fn command(&mut self, name: &str, args: &str, alias: &str) -> &mut Self {
+        let Some(args) = self.check(ArgPattern::parse(args)) else {
+            return self;
+        };
+        let item = CommandSpecItem::Cmd(CmdShape {
+            args: ArgShape::Right(args),
+            alias: Some(alias.to_owned()),
+            template: None,
+            math_args: false,
+        });
+        self.item(name, item)
+    }
+
+    /// Register a command `\name` converted by expanding a Typst output
+    /// template, e.g. `command_template("norm", "{}", "norm({0})")` converts
+    /// `\norm{x}` to `norm(x)`. See [`Template`] for the syntax.
+    ///
+    /// The command is skipped if the argument pattern or the template is
+    /// invalid.
+    pub fn command_template(&mut self, name: &str, args: &str, template: &str) -> &mut Self {
+        let Some(args) = self.check(ArgPattern::parse(args)) else {
+            return self;
+        };
+        let Some(template) = self.check(Template::parse(template)) else {
+            return self;
+        };
+        let item = CommandSpecItem::Cmd(CmdShape {
+            args: ArgShape::Right(args),
+            alias: None,
+            template: Some(template),
+            math_args: false,
+        });
+        self.item(name, item)
+    }
+
+    /// Register an environment `name` converted to a call of the Typst
+    /// handler `alias` with its body.
+    ///
+    /// The environment is skipped if the argument pattern is invalid.
+    pub fn environment(&mut self, name: &str, args: &str, alias: &str) -> &mut Self {
+        self.environment_with_feature(name, args, ContextFeature::None, alias)
+    }
+
+    /// Register an environment like [`Self::environment`], whose body is
+    /// processed according to `ctx_feature`, e.g. as matrix cells.
+    ///
+    /// The environment is skipped if the argument pattern is invalid.
+    pub fn environment_with_feature(
+        &mut self,
+        name: &str,
+        args: &str,
+        ctx_feature: ContextFeature,
+        alias: &str,
+    ) -> &mut Self {
+        let Some(args) = self.check(ArgPattern::parse(args)) else {
+            return self;
+        };
+        let item = CommandSpecItem::Env(EnvShape {
+            args,
+            ctx_feature,
+            alias: Some(alias.to_owned()),
+        });
+        self.item(name, item)
+    }
+
+    /// Register an item in its raw shape
+    pub fn item(&mut self, name: &str, item: CommandSpecItem) -> &mut Self {
+        self.commands.insert(name.to_owned(), item);
+        self
+    }
+
+    /// Build the command specification
+    ///
+    /// # Errors
+    /// Returns the first error in the registered items, e.g. an invalid
+    /// argument pattern.
+    pub fn build(&self) -> Result<CommandSpec, String> {
+        if let Some(err) = &self.error {
+            return Err(err.clone());
+        }
+        let spec = CommandSpec::new(self.commands.clone());
+        Ok(match self.comment_char {
+            Some(comment_char) => spec.with_comment_char(comment_char),
+            None => spec,
+        })
+    }
+
+    /// Keep the first error of a registration
+    fn check<T>(&mut self, res: Result<T, String>) -> Option<T> {
+        res.map_err(|err| {
+            self.error.get_or_insert(err);
+        })
+        .ok()
+    }
+}
@@ -8,16 +8,39 @@
 //! override the `\begin` and `\end` commands.
 //!
 //! See <https://github.com/mitex-rs/mitex/blob/main/docs/spec.typ> for detailed description.
+//!
+//! ## Features
+//!
+//! - `std` (default): stores the items in a hash map. Without it, the crate is
+//!   `no_std` and only needs `alloc`, and the items are stored in a B-tree map
+//!   instead, see [`CommandMap`].
+//! - `rkyv`, `rkyv-validation` and `serde` (default): (de)serialize the
+//!   specification, which requires `std`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
-use std::sync::Arc;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+#[cfg(feature = "rkyv")]
+use alloc::vec::Vec;
 
 #[cfg(feature = "rkyv")]
 use rkyv::{Archive, Deserialize as rDeser, Serialize as rSer};
 
+mod builder;
 pub mod preludes;
+#[cfg(feature = "serde")]
 pub mod query;
+#[cfg(feature = "rkyv")]
 mod stream;
+mod template;
+pub use builder::CommandSpecBuilder;
+#[cfg(feature = "serde")]
 pub use query::CommandSpecRepr as JsonCommandSpec;
+pub use template::{Template, TemplatePiece, TemplateSlot};
 
 /// An item of command specification. It is either a normal _command_ or an
 /// _environment_.
@@ -26,7 +49,7 @@ pub use query::CommandSpecRepr as JsonCommandSpec;
 ///
 /// [Command Syntax]: https://latexref.xyz/LaTeX-command-syntax.html
 /// [Environment Syntax]: https://latexref.xyz/Environment-syntax.html
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "rkyv", derive(Archive, rDeser, rSer))]
 #[cfg_attr(feature = "rkyv-validation", archive(check_bytes))]
 pub enum CommandSpecItem {
@@ -38,20 +61,36 @@ pub enum CommandSpecItem {
     Env(EnvShape),
 }
 
+/// A map from item names to the items of a specification
+///
+/// It is a hash map with the `std` feature, or a B-tree map without it.
+#[cfg(feature = "std")]
+pub type CommandMap = fxhash::FxHashMap<String, CommandSpecItem>;
+/// A map from item names to the items of a specification
+///
+/// It is a hash map with the `std` feature, or a B-tree map without it.
+#[cfg(not(feature = "std"))]
+pub type CommandMap = alloc::collections::BTreeMap<String, CommandSpecItem>;
+
 /// Command specification that contains a set of commands and environments.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "rkyv", derive(Archive, rDeser, rSer))]
 #[cfg_attr(feature = "rkyv-validation", archive(check_bytes))]
 pub struct CommandSpecRepr {
     /// A map from command name to command specification
-    pub commands: fxhash::FxHashMap<String, CommandSpecItem>,
+    pub commands: CommandMap,
 }
 
 /// Command specification that is cheap to clone
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "rkyv", derive(Archive, rDeser, rSer))]
 #[cfg_attr(feature = "rkyv-validation", archive(check_bytes))]
-pub struct CommandSpec(Arc<CommandSpecRepr>);
+pub struct CommandSpec {
+    /// The shared set of commands and environments
+    repr: Arc<CommandSpecRepr>,
+    /// The char that starts a line comment
+    comment_char: char,
+}
 
 #[cfg(feature = "rkyv")]
 impl CommandSpec {
@@ -65,7 +104,7 @@ impl CommandSpec {
         use rkyv::ser::{serializers::AllocSerializer, Serializer};
 
         let mut serializer = AllocSerializer::<0>::default();
-        serializer.serialize_value(self.0.as_ref()).unwrap();
+        serializer.serialize_value(self.repr.as_ref()).unwrap();
         let bytes = serializer.into_serializer().into_inner();
 
         bytes.into_vec()
@@ -76,7 +115,7 @@ impl CommandSpec {
     pub fn from_bytes(bytes: &[u8]) -> Self {
         let s = stream::BytesModuleStream::from_slice(bytes);
 
-        Self(Arc::new(s.checkout_owned()))
+        Self::from_repr(s.checkout_owned())
     }
 
     /// # Safety
@@ -84,24 +123,72 @@ impl CommandSpec {
     pub unsafe fn from_bytes_unchecked(bytes: &[u8]) -> Self {
         let s = stream::BytesModuleStream::from_slice(bytes);
 
-        Self(Arc::new(s.checkout_owned_unchecked()))
+        Self::from_repr(s.checkout_owned_unchecked())
     }
 }
 
 impl CommandSpec {
     /// Create a new command specification
-    pub fn new(commands: fxhash::FxHashMap<String, CommandSpecItem>) -> Self {
-        Self(Arc::new(CommandSpecRepr { commands }))
+    pub fn new(commands: CommandMap) -> Self {
+        Self::from_repr(CommandSpecRepr { commands })
+    }
+
+    fn from_repr(repr: CommandSpecRepr) -> Self {
+        Self {
+            repr: Arc::new(repr),
+            comment_char: '%',
+        }
+    }
+
+    /// Use another char to start line comments, e.g. for documents that have
+    /// changed the catcode of `%`
+    ///
+    /// With a comment char other than `%`, the `%` is lexed as an ordinary
+    /// char.
+    #[must_use]
+    pub fn with_comment_char(mut self, comment_char: char) -> Self {
+        self.comment_char = comment_char;
+        self
+    }
+
+    /// Get the char that starts a line comment, which is `%` by default
+    pub fn comment_char(&self) -> char {
+        self.comment_char
+    }
+
+    /// Merge another specification into this one, e.g. user overrides into
+    /// the default specification
+    ///
+    /// Items in `other` take precedence over the items of the same name in
+    /// `self`, whether they are commands or environments. Other settings,
+    /// like [`Self::comment_char`], are kept from `self`.
+    ///
+    /// The items of `self` are copied only if they are shared with other
+    /// clones, which is a copy of the whole set for a shared default
+    /// specification. Merge once and reuse the result if it is hot.
+    #[must_use]
+    pub fn merge(mut self, other: CommandSpec) -> CommandSpec {
+        if other.repr.commands.is_empty() {
+            return self;
+        }
+        let commands = &mut Arc::make_mut(&mut self.repr).commands;
+        match Arc::try_unwrap(other.repr) {
+            Ok(other) => commands.extend(other.commands),
+            Err(other) => {
+                commands.extend(other.commands.iter().map(|(k, v)| (k.clone(), v.clone())))
+            }
+        }
+        self
     }
 
     /// Get an item by name
     pub fn get(&self, name: &str) -> Option<&CommandSpecItem> {
-        self.0.commands.get(name)
+        self.repr.commands.get(name)
     }
 
     /// Iterate all items
     pub fn items(&self) -> impl Iterator<Item = (&str, &CommandSpecItem)> {
-        self.0.commands.iter().map(|(k, v)| (k.as_str(), v))
+        self.repr.commands.iter().map(|(k, v)| (k.as_str(), v))
     }
 
     /// Get an item by name in kind of _command_
@@ -128,7 +215,7 @@ impl CommandSpec {
 }
 
 /// Shape of a TeX command.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "rkyv", derive(Archive, rDeser, rSer))]
 #[cfg_attr(feature = "rkyv-validation", archive(check_bytes))]
 pub struct CmdShape {
@@ -137,10 +224,16 @@ pub struct CmdShape {
     /// Makes the command alias to some Typst handler.
     /// For exmaple, alias `\prod` to Typst's `product`
     pub alias: Option<String>,
+    /// Converts the command by expanding a template instead of calling the
+    /// Typst handler, e.g. `norm({0})`.
+    pub template: Option<Template>,
+    /// Parses the arguments in math mode, even in text, where the command is
+    /// then converted in an inline equation, e.g. `\ensuremath{x}`.
+    pub math_args: bool,
 }
 
 /// Shape of a TeX envionment.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "rkyv", derive(Archive, rDeser, rSer))]
 #[cfg_attr(feature = "rkyv-validation", archive(check_bytes))]
 pub struct EnvShape {
@@ -163,6 +256,10 @@ pub mod argument_kind {
     pub const ARGUMENT_KIND_BRACKET: char = 'b';
     /// The character used for matching argument in a parenthesis group
     pub const ARGUMENT_KIND_PAREN: char = 'p';
+    /// The character used for matching a star argument, e.g. `*` in
+    /// `\section*{Title}`. A star that doesn't match a pattern is matched as a
+    /// term instead.
+    pub const ARGUMENT_KIND_STAR: char = 's';
 }
 
 /// An efficient pattern used for argument matching.
@@ -188,11 +285,12 @@ pub mod argument_kind {
 /// Kinds of item to match:
 /// - Bracket/b: []
 /// - Parenthesis/p: ()
+/// - Star/s: *
 /// - Term/t: any remaining terms, typically {} or a single char
 ///
 /// Note: any prefix of the argument pattern are matched during the parse stage,
 /// so you need to check whether it is complete in later stages.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "rkyv", derive(Archive, rDeser, rSer))]
 #[cfg_attr(feature = "rkyv-validation", archive(check_bytes))]
 pub enum ArgPattern {
@@ -229,6 +327,64 @@ pub enum ArgPattern {
     Glob(Arc<str>),
 }
 
+impl ArgPattern {
+    /// Parse a short pattern string into the cheapest pattern matching it
+    ///
+    /// The pattern is a sequence of:
+    /// - `{}`: a required term, typically a curly group or a single char
+    /// - `[]`: an optional bracket group
+    /// - `()`: an optional parenthesis group
+    /// - `*`: an optional star, e.g. `\section*{Title}`
+    ///
+    /// ```
+    /// use mitex_spec::ArgPattern;
+    ///
+    /// assert_eq!(ArgPattern::parse("{}{}"), Ok(ArgPattern::FixedLenTerm(2)));
+    /// assert_eq!(ArgPattern::parse("[]{}"), Ok(ArgPattern::Glob("{,b}t".into())));
+    /// assert_eq!(ArgPattern::parse("*[]{}"), Ok(ArgPattern::Glob("{,s}{,b}t".into())));
+    /// assert!(ArgPattern::parse(&"{}".repeat(256)).is_err());
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error if the pattern contains anything else, or more than
+    /// 255 required terms.
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        use argument_kind::*;
+
+        let mut glob = String::new();
+        let mut terms = 0u8;
+        let mut rest = pattern;
+        while !rest.is_empty() {
+            let (part, next) = match rest.as_bytes()[0] {
+                b'{' if rest.starts_with("{}") => {
+                    terms = terms.checked_add(1).ok_or_else(|| {
+                        format!("too many terms in argument pattern: {pattern:?}")
+                    })?;
+                    (ARGUMENT_KIND_TERM.to_string(), &rest[2..])
+                }
+                b'[' if rest.starts_with("[]") => {
+                    (format!("{{,{ARGUMENT_KIND_BRACKET}}}"), &rest[2..])
+                }
+                b'(' if rest.starts_with("()") => {
+                    (format!("{{,{ARGUMENT_KIND_PAREN}}}"), &rest[2..])
+                }
+                b'*' => (format!("{{,{ARGUMENT_KIND_STAR}}}"), &rest[1..]),
+                _ => return Err(format!("invalid argument pattern: {pattern:?}")),
+            };
+            glob.push_str(&part);
+            rest = next;
+        }
+
+        Ok(if glob.is_empty() {
+            ArgPattern::None
+        } else if glob.len() == usize::from(terms) {
+            ArgPattern::FixedLenTerm(terms)
+        } else {
+            ArgPattern::Glob(glob.into())
+        })
+    }
+}
+
 // struct ArgShape(ArgPattern, Direction);
 
 /// Shape of arguments with direction to match since.
@@ -237,7 +393,7 @@ pub enum ArgPattern {
 /// - `Direction::Right` with any `ArgPattern`
 /// - `Direction::Left` with `ArgPattern::FixedLenTerm(1)`
 /// - `Direction::Infix` with `ArgPattern::Greedy`
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "rkyv", derive(Archive, rDeser, rSer))]
 #[cfg_attr(feature = "rkyv-validation", archive(check_bytes))]
 pub enum ArgShape {
@@ -258,7 +414,7 @@ pub enum ArgShape {
 }
 
 /// A feature that specifies how to process the content of an environment.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "rkyv", derive(Archive, rDeser, rSer))]
 #[cfg_attr(feature = "rkyv-validation", archive(check_bytes))]
 pub enum ContextFeature {
@@ -274,4 +430,11 @@ pub enum ContextFeature {
     IsItemize,
     /// Parse content like enumerate
     IsEnumerate,
+    /// Parse content like tabular, whose cells are in text mode
+    IsTable,
+    /// Parse content like description
+    IsDescription,
+    /// Parse content like theorem, whose alias is the label of the block,
+    /// e.g. `Theorem`
+    IsTheorem,
 }
@@ -2,12 +2,16 @@
 #![allow(missing_docs)]
 
 pub mod command {
+    use alloc::borrow::ToOwned;
+
     use crate::{ArgShape, CommandSpecItem};
 
     pub fn define_command(num: u8) -> CommandSpecItem {
         CommandSpecItem::Cmd(crate::CmdShape {
             args: crate::ArgShape::Right(crate::ArgPattern::FixedLenTerm(num)),
             alias: None,
+            template: None,
+            math_args: false,
         })
     }
 
@@ -15,6 +19,8 @@ pub mod command {
         CommandSpecItem::Cmd(crate::CmdShape {
             args: crate::ArgShape::Right(crate::ArgPattern::Glob(reg.into())),
             alias: Some(alias.to_owned()),
+            template: None,
+            math_args: false,
         })
     }
 
@@ -22,6 +28,8 @@ pub mod command {
         CommandSpecItem::Cmd(crate::CmdShape {
             args: crate::ArgShape::Right(crate::ArgPattern::None),
             alias: Some(alias.to_owned()),
+            template: None,
+            math_args: false,
         })
     }
 
@@ -29,6 +37,8 @@ pub mod command {
         CommandSpecItem::Cmd(crate::CmdShape {
             args: crate::ArgShape::Right(crate::ArgPattern::FixedLenTerm(num)),
             alias: Some(alias.to_owned()),
+            template: None,
+            math_args: false,
         })
     }
 
@@ -36,6 +46,8 @@ pub mod command {
         CommandSpecItem::Cmd(crate::CmdShape {
             args: crate::ArgShape::Right(crate::ArgPattern::Greedy),
             alias: Some(alias.to_owned()),
+            template: None,
+            math_args: false,
         })
     }
 
@@ -59,7 +71,12 @@ pub mod command {
         })
     }
     pub const fn define_const_command(args: ArgShape) -> CommandSpecItem {
-        CommandSpecItem::Cmd(crate::CmdShape { args, alias: None })
+        CommandSpecItem::Cmd(crate::CmdShape {
+            args,
+            alias: None,
+            template: None,
+            math_args: false,
+        })
     }
 
     pub const TEX_CMD0: CommandSpecItem =
@@ -88,7 +105,7 @@ pub mod command {
 
     #[derive(Default)]
     pub struct SpecBuilder {
-        commands: fxhash::FxHashMap<String, CommandSpecItem>,
+        commands: crate::CommandMap,
     }
 
     impl SpecBuilder {
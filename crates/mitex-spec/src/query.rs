@@ -1,9 +1,55 @@
 #![allow(missing_docs)]
 
-use std::{collections::HashMap, sync::Arc};
+use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
+impl crate::CommandSpec {
+    /// Load a command specification from a JSON string in the format of
+    /// [`CommandSpecRepr`], which is also the format of specifications
+    /// exported by the MiTeX Typst package.
+    ///
+    /// # Errors
+    /// Returns an error if the input is not a valid specification, including
+    /// the one with unknown fields.
+    pub fn from_json_str(input: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str::<CommandSpecRepr>(input).map(Self::from)
+    }
+
+    /// Load a command specification from a TOML string, whose schema is the
+    /// same as the one of [`Self::from_json_str`].
+    ///
+    /// ```
+    /// let spec = mitex_spec::CommandSpec::from_toml_str(
+    ///     r#"
+    /// [commands.abs]
+    /// kind = "cmd"
+    /// args = { kind = "right", pattern = { kind = "fixed-len", len = 1 } }
+    /// alias = "abs"
+    /// "#,
+    /// )
+    /// .unwrap();
+    /// assert!(spec.get_cmd("abs").is_some());
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error if the input is not a valid specification, including
+    /// the one with unknown fields.
+    #[cfg(feature = "toml")]
+    pub fn from_toml_str(input: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str::<CommandSpecRepr>(input).map(Self::from)
+    }
+
+    /// Serialize the command specification into a JSON string, which can be
+    /// loaded back by [`Self::from_json_str`].
+    ///
+    /// # Panics
+    /// Panics if serde_json doesn't work properly.
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string(&CommandSpecRepr::from(self)).unwrap()
+    }
+}
+
 /// A package specification.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PackageSpec {
@@ -24,7 +70,7 @@ pub struct PackagesVec(pub Vec<PackageSpec>);
 ///
 /// See [`crate::CommandSpecItem`] for more details.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "kind")]
+#[serde(tag = "kind", deny_unknown_fields)]
 pub enum CommandSpecItem {
     #[serde(rename = "cmd")]
     Cmd(CmdShape),
@@ -86,22 +132,45 @@ impl From<CommandSpecItem> for crate::CommandSpecItem {
             CommandSpecItem::CmdInfix { alias } => crate::CommandSpecItem::Cmd(crate::CmdShape {
                 args: crate::ArgShape::InfixGreedy,
                 alias: Some(alias.to_owned()),
+                template: None,
+                math_args: false,
             }),
             CommandSpecItem::CmdGlob { pattern, alias } => define_glob_command(&pattern, &alias),
         }
     }
 }
 
+impl From<&crate::CommandSpecItem> for CommandSpecItem {
+    fn from(item: &crate::CommandSpecItem) -> Self {
+        match item {
+            crate::CommandSpecItem::Cmd(shape) => Self::Cmd(shape.into()),
+            crate::CommandSpecItem::Env(shape) => Self::Env(shape.into()),
+        }
+    }
+}
+
 /// The following defined structs are copied so we don't maintain their
 /// comments. See [`crate::CommandSpecRepr`] for canonical representation.
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CommandSpecRepr {
     pub commands: HashMap<String, CommandSpecItem>,
 }
 
 impl From<CommandSpecRepr> for crate::CommandSpec {
     fn from(repr: CommandSpecRepr) -> Self {
-        Self(Arc::new(repr.into()))
+        Self::from_repr(repr.into())
+    }
+}
+
+impl From<&crate::CommandSpec> for CommandSpecRepr {
+    fn from(spec: &crate::CommandSpec) -> Self {
+        Self {
+            commands: spec
+                .items()
+                .map(|(k, v)| (k.to_owned(), v.into()))
+                .collect(),
+        }
     }
 }
 
@@ -118,9 +187,14 @@ impl From<CommandSpecRepr> for crate::CommandSpecRepr {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CmdShape {
     pub args: ArgShape,
     pub alias: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<crate::Template>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub math_args: bool,
 }
 
 impl From<CmdShape> for crate::CmdShape {
@@ -128,11 +202,25 @@ impl From<CmdShape> for crate::CmdShape {
         Self {
             args: shape.args.into(),
             alias: shape.alias,
+            template: shape.template,
+            math_args: shape.math_args,
+        }
+    }
+}
+
+impl From<&crate::CmdShape> for CmdShape {
+    fn from(shape: &crate::CmdShape) -> Self {
+        Self {
+            args: (&shape.args).into(),
+            alias: shape.alias.clone(),
+            template: shape.template.clone(),
+            math_args: shape.math_args,
         }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct EnvShape {
     pub args: ArgPattern,
     pub ctx_feature: ContextFeature,
@@ -149,8 +237,18 @@ impl From<EnvShape> for crate::EnvShape {
     }
 }
 
+impl From<&crate::EnvShape> for EnvShape {
+    fn from(shape: &crate::EnvShape) -> Self {
+        Self {
+            args: (&shape.args).into(),
+            ctx_feature: (&shape.ctx_feature).into(),
+            alias: shape.alias.clone(),
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "kind")]
+#[serde(tag = "kind", deny_unknown_fields)]
 pub enum ArgPattern {
     #[default]
     #[serde(rename = "none")]
@@ -177,10 +275,27 @@ impl From<ArgPattern> for crate::ArgPattern {
     }
 }
 
+impl From<&crate::ArgPattern> for ArgPattern {
+    fn from(pattern: &crate::ArgPattern) -> Self {
+        match pattern {
+            crate::ArgPattern::None => Self::None,
+            crate::ArgPattern::FixedLenTerm(len) => Self::FixedLenTerm { len: *len },
+            crate::ArgPattern::RangeLenTerm(min, max) => Self::RangeLenTerm {
+                min: *min,
+                max: *max,
+            },
+            crate::ArgPattern::Greedy => Self::Greedy,
+            crate::ArgPattern::Glob(pattern) => Self::Glob {
+                pattern: pattern.as_ref().into(),
+            },
+        }
+    }
+}
+
 // struct ArgShape(ArgPattern, Direction);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "kind")]
+#[serde(tag = "kind", deny_unknown_fields)]
 pub enum ArgShape {
     #[serde(rename = "right")]
     Right { pattern: ArgPattern },
@@ -200,8 +315,20 @@ impl From<ArgShape> for crate::ArgShape {
     }
 }
 
+impl From<&crate::ArgShape> for ArgShape {
+    fn from(shape: &crate::ArgShape) -> Self {
+        match shape {
+            crate::ArgShape::Right(pattern) => Self::Right {
+                pattern: pattern.into(),
+            },
+            crate::ArgShape::Left1 => Self::Left1,
+            crate::ArgShape::InfixGreedy => Self::InfixGreedy,
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "kind")]
+#[serde(tag = "kind", deny_unknown_fields)]
 pub enum ContextFeature {
     #[default]
     #[serde(rename = "none")]
@@ -216,6 +343,12 @@ pub enum ContextFeature {
     IsItemize,
     #[serde(rename = "is-enumerate")]
     IsEnumerate,
+    #[serde(rename = "is-table")]
+    IsTable,
+    #[serde(rename = "is-description")]
+    IsDescription,
+    #[serde(rename = "is-theorem")]
+    IsTheorem,
 }
 
 impl From<ContextFeature> for crate::ContextFeature {
@@ -227,6 +360,25 @@ impl From<ContextFeature> for crate::ContextFeature {
             ContextFeature::IsCases => Self::IsCases,
             ContextFeature::IsItemize => Self::IsItemize,
             ContextFeature::IsEnumerate => Self::IsEnumerate,
+            ContextFeature::IsTable => Self::IsTable,
+            ContextFeature::IsDescription => Self::IsDescription,
+            ContextFeature::IsTheorem => Self::IsTheorem,
+        }
+    }
+}
+
+impl From<&crate::ContextFeature> for ContextFeature {
+    fn from(feature: &crate::ContextFeature) -> Self {
+        match feature {
+            crate::ContextFeature::None => Self::None,
+            crate::ContextFeature::IsMath => Self::IsMath,
+            crate::ContextFeature::IsMatrix => Self::IsMatrix,
+            crate::ContextFeature::IsCases => Self::IsCases,
+            crate::ContextFeature::IsItemize => Self::IsItemize,
+            crate::ContextFeature::IsEnumerate => Self::IsEnumerate,
+            crate::ContextFeature::IsTable => Self::IsTable,
+            crate::ContextFeature::IsDescription => Self::IsDescription,
+            crate::ContextFeature::IsTheorem => Self::IsTheorem,
         }
     }
 }
@@ -0,0 +1,155 @@
+//! Typst output templates of commands
+
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+
+#[cfg(feature = "rkyv")]
+use rkyv::{Archive, Deserialize as rDeser, Serialize as rSer};
+
+/// A Typst output template of a command, e.g. `norm({0})`
+///
+/// A template is plain text with argument slots in curly braces:
+/// - `{0}`: the first term argument, e.g. `x` in `\norm{x}`, converted
+///   recursively
+/// - `{[0]}`: the first bracket (optional) argument, e.g. `3` in `\sqrt[3]{x}`
+/// - `{0:raw}`: the argument in its source text, without the enclosing braces,
+///   which is escaped if the slot is in a string, e.g. `"{0:raw}"`
+/// - `{[0]=2}`: the argument with a default text used if it is absent
+///
+/// Literal braces are written as `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "rkyv", derive(Archive, rDeser, rSer))]
+#[cfg_attr(feature = "rkyv-validation", archive(check_bytes))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub struct Template {
+    /// The source of the template
+    source: Arc<str>,
+    /// The parsed pieces of the template
+    pieces: Arc<[TemplatePiece]>,
+}
+
+/// A piece of a [`Template`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "rkyv", derive(Archive, rDeser, rSer))]
+#[cfg_attr(feature = "rkyv-validation", archive(check_bytes))]
+pub enum TemplatePiece {
+    /// Text written to the output as is
+    Text(Box<str>),
+    /// An argument slot
+    Slot(TemplateSlot),
+}
+
+/// An argument slot of a [`Template`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "rkyv", derive(Archive, rDeser, rSer))]
+#[cfg_attr(feature = "rkyv-validation", archive(check_bytes))]
+pub struct TemplateSlot {
+    /// Whether the slot refers to a bracket argument
+    pub bracket: bool,
+    /// The index among the arguments of the same kind
+    pub index: usize,
+    /// Whether the argument is written in its source text
+    pub raw: bool,
+    /// The text written if the argument is absent
+    pub default: Option<Box<str>>,
+}
+
+impl Template {
+    /// Parse a template
+    ///
+    /// # Errors
+    /// Returns an error if a slot is malformed or a brace is unpaired.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut pieces = vec![];
+        let mut text = String::new();
+        let mut rest = source;
+        while let Some(pos) = rest.find(['{', '}']) {
+            text.push_str(&rest[..pos]);
+            let tail = &rest[pos..];
+            if tail.starts_with("{{") || tail.starts_with("}}") {
+                text.push_str(&tail[..1]);
+                rest = &tail[2..];
+                continue;
+            }
+            if tail.starts_with('}') {
+                return Err(format!("unpaired `}}` in template {source:?}"));
+            }
+            let end = tail
+                .find('}')
+                .ok_or_else(|| format!("unclosed slot in template {source:?}"))?;
+            if !text.is_empty() {
+                pieces.push(TemplatePiece::Text(core::mem::take(&mut text).into()));
+            }
+            pieces.push(TemplatePiece::Slot(
+                Self::parse_slot(&tail[1..end]).ok_or_else(|| {
+                    format!("invalid slot `{}` in template {source:?}", &tail[..=end])
+                })?,
+            ));
+            rest = &tail[end + 1..];
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            pieces.push(TemplatePiece::Text(text.into()));
+        }
+
+        Ok(Self {
+            source: source.into(),
+            pieces: pieces.into(),
+        })
+    }
+
+    /// Parse the content of a slot, e.g. `[0]:raw=2`
+    fn parse_slot(slot: &str) -> Option<TemplateSlot> {
+        let (slot, default) = match slot.split_once('=') {
+            Some((slot, default)) => (slot, Some(default.into())),
+            None => (slot, None),
+        };
+        let (slot, raw) = match slot.strip_suffix(":raw") {
+            Some(slot) => (slot, true),
+            None => (slot, false),
+        };
+        let (index, bracket) = match slot.strip_prefix('[') {
+            Some(slot) => (slot.strip_suffix(']')?, true),
+            None => (slot, false),
+        };
+
+        Some(TemplateSlot {
+            bracket,
+            index: index.parse().ok()?,
+            raw,
+            default,
+        })
+    }
+
+    /// Get the source of the template
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Get the parsed pieces of the template
+    pub fn pieces(&self) -> &[TemplatePiece] {
+        &self.pieces
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Self::parse(&source)
+    }
+}
+
+impl From<Template> for String {
+    fn from(template: Template) -> Self {
+        template.source.as_ref().to_owned()
+    }
+}
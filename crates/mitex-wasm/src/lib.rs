@@ -67,6 +67,65 @@ mod impls {
         let res = mitex::convert_text(input, spec)?;
         Result::Ok(res)
     }
+
+    /// Converts a LaTeX code into a JSON object for web tooling, in the shape
+    /// of `{ typst: string, diagnostics: [{ message, severity, start, end }] }`
+    ///
+    /// The command specification is given in JSON, see
+    /// [`mitex_spec::CommandSpec::from_json_str`], and the default one is used
+    /// if it is empty. Errors never panic, but are reported as diagnostics
+    /// with an empty `typst` field.
+    #[cfg(feature = "web")]
+    #[wasm_bindgen]
+    pub fn convert_latex(input: &str, spec_json: &str) -> JsValue {
+        use serde::Serialize;
+
+        // plain objects instead of maps for `JSON.stringify`
+        let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+        let res = convert_latex_json(input, spec_json);
+        res.serialize(&serializer)
+            .unwrap_or_else(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// The JSON object of [`convert_latex`]
+    #[cfg(feature = "web")]
+    pub fn convert_latex_json(input: &str, spec_json: &str) -> serde_json::Value {
+        let spec = if spec_json.trim().is_empty() {
+            Ok(None)
+        } else {
+            mitex_spec::CommandSpec::from_json_str(spec_json).map(Some)
+        };
+        let (typst, diagnostics) = match spec {
+            Ok(spec) => {
+                let (res, mut diagnostics) = mitex::convert_text_with_diagnostics(input, spec);
+                let typst = res.unwrap_or_else(|e| {
+                    diagnostics.push(mitex::Diagnostic::error(e, 0..0));
+                    String::new()
+                });
+                (typst, diagnostics)
+            }
+            Err(e) => {
+                let message = format!("invalid command specification: {e}");
+                (String::new(), vec![mitex::Diagnostic::error(message, 0..0)])
+            }
+        };
+
+        let diagnostics = diagnostics
+            .into_iter()
+            .map(|diag| {
+                serde_json::json!({
+                    "message": diag.message,
+                    "severity": match diag.severity {
+                        mitex::Severity::Warning => "warning",
+                        mitex::Severity::Error => "error",
+                    },
+                    "start": diag.span.start,
+                    "end": diag.span.end,
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({ "typst": typst, "diagnostics": diagnostics })
+    }
 }
 
 /// Wrappers for Typst as the host
@@ -0,0 +1,22 @@
+#![cfg(all(target_arch = "wasm32", feature = "web"))]
+
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+fn test_convert_latex() {
+    let res = mitex_wasm::convert_latex(r#"$\frac{1}{2}$"#, "");
+    let res: serde_json::Value = serde_wasm_bindgen::from_value(res).unwrap();
+    let typst = res["typst"].as_str().unwrap();
+    assert!(typst.contains("frac(1 ,2 )"), "{typst}");
+    assert_eq!(res["diagnostics"], serde_json::json!([]));
+}
+
+#[wasm_bindgen_test]
+fn test_convert_latex_invalid_spec() {
+    let res = mitex_wasm::convert_latex(r#"$\frac{1}{2}$"#, "{");
+    let res: serde_json::Value = serde_wasm_bindgen::from_value(res).unwrap();
+    assert_eq!(res["typst"], "");
+    assert_eq!(res["diagnostics"][0]["severity"], "error");
+}
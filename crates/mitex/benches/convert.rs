@@ -0,0 +1,45 @@
+//! Benchmarks for converting representative documents.
+//! The corpora under `benches/data` are a large matrix, a long align block
+//! and a prose-heavy document, which are repeated to make the inputs large
+//! enough to show regressions of the lexer, the parser and the converter.
+
+use divan::{AllocProfiler, Bencher};
+
+#[global_allocator]
+static ALLOC: AllocProfiler = AllocProfiler::system();
+
+fn main() {
+    // Run registered benchmarks.
+    divan::main();
+}
+
+const MATRIX: &str = include_str!("data/matrix.tex");
+const ALIGN: &str = include_str!("data/align.tex");
+const PROSE: &str = include_str!("data/prose.tex");
+
+type Convert = fn(&str, Option<mitex::CommandSpec>) -> Result<String, String>;
+
+fn bench(bencher: Bencher, convert: Convert, corpus: &str, times: usize) {
+    let input = corpus.repeat(times);
+    bencher.bench(|| convert(&input, None).unwrap());
+}
+
+#[divan::bench]
+fn matrix(bencher: Bencher) {
+    bench(bencher, mitex::convert_math, MATRIX, 10);
+}
+
+#[divan::bench]
+fn matrix_no_macro(bencher: Bencher) {
+    bench(bencher, mitex::convert_math_no_macro, MATRIX, 10);
+}
+
+#[divan::bench]
+fn align(bencher: Bencher) {
+    bench(bencher, mitex::convert_text, ALIGN, 10);
+}
+
+#[divan::bench]
+fn prose(bencher: Bencher) {
+    bench(bencher, mitex::convert_text, PROSE, 100);
+}
@@ -1,16 +1,24 @@
 use core::fmt;
-use std::cell::RefCell;
 use std::fmt::Write;
-use std::rc::Rc;
+use std::io;
+use std::ops::Range;
 
 pub use mitex_parser::command_preludes;
+use mitex_parser::is_verbatim_arg_cmd;
+use mitex_parser::is_verbatim_env;
 use mitex_parser::parse;
+use mitex_parser::parse_in_mode;
 use mitex_parser::parse_without_macro;
 pub use mitex_parser::spec::*;
 use mitex_parser::syntax::CmdItem;
 use mitex_parser::syntax::EnvItem;
 use mitex_parser::syntax::FormulaItem;
 use mitex_parser::syntax::SyntaxNode;
+use mitex_parser::syntax::SyntaxToken;
+use mitex_parser::verb_body;
+use mitex_parser::ParseMode;
+use mitex_parser::SourceSpans;
+pub use mitex_parser::{Diagnostic, Severity};
 use mitex_spec_gen::DEFAULT_SPEC;
 use rowan::ast::AstNode;
 
@@ -36,6 +44,15 @@ enum LaTeXMode {
     Math,
 }
 
+impl From<LaTeXMode> for ParseMode {
+    fn from(mode: LaTeXMode) -> Self {
+        match mode {
+            LaTeXMode::Text => ParseMode::Text,
+            LaTeXMode::Math => ParseMode::Math,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 enum LaTeXEnv {
     #[default]
@@ -43,12 +60,56 @@ enum LaTeXEnv {
     None,
     Itemize,
     Enumerate,
+    Description,
     // Math mode
     Math,
     Matrix,
     Cases,
     SubStack,
     MathCurlyGroup,
+    // Both modes
+    Table,
+}
+
+/// A mapping from a byte range of the LaTeX input to the byte range of the
+/// Typst output converted from it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMapping {
+    /// The byte range in the LaTeX input
+    pub latex: Range<usize>,
+    /// The byte range in the Typst output
+    pub typst: Range<usize>,
+}
+
+/// The output of the converter, which counts the written bytes to locate the
+/// converted items
+struct Output<'a> {
+    inner: &'a mut dyn fmt::Write,
+    len: usize,
+    // whether only indentation is written since the last line break, where a
+    // heading or a list item could be started in typst markup
+    line_start: bool,
+}
+
+impl<'a> Output<'a> {
+    fn new(inner: &'a mut dyn fmt::Write) -> Self {
+        Self {
+            inner,
+            len: 0,
+            line_start: true,
+        }
+    }
+}
+
+impl fmt::Write for Output<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.len += s.len();
+        let trimmed = s.trim_end_matches([' ', '\t']);
+        if !trimmed.is_empty() {
+            self.line_start = trimmed.ends_with('\n');
+        }
+        self.inner.write_str(s)
+    }
 }
 
 struct Converter {
@@ -56,10 +117,42 @@ struct Converter {
     env: LaTeXEnv,
     // indent for itemize and enumerate
     indent: usize,
+    // whether the current item of the list has a label as its marker, whose
+    // body is closed by the next item or the end of the list
+    labeled_item: bool,
     // label for block equation
     label: Option<String>,
     // skip the space at the beginning of the line
     skip_next_space: bool,
+    // input ranges of the tokens, only given if a source map or diagnostics
+    // are requested
+    spans: Option<SourceSpans>,
+    // source map of the output in the order of the input, only recorded if it
+    // is requested
+    source_map: Option<Vec<SourceMapping>>,
+    // diagnostics reported by the converter, e.g. an ignored option
+    diagnostics: Vec<Diagnostic>,
+    // skip unknown commands with an error diagnostic instead of passing them
+    // through
+    strict: bool,
+    // ranges of the closing bars of the pending `abs` and `norm` calls
+    bar_closers: Vec<rowan::TextRange>,
+    // whether a text argument in math is converted, e.g. of `\text`
+    in_text_arg: bool,
+    // commands reported as discouraged, see `ConvertOptions::discouraged`
+    discouraged: Vec<String>,
+    // see `ConvertOptions::collapse_whitespace`
+    collapse_whitespace: bool,
+    // the number of line breaks of the whitespace collapsed before the next
+    // text, if there is any
+    pending_space: Option<usize>,
+}
+
+impl LaTeXEnv {
+    /// Whether the environment is a list, whose items are started by `\item`
+    fn is_list(self) -> bool {
+        matches!(self, Self::Itemize | Self::Enumerate | Self::Description)
+    }
 }
 
 impl Converter {
@@ -68,8 +161,18 @@ impl Converter {
             mode,
             env: LaTeXEnv::default(),
             indent: 0,
+            labeled_item: false,
             label: None,
             skip_next_space: true,
+            spans: None,
+            source_map: None,
+            diagnostics: Vec::new(),
+            strict: false,
+            bar_closers: Vec::new(),
+            in_text_arg: false,
+            discouraged: Vec::new(),
+            collapse_whitespace: false,
+            pending_space: None,
         }
     }
 
@@ -88,18 +191,141 @@ impl Converter {
     fn enter_env(&mut self, context: LaTeXEnv) -> LaTeXEnv {
         let prev = self.env;
         self.env = context;
-        if matches!(self.env, LaTeXEnv::Itemize | LaTeXEnv::Enumerate) {
+        if self.env.is_list() {
             self.indent += 2;
         }
         prev
     }
 
     fn exit_env(&mut self, prev: LaTeXEnv) {
-        if matches!(self.env, LaTeXEnv::Itemize | LaTeXEnv::Enumerate) {
+        if self.env.is_list() {
             self.indent -= 2;
         }
         self.env = prev;
     }
+
+    /// Start a mapping of a token or a command to the output written next
+    ///
+    /// The mapping is pushed before the mappings of its children, so that
+    /// the source map is sorted by the start of the input ranges. Returns the
+    /// index of the mapping to finish by [`Self::finish_mapping`].
+    fn start_mapping(&mut self, f: &Output, elem: &LatexSyntaxElem) -> Option<usize> {
+        self.source_map.as_ref()?;
+        let latex = match elem {
+            LatexSyntaxElem::Node(node) if node.kind() != LatexSyntaxKind::ItemCmd => {
+                return None;
+            }
+            elem => self.source_span(elem)?,
+        };
+        let source_map = self.source_map.as_mut()?;
+        source_map.push(SourceMapping {
+            latex,
+            typst: f.len..f.len,
+        });
+        Some(source_map.len() - 1)
+    }
+
+    /// Finish a mapping started by [`Self::start_mapping`]
+    fn finish_mapping(&mut self, f: &Output, index: Option<usize>) {
+        if let (Some(index), Some(source_map)) = (index, &mut self.source_map) {
+            source_map[index].typst.end = f.len;
+        }
+    }
+
+    /// The byte range of an element in the input, e.g. from the name of a
+    /// command to the end of its last argument
+    fn source_span(&self, elem: &LatexSyntaxElem) -> Option<Range<usize>> {
+        let spans = self.spans.as_ref()?;
+        let node = match elem {
+            LatexSyntaxElem::Token(token) => return spans.get(token.text_range().start()),
+            LatexSyntaxElem::Node(node) => node,
+        };
+        let mut tokens = node
+            .descendants_with_tokens()
+            .filter_map(|elem| elem.into_token())
+            .filter_map(|token| spans.get(token.text_range().start()));
+        let first = tokens.next()?;
+        let last = tokens.last().unwrap_or_else(|| first.clone());
+        Some(first.start..last.end.max(first.end))
+    }
+
+    /// Report a warning about an element, which is still converted
+    fn warn(&mut self, message: impl Into<String>, elem: &LatexSyntaxElem) {
+        let span = self.source_span(elem).unwrap_or_default();
+        self.diagnostics.push(Diagnostic::warning(message, span));
+    }
+
+    /// Convert a control space, i.e. a backslash followed by a space or at the
+    /// end of the input, whose command name is empty
+    ///
+    /// It is nothing in text mode, where the following space is kept anyway,
+    /// and a space in math mode unless the specification defines it, e.g. as
+    /// a thick space. Returns whether it is converted here.
+    fn convert_control_space(
+        &mut self,
+        f: &mut Output,
+        spec: &CommandSpec,
+    ) -> Result<bool, ConvertError> {
+        match self.mode {
+            LaTeXMode::Text => Ok(true),
+            LaTeXMode::Math if spec.get_cmd("").is_some() => Ok(false),
+            LaTeXMode::Math => {
+                f.write_str("space ")?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Whether the whitespace is collapsed here, i.e. in text mode but not
+    /// in a list, whose line breaks are already spaces
+    fn collapses_space(&self) -> bool {
+        self.collapse_whitespace && matches!(self.mode, LaTeXMode::Text) && !self.env.is_list()
+    }
+
+    /// Write the whitespace collapsed before an element, which is a paragraph
+    /// break if it has a blank line, or a space otherwise
+    fn flush_space(&mut self, f: &mut Output) -> Result<(), ConvertError> {
+        match self.pending_space.take() {
+            // the whitespace at the start of the output is dropped
+            _ if f.len == 0 => {}
+            Some(breaks) if breaks >= 2 => f.write_str("\n\n")?,
+            Some(_) => f.write_char(' ')?,
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Report a warning if a command or `$$` is discouraged by the options
+    fn check_discouraged(&mut self, name: &str, elem: &LatexSyntaxElem) {
+        if !self.discouraged.iter().any(|cmd| cmd == name) {
+            return;
+        }
+        let message = if name == "$$" {
+            "discouraged display math `$$`, use `\\[...\\]` instead".to_owned()
+        } else {
+            format!("discouraged command `\\{}`", name)
+        };
+        self.warn(message, elem);
+    }
+
+    /// Pass an unknown command through by its name followed by `suffix`, or
+    /// skip it with an error diagnostic if it is strict
+    fn unknown_command(
+        &mut self,
+        f: &mut Output,
+        name: &str,
+        elem: &LatexSyntaxElem,
+        suffix: &str,
+    ) -> Result<(), ConvertError> {
+        if !self.strict {
+            write!(f, "{}{}", name, suffix)?;
+            return Ok(());
+        }
+        let span = self.source_span(elem).unwrap_or_default();
+        let message = format!("unknown command `\\{}`", name);
+        self.diagnostics.push(Diagnostic::error(message, span));
+        Ok(())
+    }
 }
 
 // fn empty_node() -> GreenNode {
@@ -137,10 +363,31 @@ impl From<String> for ConvertError {
     }
 }
 
+impl From<&str> for ConvertError {
+    fn from(e: &str) -> Self {
+        Self::Str(e.to_owned())
+    }
+}
+
 impl Converter {
     fn convert(
         &mut self,
-        f: &mut fmt::Formatter<'_>,
+        f: &mut Output,
+        elem: LatexSyntaxElem,
+        spec: &CommandSpec,
+    ) -> Result<(), ConvertError> {
+        if self.pending_space.is_some() && !is_trivia(&elem) {
+            self.flush_space(f)?;
+        }
+        let mapping = self.start_mapping(f, &elem);
+        self.convert_elem(f, elem, spec)?;
+        self.finish_mapping(f, mapping);
+        Ok(())
+    }
+
+    fn convert_elem(
+        &mut self,
+        f: &mut Output,
         elem: LatexSyntaxElem,
         spec: &CommandSpec,
     ) -> Result<(), ConvertError> {
@@ -158,14 +405,28 @@ impl Converter {
                 LatexSyntaxElem::Token(token) => format!("error unexpected: {:?}", token.text()),
             })?,
             ItemLR | ClauseArgument | ScopeRoot | ItemText | ItemBracket | ItemParen => {
-                for child in elem.as_node().unwrap().children_with_tokens() {
+                for child in as_node(&elem)?.children_with_tokens() {
                     self.convert(f, child, spec)?;
                 }
             }
             ItemFormula => {
-                let formula = FormulaItem::cast(elem.as_node().unwrap().clone()).unwrap();
+                let formula =
+                    FormulaItem::cast(as_node(&elem)?.clone()).ok_or("invalid formula")?;
                 if !formula.is_valid() {
-                    Err("formula is not valid".to_owned())?
+                    // an unbalanced `$` in a text argument is closed by the
+                    // end of the argument, e.g. `\text{if $x}`
+                    let dollars = as_node(&elem)?
+                        .children_with_tokens()
+                        .filter(|child| child.kind() == TokenDollar)
+                        .count();
+                    if !(self.in_text_arg && formula.is_inline() && dollars == 1) {
+                        Err("formula is not valid".to_owned())?
+                    }
+                    self.warn("unterminated math in a text argument", &elem);
+                }
+                let first = as_node(&elem)?.first_token();
+                if let Some(dollars) = first.filter(|token| token.text() == "$$") {
+                    self.check_discouraged("$$", &LatexSyntaxElem::Token(dollars));
                 }
                 if matches!(self.mode, LaTeXMode::Text) {
                     if formula.is_inline() {
@@ -175,7 +436,7 @@ impl Converter {
                     }
                 }
                 let prev = self.enter_mode(LaTeXMode::Math);
-                for child in elem.as_node().unwrap().children_with_tokens() {
+                for child in as_node(&elem)?.children_with_tokens() {
                     self.convert(f, child, spec)?;
                 }
                 self.exit_mode(prev);
@@ -197,7 +458,7 @@ impl Converter {
                     enter_new_env = true;
                 }
                 let mut zws = true;
-                for child in elem.as_node().unwrap().children_with_tokens() {
+                for child in as_node(&elem)?.children_with_tokens() {
                     match &child.kind() {
                         TokenWhiteSpace | TokenLineBreak | TokenLBrace | TokenRBrace => {}
                         _ => {
@@ -218,15 +479,14 @@ impl Converter {
             }
             // handle lr
             ClauseLR => {
-                let name_and_args = elem
-                    .as_node()
-                    .unwrap()
-                    .children_with_tokens()
-                    .collect::<Vec<_>>();
-                let name = name_and_args[0].as_token().unwrap().text();
+                let name_and_args = as_node(&elem)?.children_with_tokens().collect::<Vec<_>>();
+                let Some((name, args)) = name_and_args.split_first() else {
+                    return Ok(());
+                };
+                let name = as_token(name)?.text();
                 // remove prefix \
-                let name = &name[1..];
-                let args = name_and_args[1..].to_owned();
+                let name = name.strip_prefix('\\').unwrap_or(name);
+                let args = args.to_owned();
                 if name == "left" {
                     f.write_str("lr(")?;
                 }
@@ -235,10 +495,26 @@ impl Converter {
                         LatexSyntaxElem::Node(node) => {
                             self.convert(f, LatexSyntaxElem::Node(node), spec)?;
                         }
-                        LatexSyntaxElem::Token(token) => match token.kind() {
-                            TokenWord if token.text() == "." => {}
-                            _ => self.convert(f, rowan::NodeOrToken::Token(token), spec)?,
-                        },
+                        // `<` and `>` are angle brackets as delimiters, and so are
+                        // `\lt` and `\gt`, which are less and greater signs elsewhere
+                        LatexSyntaxElem::Token(token)
+                            if matches!(
+                                (token.kind(), token.text()),
+                                (TokenWord, "<" | ">") | (TokenCommandSym, "\\lt" | "\\gt")
+                            ) =>
+                        {
+                            let angle = if matches!(token.text(), "<" | "\\lt") {
+                                "angle.l"
+                            } else {
+                                "angle.r"
+                            };
+                            let index = self.start_mapping(f, &LatexSyntaxElem::Token(token));
+                            f.write_str(angle)?;
+                            self.finish_mapping(f, index);
+                        }
+                        LatexSyntaxElem::Token(token) => {
+                            self.convert(f, rowan::NodeOrToken::Token(token), spec)?
+                        }
                     }
                     // add space
                     f.write_char(' ')?;
@@ -249,16 +525,19 @@ impl Converter {
             }
             ItemAttachComponent => {
                 if matches!(self.mode, LaTeXMode::Math) {
+                    if self.convert_brace_label(f, as_node(&elem)?, spec)? {
+                        return Ok(());
+                    }
                     let mut based = false;
                     let mut first = true;
-                    for child in elem.as_node().unwrap().children_with_tokens() {
+                    for child in as_node(&elem)?.children_with_tokens() {
                         if first {
                             let kind = child.as_token().map(|n| n.kind());
                             if matches!(kind, Some(TokenUnderscore | TokenCaret)) {
                                 if !based {
                                     f.write_str("zws")?;
                                 }
-                                write!(f, "{}(", child.as_token().unwrap().text())?;
+                                write!(f, "{}(", as_token(&child)?.text())?;
                                 first = false;
                                 continue;
                             } else if !matches!(kind, Some(TokenWhiteSpace)) {
@@ -271,54 +550,145 @@ impl Converter {
                         f.write_char(')')?;
                     }
                 } else {
-                    for child in elem.as_node().unwrap().children_with_tokens() {
+                    for child in as_node(&elem)?.children_with_tokens() {
                         self.convert(f, child, spec)?;
                     }
                 }
             }
             TokenApostrophe => {
-                f.write_char('\'')?;
+                let token = as_token(&elem)?;
+                // the primes attached one by one are paired into a `''`
+                // ligature in text mode
+                let before = std::iter::successors(token.prev_token(), |t| t.prev_token())
+                    .take_while(|t| t.kind() == TokenApostrophe)
+                    .count();
+                let paired = token
+                    .next_token()
+                    .is_some_and(|t| t.kind() == TokenApostrophe);
+                if matches!(self.mode, LaTeXMode::Math) {
+                    f.write_char('\'')?;
+                } else if before % 2 == 0 {
+                    f.write_str(if paired { "”" } else { "'" })?;
+                }
+            }
+            TokenLigature => {
+                let text = as_token(&elem)?.text();
+                if matches!(self.mode, LaTeXMode::Math) {
+                    // no ligatures in math mode, e.g. `--` is two minus signs
+                    for c in text.chars() {
+                        f.write_char(c)?;
+                        if c != '\'' {
+                            f.write_char(' ')?;
+                        }
+                    }
+                } else {
+                    f.write_str(match text {
+                        "--" => "–",
+                        "---" => "—",
+                        "``" => "“",
+                        "''" => "”",
+                        _ => text,
+                    })?;
+                }
             }
             ClauseCommandName => Err("command name outside of command".to_owned())?,
             ItemBegin | ItemEnd => Err("clauses outside of environment".to_owned())?,
             TokenWord => {
                 if matches!(self.mode, LaTeXMode::Math) {
                     // break up words into individual characters and add a space
-                    let text = elem.as_token().unwrap().text().to_string();
+                    let text = as_token(&elem)?.text().to_string();
                     for prev in text.chars() {
                         f.write_char(prev)?;
                         f.write_char(' ')?;
                     }
                 } else {
-                    f.write_str(elem.as_token().unwrap().text())?;
+                    let text = as_token(&elem)?.text();
+                    // escape the rest of typst markup syntax, e.g. a backtick
+                    // starts raw text and `<` starts a label, where a word
+                    // could also hold a backslash resolved from `^^5c`
+                    const MARKUP: [char; 4] = ['\\', '`', '<', '>'];
+                    // a heading or a list item is started at the line start
+                    let marker = f.line_start && text.starts_with(['=', '-', '+']);
+                    if marker || text.contains(MARKUP) {
+                        for (index, c) in text.char_indices() {
+                            if (marker && index == 0) || MARKUP.contains(&c) {
+                                f.write_char('\\')?;
+                            }
+                            f.write_char(c)?;
+                        }
+                    } else {
+                        f.write_str(text)?;
+                    }
                 }
             }
             // do nothing
             TokenLBrace | TokenRBrace | TokenDollar | TokenBeginMath | TokenEndMath
-            | TokenComment | ItemBlockComment => {}
+            | TokenComment | TokenEmptyDelimiter | ItemBlockComment => {}
+            // a run of whitespace is written before the next text, see
+            // `Self::flush_space`
+            TokenWhiteSpace | TokenLineBreak if self.collapses_space() => {
+                let mut breaks = as_token(&elem)?.text().matches('\n').count();
+                if elem.kind() == TokenLineBreak {
+                    breaks = breaks.max(1);
+                }
+                *self.pending_space.get_or_insert(0) += breaks;
+            }
             // space identical
             TokenWhiteSpace => {
                 if self.skip_next_space {
                     self.skip_next_space = false;
                     return Ok(());
                 }
-                write!(f, "{}", elem.as_token().unwrap().text())?;
+                write!(f, "{}", as_token(&elem)?.text())?;
+            }
+            // a line break in a list is a space, as a new line is only
+            // started by an item, which keeps the list tight
+            TokenLineBreak if self.env.is_list() => {
+                f.write_char(' ')?;
+                self.skip_next_space = true;
             }
             TokenLineBreak => {
-                write!(f, "{}", elem.as_token().unwrap().text())?;
+                write!(f, "{}", as_token(&elem)?.text())?;
                 // indent for itemize and enumerate
                 for _ in 0..self.indent {
                     f.write_char(' ')?;
                 }
                 self.skip_next_space = true;
             }
+            // a blank line or `\par` starts a new paragraph in text, which is
+            // also a space in a list as above
+            ItemParBreak if self.collapses_space() => {
+                *self.pending_space.get_or_insert(0) += 2;
+            }
+            ItemParBreak if matches!(self.mode, LaTeXMode::Text) => {
+                if self.env.is_list() {
+                    f.write_char(' ')?;
+                } else {
+                    f.write_str("\n\n")?;
+                    for _ in 0..self.indent {
+                        f.write_char(' ')?;
+                    }
+                }
+                self.skip_next_space = true;
+            }
+            ItemParBreak => {
+                for child in as_node(&elem)?.children_with_tokens() {
+                    if child.kind() == ClauseCommandName {
+                        f.write_char(' ')?;
+                    } else {
+                        self.convert(f, child, spec)?;
+                    }
+                }
+            }
             // escape
             TokenComma => {
                 f.write_str("\\,")?;
             }
-            TokenTilde => {
-                f.write_str("space.nobreak ")?;
-            }
+            // `~` is also a non-breaking space in typst markup
+            TokenTilde => match self.mode {
+                LaTeXMode::Math => f.write_str("space.nobreak ")?,
+                LaTeXMode::Text => f.write_char('~')?,
+            },
             TokenSlash => {
                 f.write_str("\\/")?;
             }
@@ -360,62 +730,101 @@ impl Converter {
                 _ => f.write_str("&")?,
             },
             ItemNewLine => match self.env {
-                LaTeXEnv::Matrix => f.write_str("zws ;")?,
+                // a line of \substack is a row of its typst handler
+                LaTeXEnv::Matrix | LaTeXEnv::SubStack => f.write_str("zws ;")?,
                 LaTeXEnv::Cases => f.write_str(",")?,
                 LaTeXEnv::MathCurlyGroup => {}
-                _ => f.write_str("\\ ")?,
+                _ => {
+                    f.write_str("\\ ")?;
+                    // the extra spacing of `\\[1em]`, which has no place in
+                    // math or table rows
+                    if matches!(self.mode, LaTeXMode::Text) {
+                        if let Some(spacing) = new_line_spacing(&elem) {
+                            write!(f, "#v({});", spacing)?;
+                        }
+                    }
+                }
             },
             // for left/right
             TokenCommandSym => {
-                let name = elem.as_token().unwrap().text();
+                let name = as_token(&elem)?.text();
                 // remove prefix \
-                let name = &name[1..];
+                let name = name.strip_prefix('\\').unwrap_or(name);
+                if name.is_empty() && self.convert_control_space(f, spec)? {
+                    return Ok(());
+                }
+                self.check_discouraged(name, &elem);
                 // get cmd_shape and arg_shape from spec
-                let cmd_shape = spec
-                    .get_cmd(name)
-                    .ok_or_else(|| format!("unknown command: \\{}", name))?;
+                let Some(cmd_shape) = spec.get_cmd(name) else {
+                    return self.unknown_command(f, name, &elem, "");
+                };
                 // typst alias name
                 let typst_name = cmd_shape.alias.as_deref().unwrap_or(name);
                 // write to output
                 write!(f, "{}", typst_name)?;
             }
             ItemCmd => {
-                let cmd = CmdItem::cast(elem.as_node().unwrap().clone()).unwrap();
-                let name = cmd.name_tok().unwrap();
-                let name = name.text();
+                let cmd = CmdItem::cast(as_node(&elem)?.clone()).ok_or("invalid command")?;
+                let name_tok = cmd.name_tok().ok_or("command name must be non-empty")?;
+                let name = name_tok.text();
                 // remove prefix \
-                let name = &name[1..];
+                let name = name.strip_prefix('\\').unwrap_or(name);
+                if name.is_empty() && self.convert_control_space(f, spec)? {
+                    return Ok(());
+                }
+                self.check_discouraged(name, &LatexSyntaxElem::Token(name_tok.clone()));
 
-                // hack for \item in itemize and enumerate
+                // hack for \item in lists, where an item with a label, e.g.
+                // `\item[a]`, is converted to an item of a term list in a
+                // description, or to an item with the label as its marker
                 if name == "item" {
-                    if matches!(self.env, LaTeXEnv::Itemize | LaTeXEnv::Enumerate) {
-                        f.write_char('\n')?;
-                        for _ in 0..(self.indent - 2) {
-                            f.write_char(' ')?;
-                        }
-                        if matches!(self.env, LaTeXEnv::Itemize) {
-                            f.write_str("- ")?;
-                        } else {
-                            f.write_str("+ ")?;
+                    if !self.env.is_list() {
+                        Err("item command outside of a list".to_owned())?;
+                    }
+                    if std::mem::take(&mut self.labeled_item) {
+                        f.write_char(']')?;
+                    }
+                    f.write_char('\n')?;
+                    for _ in 0..(self.indent - 2) {
+                        f.write_char(' ')?;
+                    }
+                    let label = cmd
+                        .arguments()
+                        .next()
+                        .and_then(|arg| arg.first_child())
+                        .filter(|group| group.kind() == ItemBracket);
+                    if let Some(label) = label {
+                        let is_term = matches!(self.env, LaTeXEnv::Description);
+                        f.write_str(if is_term { "/ " } else { "#list(marker: [" })?;
+                        for elem in label.children_with_tokens() {
+                            if !matches!(elem.kind(), TokenLBracket | TokenRBracket) {
+                                self.convert(f, elem, spec)?;
+                            }
                         }
+                        f.write_str(if is_term { ": " } else { "])[" })?;
+                        self.labeled_item = !is_term;
+                    } else if matches!(self.env, LaTeXEnv::Enumerate) {
+                        f.write_str("+ ")?;
                     } else {
-                        Err("item command outside of itemize or enumerate".to_owned())?;
+                        f.write_str("- ")?;
                     }
+                    self.skip_next_space = true;
                     return Ok(());
                 }
 
                 // hack for \label
                 if name == "label" {
-                    let arg = cmd
-                        .arguments()
-                        .next()
-                        .expect("\\label command must have one argument");
+                    let Some(arg) = cmd.arguments().next() else {
+                        self.warn("a label without a name is ignored", &elem);
+                        return Ok(());
+                    };
                     // remove { and } then trim
                     let label = arg.text().to_string();
-                    let label = &label[1..(label.len() - 1)];
-                    let label = label.trim();
+                    let label = group_content(&label);
+                    let label = typst_label(label);
+                    let label = label.as_str();
                     match self.env {
-                        LaTeXEnv::None | LaTeXEnv::Itemize | LaTeXEnv::Enumerate => {
+                        env if matches!(env, LaTeXEnv::None) || env.is_list() => {
                             if matches!(self.mode, LaTeXMode::Text) {
                                 f.write_char('<')?;
                                 f.write_str(label)?;
@@ -429,27 +838,286 @@ impl Converter {
                     return Ok(());
                 }
 
-                let args = elem
-                    .as_node()
-                    .unwrap()
+                if name == "includegraphics" {
+                    return self.convert_includegraphics(f, &cmd);
+                }
+
+                // a command whose arguments are math, e.g. `\boxed{x}`, is
+                // converted in an inline equation in text mode
+                let math_args = spec.get_cmd(name).is_some_and(|cmd| cmd.math_args);
+                if math_args && matches!(self.mode, LaTeXMode::Text) {
+                    f.write_str("#math.equation(block: false, $")?;
+                    let prev_mode = self.enter_mode(LaTeXMode::Math);
+                    self.convert(f, elem, spec)?;
+                    self.exit_mode(prev_mode);
+                    f.write_str("$);")?;
+                    return Ok(());
+                }
+
+                if matches!(self.mode, LaTeXMode::Math) && name == "genfrac" {
+                    return self.convert_genfrac(f, &cmd, spec);
+                }
+
+                if matches!(self.mode, LaTeXMode::Math) && name == "braket" {
+                    return self.convert_braket(f, &cmd, name, spec);
+                }
+
+                // hack for `\ensuremath{x}`, whose argument is converted as is
+                // in math mode, which it is always in after the equation above
+                if name == "ensuremath" {
+                    for arg in cmd.arguments() {
+                        self.convert(f, rowan::NodeOrToken::Node(arg), spec)?;
+                    }
+                    return Ok(());
+                }
+
+                if matches!(name, "num" | "si" | "SI") {
+                    return self.convert_siunitx(f, &cmd, name);
+                }
+
+                // hack for cross references, e.g. `\ref{sec:intro}` is
+                // converted to `@sec:intro`, where the key is not converted
+                if matches!(name, "ref" | "eqref" | "cite") {
+                    return self.convert_reference(f, &cmd, spec);
+                }
+
+                // hack for operator names, e.g. `\operatorname*{argmax}_x` is
+                // converted to `op("argmax", limits: #true) _(x )`, where the
+                // spaces in the name are ignored as in math mode
+                if matches!(self.mode, LaTeXMode::Math)
+                    && matches!(
+                        name,
+                        "operatorname" | "operatorname*" | "operatornamewithlimits"
+                    )
+                {
+                    let text = cmd.arguments().next().map(|arg| arg.text().to_string());
+                    let text = unescape_text(group_content(text.as_deref().unwrap_or_default()));
+                    // spaces are ignored as in math, but explicit spacing like `arg\,max`
+                    // is kept
+                    let text = text
+                        .split_whitespace()
+                        .collect::<String>()
+                        .replace(r"\!", "")
+                        .replace(r"\,", " ")
+                        .replace(r"\:", " ")
+                        .replace(r"\;", " ");
+                    if name == "operatorname" {
+                        write!(f, "op({text:?}) ")?;
+                    } else {
+                        write!(f, "op({text:?}, limits: #true) ")?;
+                    }
+                    return Ok(());
+                }
+
+                if matches!(name, "color" | "textcolor" | "colorbox") {
+                    return self.convert_color(f, &cmd, spec);
+                }
+                // hack for negated relations, e.g. `\not\in` is converted to
+                // `in.not`, while others are cancelled by the typst handler
+                if matches!(self.mode, LaTeXMode::Math) && name == "not" {
+                    let atom = cmd.arguments().next().map(|arg| arg.text().to_string());
+                    if let Some(negated) =
+                        negated(group_content(atom.as_deref().unwrap_or_default()))
+                    {
+                        write!(f, "{} ", negated)?;
+                        return Ok(());
+                    }
+                }
+
+                // hack for hyperlinks, whose url is lexed verbatim as `_`, `~`,
+                // `#` and `%` are common in urls
+                if is_verbatim_arg_cmd(name) {
+                    let mut args = cmd.arguments();
+                    let url = args.next().map(|arg| arg.text().to_string());
+                    let url = unescape_text(group_content(url.as_deref().unwrap_or_default()));
+                    write!(f, "#link({:?})", url.trim())?;
+                    if let Some(text) = args.next() {
+                        f.write_char('[')?;
+                        let prev_mode = self.enter_mode(LaTeXMode::Text);
+                        self.convert_trimmed(f, vec![rowan::NodeOrToken::Node(text)], spec)?;
+                        self.exit_mode(prev_mode);
+                        f.write_char(']')?;
+                    }
+                    f.write_char(';')?;
+                    return Ok(());
+                }
+
+                // hack for footnotes, whose number, e.g. `\footnote[3]{x}`, is
+                // ignored as typst numbers them
+                if matches!(self.mode, LaTeXMode::Text) && name == "footnote" {
+                    let body = cmd
+                        .arguments()
+                        .filter(|arg| {
+                            arg.first_child()
+                                .map_or(true, |group| group.kind() != ItemBracket)
+                        })
+                        .last();
+                    f.write_str("#footnote[")?;
+                    if let Some(body) = body {
+                        self.convert_trimmed(f, vec![rowan::NodeOrToken::Node(body)], spec)?;
+                    }
+                    f.write_str("];")?;
+                    return Ok(());
+                }
+
+                // hack for sectioning commands, e.g. `\section[Short]{Title}`
+                if matches!(self.mode, LaTeXMode::Text) {
+                    if let Some((level, numbered)) = heading_level(name) {
+                        return self.convert_heading(f, &cmd, name, level, numbered, spec);
+                    }
+                }
+
+                // escaped chars in text mode, e.g. `\%`
+                if matches!(self.mode, LaTeXMode::Text) {
+                    let escaped = match name {
+                        "%" | "&" | "{" | "}" => Some(name),
+                        "#" => Some("\\#"),
+                        "_" => Some("\\_"),
+                        "$" => Some("\\$"),
+                        _ => None,
+                    };
+                    if let Some(escaped) = escaped {
+                        f.write_str(escaped)?;
+                        return Ok(());
+                    }
+                }
+
+                // spacing commands, e.g. `\,`, where the negative `\!` has no
+                // typst symbol and becomes a negative horizontal space
+                if let Some((math_space, text_space)) = spacing(name) {
+                    match self.mode {
+                        LaTeXMode::Math => write!(f, "{math_space} ")?,
+                        LaTeXMode::Text => write!(f, "#h({text_space});")?,
+                    }
+                    return Ok(());
+                }
+
+                // paired bars, e.g. `\lvert a \rvert` is converted to
+                // `abs( a  )`, while an unpaired bar is kept as a symbol
+                if matches!(self.mode, LaTeXMode::Math) {
+                    let range = cmd.syntax().text_range();
+                    if self.bar_closers.last() == Some(&range) {
+                        self.bar_closers.pop();
+                        f.write_char(')')?;
+                        return Ok(());
+                    }
+                    if let Some((call, closer)) = find_bar_closer(cmd.syntax(), name) {
+                        self.bar_closers.push(closer);
+                        write!(f, "{}(", call)?;
+                        return Ok(());
+                    }
+                }
+
+                // sized delimiters, e.g. `\bigl(` is converted to
+                // `class(#"opening", lr(size: #120%, \())`, where the class
+                // is given by the suffix
+                if matches!(self.mode, LaTeXMode::Math) {
+                    if let Some((size, class)) = delimiter_size(name) {
+                        if let Some(class) = class {
+                            write!(f, "class(#{:?}, ", class)?;
+                        }
+                        write!(f, "lr(size: #{}, ", size)?;
+                        for arg in cmd.arguments() {
+                            self.convert(f, rowan::NodeOrToken::Node(arg), spec)?;
+                        }
+                        f.write_char(')')?;
+                        if class.is_some() {
+                            f.write_char(')')?;
+                        }
+                        return Ok(());
+                    }
+                }
+
+                let args = as_node(&elem)?
                     .children_with_tokens()
                     .filter(|node| node.kind() != ClauseCommandName)
                     .collect::<Vec<_>>();
                 // println!("name: {:?} with args {:?}", name, args);
 
                 // get cmd_shape and arg_shape from spec
-                let cmd_shape = spec
-                    .get_cmd(name)
-                    .ok_or_else(|| format!("unknown command: \\{}", name))?;
+                let Some(cmd_shape) = spec.get_cmd(name) else {
+                    return self.unknown_command(f, name, &elem, " ");
+                };
+                if let Some(template) = &cmd_shape.template {
+                    return self.expand_template(f, &cmd, template, spec);
+                }
+                // hack for ellipses
+                if matches!(self.mode, LaTeXMode::Text) && matches!(name, "dots" | "ldots") {
+                    f.write_char('…')?;
+                    return Ok(());
+                }
+                // `\dots` is centered before a binary operator or a relation as
+                // in amsmath, e.g. `a + \dots + b`, and is on the baseline
+                // otherwise, e.g. `a, \dots, b` or at the end of the input
+                if name == "dots" && cmd_shape.alias.as_deref() == Some("dots.h") {
+                    let next = as_node(&elem)?
+                        .siblings_with_tokens(rowan::Direction::Next)
+                        .skip(1)
+                        .find(|elem| !is_trivia(elem));
+                    let centered = next.as_ref().map_or(false, is_bin_or_rel);
+                    f.write_str(if centered { "dots.h.c " } else { "dots.h " })?;
+                    return Ok(());
+                }
+
                 let arg_shape = &cmd_shape.args;
                 // typst alias name
                 let mut typst_name = cmd_shape.alias.as_deref().unwrap_or(name);
 
-                // hack for textbf and textit
-                if name == "textbf" && matches!(self.mode, LaTeXMode::Text) {
-                    typst_name = "#strong";
-                } else if name == "textit" && matches!(self.mode, LaTeXMode::Text) {
-                    typst_name = "#emph";
+                // hack for extensible arrows, where the label below, e.g. `f` in
+                // `\xrightarrow[f]{g}`, is attached as a limit
+                if matches!(self.mode, LaTeXMode::Math) && is_xarrow(name) {
+                    let (below, above): (Vec<_>, Vec<_>) = cmd.arguments().partition(|arg| {
+                        arg.first_child()
+                            .map_or(false, |group| group.kind() == ItemBracket)
+                    });
+                    let below = below
+                        .first()
+                        .and_then(|arg| arg.first_child())
+                        .filter(|below| {
+                            below.children_with_tokens().any(|elem| {
+                                !matches!(elem.kind(), TokenLBracket | TokenRBracket)
+                                    && !is_trivia(&elem)
+                            })
+                        });
+                    if below.is_some() {
+                        f.write_str("limits(")?;
+                    }
+                    write!(f, "{}(", typst_name)?;
+                    if above.is_empty() {
+                        f.write_str("zws")?;
+                    }
+                    for arg in above {
+                        self.convert(f, rowan::NodeOrToken::Node(arg), spec)?;
+                    }
+                    f.write_char(')')?;
+                    if let Some(below) = below {
+                        f.write_str(")_(")?;
+                        for elem in below.children_with_tokens() {
+                            if !matches!(elem.kind(), TokenLBracket | TokenRBracket) {
+                                self.convert(f, elem, spec)?;
+                            }
+                        }
+                        f.write_char(')')?;
+                    }
+                    return Ok(());
+                }
+
+                // hack for font commands in text mode, e.g. `\textbf{x}` is
+                // converted to `#strong[x]`, which is also safe inside a word
+                // unlike the `*x*` markup, and the literal `*` and `_` in the
+                // content are escaped as usual
+                if matches!(self.mode, LaTeXMode::Text) {
+                    if name == "textbf" {
+                        typst_name = "#strong";
+                    } else if name == "textit" {
+                        typst_name = "#emph";
+                    }
+                    if name == "texttt" {
+                        let code = cmd.arguments().map(|arg| arg.text().to_string());
+                        let code = unescape_text(group_content(&code.collect::<String>()));
+                        write!(f, "#raw({:?});", code)?;
+                        return Ok(());
+                    }
                 }
 
                 if typst_name.starts_with("text") {
@@ -458,9 +1126,11 @@ impl Converter {
                     f.write_char('[')?;
 
                     let prev_mode = self.enter_mode(LaTeXMode::Text);
+                    let prev_in_text_arg = std::mem::replace(&mut self.in_text_arg, true);
                     for arg in args {
                         self.convert(f, arg, spec)?;
                     }
+                    self.in_text_arg = prev_in_text_arg;
                     self.exit_mode(prev_mode);
 
                     f.write_str("];")?;
@@ -484,11 +1154,9 @@ impl Converter {
                     // there is only one arg in greedy
                     let args = args
                         .first()
-                        .unwrap()
-                        .as_node()
-                        .unwrap()
-                        .children_with_tokens()
-                        .collect::<Vec<_>>();
+                        .and_then(|arg| arg.as_node())
+                        .map(|arg| arg.children_with_tokens().collect::<Vec<_>>())
+                        .unwrap_or_default();
                     let mut cnt = 0;
                     let args_len = args.len();
                     for arg in args {
@@ -509,11 +1177,29 @@ impl Converter {
                     for arg in args {
                         cnt += 1;
                         let kind = arg.kind();
+                        // pad an empty operand of an infix command, e.g. `{\over 2}`
+                        if matches!(arg_shape, ArgShape::InfixGreedy)
+                            && arg.as_node().map_or(false, |arg| {
+                                arg.children_with_tokens().all(|elem| is_trivia(&elem))
+                            })
+                        {
+                            f.write_str("zws ")?;
+                        }
                         self.convert(f, arg, spec)?;
                         if matches!(kind, ClauseArgument) && cnt != args_len {
                             f.write_char(',')?;
                         }
                     }
+                    // pad missing arguments, e.g. `\frac{1}`, as the typst
+                    // handler may require all of them
+                    if let ArgShape::Right(ArgPattern::FixedLenTerm(len)) = arg_shape {
+                        for index in cmd.arguments().count()..usize::from(*len) {
+                            if index > 0 {
+                                f.write_char(',')?;
+                            }
+                            f.write_str("zws")?;
+                        }
+                    }
 
                     f.write_char(')')?;
                 } else {
@@ -534,35 +1220,65 @@ impl Converter {
                 }
             }
             ItemEnv => {
-                let env = EnvItem::cast(elem.as_node().unwrap().clone()).unwrap();
+                let env = EnvItem::cast(as_node(&elem)?.clone()).ok_or("invalid environment")?;
                 let name = env
                     .name_tok()
-                    .expect("environment name must be non-empty")
+                    .ok_or("environment name must be non-empty")?
                     .text()
                     .to_string();
                 let name = name.trim();
                 let args = env.arguments();
                 // todo: handle options
 
+                if is_verbatim_env(name) {
+                    return self.convert_verbatim(f, &env, name);
+                }
+
+                if matches!(name, "figure" | "figure*" | "table" | "table*") {
+                    return self.convert_figure(f, &env, spec);
+                }
+
+                if matches!(name, "equation" | "equation*") {
+                    return self.convert_equation(f, &env, name == "equation", spec);
+                }
+
+                let block = match name {
+                    "quote" | "quotation" | "verse" => Some("quote(block: true)"),
+                    "center" => Some("align(center)"),
+                    "flushleft" => Some("align(left)"),
+                    "flushright" => Some("align(right)"),
+                    _ => None,
+                };
+                if let Some(block) = block {
+                    return self.convert_block(f, &env, block, spec);
+                }
+
                 let env_shape = spec
                     .get_env(name)
                     .ok_or_else(|| format!("unknown environment: \\{}", name))?;
                 let typst_name = env_shape.alias.as_deref().unwrap_or(name);
 
+                if matches!(env_shape.ctx_feature, ContextFeature::IsTheorem) {
+                    return self.convert_theorem(f, &env, name, typst_name, spec);
+                }
+
                 let env_kind = match env_shape.ctx_feature {
-                    ContextFeature::None => LaTeXEnv::None,
+                    ContextFeature::None | ContextFeature::IsTheorem => LaTeXEnv::None,
                     ContextFeature::IsMath => LaTeXEnv::Math,
                     ContextFeature::IsMatrix => LaTeXEnv::Matrix,
                     ContextFeature::IsCases => LaTeXEnv::Cases,
                     ContextFeature::IsItemize => LaTeXEnv::Itemize,
                     ContextFeature::IsEnumerate => LaTeXEnv::Enumerate,
+                    ContextFeature::IsDescription => LaTeXEnv::Description,
+                    ContextFeature::IsTable => LaTeXEnv::Table,
                 };
 
                 // hack for itemize and enumerate
-                if matches!(env_kind, LaTeXEnv::Itemize | LaTeXEnv::Enumerate) {
+                if env_kind.is_list() {
                     let prev = self.enter_env(env_kind);
+                    let labeled_item = std::mem::take(&mut self.labeled_item);
 
-                    for child in elem.as_node().unwrap().children_with_tokens() {
+                    for child in as_node(&elem)?.children_with_tokens() {
                         if matches!(child.kind(), ItemBegin | ItemEnd) {
                             continue;
                         }
@@ -570,17 +1286,28 @@ impl Converter {
                         self.convert(f, child, spec)?;
                     }
 
+                    if std::mem::replace(&mut self.labeled_item, labeled_item) {
+                        f.write_char(']')?;
+                    }
                     self.exit_env(prev);
+                    // the text after a list is not a part of its last item,
+                    // while a nested list is kept tight
+                    if !prev.is_list() {
+                        f.write_char('\n')?;
+                        self.skip_next_space = true;
+                    }
 
                     return Ok(());
                 }
 
+                if matches!(env_kind, LaTeXEnv::Table) {
+                    return self.convert_table(f, &env, spec);
+                }
+
                 // text mode to math mode with $ ... $
                 let is_need_dollar = matches!(self.mode, LaTeXMode::Text)
-                    && !matches!(
-                        env_kind,
-                        LaTeXEnv::None | LaTeXEnv::Itemize | LaTeXEnv::Enumerate
-                    );
+                    && !matches!(env_kind, LaTeXEnv::None)
+                    && !env_kind.is_list();
                 let prev = self.enter_env(env_kind);
                 let mut prev_mode = LaTeXMode::Text;
                 if is_need_dollar {
@@ -591,19 +1318,40 @@ impl Converter {
                 // environment name
                 f.write_str(typst_name)?;
                 f.write_char('(')?;
-                // named args
-                for (index, arg) in args.enumerate() {
-                    f.write_str(format!("arg{}: ", index).as_str())?;
-                    self.convert(f, rowan::NodeOrToken::Node(arg), spec)?;
-                    f.write_char(',')?;
-                }
 
-                for child in elem.as_node().unwrap().children_with_tokens() {
-                    if matches!(child.kind(), ItemBegin | ItemEnd) {
-                        continue;
+                if matches!(name, "array" | "subarray") {
+                    // hack for array, whose column spec drives the cells
+                    self.convert_array(f, &env, spec)?;
+                } else {
+                    // named args
+                    for (index, arg) in args.enumerate() {
+                        f.write_str(format!("arg{}: ", index).as_str())?;
+                        self.convert(f, rowan::NodeOrToken::Node(arg), spec)?;
+                        f.write_char(',')?;
                     }
 
-                    self.convert(f, child, spec)?;
+                    let children = as_node(&elem)?
+                        .children_with_tokens()
+                        .filter(|child| !matches!(child.kind(), ItemBegin | ItemEnd))
+                        .collect::<Vec<_>>();
+                    // a trailing `\\` doesn't start a new row, e.g. in
+                    // `\begin{align} a &= b \\ \end{align}`
+                    let trailing_newline = children
+                        .iter()
+                        .rposition(|child| {
+                            !matches!(
+                                child.kind(),
+                                TokenWhiteSpace | TokenLineBreak | TokenComment
+                            )
+                        })
+                        .filter(|&index| children[index].kind() == ItemNewLine);
+                    for (index, child) in children.into_iter().enumerate() {
+                        if Some(index) == trailing_newline {
+                            continue;
+                        }
+
+                        self.convert(f, child, spec)?;
+                    }
                 }
 
                 f.write_char(')')?;
@@ -616,10 +1364,7 @@ impl Converter {
                 }
 
                 // handle label
-                if matches!(
-                    self.env,
-                    LaTeXEnv::None | LaTeXEnv::Itemize | LaTeXEnv::Enumerate
-                ) {
+                if matches!(self.env, LaTeXEnv::None) || self.env.is_list() {
                     if let Some(label) = self.label.take() {
                         f.write_char('<')?;
                         f.write_str(label.as_str())?;
@@ -629,424 +1374,4632 @@ impl Converter {
                 }
             }
             ItemTypstCode => {
-                write!(f, "{}", elem.as_node().unwrap().text())?;
+                write!(f, "{}", as_node(&elem)?.text())?;
+            }
+            // \verb|...|, whose text is escaped as a typst string
+            TokenVerbatim => {
+                write!(f, "#raw({:?});", verb_body(as_token(&elem)?.text()))?;
+            }
+            // the items nested too deeply to be parsed are kept as raw text,
+            // where the parser reports them
+            ItemTooDeep => {
+                write!(f, "#raw({:?});", as_node(&elem)?.text().to_string())?;
             }
         };
 
         Ok(())
     }
-}
-
-struct TypstRepr {
-    elem: LatexSyntaxElem,
-    mode: LaTeXMode,
-    spec: CommandSpec,
-    error: Rc<RefCell<String>>,
-}
 
-impl fmt::Display for TypstRepr {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut ctx = Converter::new(self.mode);
-        if let Err(e) = ctx.convert(f, self.elem.clone(), &self.spec) {
-            self.error.borrow_mut().push_str(&e.to_string());
-            return Err(fmt::Error);
+    /// Convert a command by expanding its template
+    fn expand_template(
+        &mut self,
+        f: &mut Output,
+        cmd: &CmdItem,
+        template: &Template,
+        spec: &CommandSpec,
+    ) -> Result<(), ConvertError> {
+        use LatexSyntaxKind::*;
+
+        let (brackets, terms): (Vec<_>, Vec<_>) = cmd.arguments().partition(|arg| {
+            arg.first_child()
+                .map_or(false, |group| group.kind() == ItemBracket)
+        });
+
+        // whether the next slot is inside a string of the template, where a
+        // raw argument is escaped
+        let mut in_string = false;
+        for piece in template.pieces() {
+            let slot = match piece {
+                TemplatePiece::Text(text) => {
+                    let mut escaped = false;
+                    for c in text.chars() {
+                        match c {
+                            '"' if !escaped => in_string = !in_string,
+                            '\\' if in_string => {
+                                escaped = !escaped;
+                                continue;
+                            }
+                            _ => {}
+                        }
+                        escaped = false;
+                    }
+                    f.write_str(text)?;
+                    continue;
+                }
+                TemplatePiece::Slot(slot) => slot,
+            };
+            let args = if slot.bracket { &brackets } else { &terms };
+            let Some(arg) = args.get(slot.index) else {
+                f.write_str(slot.default.as_deref().unwrap_or_default())?;
+                continue;
+            };
+
+            // the content of the argument without the enclosing braces
+            let group = arg
+                .first_child()
+                .filter(|group| matches!(group.kind(), ItemCurly | ItemBracket));
+            let content = match &group {
+                Some(group) => group
+                    .children_with_tokens()
+                    .filter(|elem| {
+                        !matches!(
+                            elem.kind(),
+                            TokenLBrace | TokenRBrace | TokenLBracket | TokenRBracket
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+                None => arg.children_with_tokens().collect(),
+            };
+
+            if slot.raw {
+                let mut text = String::new();
+                for elem in content {
+                    match elem {
+                        LatexSyntaxElem::Node(node) => write!(text, "{}", node.text())?,
+                        LatexSyntaxElem::Token(token) => text.push_str(token.text()),
+                    }
+                }
+                if in_string {
+                    // escaped like a typst string, without the quotes
+                    let quoted = format!("{text:?}");
+                    f.write_str(&quoted[1..quoted.len() - 1])?;
+                } else {
+                    f.write_str(&text)?;
+                }
+            } else if !slot.bracket {
+                // keeps the braces so that the group is converted as usual
+                self.convert(f, rowan::NodeOrToken::Node(arg.clone()), spec)?;
+            } else {
+                for elem in content {
+                    self.convert(f, elem, spec)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Convert `\includegraphics` to an image, e.g. `#image("a.png", width:
+    /// 5cm);` for `\includegraphics[width=5cm]{a.png}`
+    ///
+    /// The options `width`, `height`, `scale` and `keepaspectratio` are
+    /// converted, and the other options are ignored with a warning.
+    fn convert_includegraphics(
+        &mut self,
+        f: &mut Output,
+        cmd: &CmdItem,
+    ) -> Result<(), ConvertError> {
+        let (options, paths): (Vec<_>, Vec<_>) = cmd.arguments().partition(|arg| {
+            arg.first_child()
+                .map_or(false, |group| group.kind() == LatexSyntaxKind::ItemBracket)
+        });
+        let path = paths.first().map(|arg| arg.text().to_string());
+        let path = group_content(path.as_deref().unwrap_or_default()).trim();
+        let elem = rowan::NodeOrToken::Node(cmd.syntax().clone());
+
+        let mut named = String::new();
+        let mut scale = None;
+        let options = options
+            .first()
+            .map(|arg| arg.text().to_string())
+            .unwrap_or_default();
+        let options = options.trim();
+        let options = options
+            .strip_prefix('[')
+            .and_then(|options| options.strip_suffix(']'))
+            .unwrap_or(options);
+        for option in options.split(',') {
+            let (key, value) = option.split_once('=').unwrap_or((option, ""));
+            let (key, value) = (key.trim(), value.trim());
+            match key {
+                "" => {}
+                "width" | "height" => match convert_length(value) {
+                    length if length == "auto" => self.warn(
+                        format!("invalid length `{value}` of `\\includegraphics` is ignored"),
+                        &elem,
+                    ),
+                    length => write!(named, ", {key}: {length}")?,
+                },
+                "scale" => match value.parse::<f64>() {
+                    Ok(value) => scale = Some(value),
+                    Err(_) => self.warn(
+                        format!("invalid scale `{value}` of `\\includegraphics` is ignored"),
+                        &elem,
+                    ),
+                },
+                "keepaspectratio" => named.push_str(", fit: \"contain\""),
+                _ => self.warn(
+                    format!("unknown option `{key}` of `\\includegraphics` is ignored"),
+                    &elem,
+                ),
+            }
+        }
+
+        match scale {
+            Some(scale) => write!(
+                f,
+                "#scale({}, reflow: true, image({:?}{}));",
+                format_percent(scale),
+                path,
+                named
+            )?,
+            None => write!(f, "#image({:?}{});", path, named)?,
+        }
+        Ok(())
+    }
+
+    /// Convert a sectioning command to a heading, e.g. `= Title` for
+    /// `\section{Title}` at the start of a line, or a call of the heading
+    /// given by the specification, e.g. `#heading(level: 1)[Title]`
+    ///
+    /// The short title of the outline, e.g. `Short` in
+    /// `\section[Short]{Title}`, is ignored with a warning, as a typst
+    /// heading has no short title.
+    fn convert_heading(
+        &mut self,
+        f: &mut Output,
+        cmd: &CmdItem,
+        name: &str,
+        level: usize,
+        numbered: bool,
+        spec: &CommandSpec,
+    ) -> Result<(), ConvertError> {
+        let (short, title): (Vec<_>, Vec<_>) = cmd.arguments().partition(|arg| {
+            arg.first_child()
+                .is_some_and(|group| group.kind() == LatexSyntaxKind::ItemBracket)
+        });
+        if let Some(short) = short.into_iter().next() {
+            let message = "the short title of a heading is ignored";
+            self.warn(message, &rowan::NodeOrToken::Node(short));
+        }
+        let title = title.into_iter().map(rowan::NodeOrToken::Node).collect();
+
+        let call = match spec.get_cmd(name).and_then(|cmd| cmd.alias.as_deref()) {
+            Some(alias) => alias.to_owned(),
+            None if numbered => format!("#heading(level: {level})"),
+            None => format!("#heading(level: {level}, numbering: none)"),
+        };
+        // a heading markup starts a line and ends at the end of the line, so
+        // that the call is used elsewhere, e.g. in a table cell
+        if f.line_start && call == format!("#heading(level: {level})") {
+            for _ in 0..level {
+                f.write_char('=')?;
+            }
+            f.write_char(' ')?;
+            self.convert_trimmed(f, title, spec)?;
+            f.write_char('\n')?;
+            self.skip_next_space = true;
+        } else {
+            write!(f, "{call}[")?;
+            self.convert_trimmed(f, title, spec)?;
+            f.write_char(']')?;
+        }
+        Ok(())
+    }
+
+    /// Convert a color command to a typst text with a fill, e.g.
+    /// `#text(fill: rgb(255, 0, 0))[x];` for `\textcolor{red}{x}`, or to a
+    /// box with a fill for `\colorbox`
+    ///
+    /// `\color` colors the rest of its group. A color which is neither a
+    /// known name nor in a known model, e.g. `[HTML]{FF0000}`, is ignored.
+    fn convert_color(
+        &mut self,
+        f: &mut Output,
+        cmd: &CmdItem,
+        spec: &CommandSpec,
+    ) -> Result<(), ConvertError> {
+        use LatexSyntaxKind::*;
+
+        let text = |elem: &LatexSyntaxElem| match elem {
+            LatexSyntaxElem::Node(node) => node.text().to_string(),
+            LatexSyntaxElem::Token(token) => token.text().to_owned(),
+        };
+        let mut model = None;
+        let mut color = None;
+        let mut body = vec![];
+        let name = cmd.name_tok().map(|name| name.text().to_owned());
+        let call = if name.as_deref() == Some("\\colorbox") {
+            "box"
+        } else {
+            "text"
+        };
+        if name.as_deref() == Some("\\color") {
+            // the greedy argument starts with the color
+            let mut children = cmd
+                .arguments()
+                .flat_map(|arg| arg.children_with_tokens())
+                .skip_while(is_trivia);
+            color = children.next().map(|color| text(&color));
+            body.extend(children);
+        } else {
+            for arg in cmd.arguments() {
+                let arg = rowan::NodeOrToken::Node(arg);
+                let is_model = arg
+                    .as_node()
+                    .and_then(|arg| arg.first_child())
+                    .map_or(false, |group| group.kind() == ItemBracket);
+                if is_model {
+                    let model_text = text(&arg);
+                    let model_text = model_text.trim();
+                    let model_text = model_text.strip_prefix('[').unwrap_or(model_text);
+                    model = Some(
+                        model_text
+                            .strip_suffix(']')
+                            .unwrap_or(model_text)
+                            .to_owned(),
+                    );
+                } else if color.is_none() {
+                    color = Some(text(&arg));
+                } else {
+                    body.push(arg);
+                }
+            }
+        }
+
+        let color = color.as_deref().map(group_content).unwrap_or_default();
+        let Some(fill) = typst_color(model.as_deref(), color) else {
+            let elem = rowan::NodeOrToken::Node(cmd.syntax().clone());
+            self.warn(format!("unknown color `{}` is ignored", color), &elem);
+            for elem in body {
+                self.convert(f, elem, spec)?;
+            }
+            return Ok(());
+        };
+        if matches!(self.mode, LaTeXMode::Math) {
+            write!(f, "{}(fill: #{},", call, fill)?;
+            for elem in body {
+                self.convert(f, elem, spec)?;
+            }
+            f.write_char(')')?;
+        } else {
+            write!(f, "#{}(fill: {})[", call, fill)?;
+            self.convert_trimmed(f, body, spec)?;
+            f.write_str("];")?;
+        }
+        Ok(())
+    }
+
+    /// Convert the generalized fraction `\genfrac{left}{right}{thickness}{style}{num}{den}`
+    ///
+    /// A zero thickness stacks the numerator over the denominator without a
+    /// rule, i.e. `binom(n ,k )` if the delimiters are parentheses or `atop`
+    /// if there are none, and any other thickness is a fraction, since typst
+    /// has no fraction with a custom rule. Other delimiters wrap the stack in
+    /// `lr`, and an empty delimiter or `.` is no delimiter. A style `0` to `3`
+    /// is the display, text, script and scriptscript style respectively.
+    fn convert_genfrac(
+        &mut self,
+        f: &mut Output,
+        cmd: &CmdItem,
+        spec: &CommandSpec,
+    ) -> Result<(), ConvertError> {
+        let mut args = cmd.arguments();
+        let mut text_arg = || {
+            let text = args.next().map(|arg| arg.text().to_string());
+            group_content(text.as_deref().unwrap_or_default()).to_owned()
+        };
+        let left = text_arg();
+        let right = text_arg();
+        let thickness = text_arg();
+        let style = text_arg();
+        let body = args.collect::<Vec<_>>();
+
+        let ruled = !is_zero_length(&thickness);
+        let (left, right) = match (genfrac_delimiter(&left), genfrac_delimiter(&right)) {
+            (Some(left), Some(right)) => (left, right),
+            _ => {
+                let elem = rowan::NodeOrToken::Node(cmd.syntax().clone());
+                self.warn(
+                    format!("unknown delimiter `{left}` or `{right}` of `\\genfrac` is ignored"),
+                    &elem,
+                );
+                (None, None)
+            }
+        };
+        let (callee, left, right) = match (ruled, left, right) {
+            (false, Some("paren.l"), Some("paren.r")) => ("binom", None, None),
+            (false, left, right) => ("atop", left, right),
+            (true, left, right) => ("frac", left, right),
+        };
+        let style = match style.trim() {
+            "0" => Some("display"),
+            "1" => Some("inline"),
+            "2" => Some("script"),
+            "3" => Some("sscript"),
+            _ => None,
+        };
+
+        if let Some(style) = style {
+            write!(f, "{style}(")?;
+        }
+        let delimited = left.is_some() || right.is_some();
+        if delimited {
+            write!(f, "lr({} ", left.unwrap_or_default())?;
+        }
+        write!(f, "{callee}(")?;
+        for index in 0..2 {
+            if index > 0 {
+                f.write_char(',')?;
+            }
+            match body.get(index) {
+                Some(arg) => self.convert(f, rowan::NodeOrToken::Node(arg.clone()), spec)?,
+                None => f.write_str("zws")?,
+            }
+        }
+        f.write_char(')')?;
+        if delimited {
+            write!(f, " {})", right.unwrap_or_default())?;
+        }
+        if style.is_some() {
+            f.write_char(')')?;
+        }
+        Ok(())
+    }
+
+    /// Convert `\braket{a|b}` of the physics package to `braket(a ,b )`,
+    /// where the bra and the ket are paired by the typst handler
+    ///
+    /// The argument is split at its first bar outside of groups, and
+    /// `\braket{a}` is passed as is.
+    fn convert_braket(
+        &mut self,
+        f: &mut Output,
+        cmd: &CmdItem,
+        name: &str,
+        spec: &CommandSpec,
+    ) -> Result<(), ConvertError> {
+        use LatexSyntaxKind::*;
+
+        let arg = cmd.arguments().next();
+        // the items of the argument, where the words of a text are split
+        // out to find the bar
+        let group = arg
+            .iter()
+            .flat_map(|arg| arg.children())
+            .find(|node| node.kind() == ItemCurly);
+        let mut items = group
+            .or(arg)
+            .iter()
+            .flat_map(|node| node.children_with_tokens())
+            .flat_map(|child| match child {
+                rowan::NodeOrToken::Node(node) if node.kind() == ItemText => {
+                    node.children_with_tokens().collect()
+                }
+                child => vec![child],
+            })
+            .filter(|item| !matches!(item.kind(), TokenLBrace | TokenRBrace))
+            .collect::<Vec<_>>();
+        let bar = items.iter().position(|item| {
+            item.as_token()
+                .is_some_and(|token| token.kind() == TokenWord && token.text().contains('|'))
+        });
+
+        // the word around the bar is split into new words, which are
+        // converted as usual
+        let word = |text: &str| {
+            let token = rowan::GreenToken::new(TokenWord.into(), text);
+            let text = rowan::GreenNode::new(ItemText.into(), [rowan::NodeOrToken::Token(token)]);
+            LatexSyntaxElem::Node(SyntaxNode::new_root(text))
+        };
+        let halves = match bar {
+            Some(index) => {
+                let mut ket = items.split_off(index);
+                let text = as_token(&ket.remove(0))?.text().to_owned();
+                let (bra_end, ket_start) = text.split_once('|').unwrap_or((&text, ""));
+                if !bra_end.is_empty() {
+                    items.push(word(bra_end));
+                }
+                if !ket_start.is_empty() {
+                    ket.insert(0, word(ket_start));
+                }
+                vec![items, ket]
+            }
+            None => vec![items],
+        };
+
+        let typst_name = spec
+            .get_cmd(name)
+            .and_then(|cmd| cmd.alias.as_deref())
+            .unwrap_or(name);
+        write!(f, "{typst_name}(")?;
+        let prev = self.enter_env(LaTeXEnv::MathCurlyGroup);
+        for (index, half) in halves.into_iter().enumerate() {
+            if index > 0 {
+                f.write_char(',')?;
+            }
+            // an empty half is padded as an empty group
+            if half.iter().all(is_trivia) {
+                f.write_str("zws ")?;
+            }
+            for elem in half {
+                self.convert(f, elem, spec)?;
+            }
+        }
+        self.exit_env(prev);
+        f.write_char(')')?;
+        Ok(())
+    }
+
+    /// Convert a brace with a label, e.g. `overbrace(x ,n )` for
+    /// `\overbrace{x}^{n}`, where the label is passed to the typst function
+    /// instead of being attached
+    ///
+    /// Returns `false` if the attachment is not the label of a brace.
+    fn convert_brace_label(
+        &mut self,
+        f: &mut Output,
+        attach: &SyntaxNode,
+        spec: &CommandSpec,
+    ) -> Result<bool, ConvertError> {
+        use LatexSyntaxKind::*;
+
+        let mut children = attach
+            .children_with_tokens()
+            .filter(|elem| !is_trivia(elem));
+        let (Some(base), Some(script)) = (children.next(), children.next()) else {
+            return Ok(false);
+        };
+        let mut base = base
+            .as_node()
+            .into_iter()
+            .flat_map(|base| base.children_with_tokens())
+            .filter(|elem| !is_trivia(elem));
+        let (Some(cmd), None) = (base.next(), base.next()) else {
+            return Ok(false);
+        };
+        let Some(cmd) = cmd.into_node().and_then(CmdItem::cast) else {
+            return Ok(false);
+        };
+        let name = cmd.name_tok().map(|name| name.text().to_owned());
+        let name = match (name.as_deref(), script.kind()) {
+            (Some("\\overbrace"), TokenCaret) => "overbrace",
+            (Some("\\overbracket"), TokenCaret) => "overbracket",
+            (Some("\\underbrace"), TokenUnderscore) => "underbrace",
+            (Some("\\underbracket"), TokenUnderscore) => "underbracket",
+            _ => return Ok(false),
+        };
+
+        write!(f, "{}(", name)?;
+        for arg in cmd.syntax().children_with_tokens() {
+            if arg.kind() != ClauseCommandName {
+                self.convert(f, arg, spec)?;
+            }
+        }
+        f.write_char(',')?;
+        for label in children {
+            self.convert(f, label, spec)?;
+        }
+        f.write_char(')')?;
+        Ok(true)
+    }
+
+    /// Convert a number or a quantity of siunitx, e.g. `9.8 thin "m/s²" ` for
+    /// `\SI{9.8}{\meter\per\second\squared}`, where the options and the
+    /// pre-unit are ignored
+    ///
+    /// The number and the unit are converted from the source of the
+    /// arguments, see [`si_number`] and [`si_unit`], and they are put in
+    /// inline math in text mode.
+    fn convert_siunitx(
+        &mut self,
+        f: &mut Output,
+        cmd: &CmdItem,
+        name: &str,
+    ) -> Result<(), ConvertError> {
+        let args = cmd
+            .arguments()
+            .filter(|arg| {
+                arg.first_child()
+                    .map_or(true, |group| group.kind() != LatexSyntaxKind::ItemBracket)
+            })
+            .map(|arg| group_content(&arg.text().to_string()).to_owned())
+            .collect::<Vec<_>>();
+        let arg = |index: usize| args.get(index).map_or("", String::as_str);
+        let (number, unit) = match name {
+            "num" => (Some(arg(0)), None),
+            "si" => (None, Some(arg(0))),
+            _ => (Some(arg(0)), Some(arg(1))),
+        };
+
+        let elem = rowan::NodeOrToken::Node(cmd.syntax().clone());
+        let in_text = matches!(self.mode, LaTeXMode::Text);
+        if in_text {
+            f.write_str("#math.equation(block: false, $")?;
+        }
+        if let Some(number) = number {
+            match si_number(number) {
+                Some(number) => write!(f, "{number} ")?,
+                None => {
+                    write!(f, "{number:?} ")?;
+                    let message = format!("the number `{number}` of siunitx is kept as text");
+                    self.warn(message, &elem);
+                }
+            }
+        }
+        if let Some(unit) = unit {
+            let mut warnings = vec![];
+            let unit = si_unit(unit, &mut warnings);
+            if number.is_some() {
+                f.write_str("thin ")?;
+            }
+            write!(f, "{unit:?} ")?;
+            for message in warnings {
+                self.warn(message, &elem);
+            }
+        }
+        if in_text {
+            f.write_str("$);")?;
+        }
+        Ok(())
+    }
+
+    /// Convert a reference to a label or a citation, e.g. `@key[p.~5]` for
+    /// `\cite[p.~5]{key}`
+    ///
+    /// The postnote of a citation of many keys is put after the last one.
+    ///
+    /// A citation with two notes, e.g. `\cite[see][p.~5]{key}`, has a prenote
+    /// as well, which is put before the reference as typst has no place for
+    /// it.
+    fn convert_reference(
+        &mut self,
+        f: &mut Output,
+        cmd: &CmdItem,
+        spec: &CommandSpec,
+    ) -> Result<(), ConvertError> {
+        use LatexSyntaxKind::*;
+
+        let (mut notes, keys): (Vec<_>, Vec<_>) = cmd.arguments().partition(|arg| {
+            arg.first_child()
+                .map_or(false, |group| group.kind() == ItemBracket)
+        });
+        let key = keys.first().map(|arg| arg.text().to_string());
+        let key = group_content(key.as_deref().unwrap_or_default());
+        // a citation of many keys is split into references, e.g. `@a @b` for
+        // `\cite{a, b}`, where empty keys are skipped
+        let labels = if cmd.name_tok().map_or(false, |name| name.text() == "\\cite") {
+            key.split(',')
+                .filter(|key| !key.trim().is_empty())
+                .map(typst_label)
+                .collect::<Vec<_>>()
+        } else {
+            vec![typst_label(key)]
+        };
+        let postnote = notes.pop();
+        let prenote = notes.pop();
+        // the content of a note without the brackets, or nothing if it is empty
+        let content = |note: Option<SyntaxNode>| {
+            let group = note?.first_child()?;
+            let content = group
+                .children_with_tokens()
+                .filter(|elem| !matches!(elem.kind(), TokenLBracket | TokenRBracket))
+                .collect::<Vec<_>>();
+            (!content.iter().all(is_trivia)).then_some(content)
+        };
+
+        let prev_mode = self.enter_mode(LaTeXMode::Text);
+        if matches!(prev_mode, LaTeXMode::Math) {
+            for label in &labels {
+                write!(f, "#ref(<{}>);", label)?;
+            }
+        } else {
+            if let Some(prenote) = content(prenote) {
+                self.convert_trimmed(f, prenote, spec)?;
+                f.write_char(' ')?;
+            }
+            let refs = labels.iter().map(|label| format!("@{}", label));
+            f.write_str(&refs.collect::<Vec<_>>().join(" "))?;
+            if let Some(postnote) = content(postnote) {
+                f.write_char('[')?;
+                self.convert_trimmed(f, postnote, spec)?;
+                f.write_char(']')?;
+            }
+        }
+        self.exit_mode(prev_mode);
+        Ok(())
+    }
+
+    /// Convert a float environment to a typst figure, e.g. `#figure(image("x"),
+    /// caption: [A figure]) <fig:x>` for `\begin{figure} \includegraphics{x}
+    /// \caption{A figure} \label{fig:x} \end{figure}`
+    ///
+    /// The placement specifier, e.g. `[htbp]`, and `\centering` are ignored.
+    /// A body of a single image or table is passed as a call, and the other
+    /// bodies are passed as content.
+    fn convert_figure(
+        &mut self,
+        f: &mut Output,
+        env: &EnvItem,
+        spec: &CommandSpec,
+    ) -> Result<(), ConvertError> {
+        use LatexSyntaxKind::*;
+
+        let mut body = vec![];
+        let mut caption = None;
+        let mut label = None;
+        for child in env.syntax().children_with_tokens() {
+            let cmd = child.as_node().cloned().and_then(CmdItem::cast);
+            let cmd_name = cmd.as_ref().and_then(|cmd| cmd.name_tok());
+            match cmd_name.as_ref().map(|name| name.text()) {
+                _ if matches!(child.kind(), ItemBegin | ItemEnd) => {}
+                Some("\\caption") => caption = cmd.and_then(|cmd| cmd.arguments().last()),
+                Some("\\label") => {
+                    let key = cmd.and_then(|cmd| cmd.arguments().next());
+                    let key = key.map(|arg| arg.text().to_string());
+                    label = Some(typst_label(group_content(
+                        key.as_deref().unwrap_or_default(),
+                    )));
+                }
+                Some("\\centering") => {}
+                _ => body.push(child),
+            }
+        }
+
+        let prev_mode = self.enter_mode(LaTeXMode::Text);
+        let mut content = String::new();
+        let first_mapping = self.source_map.as_ref().map_or(0, Vec::len);
+        let mut out = Output {
+            inner: &mut content,
+            len: f.len,
+            line_start: f.line_start,
+        };
+        self.convert_trimmed(&mut out, body, spec)?;
+        // a single call, e.g. `#image("x");`, is passed without its markup
+        let call = content
+            .strip_prefix('#')
+            .and_then(|call| call.strip_suffix(';'))
+            .filter(|call| !call.contains(['#', '\n']));
+        let (prefix, content) = match call {
+            Some(call) => ("#figure(", call.to_owned()),
+            None => ("#figure([", format!("{content}]")),
+        };
+        // the mappings of the body are moved along with it
+        let shift = prefix.len() - usize::from(call.is_some());
+        let mappings = self.source_map.as_deref_mut().unwrap_or_default();
+        for mapping in &mut mappings[first_mapping..] {
+            mapping.typst = mapping.typst.start + shift..mapping.typst.end + shift;
+        }
+        f.write_str(prefix)?;
+        f.write_str(&content)?;
+        if let Some(caption) = caption.and_then(|arg| arg.first_child()) {
+            let caption = caption
+                .children_with_tokens()
+                .filter(|elem| !matches!(elem.kind(), TokenLBrace | TokenRBrace))
+                .collect();
+            f.write_str(", caption: [")?;
+            self.convert_trimmed(f, caption, spec)?;
+            f.write_char(']')?;
+        }
+        f.write_char(')')?;
+        self.exit_mode(prev_mode);
+
+        if let Some(label) = label {
+            write!(f, " <{label}>")?;
+        }
+        Ok(())
+    }
+
+    /// Convert an equation environment to a block equation, e.g.
+    /// `#math.equation(block: true, numbering: "(1)", $ E = m c ^(2 ) $)
+    /// <e>` for `\begin{equation}\label{e}E=mc^2\end{equation}`
+    ///
+    /// The argument of `\tag` overrides the number, e.g. `(*)` for `\tag{*}`.
+    /// An unnumbered `equation*` has no label, and only its body is converted
+    /// in math mode.
+    fn convert_equation(
+        &mut self,
+        f: &mut Output,
+        env: &EnvItem,
+        numbered: bool,
+        spec: &CommandSpec,
+    ) -> Result<(), ConvertError> {
+        use LatexSyntaxKind::*;
+
+        let mut body = vec![];
+        let mut tag = None;
+        let mut label = None;
+        for child in env.syntax().children_with_tokens() {
+            let cmd = child.as_node().cloned().and_then(CmdItem::cast);
+            let cmd_name = cmd.as_ref().and_then(|cmd| cmd.name_tok());
+            let arg = cmd.and_then(|cmd| cmd.arguments().next());
+            let arg = arg.map(|arg| arg.text().to_string());
+            let arg = arg.as_deref().map(|arg| group_content(arg).trim());
+            match cmd_name.as_ref().map(|name| name.text()) {
+                _ if matches!(child.kind(), ItemBegin | ItemEnd) => {}
+                Some("\\tag") => tag = arg.map(|tag| format!("({tag})")),
+                Some("\\label") => label = arg.map(typst_label),
+                _ => body.push(child),
+            }
+        }
+
+        let numbering = match tag {
+            Some(tag) => Some(format!("_ => {tag:?}")),
+            None => numbered.then(|| "\"(1)\"".to_owned()),
+        };
+        let in_text = matches!(self.mode, LaTeXMode::Text);
+        if in_text {
+            match &numbering {
+                Some(numbering) => {
+                    write!(f, "#math.equation(block: true, numbering: {numbering}, $ ")?
+                }
+                None => f.write_str("$ ")?,
+            }
+        }
+        let prev = self.enter_env(LaTeXEnv::Math);
+        let prev_mode = self.enter_mode(LaTeXMode::Math);
+        self.convert_trimmed(f, body, spec)?;
+        self.exit_mode(prev_mode);
+        self.exit_env(prev);
+        if in_text {
+            f.write_str(" $")?;
+            if numbering.is_some() {
+                f.write_char(')')?;
+                if let Some(label) = label {
+                    write!(f, " <{label}>")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Convert a theorem-like environment to a block with a bold label, e.g.
+    /// `#block[*Theorem (Euler).* ...]` for `\begin{theorem}[Euler] ...
+    /// \end{theorem}`, where the label is the alias of the environment
+    ///
+    /// A proof has an italic label instead, which is replaced by its optional
+    /// argument, and ends with a QED square.
+    fn convert_theorem(
+        &mut self,
+        f: &mut Output,
+        env: &EnvItem,
+        name: &str,
+        label: &str,
+        spec: &CommandSpec,
+    ) -> Result<(), ConvertError> {
+        use LatexSyntaxKind::*;
+
+        let note = env
+            .arguments()
+            .filter_map(|arg| arg.first_child())
+            .find(|group| group.kind() == ItemBracket)
+            .map(|group| {
+                group
+                    .children_with_tokens()
+                    .filter(|elem| !matches!(elem.kind(), TokenLBracket | TokenRBracket))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|note| !note.iter().all(is_trivia));
+        let body = env
+            .syntax()
+            .children_with_tokens()
+            .filter(|child| !matches!(child.kind(), ItemBegin | ItemEnd))
+            .collect();
+        let is_proof = name == "proof";
+        let emph = if is_proof { '_' } else { '*' };
+
+        let prev_mode = self.enter_mode(LaTeXMode::Text);
+        f.write_str("#block[")?;
+        f.write_char(emph)?;
+        match note {
+            Some(note) if is_proof => self.convert_trimmed(f, note, spec)?,
+            Some(note) => {
+                write!(f, "{label} (")?;
+                self.convert_trimmed(f, note, spec)?;
+                f.write_char(')')?;
+            }
+            None => f.write_str(label)?,
+        }
+        write!(f, ".{emph} ")?;
+        self.convert_trimmed(f, body, spec)?;
+        if is_proof {
+            f.write_str(" #h(1fr) $square$")?;
+        }
+        f.write_char(']')?;
+        self.exit_mode(prev_mode);
+        Ok(())
+    }
+
+    /// Convert an environment whose body is the content of a typst function
+    /// call, e.g. `#quote(block: true)[...]` for `\begin{quote} ... \end{quote}`
+    /// or `#align(center)[...]` for `center`
+    ///
+    /// The body is converted in text mode, where the lines of a verse ended by
+    /// `\\` are kept as line breaks, and its stanzas as paragraphs.
+    fn convert_block(
+        &mut self,
+        f: &mut Output,
+        env: &EnvItem,
+        callee: &str,
+        spec: &CommandSpec,
+    ) -> Result<(), ConvertError> {
+        use LatexSyntaxKind::*;
+
+        let body = env
+            .syntax()
+            .children_with_tokens()
+            .filter(|child| !matches!(child.kind(), ItemBegin | ItemEnd))
+            .collect();
+
+        let prev_mode = self.enter_mode(LaTeXMode::Text);
+        // the paragraphs of a block in a list are not items
+        let prev_env = self.enter_env(LaTeXEnv::None);
+        write!(f, "#{callee}[")?;
+        self.convert_trimmed(f, body, spec)?;
+        f.write_char(']')?;
+        self.exit_env(prev_env);
+        self.exit_mode(prev_mode);
+        Ok(())
+    }
+
+    /// Convert the body of a verbatim environment to a typst raw block, whose
+    /// language is given by the `language` option of `lstlisting`, e.g.
+    /// `\begin{lstlisting}[language=C]`
+    ///
+    /// Only `lstlisting` and `Verbatim` take options, and the body of the
+    /// other environments is kept as is, even if it starts with a `[`.
+    fn convert_verbatim(
+        &mut self,
+        f: &mut Output,
+        env: &EnvItem,
+        name: &str,
+    ) -> Result<(), ConvertError> {
+        let body = env
+            .syntax()
+            .children_with_tokens()
+            .filter_map(|elem| elem.into_token())
+            .find(|token| token.kind() == LatexSyntaxKind::TokenVerbatim);
+        let body = body.as_ref().map_or("", |token| token.text());
+
+        // the options of lstlisting are also lexed as a part of the body
+        let mut lang = None;
+        let mut code = body;
+        let options = body.trim_start_matches([' ', '\t']).strip_prefix('[');
+        if let Some(rest) = options.filter(|_| matches!(name, "lstlisting" | "Verbatim")) {
+            if let Some((options, rest)) = rest.split_once(']') {
+                lang = options.split(',').find_map(|option| {
+                    let (key, value) = option.split_once('=')?;
+                    (key.trim() == "language").then(|| value.trim().to_lowercase())
+                });
+                code = rest;
+            }
+        }
+        // the line breaks around the body are not a part of the code
+        let code = code
+            .strip_prefix("\r\n")
+            .or_else(|| code.strip_prefix('\n'))
+            .unwrap_or(code)
+            .trim_end();
+
+        match self.mode {
+            LaTeXMode::Text => {
+                // the fence is longer than any run of backticks in the code
+                let mut longest = 0;
+                let mut run = 0;
+                for c in code.chars() {
+                    run = if c == '`' { run + 1 } else { 0 };
+                    longest = longest.max(run);
+                }
+                let fence = "`".repeat((longest + 1).max(3));
+                let lang = lang.as_deref().unwrap_or_default();
+                write!(f, "{fence}{lang}\n{code}\n{fence}")?;
+            }
+            LaTeXMode::Math => {
+                f.write_str("#raw(block: true, ")?;
+                if let Some(lang) = &lang {
+                    write!(f, "lang: {:?}, ", lang)?;
+                }
+                write!(f, "{:?});", code)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Convert the column spec and the body of an `array` environment to the
+    /// arguments of `mitexarray`, e.g. `align: #(left, right,), vlines: #(1,),`
+    /// followed by the cells
+    ///
+    /// The number of columns is given by the column spec, and a row with
+    /// fewer cells is padded with empty cells.
+    fn convert_array(
+        &mut self,
+        f: &mut Output,
+        env: &EnvItem,
+        spec: &CommandSpec,
+    ) -> Result<(), ConvertError> {
+        let columns = env
+            .arguments()
+            .next()
+            .map(|arg| TableColumns::parse(group_content(&arg.text().to_string())))
+            .unwrap_or_default();
+        if !columns.align.is_empty() {
+            write!(f, "align: #({},), ", columns.align.join(", "))?;
+        }
+        if !columns.vlines.is_empty() {
+            let vlines = columns.vlines.iter().map(ToString::to_string);
+            write!(f, "vlines: #({},), ", vlines.collect::<Vec<_>>().join(", "))?;
+        }
+
+        // the rules are ignored in math, e.g. `\hline`
+        let mut rows = split_table_rows(env)
+            .into_iter()
+            .map(|row| {
+                let cells = row.into_iter().filter_map(|cell| match cell {
+                    TableCell::Cell(elems) => Some(elems),
+                    TableCell::HLine => None,
+                });
+                cells.collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        // a trailing `\\` doesn't start a new row, and an empty body has no rows
+        if rows.last().map_or(false, |row| is_empty_row(row)) {
+            rows.pop();
+        }
+
+        let columns_len = rows
+            .iter()
+            .map(Vec::len)
+            .chain([columns.align.len()])
+            .max()
+            .unwrap_or_default();
+        for (index, row) in rows.into_iter().enumerate() {
+            if index > 0 {
+                f.write_str("zws ;")?;
+            }
+            let cells = row.into_iter().chain(std::iter::repeat_with(Vec::new));
+            for (index, cell) in cells.take(columns_len).enumerate() {
+                if index > 0 {
+                    f.write_str("zws ,")?;
+                }
+                // an empty cell is filled, or it is dropped as a trailing comma
+                if cell.iter().all(is_trivia) {
+                    f.write_str("zws ")?;
+                    continue;
+                }
+                for elem in cell {
+                    self.convert(f, elem, spec)?;
+                }
+            }
         }
         Ok(())
     }
-}
 
-#[inline(always)]
-fn convert_inner(
-    input: &str,
-    mode: LaTeXMode,
-    spec: Option<CommandSpec>,
-    do_parse: fn(input: &str, spec: CommandSpec) -> SyntaxNode,
-) -> Result<String, String> {
-    let node = do_parse(input, spec.unwrap_or_else(|| DEFAULT_SPEC.clone()));
-    // println!("{:#?}", node);
-    // println!("{:#?}", node.text());
-    let mut output = String::new();
-    let err = String::new();
-    let err = Rc::new(RefCell::new(err));
-    let repr = TypstRepr {
-        elem: LatexSyntaxElem::Node(node),
-        mode,
-        spec: DEFAULT_SPEC.clone(),
-        error: err.clone(),
-    };
-    core::fmt::write(&mut output, format_args!("{}", repr)).map_err(|_| err.borrow().to_owned())?;
-    Ok(output)
-}
+    /// Convert a `tabular` environment to a typst table, e.g.
+    /// `#table(columns: 2, align: (left, right), stroke: none, [a], [b]);`
+    ///
+    /// The cells are split on `&` and the rows on `\\`, and they are converted
+    /// in text mode. `\hline` is converted to a horizontal line, and
+    /// `\multicolumn` to a cell spanning columns.
+    fn convert_table(
+        &mut self,
+        f: &mut Output,
+        env: &EnvItem,
+        spec: &CommandSpec,
+    ) -> Result<(), ConvertError> {
+        let columns = env
+            .arguments()
+            .next()
+            .map(|arg| TableColumns::parse(group_content(&arg.text().to_string())))
+            .unwrap_or_default();
+
+        let rows = split_table_rows(env);
+
+        let columns_len = match columns.align.len() {
+            0 => rows
+                .iter()
+                .map(|row| row.iter().filter(|cell| !cell.is_rule()).count())
+                .max()
+                .unwrap_or(1),
+            len => len,
+        };
+
+        // a tuple of one item is the same as the item itself here
+        f.write_str("#table(columns: ")?;
+        if columns.width.iter().any(Option::is_some) {
+            let widths = columns
+                .width
+                .iter()
+                .map(|width| width.as_deref().unwrap_or("auto"));
+            write!(f, "({})", widths.collect::<Vec<_>>().join(", "))?;
+        } else {
+            write!(f, "{}", columns_len)?;
+        }
+        if !columns.align.is_empty() {
+            write!(f, ", align: ({})", columns.align.join(", "))?;
+        }
+        f.write_str(", stroke: none")?;
+        for x in &columns.vlines {
+            write!(f, ", table.vline(x: {})", x)?;
+        }
+
+        let prev = self.enter_env(LaTeXEnv::Table);
+        let prev_mode = self.enter_mode(LaTeXMode::Text);
+        for row in rows {
+            // the empty row after a trailing `\\` is not a row
+            let cells = row.iter().filter(|cell| !cell.is_rule()).count();
+            let is_empty_row = cells == 1
+                && row.iter().all(|cell| match cell {
+                    TableCell::Cell(elems) => elems.iter().all(is_trivia),
+                    TableCell::HLine => true,
+                });
+            for cell in row {
+                match cell {
+                    TableCell::HLine => f.write_str(", table.hline()")?,
+                    TableCell::Cell(_) if is_empty_row => {}
+                    TableCell::Cell(elems) => {
+                        f.write_str(", ")?;
+                        self.convert_table_cell(f, elems, spec)?;
+                    }
+                }
+            }
+        }
+        self.exit_mode(prev_mode);
+        self.exit_env(prev);
+
+        f.write_str(");")?;
+        Ok(())
+    }
+
+    /// Convert a cell of a table, which is a content block or a spanning
+    /// cell, e.g. `table.cell(colspan: 2, align: center)[a]`
+    fn convert_table_cell(
+        &mut self,
+        f: &mut Output,
+        elems: Vec<LatexSyntaxElem>,
+        spec: &CommandSpec,
+    ) -> Result<(), ConvertError> {
+        let mut significant = elems.iter().filter(|elem| !is_trivia(elem));
+        let multicolumn = match (significant.next(), significant.next()) {
+            (Some(LatexSyntaxElem::Node(node)), None) => {
+                CmdItem::cast(node.clone()).filter(|cmd| {
+                    cmd.name_tok()
+                        .map_or(false, |name| name.text() == "\\multicolumn")
+                })
+            }
+            _ => None,
+        };
+        let Some(cmd) = multicolumn else {
+            f.write_char('[')?;
+            self.convert_trimmed(f, elems, spec)?;
+            f.write_char(']')?;
+            return Ok(());
+        };
+
+        let mut args = cmd.arguments();
+        let colspan = args
+            .next()
+            .map(|arg| group_content(&arg.text().to_string()).trim().to_owned());
+        let align = args.next().and_then(|arg| {
+            TableColumns::parse(group_content(&arg.text().to_string()))
+                .align
+                .first()
+                .copied()
+        });
+        let colspan = colspan.filter(|colspan| colspan.parse::<usize>().is_ok());
+        let named = [
+            colspan.map(|colspan| format!("colspan: {}", colspan)),
+            align.map(|align| format!("align: {}", align)),
+        ];
+        f.write_str("table.cell(")?;
+        f.write_str(&named.into_iter().flatten().collect::<Vec<_>>().join(", "))?;
+        f.write_str(")[")?;
+        if let Some(content) = args.next() {
+            self.convert_trimmed(f, vec![rowan::NodeOrToken::Node(content)], spec)?;
+        }
+        f.write_char(']')?;
+        Ok(())
+    }
+
+    /// Convert elements without the leading and trailing whitespace of the
+    /// output, e.g. the content of a table cell
+    fn convert_trimmed(
+        &mut self,
+        f: &mut Output,
+        elems: Vec<LatexSyntaxElem>,
+        spec: &CommandSpec,
+    ) -> Result<(), ConvertError> {
+        let mut buf = String::new();
+        let start = f.len;
+        let first_mapping = self.source_map.as_ref().map_or(0, Vec::len);
+        let mut out = Output {
+            inner: &mut buf,
+            len: start,
+            line_start: f.line_start,
+        };
+        self.skip_next_space = true;
+        for elem in elems {
+            self.convert(&mut out, elem, spec)?;
+        }
+
+        let leading = buf.len() - buf.trim_start().len();
+        let trimmed = buf.trim();
+        // the mappings are moved along with the trimmed output
+        let end = start + trimmed.len();
+        let mappings = self.source_map.as_deref_mut().unwrap_or_default();
+        for mapping in &mut mappings[first_mapping..] {
+            let shift = |pos: usize| pos.saturating_sub(leading).clamp(start, end);
+            mapping.typst = shift(mapping.typst.start)..shift(mapping.typst.end);
+        }
+        f.write_str(trimmed)?;
+        Ok(())
+    }
+}
+
+/// Unescape the special chars of TeX in text, e.g. `a\_b` to `a_b`
+fn unescape_text(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some('_' | '#' | '$' | '%' | '&' | '{' | '}' | '~' | '^')) => {}
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+/// The level of a sectioning command and whether it is numbered, e.g.
+/// `(2, false)` for `\subsection*`
+fn heading_level(name: &str) -> Option<(usize, bool)> {
+    let (name, numbered) = match name.strip_suffix('*') {
+        Some(name) => (name, false),
+        None => (name, true),
+    };
+    let level = match name {
+        "section" => 1,
+        "subsection" => 2,
+        "subsubsection" => 3,
+        "paragraph" => 4,
+        "subparagraph" => 5,
+        _ => return None,
+    };
+    Some((level, numbered))
+}
+
+/// A cell or a rule of a `tabular` environment
+enum TableCell {
+    /// The elements of a cell
+    Cell(Vec<LatexSyntaxElem>),
+    /// A horizontal rule, e.g. `\hline`
+    HLine,
+}
+
+impl TableCell {
+    fn is_rule(&self) -> bool {
+        matches!(self, Self::HLine)
+    }
+}
+
+/// The columns of a `tabular` environment, parsed from its column
+/// specification, e.g. `|l|c|p{2cm}|`
+#[derive(Debug, Default, PartialEq)]
+struct TableColumns {
+    /// The typst alignment of each column
+    align: Vec<&'static str>,
+    /// The typst width of each column, if it is given, e.g. by `p{2cm}`
+    width: Vec<Option<String>>,
+    /// The column indices of the vertical rules
+    vlines: Vec<usize>,
+}
+
+impl TableColumns {
+    /// The maximum number of columns, where the rest are ignored, since nested
+    /// repetitions, e.g. `*{64}{*{64}{c}}`, multiply
+    const MAX_COLUMNS: usize = 1024;
+
+    fn parse(spec: &str) -> Self {
+        let mut columns = Self::default();
+        columns.parse_into(spec);
+        columns
+    }
+
+    fn parse_into(&mut self, spec: &str) {
+        let mut rest = spec;
+        while let Some(c) = rest.chars().next() {
+            if self.align.len() >= Self::MAX_COLUMNS {
+                return;
+            }
+            rest = &rest[c.len_utf8()..];
+            let (align, width) = match c {
+                'l' => ("left", None),
+                'c' => ("center", None),
+                'r' => ("right", None),
+                // paragraph columns, e.g. `p{2cm}`
+                'p' | 'm' | 'b' => {
+                    let (width, next) = take_group(rest);
+                    rest = next;
+                    ("left", Some(convert_length(width)))
+                }
+                // a column of `tabularx`
+                'X' => ("left", Some("1fr".to_owned())),
+                '|' => {
+                    if self.vlines.last() != Some(&self.align.len()) {
+                        self.vlines.push(self.align.len());
+                    }
+                    continue;
+                }
+                // repeated columns, e.g. `*{3}{c}`
+                '*' => {
+                    let (count, next) = take_group(rest);
+                    let (repeated, next) = take_group(next);
+                    rest = next;
+                    for _ in 0..count.trim().parse().unwrap_or(0usize) {
+                        let len = self.align.len();
+                        self.parse_into(repeated);
+                        // stop repeating once no more columns are added,
+                        // e.g. for a full table or only rules
+                        if self.align.len() == len {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                // inter-column material and column decorations are ignored,
+                // e.g. `@{}` or `>{\bfseries}`
+                '@' | '!' | '>' | '<' => {
+                    rest = take_group(rest).1;
+                    continue;
+                }
+                _ => continue,
+            };
+            self.align.push(align);
+            self.width.push(width);
+        }
+    }
+}
+
+/// Take the content of a leading curly group, e.g. `2cm` in `{2cm}c`, and
+/// the rest of the text
+fn take_group(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    if !text.starts_with('{') {
+        // a single char
+        let len = text.chars().next().map_or(0, char::len_utf8);
+        return (&text[..len], &text[len..]);
+    }
+    let mut depth = 0usize;
+    for (index, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return (&text[1..index], &text[index + 1..]);
+                }
+            }
+            _ => {}
+        }
+    }
+    (&text[1..], "")
+}
+
+/// The content of a curly group without the enclosing braces
+fn group_content(text: &str) -> &str {
+    let text = text.trim();
+    text.strip_prefix('{')
+        .and_then(|text| text.strip_suffix('}'))
+        .unwrap_or(text)
+}
+
+/// Get the typst length of the spacing argument of a row break, e.g. `1em`
+/// for `\\[1em]`
+fn new_line_spacing(elem: &LatexSyntaxElem) -> Option<String> {
+    let arg = elem
+        .as_node()?
+        .children()
+        .find(|node| node.kind() == LatexSyntaxKind::ClauseArgument)?;
+    let text = arg.text().to_string();
+    let length = text.trim().strip_prefix('[')?.strip_suffix(']')?;
+    Some(convert_length(length)).filter(|length| length != "auto")
+}
+
+/// Convert the key of a label to a typst label, where the chars disallowed in
+/// typst labels, e.g. spaces, are replaced with `_`
+fn typst_label(key: &str) -> String {
+    key.trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '_' | '-' | ':' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Convert a TeX length to a typst length, e.g. `0.5\linewidth` to `50%`
+fn convert_length(length: &str) -> String {
+    let length = length.trim();
+    for relative in ["\\textwidth", "\\linewidth", "\\columnwidth"] {
+        if let Some(ratio) = length.strip_suffix(relative) {
+            return match ratio.trim().parse::<f64>() {
+                Ok(ratio) => format_percent(ratio),
+                Err(_) if ratio.trim().is_empty() => "100%".to_owned(),
+                Err(_) => "auto".to_owned(),
+            };
+        }
+    }
+    let unit = length.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+    match unit {
+        "pt" | "mm" | "cm" | "in" | "em" if unit.len() < length.len() => length.to_owned(),
+        _ => "auto".to_owned(),
+    }
+}
+
+/// Convert a color to a typst color, e.g. `rgb("#FF0000")` for `FF0000` in
+/// the `HTML` model, where a color without a model is a name of `xcolor`
+///
+/// It is the only map of the color names, as all color commands are
+/// converted here instead of by typst handlers.
+fn typst_color(model: Option<&str>, color: &str) -> Option<String> {
+    let color = color.trim();
+    let components = |len: usize| {
+        let components = color
+            .split(',')
+            .map(|component| component.trim().parse::<f64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        (components.len() == len).then_some(components)
+    };
+
+    Some(match model.map(str::trim) {
+        None => {
+            let (r, g, b) = match color.to_ascii_lowercase().as_str() {
+                "red" => (255, 0, 0),
+                "green" => (0, 255, 0),
+                "blue" => (0, 0, 255),
+                "cyan" => (0, 255, 255),
+                "magenta" => (255, 0, 255),
+                "yellow" => (255, 255, 0),
+                "black" => (0, 0, 0),
+                "white" => (255, 255, 255),
+                "gray" => (128, 128, 128),
+                "lightgray" => (192, 192, 192),
+                "darkgray" => (64, 64, 64),
+                "brown" => (165, 42, 42),
+                "orange" => (255, 165, 0),
+                "pink" => (255, 182, 193),
+                "purple" => (128, 0, 128),
+                "teal" => (0, 128, 128),
+                "olive" => (128, 128, 0),
+                _ => return None,
+            };
+            format!("rgb({}, {}, {})", r, g, b)
+        }
+        Some("HTML") if color.len() == 6 && color.chars().all(|c| c.is_ascii_hexdigit()) => {
+            format!("rgb(\"#{}\")", color)
+        }
+        Some("RGB") => {
+            let rgb = components(3)?;
+            format!("rgb({}, {}, {})", rgb[0], rgb[1], rgb[2])
+        }
+        Some("rgb") => {
+            let rgb = components(3)?;
+            let rgb = rgb.iter().map(|c| format_percent(*c)).collect::<Vec<_>>();
+            format!("rgb({})", rgb.join(", "))
+        }
+        Some("gray") => format!("luma({})", format_percent(components(1)?[0])),
+        _ => return None,
+    })
+}
+
+/// Format a ratio as a typst percentage, e.g. `0.5` to `50%`
+fn format_percent(ratio: f64) -> String {
+    format!("{}%", (ratio * 1e4).round() / 1e2)
+}
+
+/// Split the body of a table-like environment, e.g. `tabular` or `array`, into
+/// rows of cells on `&` and `\\`
+///
+/// A curly group directly in a cell is split as well, so that a `\\` in it
+/// still ends the row, e.g. `{a \\ b}`. A rule between rows, e.g. `\hline`, is
+/// an item of the row after it.
+fn split_table_rows(env: &EnvItem) -> Vec<Vec<TableCell>> {
+    use LatexSyntaxKind::*;
+
+    let is_separator = |elem: &LatexSyntaxElem| matches!(elem.kind(), TokenAmpersand | ItemNewLine);
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut cell = vec![];
+    // the children of the body and of the split groups in it
+    let mut stack = vec![env.syntax().children_with_tokens()];
+    while let Some(children) = stack.last_mut() {
+        let Some(child) = children.next() else {
+            stack.pop();
+            continue;
+        };
+        let nested = stack.len() > 1;
+        match child.kind() {
+            ItemBegin | ItemEnd => {}
+            TokenLBrace | TokenRBrace if nested => {}
+            TokenAmpersand => row.push(TableCell::Cell(std::mem::take(&mut cell))),
+            ItemNewLine => {
+                row.push(TableCell::Cell(std::mem::take(&mut cell)));
+                rows.push(std::mem::take(&mut row));
+            }
+            ItemCmd if is_table_rule(&child) => {
+                // a rule is placed between rows, e.g. `\\ \hline`
+                if !cell.iter().all(is_trivia) {
+                    cell.push(child);
+                } else {
+                    row.push(TableCell::HLine);
+                }
+            }
+            ItemCurly => match child.as_node() {
+                Some(group) if group.children_with_tokens().any(|elem| is_separator(&elem)) => {
+                    stack.push(group.children_with_tokens());
+                }
+                _ => cell.push(child),
+            },
+            _ => cell.push(child),
+        }
+    }
+    row.push(TableCell::Cell(cell));
+    rows.push(row);
+    rows
+}
+
+/// Whether a row of cells is empty, e.g. the row after a trailing `\\`
+fn is_empty_row(row: &[Vec<LatexSyntaxElem>]) -> bool {
+    matches!(row, [cell] if cell.iter().all(is_trivia))
+}
+
+/// Whether an element is a horizontal rule of a table, e.g. `\hline`
+fn is_table_rule(elem: &LatexSyntaxElem) -> bool {
+    let Some(cmd) = elem.as_node().cloned().and_then(CmdItem::cast) else {
+        return false;
+    };
+    cmd.name_tok().map_or(false, |name| {
+        matches!(
+            name.text(),
+            "\\hline" | "\\toprule" | "\\midrule" | "\\bottomrule"
+        )
+    })
+}
+
+/// Whether a command is an extensible arrow with labels, e.g. `\xrightarrow`
+fn is_xarrow(name: &str) -> bool {
+    name.starts_with('x')
+        && (name.ends_with("arrow")
+            || name.contains("harpoon")
+            || matches!(name, "xtofrom" | "xmapsto" | "xlongequal"))
+}
+
+/// Get the converted math and text mode spaces of a spacing command, e.g.
+/// `thin` and `1em/6` for `\,`
+fn spacing(name: &str) -> Option<(&'static str, &'static str)> {
+    Some(match name {
+        "," | "thinspace" => ("thin", "1em/6"),
+        ":" | ">" | "medspace" => ("med", "2em/9"),
+        ";" | "thickspace" => ("thick", "5em/18"),
+        "!" | "negthinspace" => ("#h(-1em/6)", "-1em/6"),
+        "quad" => ("quad", "1em"),
+        "qquad" => ("wide", "2em"),
+        _ => return None,
+    })
+}
+
+/// Find the bar closing an opening bar command, e.g. `\rvert` for `\lvert`,
+/// among the following siblings, and get the typst call of the pair
+///
+/// A single `|` is not paired, as it is ambiguous, e.g. in `\{x | x > 0\}`.
+/// Bars are not paired across the cells or rows of a matrix either.
+fn find_bar_closer(open: &SyntaxNode, name: &str) -> Option<(&'static str, rowan::TextRange)> {
+    let (call, close) = match name {
+        "lvert" => ("abs", "rvert"),
+        "lVert" => ("norm", "rVert"),
+        "|" => ("norm", "|"),
+        _ => return None,
+    };
+    let mut depth = 0usize;
+    for sibling in open.siblings_with_tokens(rowan::Direction::Next).skip(1) {
+        if matches!(
+            sibling.kind(),
+            LatexSyntaxKind::TokenAmpersand | LatexSyntaxKind::ItemNewLine
+        ) {
+            break;
+        }
+        let Some((bar, range)) = bar_command(&sibling) else {
+            continue;
+        };
+        if bar == close {
+            if depth == 0 {
+                return Some((call, range));
+            }
+            depth -= 1;
+        } else if bar == name {
+            depth += 1;
+        }
+    }
+    None
+}
+
+/// Get the name and range of a bar command at the start of an element, e.g.
+/// `rvert` in `\rvert^2`
+fn bar_command(elem: &LatexSyntaxElem) -> Option<(String, rowan::TextRange)> {
+    use LatexSyntaxKind::*;
+
+    let mut elem = elem.clone();
+    while matches!(elem.kind(), ItemAttachComponent | ClauseArgument) {
+        elem = elem
+            .as_node()?
+            .children_with_tokens()
+            .find(|elem| !is_trivia(elem))?;
+    }
+    let cmd = elem.into_node().and_then(CmdItem::cast)?;
+    let name = cmd.name_tok()?.text().strip_prefix('\\')?.to_owned();
+    matches!(name.as_str(), "lvert" | "rvert" | "lVert" | "rVert" | "|")
+        .then(|| (name, cmd.syntax().text_range()))
+}
+
+/// Get the typst size and math class of a sized delimiter, e.g. `120%` and
+/// `opening` for `\bigl`
+fn delimiter_size(name: &str) -> Option<(&'static str, Option<&'static str>)> {
+    let (tier, class) = match name.as_bytes().last()? {
+        b'l' => (&name[..name.len() - 1], Some("opening")),
+        b'r' => (&name[..name.len() - 1], Some("closing")),
+        b'm' => (&name[..name.len() - 1], Some("relation")),
+        _ => (name, None),
+    };
+    let size = match tier {
+        "big" => "120%",
+        "Big" => "180%",
+        "bigg" => "240%",
+        "Bigg" => "300%",
+        _ => return None,
+    };
+    Some((size, class))
+}
+
+/// Get the typst symbol of a delimiter of `\genfrac`, e.g. `paren.l` for `(`
+///
+/// Returns `Some(None)` for no delimiter, and `None` for an unknown one.
+fn genfrac_delimiter(delim: &str) -> Option<Option<&'static str>> {
+    Some(Some(match delim.trim() {
+        "" | "." => return Some(None),
+        "(" => "paren.l",
+        ")" => "paren.r",
+        "[" | "\\lbrack" => "bracket.l",
+        "]" | "\\rbrack" => "bracket.r",
+        "\\{" | "\\lbrace" => "brace.l",
+        "\\}" | "\\rbrace" => "brace.r",
+        "\\langle" | "<" => "angle.l",
+        "\\rangle" | ">" => "angle.r",
+        "|" | "\\vert" | "\\lvert" | "\\rvert" => "bar.v",
+        "\\|" | "\\Vert" | "\\lVert" | "\\rVert" => "bar.v.double",
+        "/" => "slash",
+        _ => return None,
+    }))
+}
+
+/// Check whether a length is zero, e.g. `0pt` or `0.0em`, where an empty
+/// length is the default thickness, which is not zero
+fn is_zero_length(length: &str) -> bool {
+    let length = length.trim();
+    let number = length.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    !number.is_empty() && number.parse::<f64>().map_or(false, |number| number == 0.)
+}
+
+/// Convert a number of siunitx to typst math, e.g. `1.5 times 10^(3)` for
+/// `1.5e3` or `1.5\times10^3`, where the spaces are ignored and a decimal
+/// comma is a point
+///
+/// The number is a signed decimal with an optional uncertainty after `\pm`,
+/// and an optional exponent after `e` or a power after `\times`. It returns
+/// `None` for any other chars or commands, which are not a number.
+fn si_number(number: &str) -> Option<String> {
+    /// Strip a sign, e.g. `-` or `\pm`, and get it in typst math
+    fn sign(rest: &mut &str) -> &'static str {
+        const SIGNS: [(&str, &str); 4] = [
+            ("+", "+"),
+            ("-", "-"),
+            ("\\pm", "plus.minus "),
+            ("\\mp", "minus.plus "),
+        ];
+        for (sign, math) in SIGNS {
+            if let Some(tail) = rest.strip_prefix(sign) {
+                *rest = tail;
+                return math;
+            }
+        }
+        ""
+    }
+    /// Strip the digits of a decimal, e.g. `2.5`
+    fn digits<'a>(rest: &mut &'a str) -> &'a str {
+        let len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let (digits, tail) = rest.split_at(len);
+        *rest = tail;
+        digits
+    }
+    /// Strip a signed integer, e.g. `-3`, which may be in a group
+    fn integer(rest: &mut &str) -> Option<String> {
+        let grouped = rest.strip_prefix('{');
+        let mut tail = grouped.unwrap_or(*rest);
+        let sign = sign(&mut tail);
+        let digits = digits(&mut tail);
+        if grouped.is_some() {
+            tail = tail.strip_prefix('}')?;
+        }
+        *rest = tail;
+        (!digits.is_empty()).then(|| format!("{sign}{digits}"))
+    }
+
+    let number = number
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| if c == ',' { '.' } else { c })
+        .collect::<String>();
+    let mut rest = number.as_str();
+
+    let mut math = sign(&mut rest).to_owned();
+    let mantissa = digits(&mut rest);
+    math.push_str(mantissa);
+    if let Some(tail) = rest.strip_prefix("\\pm") {
+        rest = tail;
+        let uncertainty = digits(&mut rest);
+        if mantissa.is_empty() || uncertainty.is_empty() {
+            return None;
+        }
+        math.push_str(" plus.minus ");
+        math.push_str(uncertainty);
+    }
+
+    let power = if let Some(tail) = rest.strip_prefix(['e', 'E']) {
+        rest = tail;
+        Some(format!("10^({})", integer(&mut rest)?))
+    } else if let Some(tail) = rest.strip_prefix("\\times") {
+        rest = tail;
+        let base = digits(&mut rest);
+        if base.is_empty() {
+            return None;
+        }
+        match rest.strip_prefix('^') {
+            Some(tail) => {
+                rest = tail;
+                Some(format!("{base}^({})", integer(&mut rest)?))
+            }
+            None => Some(base.to_owned()),
+        }
+    } else {
+        None
+    };
+    if !rest.is_empty() {
+        return None;
+    }
+
+    match power {
+        // a power without a mantissa, e.g. `e3`
+        Some(power) if mantissa.is_empty() => Some(format!("{math}{power}")),
+        Some(power) => Some(format!("{math} times {power}")),
+        None if mantissa.is_empty() => None,
+        None => Some(math),
+    }
+}
+
+/// Convert a unit of siunitx to a readable string, e.g. `m/s²` for
+/// `\meter\per\second\squared`
+///
+/// The units after `\per` are put in the denominator, and the other units
+/// are separated by spaces, e.g. `kg m/(s² A)`. A literal unit, e.g. `m/s`,
+/// is kept as is. Unknown unit macros are kept as units by their names, and
+/// a prefix without a unit as a unit, where a warning is pushed to
+/// `warnings` for each of them.
+fn si_unit(unit: &str, warnings: &mut Vec<String>) -> String {
+    // the units with their powers in the numerator and the denominator
+    let mut units: [Vec<(String, String)>; 2] = Default::default();
+    let mut last = None;
+    let mut prefix = String::new();
+    let mut power = None;
+    let mut per = false;
+    let mut rest = unit.trim();
+    while !rest.is_empty() {
+        let symbol = if let Some(cmd) = rest.strip_prefix('\\') {
+            let len = cmd
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(cmd.len());
+            let (name, tail) = cmd.split_at(len);
+            rest = tail.trim_start();
+            // the power given as an argument, e.g. `\tothe{4}`
+            let mut arg = || {
+                let (arg, tail) = rest.strip_prefix('{')?.split_once('}')?;
+                rest = tail.trim_start();
+                Some(arg.trim().to_owned())
+            };
+            match name {
+                // a control symbol, e.g. a space `\,`, is ignored
+                "" => {
+                    let mut chars = tail.chars();
+                    chars.next();
+                    rest = chars.as_str().trim_start();
+                    None
+                }
+                "per" => {
+                    per = true;
+                    None
+                }
+                "square" | "cubic" | "raiseto" => {
+                    power = match name {
+                        "square" => Some("2".to_owned()),
+                        "cubic" => Some("3".to_owned()),
+                        _ => arg(),
+                    };
+                    None
+                }
+                "squared" | "cubed" | "tothe" => {
+                    let exponent = match name {
+                        "squared" => Some("2".to_owned()),
+                        "cubed" => Some("3".to_owned()),
+                        _ => arg(),
+                    };
+                    if let Some(unit) = last.and_then(|index: usize| units[index].last_mut()) {
+                        unit.1 = exponent.unwrap_or_default();
+                    }
+                    None
+                }
+                _ => {
+                    if let Some(symbol) = si_prefix(name) {
+                        prefix.push_str(symbol);
+                        continue;
+                    }
+                    let symbol = si_symbol(name).unwrap_or_else(|| {
+                        warnings.push(format!(
+                            "unknown unit `\\{name}` of siunitx is kept by its name"
+                        ));
+                        name
+                    });
+                    Some(format!("{}{symbol}", std::mem::take(&mut prefix)))
+                }
+            }
+        } else {
+            let len = rest.find('\\').unwrap_or(rest.len());
+            let (symbol, tail) = rest.split_at(len);
+            rest = tail;
+            Some(format!("{}{}", std::mem::take(&mut prefix), symbol.trim()))
+        };
+        if let Some(symbol) = symbol {
+            let index = usize::from(std::mem::take(&mut per));
+            units[index].push((symbol, power.take().unwrap_or_default()));
+            last = Some(index);
+        }
+    }
+    // a trailing prefix, e.g. `\kilo`, is not dropped
+    if !prefix.is_empty() {
+        warnings.push(format!(
+            "the prefix `{prefix}` of siunitx has no unit and is kept as a unit"
+        ));
+        let index = usize::from(per);
+        units[index].push((prefix, power.unwrap_or_default()));
+    }
+
+    let join = |units: &[(String, String)]| {
+        let units = units
+            .iter()
+            .map(|(symbol, power)| format!("{symbol}{}", superscript(power)));
+        units.collect::<Vec<_>>().join(" ")
+    };
+    let [num, den] = &units;
+    let num = if num.is_empty() && !den.is_empty() {
+        "1".to_owned()
+    } else {
+        join(num)
+    };
+    match den.len() {
+        0 => num,
+        1 => format!("{num}/{}", join(den)),
+        _ => format!("{num}/({})", join(den)),
+    }
+}
+
+/// Write a power of a unit in unicode superscripts, e.g. `⁻¹` for `-1`, or
+/// after a caret if it has other chars
+fn superscript(power: &str) -> String {
+    let chars = power.chars().map(|c| {
+        Some(match c {
+            '-' => '⁻',
+            '0' => '⁰',
+            '1' => '¹',
+            '2' => '²',
+            '3' => '³',
+            '4' => '⁴',
+            '5' => '⁵',
+            '6' => '⁶',
+            '7' => '⁷',
+            '8' => '⁸',
+            '9' => '⁹',
+            _ => None?,
+        })
+    });
+    chars
+        .collect::<Option<String>>()
+        .unwrap_or_else(|| format!("^{power}"))
+}
+
+/// Get the symbol of a prefix macro of siunitx, e.g. `k` for `\kilo`
+fn si_prefix(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "femto" => "f",
+        "pico" => "p",
+        "nano" => "n",
+        "micro" => "µ",
+        "milli" => "m",
+        "centi" => "c",
+        "deci" => "d",
+        "kilo" => "k",
+        "mega" => "M",
+        "giga" => "G",
+        "tera" => "T",
+        _ => return None,
+    })
+}
+
+/// Get the symbol of a unit macro of siunitx, e.g. `m` for `\meter`
+fn si_symbol(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "meter" | "metre" => "m",
+        "second" => "s",
+        "gram" => "g",
+        "kilogram" => "kg",
+        "ampere" => "A",
+        "kelvin" => "K",
+        "mole" => "mol",
+        "candela" => "cd",
+        "hertz" => "Hz",
+        "newton" => "N",
+        "pascal" => "Pa",
+        "joule" => "J",
+        "watt" => "W",
+        "coulomb" => "C",
+        "volt" => "V",
+        "ohm" => "Ω",
+        "farad" => "F",
+        "tesla" => "T",
+        "henry" => "H",
+        "liter" | "litre" => "L",
+        "minute" => "min",
+        "hour" => "h",
+        "day" => "d",
+        "electronvolt" => "eV",
+        "degree" => "°",
+        "degreeCelsius" => "°C",
+        "percent" => "%",
+        _ => return None,
+    })
+}
+
+/// Get the typst symbol of a negated relation, e.g. `in.not` for `\in`
+fn negated(atom: &str) -> Option<&'static str> {
+    Some(match atom.trim() {
+        "=" => "!=",
+        "<" => "lt.not",
+        ">" => "gt.not",
+        "\\le" | "\\leq" => "lt.eq.not",
+        "\\ge" | "\\geq" => "gt.eq.not",
+        "\\in" => "in.not",
+        "\\ni" => "in.rev.not",
+        "\\subset" => "subset.not",
+        "\\supset" => "supset.not",
+        "\\subseteq" => "subset.eq.not",
+        "\\supseteq" => "supset.eq.not",
+        "\\equiv" => "equiv.not",
+        "\\approx" => "approx.not",
+        "\\sim" => "tilde.not",
+        "\\simeq" => "tilde.eq.not",
+        "\\cong" => "tilde.equiv.not",
+        "\\prec" => "prec.not",
+        "\\succ" => "succ.not",
+        "\\mid" => "divides.not",
+        "\\parallel" => "parallel.not",
+        "\\exists" => "exists.not",
+        _ => return None,
+    })
+}
+
+/// Whether an element starts with a binary operator or a relation, e.g. `+`
+/// or `\le`
+fn is_bin_or_rel(elem: &LatexSyntaxElem) -> bool {
+    if let Some(cmd) = elem.as_node().cloned().and_then(CmdItem::cast) {
+        let name = cmd.name_tok().map(|name| name.text().to_owned());
+        return matches!(
+            name.as_deref(),
+            Some(
+                "\\cdot"
+                    | "\\times"
+                    | "\\pm"
+                    | "\\mp"
+                    | "\\cup"
+                    | "\\cap"
+                    | "\\wedge"
+                    | "\\vee"
+                    | "\\le"
+                    | "\\leq"
+                    | "\\ge"
+                    | "\\geq"
+                    | "\\ne"
+                    | "\\neq"
+                    | "\\equiv"
+                    | "\\approx"
+                    | "\\sim"
+                    | "\\subset"
+                    | "\\subseteq"
+                    | "\\to"
+                    | "\\rightarrow"
+            )
+        );
+    }
+    let text = match elem {
+        LatexSyntaxElem::Node(node) => node.text().to_string(),
+        LatexSyntaxElem::Token(token) => token.text().to_owned(),
+    };
+    text.trim_start()
+        .starts_with(['+', '-', '=', '<', '>', '*'])
+}
+
+/// Get the node of an element, or an error if the syntax tree is malformed
+fn as_node(elem: &LatexSyntaxElem) -> Result<&SyntaxNode, ConvertError> {
+    elem.as_node()
+        .ok_or_else(|| format!("expected a node: {:?}", elem.kind()).into())
+}
+
+/// Get the token of an element, or an error if the syntax tree is malformed
+fn as_token(elem: &LatexSyntaxElem) -> Result<&SyntaxToken, ConvertError> {
+    elem.as_token()
+        .ok_or_else(|| format!("expected a token: {:?}", elem.kind()).into())
+}
+
+/// Whether an element is converted to whitespace only
+fn is_trivia(elem: &LatexSyntaxElem) -> bool {
+    use LatexSyntaxKind::*;
+
+    match elem {
+        LatexSyntaxElem::Token(token) => matches!(
+            token.kind(),
+            TokenWhiteSpace | TokenLineBreak | TokenComment
+        ),
+        LatexSyntaxElem::Node(node) => {
+            matches!(node.kind(), ItemBlockComment | ItemParBreak)
+                || node.kind() == ItemText
+                    && node.children_with_tokens().all(|elem| is_trivia(&elem))
+        }
+    }
+}
+
+#[inline(always)]
+fn convert_inner(
+    input: &str,
+    mode: LaTeXMode,
+    spec: Option<CommandSpec>,
+    do_parse: fn(input: &str, spec: CommandSpec) -> SyntaxNode,
+) -> Result<String, String> {
+    let spec = spec.unwrap_or_else(|| DEFAULT_SPEC.clone());
+    let node = do_parse(input, spec.clone());
+    convert_node(node, spec, Converter::new(mode)).map(|(output, _)| output)
+}
+
+#[inline(always)]
+fn convert_with_diagnostics(
+    input: &str,
+    mode: LaTeXMode,
+    options: ConvertOptions,
+) -> (Result<String, String>, Vec<Diagnostic>) {
+    let spec = options.spec.unwrap_or_else(|| DEFAULT_SPEC.clone());
+    let (node, mut diagnostics, spans) = parse_in_mode(input, spec.clone(), mode.into());
+    let mut ctx = Converter::new(mode);
+    ctx.spans = Some(spans);
+    ctx.strict = options.strict;
+    ctx.discouraged = options.discouraged;
+    ctx.collapse_whitespace = options.collapse_whitespace;
+    let output = convert_node(node, spec, ctx).map(|(output, ctx)| {
+        // the errors of unknown commands replace the warnings of the parser
+        diagnostics.retain(|diagnostic| {
+            !ctx.diagnostics
+                .iter()
+                .any(|error| error.span == diagnostic.span && error.message == diagnostic.message)
+        });
+        diagnostics.extend(ctx.diagnostics);
+        output
+    });
+    (output, diagnostics)
+}
+
+#[inline(always)]
+fn convert_with_source_map(
+    input: &str,
+    mode: LaTeXMode,
+    spec: Option<CommandSpec>,
+) -> Result<(String, Vec<SourceMapping>), String> {
+    let spec = spec.unwrap_or_else(|| DEFAULT_SPEC.clone());
+    let (node, _, spans) = parse_in_mode(input, spec.clone(), mode.into());
+    let mut ctx = Converter::new(mode);
+    ctx.spans = Some(spans);
+    ctx.source_map = Some(Vec::new());
+    let (output, ctx) = convert_node(node, spec, ctx)?;
+    let mut source_map = ctx.source_map.unwrap_or_default();
+    // items converted to nothing, e.g. a brace, are not located
+    source_map.retain(|mapping| !mapping.typst.is_empty());
+    Ok((output, source_map))
+}
+
+fn convert_node(
+    node: SyntaxNode,
+    spec: CommandSpec,
+    ctx: Converter,
+) -> Result<(String, Converter), String> {
+    let mut output = String::new();
+    let ctx = convert_node_to(node, spec, ctx, &mut output).map_err(|e| e.to_string())?;
+    Ok((output, ctx))
+}
+
+/// Convert a syntax tree, writing the output to `out` incrementally
+fn convert_node_to(
+    node: SyntaxNode,
+    spec: CommandSpec,
+    mut ctx: Converter,
+    out: &mut dyn fmt::Write,
+) -> Result<Converter, ConvertError> {
+    // println!("{:#?}", node);
+    // println!("{:#?}", node.text());
+    ctx.convert(&mut Output::new(out), LatexSyntaxElem::Node(node), &spec)?;
+    Ok(ctx)
+}
+
+/// Adapts an [`io::Write`] to the [`fmt::Write`] used by the converter,
+/// keeping the io error which [`fmt::Error`] can't carry
+struct IoOutput<'a, W: io::Write> {
+    inner: &'a mut W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> fmt::Write for IoOutput<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+#[inline(always)]
+fn convert_to_writer_inner<W: io::Write>(
+    input: &str,
+    mode: LaTeXMode,
+    spec: Option<CommandSpec>,
+    writer: &mut W,
+) -> io::Result<()> {
+    let spec = spec.unwrap_or_else(|| DEFAULT_SPEC.clone());
+    let (node, _, _) = parse_in_mode(input, spec.clone(), mode.into());
+    let mut writer = io::BufWriter::new(writer);
+    let mut out = IoOutput {
+        inner: &mut writer,
+        error: None,
+    };
+    let res = convert_node_to(node, spec, Converter::new(mode), &mut out);
+    if let Some(e) = out.error {
+        return Err(e);
+    }
+    res.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    writer.flush()
+}
+
+/// The options of a conversion, see [`convert_text_with_options`]
+///
+/// ```
+/// use mitex::{convert_text_with_options, ConvertOptions};
+///
+/// let options = ConvertOptions {
+///     strict: true,
+///     ..Default::default()
+/// };
+/// let (output, diagnostics) = convert_text_with_options(r#"a \foobar b"#, options);
+/// assert_eq!(output.unwrap(), "a  b");
+/// assert_eq!(diagnostics[0].message, "unknown command `\\foobar`");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// The command specification, or the default one if it is `None`
+    pub spec: Option<CommandSpec>,
+    /// Whether to skip a command which is neither in the specification nor
+    /// defined by a macro and report it as an error diagnostic, instead of
+    /// passing it through by its name
+    pub strict: bool,
+    /// The names of the commands reported as warning diagnostics without
+    /// changing the output, e.g. `over` for `\over`, or `$$` for display math
+    /// delimited by `$$`
+    pub discouraged: Vec<String>,
+    /// Whether to collapse a run of spaces and line breaks in text mode into a
+    /// space, or into a paragraph break if it has a blank line, instead of
+    /// keeping it as is
+    pub collapse_whitespace: bool,
+}
+
+/// Convert LaTeX text, e.g. a paragraph of a document, to typst markup
+///
+/// The input is parsed and converted in text mode, where only the formulas,
+/// e.g. `$a_b$`, are math, and the other markup chars are escaped. The default
+/// specification is used if `spec` is `None`.
+///
+/// ```
+/// assert_eq!(mitex::convert_text("a_b", None).unwrap(), "a\\_b");
+/// ```
+pub fn convert_text(input: &str, spec: Option<CommandSpec>) -> Result<String, String> {
+    convert_inner(input, LaTeXMode::Text, spec, |input, spec| {
+        parse_in_mode(input, spec, ParseMode::Text).0
+    })
+}
+
+/// Convert a LaTeX equation, e.g. the content of `$...$`, to typst math
+///
+/// The input is converted in math mode like the body of a formula, see
+/// [`convert_text`] for the conversion of text.
+///
+/// ```
+/// assert_eq!(mitex::convert_math("a_b", None).unwrap(), "a _(b )");
+/// ```
+pub fn convert_math(input: &str, spec: Option<CommandSpec>) -> Result<String, String> {
+    convert_inner(input, LaTeXMode::Math, spec, parse)
+}
+
+/// Convert LaTeX text like [`convert_text`], and also return the diagnostics
+/// of the input, e.g. an unterminated environment
+pub fn convert_text_with_diagnostics(
+    input: &str,
+    spec: Option<CommandSpec>,
+) -> (Result<String, String>, Vec<Diagnostic>) {
+    let options = ConvertOptions {
+        spec,
+        ..Default::default()
+    };
+    convert_with_diagnostics(input, LaTeXMode::Text, options)
+}
+
+/// Convert a LaTeX equation like [`convert_math`], and also return the
+/// diagnostics of the input
+pub fn convert_math_with_diagnostics(
+    input: &str,
+    spec: Option<CommandSpec>,
+) -> (Result<String, String>, Vec<Diagnostic>) {
+    let options = ConvertOptions {
+        spec,
+        ..Default::default()
+    };
+    convert_with_diagnostics(input, LaTeXMode::Math, options)
+}
+
+/// Convert LaTeX text like [`convert_text_with_diagnostics`] with options,
+/// e.g. in the strict mode, where the unknown commands are collected as
+/// errors
+pub fn convert_text_with_options(
+    input: &str,
+    options: ConvertOptions,
+) -> (Result<String, String>, Vec<Diagnostic>) {
+    convert_with_diagnostics(input, LaTeXMode::Text, options)
+}
+
+/// Convert a LaTeX equation like [`convert_math_with_diagnostics`] with
+/// options, see [`convert_text_with_options`]
+pub fn convert_math_with_options(
+    input: &str,
+    options: ConvertOptions,
+) -> (Result<String, String>, Vec<Diagnostic>) {
+    convert_with_diagnostics(input, LaTeXMode::Math, options)
+}
+
+/// Convert LaTeX text like [`convert_text`], and also return the source map
+/// from the input to the output
+///
+/// The source map is coarse-grained, i.e. each token and each command is
+/// mapped to the output converted from it. A command is mapped with its
+/// arguments, which are also mapped on their own. The mappings are sorted by
+/// the start of their ranges in the input, and a token without a range in the
+/// input, e.g. a token synthesized by the macro engine, is not mapped.
+pub fn convert_text_with_source_map(
+    input: &str,
+    spec: Option<CommandSpec>,
+) -> Result<(String, Vec<SourceMapping>), String> {
+    convert_with_source_map(input, LaTeXMode::Text, spec)
+}
+
+/// Convert a LaTeX equation like [`convert_math`], and also return the source
+/// map from the input to the output, see [`convert_text_with_source_map`]
+pub fn convert_math_with_source_map(
+    input: &str,
+    spec: Option<CommandSpec>,
+) -> Result<(String, Vec<SourceMapping>), String> {
+    convert_with_source_map(input, LaTeXMode::Math, spec)
+}
+
+/// Convert LaTeX text like [`convert_text`], but write the output to `writer`
+/// while walking the syntax tree instead of collecting it in a string
+///
+/// The output is the same as the one of [`convert_text`]. Small writes are
+/// buffered, and the buffer is flushed before returning.
+///
+/// # Errors
+/// Returns the first error of `writer`, or an error of kind
+/// [`io::ErrorKind::InvalidData`] if the input can't be converted. In both
+/// cases a part of the output may be written already.
+pub fn convert_to_writer<W: io::Write>(
+    input: &str,
+    spec: Option<CommandSpec>,
+    writer: &mut W,
+) -> io::Result<()> {
+    convert_to_writer_inner(input, LaTeXMode::Text, spec, writer)
+}
+
+/// Convert a LaTeX equation like [`convert_math`], but write the output to
+/// `writer` like [`convert_to_writer`]
+///
+/// # Errors
+/// See [`convert_to_writer`].
+pub fn convert_math_to_writer<W: io::Write>(
+    input: &str,
+    spec: Option<CommandSpec>,
+    writer: &mut W,
+) -> io::Result<()> {
+    convert_to_writer_inner(input, LaTeXMode::Math, spec, writer)
+}
+
+/// Convert LaTeX text like [`convert_text`], where the top-level blocks of the
+/// input are converted in parallel on the rayon thread pool
+///
+/// The input is still parsed as a whole, since a block may use a macro
+/// defined in an earlier one. It is then split after each paragraph break,
+/// and each line break after a display formula, that is not inside an
+/// environment, a group or another formula, and the converted blocks are
+/// concatenated in order, so that the output is the same as that of
+/// [`convert_text`].
+///
+/// ```
+/// let input = "a $x$\n\nb \\[y\\] c";
+/// assert_eq!(
+///     mitex::convert_parallel(input, None),
+///     mitex::convert_text(input, None)
+/// );
+/// ```
+#[cfg(feature = "rayon")]
+pub fn convert_parallel(input: &str, spec: Option<CommandSpec>) -> Result<String, String> {
+    use rayon::prelude::*;
+
+    let spec = spec.unwrap_or_else(|| DEFAULT_SPEC.clone());
+    let (node, _, _) = parse_in_mode(input, spec.clone(), ParseMode::Text);
+    let outputs = split_blocks(&node)
+        .into_par_iter()
+        .enumerate()
+        .map(|(index, block)| convert_block(index, block, &spec))
+        .collect::<Vec<Result<String, String>>>();
+    // the first error in the order of the input, as the sequential converter
+    // stops at it
+    Ok(outputs.into_iter().collect::<Result<Vec<_>, _>>()?.concat())
+}
+
+/// Split the top-level items of a syntax tree into the roots of blocks, where
+/// the converter starts each block in the same state as a new one does
+///
+/// It is the case after a paragraph break, or a line break after a display
+/// formula, since the converter is back in text mode at the start of a line
+/// and outside of any environment, unless the next item is a space, which is
+/// skipped or not depending on whether it starts a line. The text right after
+/// a display formula is not at the start of a line, e.g. the `-` of `\[y\]-x`
+/// is not a list marker.
+#[cfg(any(feature = "rayon", test))]
+fn split_blocks(root: &SyntaxNode) -> Vec<rowan::GreenNode> {
+    use LatexSyntaxKind::*;
+
+    let mut blocks = vec![];
+    let mut block = vec![];
+    let mut ended = false;
+    // whether the last item that is not a space is a display formula
+    let mut display = false;
+    for child in root.children_with_tokens() {
+        let kind = child.kind();
+        if ended && kind != TokenWhiteSpace {
+            blocks.push(rowan::GreenNode::new(
+                ScopeRoot.into(),
+                std::mem::take(&mut block),
+            ));
+            ended = false;
+        }
+        if kind == ItemParBreak || (display && kind == TokenLineBreak) {
+            ended = true;
+            display = false;
+        } else if kind != TokenWhiteSpace {
+            display = child
+                .as_node()
+                .cloned()
+                .and_then(FormulaItem::cast)
+                .map_or(false, |formula| formula.is_display());
+        }
+        block.push(match child {
+            rowan::NodeOrToken::Node(node) => rowan::NodeOrToken::Node(node.green().into_owned()),
+            rowan::NodeOrToken::Token(token) => rowan::NodeOrToken::Token(token.green().to_owned()),
+        });
+    }
+    blocks.push(rowan::GreenNode::new(ScopeRoot.into(), block));
+    blocks
+}
+
+/// Convert the block at an index of the ones split by [`split_blocks`], which
+/// doesn't depend on the other blocks
+#[cfg(any(feature = "rayon", test))]
+fn convert_block(
+    index: usize,
+    block: rowan::GreenNode,
+    spec: &CommandSpec,
+) -> Result<String, String> {
+    let mut output = String::new();
+    let mut out = Output::new(&mut output);
+    // the space at the start of the output is only dropped for the first
+    // block, see `Converter::flush_space`
+    out.len = usize::from(index > 0);
+    let mut ctx = Converter::new(LaTeXMode::Text);
+    let block = LatexSyntaxElem::Node(SyntaxNode::new_root(block));
+    ctx.convert(&mut out, block, spec)
+        .map_err(|e| e.to_string())?;
+    Ok(output)
+}
+
+/// For internal testing
+pub fn convert_math_no_macro(input: &str, spec: Option<CommandSpec>) -> Result<String, String> {
+    convert_inner(input, LaTeXMode::Math, spec, parse_without_macro)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::DEFAULT_SPEC;
+    use insta::{assert_debug_snapshot, assert_snapshot};
+
+    fn convert_text(input: &str) -> Result<String, String> {
+        crate::convert_text(input, Some(DEFAULT_SPEC.clone()))
+    }
+
+    fn convert_math(input: &str) -> Result<String, String> {
+        crate::convert_math(input, Some(DEFAULT_SPEC.clone()))
+    }
+
+    #[test]
+    fn test_convert_text_mode() {
+        assert_debug_snapshot!(convert_text(r#"abc"#), @r###"
+        Ok(
+            "abc",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\section{Title}"#), @r###"
+        Ok(
+            "= Title\n",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"a \textbf{strong} text"#), @r###"
+        Ok(
+            "a #strong[strong] text",
+        )
+        "###);
+        // the same input is a subscript only in math mode
+        assert_debug_snapshot!(convert_text(r#"a_b"#), @r###"
+        Ok(
+            "a\\_b",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"a_b"#), @r###"
+        Ok(
+            "a _(b )",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_heading() {
+        assert_debug_snapshot!(convert_text(r#"\section{A}"#), @r###"
+        Ok(
+            "= A\n",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\subsection*{B}"#), @r###"
+        Ok(
+            "#heading(level: 2, numbering: none)[B]",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\section[Short]{Long Title}"#), @r###"
+        Ok(
+            "= Long Title\n",
+        )
+        "###);
+        // a heading call is used if it doesn't start a line
+        assert_debug_snapshot!(convert_text(r#"Intro \section{A} Body"#), @r###"
+        Ok(
+            "Intro #heading(level: 1)[A] Body",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\footnote{\subsection{B}}"#), @r###"
+        Ok(
+            "#footnote[#heading(level: 2)[B]];",
+        )
+        "###);
+        // the short title is ignored with a warning
+        let (output, diagnostics) =
+            crate::convert_text_with_diagnostics(r#"\section[Short]{A}"#, None);
+        assert_eq!(output.unwrap(), "= A\n");
+        assert_eq!(
+            diagnostics,
+            vec![crate::Diagnostic::warning(
+                "the short title of a heading is ignored",
+                8..15
+            )]
+        );
+    }
+
+    #[test]
+    fn test_convert_font() {
+        assert_debug_snapshot!(convert_text(r#"\textbf{\textit{x}} \emph{y}"#), @r###"
+        Ok(
+            "#strong[#emph[x]] #emph[y]",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\textbf{a_b*c}"#), @r###"
+        Ok(
+            "#strong[a\\_b\\*c]",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\texttt{a_b \#c}"#), @r###"
+        Ok(
+            "#raw(\"a_b #c\");",
+        )
+        "###);
+        // inside a word and with inner spaces
+        assert_debug_snapshot!(convert_text(r#"a\textbf{b}c \emph{ x }"#), @r###"
+        Ok(
+            "a#strong[b]c #emph[ x ]",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_text_escape() {
+        assert_debug_snapshot!(convert_text(r#"a_b #tag 50% comment"#), @r###"
+        Ok(
+            "a\\_b \\#tag 50",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"50\% \& \#1 \_ \$ \{x\}"#), @r###"
+        Ok(
+            "50% & \\#1 \\_ \\$ {x}",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"a <b> `c` @d *e*"#), @r###"
+        Ok(
+            "a \\<b\\> \\`c\\` \\@d \\*e\\*",
+        )
+        "###);
+        // partly math and partly text
+        assert_debug_snapshot!(convert_text(r#"\% $\%_1$ \%"#), @r###"
+        Ok(
+            "% #math.equation(block: false, $percent _(1 )$); %",
+        )
+        "###);
+        // a heading or a list item is not started at the line start
+        assert_debug_snapshot!(convert_text("= a\n- b\n  +c = d - e"), @r###"
+        Ok(
+            "\\= a\n\\- b\n\\+c = d - e",
+        )
+        "###);
+        // a backslash resolved from the caret notation
+        assert_debug_snapshot!(convert_text(r#"a^^5cb"#), @r###"
+        Ok(
+            "a\\\\b",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_diagnostics() {
+        let (output, diagnostics) =
+            crate::convert_math_with_diagnostics(r#"\begin{pmatrix}x"#, None);
+        assert_debug_snapshot!(output, @r###"
+        Ok(
+            "pmatrix(x )",
+        )
+        "###);
+        assert_eq!(
+            diagnostics,
+            vec![crate::Diagnostic::error(
+                "unterminated environment `pmatrix`",
+                7..14
+            )]
+        );
+    }
+
+    #[test]
+    fn test_convert_strict() {
+        let strict = crate::ConvertOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let (output, diagnostics) =
+            crate::convert_math_with_options(r#"x \foobar y \left\baz x \right)"#, strict.clone());
+        assert_debug_snapshot!(output, @r###"
+        Ok(
+            "x   y  lr(  x  \\) )",
+        )
+        "###);
+        assert_eq!(
+            diagnostics,
+            vec![
+                crate::Diagnostic::error("unknown command `\\foobar`", 2..9),
+                crate::Diagnostic::error("unknown command `\\baz`", 17..21),
+            ]
+        );
+        // a command defined by a macro is known
+        let (output, diagnostics) =
+            crate::convert_math_with_options(r#"\newcommand{\foobar}{x}\foobar"#, strict);
+        assert!(output.is_ok());
+        assert_eq!(diagnostics, vec![]);
+
+        // the command is passed through otherwise
+        let (output, diagnostics) = crate::convert_math_with_diagnostics(r#"x \foobar y"#, None);
+        assert_debug_snapshot!(output, @r###"
+        Ok(
+            "x  foobar  y",
+        )
+        "###);
+        assert_eq!(
+            diagnostics,
+            vec![crate::Diagnostic::warning(
+                "unknown command `\\foobar`",
+                2..9
+            )]
+        );
+    }
+
+    #[test]
+    fn test_convert_discouraged() {
+        let options = crate::ConvertOptions {
+            discouraged: vec!["$$".to_owned(), "over".to_owned()],
+            ..Default::default()
+        };
+        let (output, diagnostics) =
+            crate::convert_text_with_options(r#"$$x$$ and \[{1 \over 2}\]"#, options);
+        assert_eq!(
+            output,
+            crate::convert_text(r#"$$x$$ and \[{1 \over 2}\]"#, None)
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                crate::Diagnostic::warning(
+                    "discouraged display math `$$`, use `\\[...\\]` instead",
+                    0..2
+                ),
+                crate::Diagnostic::warning("discouraged command `\\over`", 15..20),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_convert_collapse_whitespace() {
+        let collapse = |input| {
+            let options = crate::ConvertOptions {
+                collapse_whitespace: true,
+                ..Default::default()
+            };
+            crate::convert_text_with_options(input, options).0
+        };
+        assert_debug_snapshot!(collapse("  a   b\n  c\td  "), @r###"
+        Ok(
+            "a b c d",
+        )
+        "###);
+        assert_debug_snapshot!(collapse("a \n\n  \n b\r\n\r\nc"), @r###"
+        Ok(
+            "a\n\nb\n\nc",
+        )
+        "###);
+        assert_debug_snapshot!(collapse("a \\emph{b}  c"), @r###"
+        Ok(
+            "a #emph[b] c",
+        )
+        "###);
+        // the spacing of math is kept
+        assert_eq!(collapse("a  $x  y$"), crate::convert_text("a $x  y$", None));
+    }
+
+    #[test]
+    fn test_convert_quote() {
+        assert_debug_snapshot!(convert_text(r#"\begin{quote}To be or not to be.\end{quote}"#), @r###"
+        Ok(
+            "#quote(block: true)[To be or not to be.]",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\begin{quotation}a \begin{quote}b\end{quote} c\end{quotation}"#), @r###"
+        Ok(
+            "#quote(block: true)[a #quote(block: true)[b] c]",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text("\\begin{verse}\nRoses are red\\\\\nviolets are blue\n\\end{verse}"), @r###"
+        Ok(
+            "#quote(block: true)[Roses are red\\ \nviolets are blue]",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_alignment() {
+        assert_debug_snapshot!(convert_text(r#"\begin{center}a \emph{b}\end{center}"#), @r###"
+        Ok(
+            "#align(center)[a #emph[b]]",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\begin{flushleft}a\end{flushleft}"#), @r###"
+        Ok(
+            "#align(left)[a]",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\begin{flushright}a\end{flushright}"#), @r###"
+        Ok(
+            "#align(right)[a]",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\begin{center}
+\begin{tabular}{lr} a & b \end{tabular}
+\end{center}"#), @r###"
+        Ok(
+            "#align(center)[#table(columns: 2, align: (left, right), stroke: none, [a], [b]);]",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_footnote() {
+        assert_debug_snapshot!(convert_text(r#"a\footnote{See $x^2$ and \emph{this}.} b"#), @r###"
+        Ok(
+            "a#footnote[See #math.equation(block: false, $x ^(2 )$); and #emph[this].]; b",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"a\footnote[3]{b}"#), @r###"
+        Ok(
+            "a#footnote[b];",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_par_break() {
+        assert_debug_snapshot!(convert_text("a\nb"), @r###"
+        Ok(
+            "a\nb",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text("a\n\n  b"), @r###"
+        Ok(
+            "a\n\nb",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"a\par b"#), @r###"
+        Ok(
+            "a\n\nb",
+        )
+        "###);
+    }
+
+    /// The top-level blocks of a document, see `split_blocks`
+    const BLOCKS: &str = r#"\section{Intro}
+Some text with $x^2$ and \emph{emphasis}.
+
+\[ \frac{a}{b} \] right after a display formula,
+$$y$$\textbf{bold} and
+\begin{itemize}
+\item a
+
+\item b
+\end{itemize}
+
+\par
+    a paragraph with \% escapes
+
+\section{End}
+
+\[y\]-x
+\[z\]
+\section{A}
+\[w\]\section{B}"#;
+
+    #[test]
+    fn test_convert_blocks() {
+        use mitex_parser::{parse_in_mode, ParseMode};
+
+        let (node, _, _) = parse_in_mode(BLOCKS, DEFAULT_SPEC.clone(), ParseMode::Text);
+        let blocks = crate::split_blocks(&node);
+        assert_eq!(blocks.len(), 7);
+        // the blocks are converted from the last one, which gives the same
+        // output as the sequential converter
+        let mut outputs = blocks
+            .into_iter()
+            .enumerate()
+            .rev()
+            .map(|(index, block)| crate::convert_block(index, block, &DEFAULT_SPEC))
+            .collect::<Result<Vec<_>, _>>();
+        if let Ok(outputs) = &mut outputs {
+            outputs.reverse();
+        }
+        assert_eq!(
+            outputs.map(|outputs| outputs.concat()),
+            convert_text(BLOCKS)
+        );
+        // the text right after a display formula doesn't start a line
+        let output = convert_text(BLOCKS).unwrap();
+        assert!(output.contains("$-x"), "{output}");
+        assert!(output.contains("$#heading(level: 1)[B]"), "{output}");
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_convert_parallel() {
+        assert_eq!(
+            crate::convert_parallel(BLOCKS, None),
+            crate::convert_text(BLOCKS, None)
+        );
+        // the first error is reported
+        let input = "a\n\n\\item b\n\n\\item c";
+        assert_eq!(
+            crate::convert_text(input, None),
+            Err("error: item command outside of a list".to_owned())
+        );
+        assert_eq!(
+            crate::convert_parallel(input, None),
+            crate::convert_text(input, None)
+        );
+    }
+
+    #[test]
+    fn test_convert_source_map() {
+        use crate::SourceMapping;
+
+        let input = r#"\frac{a}{b}"#;
+        let (output, source_map) = crate::convert_math_with_source_map(input, None).unwrap();
+        assert_eq!(output, "frac(a ,b )");
+        assert_eq!(
+            source_map,
+            vec![
+                SourceMapping {
+                    latex: 0..11,
+                    typst: 0..11,
+                },
+                SourceMapping {
+                    latex: 6..7,
+                    typst: 5..7,
+                },
+                SourceMapping {
+                    latex: 9..10,
+                    typst: 8..10,
+                },
+            ]
+        );
+        assert_eq!(&input[source_map[1].latex.clone()], "a");
+        assert_eq!(&output[source_map[1].typst.clone()], "a ");
+        assert_eq!(&input[source_map[2].latex.clone()], "b");
+        assert_eq!(&output[source_map[2].typst.clone()], "b ");
+
+        // a word is expanded to many chars, while a group is only mapped by its
+        // braces, which are converted to nothing
+        let (output, source_map) = crate::convert_math_with_source_map(r#"ab{}"#, None).unwrap();
+        assert_eq!(output, "a b zws ");
+        assert_eq!(
+            source_map,
+            vec![SourceMapping {
+                latex: 0..2,
+                typst: 0..4,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_convert_to_writer() {
+        let input = r#"Hello \textbf{world} $\frac{a}{b}$ \begin{itemize} \item x \end{itemize}"#;
+        let mut output = Vec::<u8>::new();
+        crate::convert_to_writer(input, None, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            convert_text(input).unwrap()
+        );
+
+        let input = r#"\begin{pmatrix} a & b \\ c & d \end{pmatrix} \sqrt[3]{x}"#;
+        let mut output = Vec::<u8>::new();
+        crate::convert_math_to_writer(input, None, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            convert_math(input).unwrap()
+        );
+
+        // a conversion error is reported as invalid data
+        let mut output = Vec::<u8>::new();
+        let err = crate::convert_math_to_writer(r#"\item"#, None, &mut output).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_convert_to_writer_error() {
+        /// A writer failing after some bytes
+        struct Limited(usize);
+
+        impl std::io::Write for Limited {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                if buf.len() > self.0 {
+                    return Err(std::io::Error::other("full"));
+                }
+                self.0 -= buf.len();
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let input = "a ".repeat(10000);
+        let err = crate::convert_to_writer(&input, None, &mut Limited(100)).unwrap_err();
+        assert_eq!(err.to_string(), "full");
+    }
+
+    #[test]
+    fn test_convert_custom_command() {
+        let mut builder = crate::CommandSpecBuilder::from_spec(&DEFAULT_SPEC);
+        builder.command("abs", "{}", "abs");
+        let spec = builder.build().unwrap();
+        assert_debug_snapshot!(crate::convert_math(r#"$\abs{y}$"#, Some(spec)), @r###"
+        Ok(
+            "abs(y )",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_template() {
+        let mut builder = crate::CommandSpecBuilder::from_spec(&DEFAULT_SPEC);
+        builder
+            .command_template("norm", "{}", "norm({0})")
+            .command_template("nroot", "[]{}", "root({[0]=2}, {0})")
+            .command_template("str", "{}", "\"{0:raw}\"");
+        let spec = builder.build().unwrap();
+        let convert_math = |input| crate::convert_math(input, Some(spec.clone()));
+        assert_debug_snapshot!(convert_math(r#"$\norm{x}$"#), @r###"
+        Ok(
+            "norm(x )",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\norm{\frac{a}{b}}$"#), @r###"
+        Ok(
+            "norm(frac(a ,b ))",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\nroot{x}$"#), @r###"
+        Ok(
+            "root(2, x )",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\nroot[3]{x}$"#), @r###"
+        Ok(
+            "root(3 , x )",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\str{a_b}$"#), @r###"
+        Ok(
+            "\"a_b\"",
+        )
+        "###);
+        // the raw argument is escaped in a string
+        assert_debug_snapshot!(convert_math(r#"$\str{"\alpha"}$"#), @r###"
+        Ok(
+            "\"\\\"\\\\alpha\\\"\"",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_word() {
+        assert_debug_snapshot!(convert_math(r#"$abc$"#), @r###"
+        Ok(
+            "a b c ",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_greek() {
+        assert_debug_snapshot!(convert_math(r#"$\alpha x$"#), @r###"
+        Ok(
+            "alpha  x ",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_command() {
+        assert_debug_snapshot!(convert_math(r#"$\int_1^2 x \mathrm{d} x$"#), @r###"
+        Ok(
+            "integral _(1 )^(2 ) x  upright(d ) x ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\underline{T}$"#), @r###"
+        Ok(
+            "underline(T )",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_dots() {
+        assert_debug_snapshot!(convert_math(r#"$\ldots\cdots\vdots\ddots$"#), @r###"
+        Ok(
+            "dots.h dots.h.c dots.v dots.down ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$a, \dots, b$"#), @r###"
+        Ok(
+            "a \\, dots.h \\, b ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$a + \dots + b$"#), @r###"
+        Ok(
+            "a  +  dots.h.c  +  b ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$x_1 \dots$"#), @r###"
+        Ok(
+            "x _(1 ) dots.h ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"and so on\dots"#), @r###"
+        Ok(
+            "and so on…",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_spacing() {
+        assert_debug_snapshot!(convert_math(r#"$a\,b$"#), @r###"
+        Ok(
+            "a thin b ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$a\:b\;c$"#), @r###"
+        Ok(
+            "a med b thick c ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$a\quad b\qquad c$"#), @r###"
+        Ok(
+            "a quad  b wide  c ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$a\!b$"#), @r###"
+        Ok(
+            "a #h(-1em/6) b ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"a\,b\quad c"#), @r###"
+        Ok(
+            "a#h(1em/6);b#h(1em); c",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_operatorname() {
+        assert_debug_snapshot!(convert_math(r#"$\operatorname{lcm}(a,b)$"#), @r###"
+        Ok(
+            "op(\"lcm\") \\(a \\,b \\)",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\operatorname{arg max}_x f$"#), @r###"
+        Ok(
+            "op(\"argmax\") _(x ) f ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\operatorname*{argmax}_{x \in X} f$"#), @r###"
+        Ok(
+            "op(\"argmax\", limits: #true) _(x  in  X ) f ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\max_x f$"#), @r###"
+        Ok(
+            "max _(x ) f ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\DeclareMathOperator{\argmax}{arg\,max}\argmax_x f(x)$"#), @r###"
+        Ok(
+            "op(\"arg max\") _(x ) f \\(x \\)",
+        )
+        "###);
+        // the starred declaration defines an operator with limits
+        assert_debug_snapshot!(convert_math(r#"$\DeclareMathOperator*{\argmax}{argmax}\argmax_x f$"#), @r###"
+        Ok(
+            "op(\"argmax\", limits: #true) _(x ) f ",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_binom() {
+        assert_debug_snapshot!(convert_math(r#"$\binom{n}{k}$"#), @r###"
+        Ok(
+            "binom(n ,k )",
+        )
+        "###
+        );
+        // the sizing of the variants is done by their typst handlers
+        assert_debug_snapshot!(convert_math(r#"$\dbinom{n}{k}\tbinom{n+1}{2}$"#), @r###"
+        Ok(
+            "dbinom(n ,k )tbinom(n + 1 ,2 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"${n \choose k}$"#), @r###"
+        Ok(
+            "binom(n  , k )",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_infix() {
+        assert_debug_snapshot!(convert_math(r#"${1 \over 2}$"#), @r###"
+        Ok(
+            "frac(1  , 2 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$1 + {a \atop b}$"#), @r###"
+        Ok(
+            "1  +  atop(a  , b )",
+        )
+        "###
+        );
+        // an empty numerator
+        assert_debug_snapshot!(convert_math(r#"${\over 2}$"#), @r###"
+        Ok(
+            "frac(zws , 2 )",
+        )
+        "###
+        );
+        // ambiguous in TeX, which is associated to the right
+        assert_debug_snapshot!(convert_math(r#"$a \over b \over c$"#), @r###"
+        Ok(
+            "frac(a  ,frac( b  , c ))",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_brace() {
+        assert_debug_snapshot!(convert_math(r#"$\overline{x+y}\underline{ab}$"#), @r###"
+        Ok(
+            "overline(x + y )underline(a b )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\overbrace{1+2+3}^{three}$"#), @r###"
+        Ok(
+            "overbrace(1 + 2 + 3 ,t h r e e )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\underbrace{x}_n$"#), @r###"
+        Ok(
+            "underbrace(x ,n )",
+        )
+        "###
+        );
+        // without a label
+        assert_debug_snapshot!(convert_math(r#"$\overbrace{x}$"#), @r###"
+        Ok(
+            "mitexoverbrace(x )",
+        )
+        "###
+        );
+        // a subscript is not a label of an overbrace
+        assert_debug_snapshot!(convert_math(r#"$\overbrace{x}_n$"#), @r###"
+        Ok(
+            "mitexoverbrace(x )_(n )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\overbrace{\overbrace{a}^{b}+c}^{d}$"#), @r###"
+        Ok(
+            "overbrace(overbrace(a ,b )+ c ,d )",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_xarrow() {
+        assert_debug_snapshot!(convert_math(r#"$A \xrightarrow[f]{g} B$"#), @r###"
+        Ok(
+            "A  limits(xrightarrow(g ))_(f ) B ",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$A \xrightarrow{g} B$"#), @r###"
+        Ok(
+            "A  xrightarrow(g ) B ",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\xLeftrightarrow[]{}$"#), @r###"
+        Ok(
+            "xLeftrightarrow(zws )",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_frac() {
+        assert_debug_snapshot!(convert_math(r#"$\frac{a}{b}$"#), @r###"
+        Ok(
+            "frac(a ,b )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\frac 12_3$"#), @r###"
+        Ok(
+            "frac(1 ,2 )_(3 )",
+        )
+        "###
+        );
+        // the typst handler parenthesizes the numerator and the denominator
+        assert_debug_snapshot!(convert_math(r#"$\frac{a+b}{c}$"#), @r###"
+        Ok(
+            "frac(a + b ,c )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\dfrac{1}{2}\tfrac{1}{2}$"#), @r###"
+        Ok(
+            "dfrac(1 ,2 )tfrac(1 ,2 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\cfrac{1}{1+\cfrac{1}{1+\cfrac{1}{2}}}$"#), @r###"
+        Ok(
+            "cfrac(1 ,1 + cfrac(1 ,1 + cfrac(1 ,2 )))",
+        )
+        "###
+        );
+        // missing arguments
+        assert_debug_snapshot!(convert_math(r#"$\frac{1}$"#), @r###"
+        Ok(
+            "frac(1 ,zws)",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\frac$"#), @r###"
+        Ok(
+            "frac(zws,zws)",
+        )
+        "###
+        );
+        // Note: the following is invalid in TeX, hence we may output anything.
+        let _ = convert_math(r#"$\frac a_c b$"#);
+    }
+
+    #[test]
+    fn test_convert_genfrac() {
+        // reproduces `\binom`, `\frac` and `\atop`
+        assert_eq!(
+            convert_math(r#"$\genfrac{(}{)}{0pt}{}{n}{k}$"#),
+            convert_math(r#"$\binom{n}{k}$"#)
+        );
+        assert_eq!(
+            convert_math(r#"$\genfrac{}{}{}{}{a}{b}$"#),
+            convert_math(r#"$\frac{a}{b}$"#)
+        );
+        assert_debug_snapshot!(convert_math(r#"$\genfrac{}{}{0pt}{}{a}{b}$"#), @r###"
+        Ok(
+            "atop(a ,b )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\genfrac{.}{.}{1.5pt}{0}{a}{b}$"#), @r###"
+        Ok(
+            "display(frac(a ,b ))",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\genfrac{[}{]}{0pt}{1}{a}{b}$"#), @r###"
+        Ok(
+            "inline(lr(bracket.l atop(a ,b ) bracket.r))",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\genfrac{\langle}{|}{}{}{a}{b}$"#), @r###"
+        Ok(
+            "lr(angle.l frac(a ,b ) bar.v)",
+        )
+        "###
+        );
+        // only the left delimiter
+        assert_debug_snapshot!(convert_math(r#"$\genfrac{\{}{}{0pt}{}{a}{b}$"#), @r###"
+        Ok(
+            "lr(brace.l atop(a ,b ) )",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_braket() {
+        assert_debug_snapshot!(convert_math(r#"$\ket{0}$"#), @r###"
+        Ok(
+            "ket(0 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\bra{1}$"#), @r###"
+        Ok(
+            "bra(1 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\braket{a|b}$"#), @r###"
+        Ok(
+            "braket(a ,b )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\braket{\phi|\psi}$"#), @r###"
+        Ok(
+            "braket(phi ,psi )",
+        )
+        "###
+        );
+        // the bra is paired with itself by the typst handler without a bar
+        assert_debug_snapshot!(convert_math(r#"$\braket{a}$"#), @r###"
+        Ok(
+            "braket(a )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\braket{a|}$"#), @r###"
+        Ok(
+            "braket(a ,zws )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\ket{}$"#), @r###"
+        Ok(
+            "ket(zws )",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_siunitx() {
+        assert_debug_snapshot!(convert_math(r#"$\SI{9.8}{\meter\per\second\squared}$"#), @r###"
+        Ok(
+            "9.8 thin \"m/s²\" ",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\num{1.5e3}$"#), @r###"
+        Ok(
+            "1.5 times 10^(3) ",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\num{-2,5 E-3}$"#), @r###"
+        Ok(
+            "-2.5 times 10^(-3) ",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\num{2\times10^3}$"#), @r###"
+        Ok(
+            "2 times 10^(3) ",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\num{1.2\pm0.1e-2}$"#), @r###"
+        Ok(
+            "1.2 plus.minus 0.1 times 10^(-2) ",
+        )
+        "###
+        );
+        // a number with another command is kept as text
+        assert_debug_snapshot!(convert_math(r#"$\num{\alpha}$"#), @r###"
+        Ok(
+            "\"\\\\alpha\" ",
+        )
+        "###
+        );
+        // compound units
+        assert_debug_snapshot!(convert_math(r#"$\si{\kilogram\metre\per\ampere\per\second\squared}$"#), @r###"
+        Ok(
+            "\"kg m/(A s²)\" ",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\SI[round-mode=places]{10}{\square\centi\meter}$"#), @r###"
+        Ok(
+            "10 thin \"cm²\" ",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\si{\per\second}$"#), @r###"
+        Ok(
+            "\"1/s\" ",
+        )
+        "###
+        );
+        // a prefix without a unit is kept as a unit
+        assert_debug_snapshot!(convert_math(r#"$\si{\meter\per\kilo}$"#), @r###"
+        Ok(
+            "\"m/k\" ",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\si{\kilo m}$"#), @r###"
+        Ok(
+            "\"km\" ",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\si{m/s}$"#), @r###"
+        Ok(
+            "\"m/s\" ",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_text(r#"at \SI{3.2}{\meter\per\second}"#), @r###"
+        Ok(
+            "at #math.equation(block: false, $3.2 thin \"m/s\" $);",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_displaystyle() {
+        assert_debug_snapshot!(convert_math(r#"$\displaystyle xyz\frac{1}{2}$"#), @r###"
+        Ok(
+            "mitexdisplay( x y z frac(1 ,2 ))",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$1 + {\displaystyle 23} + 4$"#), @r###"
+        Ok(
+            "1  +  mitexdisplay( 2 3 ) +  4 ",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_limits() {
+        assert_debug_snapshot!(convert_math(r#"$\sum\limits_1^2$"#), @r###"
+        Ok(
+            "limits(sum )_(1 )^(2 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\int\nolimits_0^1$"#), @r###"
+        Ok(
+            "scripts(integral )_(0 )^(1 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\sum_{i=1}^{n} i$"#), @r###"
+        Ok(
+            "sum _(i = 1 )^(n ) i ",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\int_0^1 \prod_i \bigcup^n$"#), @r###"
+        Ok(
+            "integral _(0 )^(1 ) product _(i ) union.big ^(n )",
+        )
+        "###
+        );
+        // nested operators
+        assert_debug_snapshot!(convert_math(r#"$\sum_{i} \sum_{j}^{\sum_k k} a$"#), @r###"
+        Ok(
+            "sum _(i ) sum _(j )^(sum _(k ) k ) a ",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_primes() {
+        assert_debug_snapshot!(convert_math(r#"$f'(x)$"#), @r###"
+        Ok(
+            "f '\\(x \\)",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$x''_i y'''$"#), @r###"
+        Ok(
+            "x ''_(i ) y '''",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$f'_0 f'^2$"#), @r###"
+        Ok(
+            "f '_(0 ) f '^(2 )",
+        )
+        "###
+        );
+        // a lone prime
+        assert_debug_snapshot!(convert_math(r#"$'$"#), @r###"
+        Ok(
+            "'",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_subsup() {
+        assert_debug_snapshot!(convert_math(r#"$x_1^2$"#), @r###"
+        Ok(
+            "x _(1 )^(2 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$x^2_1$"#), @r###"
+        Ok(
+            "x ^(2 )_(1 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$x''_1$"#), @r###"
+        Ok(
+            "x ''_(1 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\overbrace{a + b + c}^{\text{This is an overbrace}}$"#), @r###"
+        Ok(
+            "mitexoverbrace(a  +  b  +  c )^(#textmath[This is an overbrace];)",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$x_1''$"#), @r###"
+        Ok(
+            "x _(1 )''",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"${}_1^2x_3^4$"#), @r###"
+        Ok(
+            "zws _(1 )^(2 )x _(3 )^(4 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$_1^2$"#), @r###"
+        Ok(
+            "zws_(1 )zws^(2 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\frac{_1^2}{3}$"#), @r###"
+        Ok(
+            "frac(zws_(1 )zws^(2 ),3 )",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_over() {
+        assert_debug_snapshot!(convert_math(r#"$x + 1 \over y + 2$"#), @r###"
+        Ok(
+            "frac(x  +  1  , y  +  2 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$1 + {2 \over 3}$"#), @r###"
+        Ok(
+            "1  +  frac(2  , 3 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"${l \over 2'}$"#), @r###"
+        Ok(
+            "frac(l  , 2 ')",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_divide() {
+        assert_debug_snapshot!(convert_math(r#"$x / y$"#), @r###"
+        Ok(
+            "x  \\/ y ",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_space() {
+        assert_debug_snapshot!(convert_math(r#"$x~\! \, \> \: \; \ \quad \qquad y$"#), @r###"
+        Ok(
+            "x space.nobreak negthinspace  thin  med  med  thick  thick  quad  wide  y ",
+        )
+        "###
+        );
+        // control spaces, whose command names are empty
+        assert_debug_snapshot!(convert_math(r#"a\ b\"#), @r###"
+        Ok(
+            "a thick  b thick ",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_text(r#"a\ b\	c \"#), @r###"
+        Ok(
+            "a b\tc ",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_escape() {
+        assert_debug_snapshot!(convert_math(r#"$\|x\|| \& \# \% \$ y$"#), @r###"
+        Ok(
+            "|| x || |  amp  hash  percent  dollar  y ",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$a*b * c$"#).unwrap(), @r###""a \\*b  \\* c ""###
+        );
+        assert_debug_snapshot!(convert_math(r#"$"$"#).unwrap(), @r###""\\\"""###
+        );
+        assert_debug_snapshot!(convert_text(r#"@abc"#).unwrap(), @r###""\\@abc""###
+        );
+    }
+
+    #[test]
+    fn test_unreachable() {
+        // println!("{:#?}", convert_math(r#"$u^-$"#));
+        assert_debug_snapshot!(convert_math(r#"$u^−$"#).unwrap(), @r###""u ^(− )""###
+        );
+    }
+
+    #[test]
+    fn test_convert_math_alphabets() {
+        assert_debug_snapshot!(convert_math(r#"$\mathbb{R}\mathcal{L}\mathfrak{g}$"#), @r###"
+        Ok(
+            "bb(R )cal(L )frak(g )",
+        )
+        "###
+        );
+        // `\mathbf` is upright as well as bold in LaTeX
+        assert_debug_snapshot!(convert_math(r#"$\mathbf{x}\mathrm{d}$"#), @r###"
+        Ok(
+            "mitexmathbf(x )upright(d )",
+        )
+        "###
+        );
+        // the whole group is wrapped
+        assert_debug_snapshot!(convert_math(r#"$\mathbb{RR} \mathbf{x_1}$"#), @r###"
+        Ok(
+            "bb(R R ) mitexmathbf(x _(1 ))",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_accents() {
+        assert_debug_snapshot!(convert_math(r#"$\vec{v}\dot{x}\ddot{x}\bar{y}$"#), @r###"
+        Ok(
+            "arrow(v )dot(x )dot.double(x )macron(y )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\widehat{ABC}\widetilde{x+y}$"#), @r###"
+        Ok(
+            "hat(A B C )tilde(x + y )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\hat{AB} \hat \alpha$"#), @r###"
+        Ok(
+            "hat(A B ) hat(alpha )",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_sqrt() {
+        assert_debug_snapshot!(convert_math(r#"$\sqrt 1$"#), @r###"
+        Ok(
+            "mitexsqrt(1 )",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\sqrt [1]2$"#), @r###"
+        Ok(
+            "mitexsqrt(\\[1 \\],2 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\sqrt{x}\sqrt[3]{8}$"#), @r###"
+        Ok(
+            "mitexsqrt(x )mitexsqrt(\\[3 \\],8 )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\sqrt{\frac{a}{b}}$"#), @r###"
+        Ok(
+            "mitexsqrt(frac(a ,b ))",
+        )
+        "###
+        );
+        // takes a single token as TeX does
+        assert_debug_snapshot!(convert_math(r#"$\sqrt\alpha x$"#), @r###"
+        Ok(
+            "mitexsqrt(alpha ) x ",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_lr() {
+        assert_debug_snapshot!(convert_math(r#"$\left.\right.$"#), @r###"
+        Ok(
+            "lr(  )",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\left.a\right.$"#), @r###"
+        Ok(
+            "lr( a  )",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\alpha\left.\right.$"#), @r###"
+        Ok(
+            "alpha lr(  )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\left( a \right.$"#), @r###"
+        Ok(
+            "lr(\\(  a   )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\left. a \right)$"#), @r###"
+        Ok(
+            "lr(  a  \\) )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\left  . a \right    \|$"#), @r###"
+        Ok(
+            "lr(     a       || )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\left\langle a\right\|$"#), @r###"
+        Ok(
+            "lr(angle.l  a || )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\left(\frac{a}{b}\right)$"#), @r###"
+        Ok(
+            "lr(\\( frac(a ,b )\\) )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\left\{ x \right.$"#), @r###"
+        Ok(
+            "lr(\\{  x   )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\left| x \right|$"#), @r###"
+        Ok(
+            "lr(|  x  | )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\left< a \right>$"#), @r###"
+        Ok(
+            "lr(angle.l  a  angle.r )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\left\lbrack\lbrack x\rbrack\right\rbrack$"#), @r###"
+        Ok(
+            "lr(bracket.l bracket.l  x bracket.r bracket.r )",
+        )
+        "###
+        );
+        // command delimiters
+        assert_debug_snapshot!(convert_math(r#"$\left\langle x \right\rangle$"#), @r###"
+        Ok(
+            "lr(angle.l  x  angle.r )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\left\lfloor x \right\rfloor$"#), @r###"
+        Ok(
+            "lr(⌊  x  ⌋ )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\left\| x \right\|$"#), @r###"
+        Ok(
+            "lr(||  x  || )",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$\left\lt x \right\gt < 1$"#), @r###"
+        Ok(
+            "lr(angle.l  x  angle.r ) <  1 ",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_color() {
+        assert_debug_snapshot!(convert_math(r#"$x\color{red}yz\frac{1}{2}$"#), @r###"
+        Ok(
+            "x text(fill: #rgb(255, 0, 0),y z frac(1 ,2 ))",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$x\textcolor{red}yz$"#), @r###"
+        Ok(
+            "x text(fill: #rgb(255, 0, 0),y )z ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$x\textcolor{red}{yz}$"#), @r###"
+        Ok(
+            "x text(fill: #rgb(255, 0, 0),y z )",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$x\colorbox{red}yz$"#), @r###"
+        Ok(
+            "x box(fill: #rgb(255, 0, 0),y )z ",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(r#"$x\colorbox{red}{yz}$"#), @r###"
+        Ok(
+            "x box(fill: #rgb(255, 0, 0),y z )",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_text_color() {
+        let convert = |input| crate::convert_text_with_diagnostics(input, None);
+
+        assert_debug_snapshot!(convert(r#"\textcolor{red}{warn}"#).0, @r###"
+        Ok(
+            "#text(fill: rgb(255, 0, 0))[warn];",
+        )
+        "###);
+        assert_debug_snapshot!(convert(r#"{\color{blue} rest} done"#).0, @r###"
+        Ok(
+            "#text(fill: rgb(0, 0, 255))[rest]; done",
+        )
+        "###);
+        assert_debug_snapshot!(convert(r#"\textcolor[HTML]{FF0000}{x}"#).0, @r###"
+        Ok(
+            "#text(fill: rgb(\"#FF0000\"))[x];",
+        )
+        "###);
+        assert_debug_snapshot!(convert(r#"\textcolor[rgb]{1,0.5,0}{x}"#).0, @r###"
+        Ok(
+            "#text(fill: rgb(100%, 50%, 0%))[x];",
+        )
+        "###);
+        assert_debug_snapshot!(convert(r#"\colorbox[HTML]{FFFF00}{note}"#).0, @r###"
+        Ok(
+            "#box(fill: rgb(\"#FFFF00\"))[note];",
+        )
+        "###);
+        // an unknown color is ignored with a warning
+        let (output, diagnostics) = convert(r#"\textcolor{foo}{x}"#);
+        assert_eq!(output.unwrap(), "x");
+        assert_eq!(
+            diagnostics,
+            vec![crate::Diagnostic::warning(
+                "unknown color `foo` is ignored",
+                0..18
+            )]
+        );
+    }
+
+    #[test]
+    fn test_convert_matrix() {
+        assert_debug_snapshot!(convert_math(
+                     r#"$\begin{pmatrix}x{\\}x\end{pmatrix}$"#
+            ).unwrap(),
+            @r###""pmatrix(x x )""###
+        );
+        assert_debug_snapshot!(convert_math(
+                     r#"$\begin{pmatrix} \\ & \ddots \end{pmatrix}$"#
+            ).unwrap(),
+            @r###""pmatrix( zws ; zws , dots.down  )""###
+        );
+        assert_debug_snapshot!(convert_math(
+                r#"$\begin{matrix}
+        1 & 2 & 3\\
+a & b & c
+\end{matrix}$"#
+            ),
+            @r###"
+        Ok(
+            "matrix(\n1  zws , 2  zws , 3 zws ;\na  zws , b  zws , c \n)",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(
+                r#"$\begin{Vmatrix}
+        1 & 2 & 3\\
+a & b & c
+\end{Vmatrix}$"#
+            ),
+            @r###"
+        Ok(
+            "Vmatrix(\n1  zws , 2  zws , 3 zws ;\na  zws , b  zws , c \n)",
+        )
+        "###
+        );
+        assert_debug_snapshot!(convert_math(
+                r#"$\begin{array}{lcr}
+        1 & 2 & 3\\
+a & b & c
+\end{array}$"#
+            ),
+            @r###"
+        Ok(
+            "mitexarray(align: #(left, center, right,), \n1  zws , 2  zws , 3 zws ;\na  zws , b  zws , c \n)",
+        )
+        "###
+        );
+    }
+
+    #[test]
+    fn test_convert_array() {
+        assert_debug_snapshot!(convert_math(r#"$\begin{array}{cc} a & b \\ c & d \end{array}$"#), @r###"
+        Ok(
+            "mitexarray(align: #(center, center,),  a  zws , b  zws ; c  zws , d  )",
+        )
+        "###);
+        // vertical rules
+        assert_debug_snapshot!(convert_math(r#"$\begin{array}{c|c} a & b \end{array}$"#), @r###"
+        Ok(
+            "mitexarray(align: #(center, center,), vlines: #(1,),  a  zws , b  )",
+        )
+        "###);
+        // a short row is padded, and `\\` in a group is still a new row
+        assert_debug_snapshot!(convert_math(r#"$\begin{array}{ccc} a & b & c \\ {x \\ y} \end{array}$"#), @r###"
+        Ok(
+            "mitexarray(align: #(center, center, center,),  a  zws , b  zws , c  zws ; x  zws ,zws zws ,zws zws ; y  zws ,zws zws ,zws )",
+        )
+        "###);
+        // a trailing `\\` doesn't start a new row
+        assert_debug_snapshot!(convert_math(r#"$\begin{array}{cc} a & \\ \end{array}$"#), @r###"
+        Ok(
+            "mitexarray(align: #(center, center,),  a  zws ,zws )",
+        )
+        "###);
+    }
 
-pub fn convert_text(input: &str, spec: Option<CommandSpec>) -> Result<String, String> {
-    convert_inner(input, LaTeXMode::Text, spec, parse)
-}
+    #[test]
+    fn test_convert_align() {
+        assert_debug_snapshot!(convert_math(r#"$\begin{align}a &= b \\ c &= d\end{align}$"#), @r###"
+        Ok(
+            "aligned(a  &=  b  \\  c  &=  d )",
+        )
+        "###);
+        // trailing newline
+        assert_debug_snapshot!(convert_math(r#"$\begin{align*}a &= b \\ c &= d \\\end{align*}$"#), @r###"
+        Ok(
+            "aligned(a  &=  b  \\  c  &=  d  )",
+        )
+        "###);
+        // three columns
+        assert_debug_snapshot!(convert_math(r#"$\begin{align}a &= b & c &= d\end{align}$"#), @r###"
+        Ok(
+            "aligned(a  &=  b  & c  &=  d )",
+        )
+        "###);
+        // empty cells
+        assert_debug_snapshot!(convert_math(r#"$\begin{align}&= b \\ &\end{align}$"#), @r###"
+        Ok(
+            "aligned(&=  b  \\  &)",
+        )
+        "###);
+    }
 
-pub fn convert_math(input: &str, spec: Option<CommandSpec>) -> Result<String, String> {
-    convert_inner(input, LaTeXMode::Math, spec, parse)
-}
+    #[test]
+    fn test_convert_matrix_variants() {
+        assert_debug_snapshot!(convert_math(r#"$\begin{pmatrix} a & b \\ c & d \end{pmatrix}$"#), @r###"
+        Ok(
+            "pmatrix( a  zws , b  zws ; c  zws , d  )",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\begin{vmatrix} a & b \\ c & d \end{vmatrix}$"#), @r###"
+        Ok(
+            "vmatrix( a  zws , b  zws ; c  zws , d  )",
+        )
+        "###);
+        // ragged rows
+        assert_debug_snapshot!(convert_math(r#"$\begin{pmatrix} 1 & 2 \\ 3 \end{pmatrix}$"#), @r###"
+        Ok(
+            "pmatrix( 1  zws , 2  zws ; 3  )",
+        )
+        "###);
+        // empty matrix
+        assert_debug_snapshot!(convert_math(r#"$\begin{pmatrix}\end{pmatrix}$"#), @r###"
+        Ok(
+            "pmatrix()",
+        )
+        "###);
+        // a matrix in the script size
+        assert_debug_snapshot!(convert_math(r#"$\begin{smallmatrix} a & b \\ c & d \end{smallmatrix}$"#), @r###"
+        Ok(
+            "smallmatrix( a  zws , b  zws ; c  zws , d  )",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\begin{smallmatrix} a & b \\ c \end{smallmatrix}$"#), @r###"
+        Ok(
+            "smallmatrix( a  zws , b  zws ; c  )",
+        )
+        "###);
+        // starred matrix with an alignment option
+        assert_debug_snapshot!(convert_math(r#"$\begin{pmatrix*}[r] 1 & 2 \\ 3 & 4 \end{pmatrix*}$"#), @r###"
+        Ok(
+            "mitexpmatrixstar(arg0: \\[r \\], 1  zws , 2  zws ; 3  zws , 4  )",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\begin{matrix*} 1 & 2 \\ 3 & 4 \end{matrix*}$"#), @r###"
+        Ok(
+            "mitexmatrixstar( 1  zws , 2  zws ; 3  zws , 4  )",
+        )
+        "###);
+    }
 
-/// For internal testing
-pub fn convert_math_no_macro(input: &str, spec: Option<CommandSpec>) -> Result<String, String> {
-    convert_inner(input, LaTeXMode::Math, spec, parse_without_macro)
-}
+    #[test]
+    fn test_convert_cases() {
+        assert_debug_snapshot!(convert_math(r#"$\begin{cases} x & a<0 \\ y & a\ge0 \end{cases}$"#), @r###"
+        Ok(
+            "cases( x  & a < 0  , y  & a >= 0  )",
+        )
+        "###);
+        // single row
+        assert_debug_snapshot!(convert_math(r#"$\begin{cases} x & a<0 \end{cases}$"#), @r###"
+        Ok(
+            "cases( x  & a < 0  )",
+        )
+        "###);
+        // trailing newline
+        assert_debug_snapshot!(convert_math(r#"$\begin{cases} x & a<0 \\ \end{cases}$"#), @r###"
+        Ok(
+            "cases( x  & a < 0   )",
+        )
+        "###);
+        // the mathtools variant
+        assert_debug_snapshot!(convert_math(r#"$\begin{dcases} x & a<0 \\ y & a\ge0 \end{dcases}$"#), @r###"
+        Ok(
+            "cases( x  & a < 0  , y  & a >= 0  )",
+        )
+        "###);
+    }
 
-#[cfg(test)]
-mod tests {
-    use crate::DEFAULT_SPEC;
-    use insta::{assert_debug_snapshot, assert_snapshot};
+    #[test]
+    fn test_convert_list() {
+        assert_debug_snapshot!(convert_text(r#"\begin{enumerate}
+\item a
+\begin{itemize}
+\item b
+\end{itemize}
+\item c
+\end{enumerate}"#), @r###"
+        Ok(
+            " \n+ a  \n  - b  \n+ c \n",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\begin{description}
+\item[Apple] red
+\item[Banana] yellow
+\end{description}"#), @r###"
+        Ok(
+            " \n/ Apple: red \n/ Banana: yellow \n",
+        )
+        "###);
+        // text before the first item and after the list
+        assert_debug_snapshot!(convert_text(r#"\begin{itemize} intro \item a \end{itemize} after"#), @r###"
+        Ok(
+            " intro \n- a \nafter",
+        )
+        "###);
+        // a label is the marker of the item
+        assert_debug_snapshot!(convert_text(r#"\begin{itemize} \item[--] a \item b \end{itemize}"#), @r###"
+        Ok(
+            " \n#list(marker: [–])[a ]\n- b \n",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\begin{enumerate} \item a \item[(i)] b \end{enumerate}"#), @r###"
+        Ok(
+            " \n+ a \n#list(marker: [\\(i\\)])[b ]\n",
+        )
+        "###);
+    }
 
-    fn convert_text(input: &str) -> Result<String, String> {
-        crate::convert_text(input, Some(DEFAULT_SPEC.clone()))
+    #[test]
+    fn test_convert_link() {
+        assert_debug_snapshot!(convert_text(r#"\url{https://example.com/~user/a_b#top}"#), @r###"
+        Ok(
+            "#link(\"https://example.com/~user/a_b#top\");",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"see \href{https://example.com/\#x}{the \textbf{docs}}."#), @r###"
+        Ok(
+            "see #link(\"https://example.com/#x\")[the #strong[docs]];.",
+        )
+        "###);
+        // the url is lexed verbatim, so `%` doesn't start a comment
+        assert_debug_snapshot!(convert_text(r#"\href{https://example.com/a%20b}{c} d"#), @r###"
+        Ok(
+            "#link(\"https://example.com/a%20b\")[c]; d",
+        )
+        "###);
     }
 
-    fn convert_math(input: &str) -> Result<String, String> {
-        crate::convert_math(input, Some(DEFAULT_SPEC.clone()))
+    #[test]
+    fn test_convert_includegraphics() {
+        let convert = |input| crate::convert_text_with_diagnostics(input, None);
+
+        assert_debug_snapshot!(convert(r#"\includegraphics{a.pdf}"#), @r###"
+        (
+            Ok(
+                "#image(\"a.pdf\");",
+            ),
+            [],
+        )
+        "###);
+        assert_debug_snapshot!(convert(r#"\includegraphics[width=5cm]{fig.png}"#).0, @r###"
+        Ok(
+            "#image(\"fig.png\", width: 5cm);",
+        )
+        "###);
+        assert_debug_snapshot!(convert(r#"\includegraphics[width=\linewidth, height=0.5\textwidth]{fig.png}"#).0, @r###"
+        Ok(
+            "#image(\"fig.png\", width: 100%, height: 50%);",
+        )
+        "###);
+        assert_debug_snapshot!(convert(r#"\includegraphics[scale=0.5]{fig.png}"#).0, @r###"
+        Ok(
+            "#scale(50%, reflow: true, image(\"fig.png\"));",
+        )
+        "###);
+        // an unknown option is ignored with a warning
+        let (output, diagnostics) = convert(r#"\includegraphics[angle=90,width=5cm]{a.png}"#);
+        assert_eq!(output.unwrap(), "#image(\"a.png\", width: 5cm);");
+        assert_eq!(
+            diagnostics,
+            vec![crate::Diagnostic::warning(
+                "unknown option `angle` of `\\includegraphics` is ignored",
+                0..43
+            )]
+        );
     }
 
     #[test]
-    fn test_convert_text_mode() {
-        assert_debug_snapshot!(convert_text(r#"abc"#), @r###"
+    fn test_convert_tabular() {
+        assert_debug_snapshot!(convert_text(r#"\begin{tabular}{lcr} a & b & c \\ d & e & f \end{tabular}"#), @r###"
         Ok(
-            "abc",
+            "#table(columns: 3, align: (left, center, right), stroke: none, [a], [b], [c], [d], [e], [f]);",
         )
         "###);
-        assert_debug_snapshot!(convert_text(r#"\section{Title}"#), @r###"
+        assert_debug_snapshot!(convert_text(r#"\begin{tabular}{|l|r|}\hline a & b \\ \hline c & d \\ \hline\end{tabular}"#), @r###"
         Ok(
-            "#heading(level: 1)[Title]",
+            "#table(columns: 2, align: (left, right), stroke: none, table.vline(x: 0), table.vline(x: 1), table.vline(x: 2), table.hline(), [a], [b], table.hline(), [c], [d], table.hline());",
         )
         "###);
-        assert_debug_snapshot!(convert_text(r#"a \textbf{strong} text"#), @r###"
+        assert_debug_snapshot!(convert_text(r#"\begin{tabular}{p{2cm}r} \multicolumn{2}{c}{\textbf{Title}} \\ a & b \end{tabular}"#), @r###"
         Ok(
-            "a #strong[strong] text",
+            "#table(columns: (2cm, auto), align: (left, right), stroke: none, table.cell(colspan: 2, align: center)[#strong[Title]], [a], [b]);",
         )
         "###);
     }
 
     #[test]
-    fn test_convert_word() {
-        assert_debug_snapshot!(convert_math(r#"$abc$"#), @r###"
+    fn test_table_columns_repeated() {
+        let columns = crate::TableColumns::parse("*{3}{|c}|");
+        assert_eq!(columns.align, vec!["center"; 3]);
+        assert_eq!(columns.vlines, vec![0, 1, 2, 3]);
+        // nested repetitions are capped in total
+        let columns = crate::TableColumns::parse("*{64}{*{64}{*{64}{*{64}{*{64}{c}}}}}");
+        assert_eq!(columns.align.len(), crate::TableColumns::MAX_COLUMNS);
+        // and are not repeated without columns
+        let columns = crate::TableColumns::parse("*{64}{*{64}{*{64}{*{64}{*{64}{|}}}}}");
+        assert_eq!(columns.align.len(), 0);
+        assert_eq!(columns.vlines, vec![0]);
+    }
+
+    #[test]
+    fn test_convert_overset() {
+        assert_debug_snapshot!(convert_math(r#"$a \overset{def}{=} b$"#), @r###"
         Ok(
-            "a b c ",
+            "a  overset(d e f ,= ) b ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\underset{x}{y}$"#), @r###"
+        Ok(
+            "underset(x ,y )",
+        )
+        "###);
+        // nested
+        assert_debug_snapshot!(convert_math(r#"$\underset{a}{\overset{b}{c}}$"#), @r###"
+        Ok(
+            "underset(a ,overset(b ,c ))",
         )
         "###);
     }
 
     #[test]
-    fn test_convert_greek() {
-        assert_debug_snapshot!(convert_math(r#"$\alpha x$"#), @r###"
+    fn test_convert_stackrel() {
+        assert_debug_snapshot!(convert_math(r#"$a \stackrel{?}{=} b$"#), @r###"
         Ok(
-            "alpha  x ",
+            "a  stackrel(? ,= ) b ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\stackrel{\text{def}}{=}$"#), @r###"
+        Ok(
+            "stackrel(#textmath[def];,= )",
         )
         "###);
     }
 
     #[test]
-    fn test_convert_command() {
-        assert_debug_snapshot!(convert_math(r#"$\int_1^2 x \mathrm{d} x$"#), @r###"
+    fn test_convert_not() {
+        assert_debug_snapshot!(convert_math(r#"$a \not= b$"#), @r###"
         Ok(
-            "integral _(1 )^(2 ) x  upright(d ) x ",
+            "a  !=  b ",
         )
         "###);
-        assert_debug_snapshot!(convert_math(r#"$\underline{T}$"#), @r###"
+        assert_debug_snapshot!(convert_math(r#"$x \not\in A$"#), @r###"
         Ok(
-            "underline(T )",
+            "x  in.not  A ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\not{\subset}$"#), @r###"
+        Ok(
+            "subset.not ",
+        )
+        "###);
+        // no negated symbol
+        assert_debug_snapshot!(convert_math(r#"$\not a$"#), @r###"
+        Ok(
+            "cancel(a )",
         )
         "###);
     }
 
     #[test]
-    fn test_convert_frac() {
-        assert_debug_snapshot!(convert_math(r#"$\frac{a}{b}$"#), @r###"
+    fn test_convert_cancel() {
+        assert_debug_snapshot!(convert_math(r#"$\cancel{x}$"#), @r###"
         Ok(
-            "frac(a ,b )",
+            "cancel(x )",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"$\frac 12_3$"#), @r###"
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\bcancel{y} + \xcancel{a + b}$"#), @r###"
         Ok(
-            "frac(1 ,2 )_(3 )",
+            "bcancel(y ) +  xcancel(a  +  b )",
         )
-        "###
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\cancelto{0}{x^2}$"#), @r###"
+        Ok(
+            "cancelto(0 ,x ^(2 ))",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_sized_delimiter() {
+        assert_debug_snapshot!(convert_math(r#"$\bigl(x\bigr)$"#), @r###"
+        Ok(
+            "class(#\"opening\", lr(size: #120%, \\())x class(#\"closing\", lr(size: #120%, \\)))",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\Bigg[y\Bigg]$"#), @r###"
+        Ok(
+            "lr(size: #300%, \\[)y lr(size: #300%, \\])",
+        )
+        "###);
+        // mismatched sizes
+        assert_debug_snapshot!(convert_math(r#"$\bigl( x \Bigr)$"#), @r###"
+        Ok(
+            "class(#\"opening\", lr(size: #120%, \\()) x  class(#\"closing\", lr(size: #180%, \\)))",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_boxed() {
+        assert_debug_snapshot!(convert_math(r#"$\boxed{E=mc^2}$"#), @r###"
+        Ok(
+            "boxed(E = m c ^(2 ))",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$a + \boxed{\frac{1}{2}}$"#), @r###"
+        Ok(
+            "a  +  boxed(frac(1 ,2 ))",
+        )
+        "###);
+        // the argument is still math in text mode
+        assert_debug_snapshot!(convert_text(r#"\boxed{x_1}"#), @r###"
+        Ok(
+            "#math.equation(block: false, $boxed(x _(1 ))$);",
+        )
+        "###);
+        // so are the arguments of any command taking them in math
+        let mut builder = crate::CommandSpecBuilder::from_spec(&DEFAULT_SPEC);
+        let shape = crate::CmdShape {
+            args: crate::ArgShape::Right(crate::ArgPattern::FixedLenTerm(1)),
+            alias: Some("cancel".to_owned()),
+            template: None,
+            math_args: true,
+        };
+        builder.item("cancel", crate::CommandSpecItem::Cmd(shape));
+        let spec = builder.build().unwrap();
+        assert_debug_snapshot!(crate::convert_text(r#"\cancel{x_1}"#, Some(spec)), @r###"
+        Ok(
+            "#math.equation(block: false, $cancel(x _(1 ))$);",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_ensuremath() {
+        assert_debug_snapshot!(convert_text(r#"a \ensuremath{\alpha} b"#), @r###"
+        Ok(
+            "a #math.equation(block: false, $alpha $); b",
+        )
+        "###);
+        assert_eq!(
+            convert_text(r#"if \ensuremath{x_1 > 0}"#),
+            convert_text(r#"if ${x_1 > 0}$"#)
+        );
+        // it is a no-op in math
+        assert_eq!(
+            convert_math(r#"$\ensuremath{\alpha} + 1$"#),
+            convert_math(r#"${\alpha} + 1$"#)
         );
-        // Note: the following is invalid in TeX, hence we may output anything.
-        let _ = convert_math(r#"$\frac a_c b$"#);
     }
 
     #[test]
-    fn test_convert_displaystyle() {
-        assert_debug_snapshot!(convert_math(r#"$\displaystyle xyz\frac{1}{2}$"#), @r###"
+    fn test_convert_equation() {
+        assert_debug_snapshot!(convert_text(r#"\begin{equation}\label{e}E=mc^2\end{equation}"#), @r###"
+        Ok(
+            "#math.equation(block: true, numbering: \"(1)\", $ E = m c ^(2 ) $) <e>",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\begin{equation} a = b \tag{*} \label{eq:ab} \end{equation}"#), @r###"
+        Ok(
+            "#math.equation(block: true, numbering: _ => \"(*)\", $ a  =  b $) <eq:ab>",
+        )
+        "###);
+        // an unnumbered equation has no label
+        assert_debug_snapshot!(convert_text(r#"\begin{equation*} a \label{x} \end{equation*}"#), @r###"
+        Ok(
+            "$ a $",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\begin{equation*} a \tag{1.2} \end{equation*}"#), @r###"
+        Ok(
+            "#math.equation(block: true, numbering: _ => \"(1.2)\", $ a $)",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_theorem() {
+        assert_debug_snapshot!(convert_text(r#"\begin{theorem} Every $n > 1$ has a prime factor. \end{theorem}"#), @r###"
+        Ok(
+            "#block[*Theorem.* Every #math.equation(block: false, $n  >  1 $); has a prime factor.]",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\begin{theorem}[Euler] $e^{i \pi} + 1 = 0$ \end{theorem}"#), @r###"
+        Ok(
+            "#block[*Theorem (Euler).* #math.equation(block: false, $e ^(i  pi ) +  1  =  0 $);]",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\begin{proof} Trivial. \end{proof}"#), @r###"
+        Ok(
+            "#block[_Proof._ Trivial. #h(1fr) $square$]",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\begin{proof}[Proof of the claim] Trivial. \end{proof}"#), @r###"
         Ok(
-            "mitexdisplay( x y z frac(1 ,2 ))",
+            "#block[_Proof of the claim._ Trivial. #h(1fr) $square$]",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"$1 + {\displaystyle 23} + 4$"#), @r###"
+        "###);
+        // the other theorem-like environments are converted alike
+        assert_debug_snapshot!(convert_text(r#"\begin{lemma}[Zorn] Maximal. \end{lemma}"#), @r###"
         Ok(
-            "1  +  mitexdisplay( 2 3 ) +  4 ",
+            "#block[*Lemma (Zorn).* Maximal.]",
         )
-        "###
-        );
+        "###);
     }
 
     #[test]
-    fn test_convert_limits() {
-        assert_debug_snapshot!(convert_math(r#"$\sum\limits_1^2$"#), @r###"
+    fn test_convert_figure() {
+        assert_debug_snapshot!(convert_text(r#"\begin{figure} \includegraphics{x} \caption{A figure} \label{fig:x} \end{figure}"#), @r###"
         Ok(
-            "limits(sum )_(1 )^(2 )",
+            "#figure(image(\"x\"), caption: [A figure]) <fig:x>",
         )
-        "###
-        );
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\begin{table}[htbp]
+\centering
+\begin{tabular}{lr} a & b \end{tabular}
+\caption[Short]{A \emph{table}}
+\label{tab:1}
+\end{table}"#), @r###"
+        Ok(
+            "#figure(table(columns: 2, align: (left, right), stroke: none, [a], [b]), caption: [A #emph[table]]) <tab:1>",
+        )
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\begin{figure}[h] A \textbf{B} \end{figure}"#), @r###"
+        Ok(
+            "#figure([A #strong[B]])",
+        )
+        "###);
     }
 
     #[test]
-    fn test_convert_subsup() {
-        assert_debug_snapshot!(convert_math(r#"$x_1^2$"#), @r###"
-        Ok(
-            "x _(1 )^(2 )",
-        )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"$x^2_1$"#), @r###"
+    fn test_convert_reference() {
+        assert_debug_snapshot!(convert_text(r#"see \ref{sec:intro}."#), @r###"
         Ok(
-            "x ^(2 )_(1 )",
+            "see @sec:intro.",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"$x''_1$"#), @r###"
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\label{eq 1}\eqref{eq 1}"#), @r###"
         Ok(
-            "x ''_(1 )",
+            "<eq_1>@eq_1",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"$\overbrace{a + b + c}^{\text{This is an overbrace}}$"#), @r###"
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\cite{key}"#), @r###"
         Ok(
-            "mitexoverbrace(a  +  b  +  c )^(#textmath[This is an overbrace];)",
+            "@key",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"$x_1''$"#), @r###"
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\cite[p.~5]{key}"#), @r###"
         Ok(
-            "x _(1 )''",
+            "@key[p.~5]",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"${}_1^2x_3^4$"#), @r###"
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\cite[see][]{x}"#), @r###"
         Ok(
-            "zws _(1 )^(2 )x _(3 )^(4 )",
+            "see @x",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"$_1^2$"#), @r###"
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\cite{a, b:2,c-d.e}"#), @r###"
         Ok(
-            "zws_(1 )zws^(2 )",
+            "@a @b:2 @c-d.e",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"$\frac{_1^2}{3}$"#), @r###"
+        "###);
+        // a trailing comma doesn't cite an empty key
+        assert_debug_snapshot!(convert_text(r#"\cite[p.~5]{a,b,}"#), @r###"
         Ok(
-            "frac(zws_(1 )zws^(2 ),3 )",
+            "@a @b[p.~5]",
         )
-        "###
-        );
+        "###);
     }
 
     #[test]
-    fn test_convert_over() {
-        assert_debug_snapshot!(convert_math(r#"$x + 1 \over y + 2$"#), @r###"
+    fn test_convert_verbatim() {
+        assert_debug_snapshot!(convert_text(r#"\begin{verbatim}
+#include <a.h>
+  x_1 \frac{a}{b} % not a comment
+\end{verbatim}"#), @r###"
         Ok(
-            "frac(x  +  1  , y  +  2 )",
+            "```\n#include <a.h>\n  x_1 \\frac{a}{b} % not a comment\n```",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"$1 + {2 \over 3}$"#), @r###"
+        "###);
+        assert_debug_snapshot!(convert_text(r#"\begin{lstlisting}[language=C]
+int main() { return 0; }
+\end{lstlisting}"#), @r###"
         Ok(
-            "1  +  frac(2  , 3 )",
+            "```c\nint main() { return 0; }\n```",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"${l \over 2'}$"#), @r###"
+        "###);
+        // only the environments with options strip them
+        assert_debug_snapshot!(convert_text(r#"\begin{verbatim}[x] = 1\end{verbatim}"#), @r###"
         Ok(
-            "frac(l  , 2 ')",
+            "```\n[x] = 1\n```",
         )
         "###);
-    }
-
-    #[test]
-    fn test_convert_divide() {
-        assert_debug_snapshot!(convert_math(r#"$x / y$"#), @r###"
+        assert_debug_snapshot!(convert_text(r#"\begin{Verbatim}[frame=single]
+[x] = 1
+\end{Verbatim}"#), @r###"
         Ok(
-            "x  \\/ y ",
+            "```\n[x] = 1\n```",
         )
-        "###
-        );
-    }
-
-    #[test]
-    fn test_convert_space() {
-        assert_debug_snapshot!(convert_math(r#"$x~\! \, \> \: \; \ \quad \qquad y$"#), @r###"
+        "###);
+        // the body ends at the first `\end{verbatim}`
+        assert_debug_snapshot!(convert_text(r#"\begin{verbatim}a \begin{verbatim} b\end{verbatim} c"#), @r###"
         Ok(
-            "x space.nobreak negthinspace  thin  med  med  thick  thick  quad  wide  y ",
+            "```\na \\begin{verbatim} b\n``` c",
         )
-        "###
-        );
+        "###);
     }
 
     #[test]
-    fn test_convert_escape() {
-        assert_debug_snapshot!(convert_math(r#"$\|x\|| \& \# \% \$ y$"#), @r###"
+    fn test_convert_new_line_spacing() {
+        assert_debug_snapshot!(convert_text(r#"a\\[1em]b"#), @r###"
         Ok(
-            "|| x || |  amp  hash  percent  dollar  y ",
+            "a\\ #v(1em);b",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"$a*b * c$"#).unwrap(), @r###""a \\*b  \\* c ""###
-        );
-        assert_debug_snapshot!(convert_math(r#"$"$"#).unwrap(), @r###""\\\"""###
-        );
-        assert_debug_snapshot!(convert_text(r#"@abc"#).unwrap(), @r###""\\@abc""###
-        );
-    }
-
-    #[test]
-    fn test_unreachable() {
-        // println!("{:#?}", convert_math(r#"$u^-$"#));
-        assert_debug_snapshot!(convert_math(r#"$u^−$"#).unwrap(), @r###""u ^(− )""###
-        );
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$a \\[1em] b$"#), @r###"
+        Ok(
+            "a  \\  b ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$a \\* b$"#), @r###"
+        Ok(
+            "a  \\  b ",
+        )
+        "###);
+        // a bracket group after a space is the content of the next row
+        assert_debug_snapshot!(convert_math(r#"$a \\ [b]$"#), @r###"
+        Ok(
+            "a  \\  \\[b \\]",
+        )
+        "###);
     }
 
     #[test]
-    fn test_convert_sqrt() {
-        assert_debug_snapshot!(convert_math(r#"$\sqrt 1$"#), @r###"
+    fn test_convert_modulus() {
+        assert_debug_snapshot!(convert_math(r#"$a \pmod{n}$"#), @r###"
         Ok(
-            "mitexsqrt(1 )",
+            "a  pmod(n )",
         )
         "###);
-        assert_debug_snapshot!(convert_math(r#"$\sqrt [1]2$"#), @r###"
+        assert_debug_snapshot!(convert_math(r#"$a \pmod{n^2 + 1}$"#), @r###"
         Ok(
-            "mitexsqrt(\\[1 \\],2 )",
+            "a  pmod(n ^(2 ) +  1 )",
         )
-        "###
-        );
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$a \bmod n$"#), @r###"
+        Ok(
+            "a  bmod  n ",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$a \mod{n}$"#), @r###"
+        Ok(
+            "a  mod(n )",
+        )
+        "###);
     }
 
     #[test]
-    fn test_convert_lr() {
-        assert_debug_snapshot!(convert_math(r#"$\left.\right.$"#), @r###"
+    fn test_convert_bars() {
+        assert_debug_snapshot!(convert_math(r#"$\lVert v \rVert$"#), @r###"
         Ok(
-            "lr(  )",
+            "norm( v  )",
         )
         "###);
-        assert_debug_snapshot!(convert_math(r#"$\left.a\right.$"#), @r###"
+        assert_debug_snapshot!(convert_math(r#"$\lvert a \rvert + \|b\|^2$"#), @r###"
         Ok(
-            "lr( a  )",
+            "abs( a  ) +  norm(b )^(2 )",
         )
         "###);
-        assert_debug_snapshot!(convert_math(r#"$\alpha\left.\right.$"#), @r###"
+        // nested pairs
+        assert_debug_snapshot!(convert_math(r#"$\lvert\lvert a\rvert\rvert$"#), @r###"
         Ok(
-            "alpha lr(  )",
+            "abs(abs(a ))",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"$\left  . a \right    \|$"#), @r###"
+        "###);
+        // unpaired bars are kept
+        assert_debug_snapshot!(convert_math(r#"$\lvert a | b$"#), @r###"
         Ok(
-            "lr(     a       || )",
+            "|  a  |  b ",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"$\left\langle a\right\|$"#), @r###"
+        "###);
+        // bars in different cells or rows are not paired
+        assert_debug_snapshot!(convert_math(r#"$\begin{pmatrix} \lvert a & b \rvert \end{pmatrix}$"#), @r###"
         Ok(
-            "lr(angle.l  a || )",
+            "pmatrix( |  a  zws , b  |  )",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"$\left\lbrack\lbrack x\rbrack\right\rbrack$"#), @r###"
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$\begin{pmatrix} \lvert a \\ b \rvert \end{pmatrix}$"#), @r###"
         Ok(
-            "lr(bracket.l bracket.l  x bracket.r bracket.r )",
+            "pmatrix( |  a  zws ; b  |  )",
         )
-        "###
-        );
+        "###);
     }
 
     #[test]
-    fn test_convert_color() {
-        assert_debug_snapshot!(convert_math(r#"$x\color{red}yz\frac{1}{2}$"#), @r###"
+    fn test_convert_phantom() {
+        assert_debug_snapshot!(convert_math(r#"$a \phantom{x} b$"#), @r###"
         Ok(
-            "x mitexcolor(r e d ,y z frac(1 ,2 ))",
+            "a  phantom(x ) b ",
         )
         "###);
-        assert_debug_snapshot!(convert_math(r#"$x\textcolor{red}yz$"#), @r###"
+        assert_debug_snapshot!(convert_math(r#"$\hphantom{abc}\vphantom{\frac{1}{2}}$"#), @r###"
         Ok(
-            "x colortext(r e d ,y )z ",
+            "hphantom(a b c )vphantom(frac(1 ,2 ))",
         )
         "###);
-        assert_debug_snapshot!(convert_math(r#"$x\textcolor{red}{yz}$"#), @r###"
+    }
+
+    #[test]
+    fn test_convert_substack() {
+        assert_debug_snapshot!(convert_math(r#"$\sum_{\substack{0 \le i \le n \\ i \ne k}} a_i$"#), @r###"
         Ok(
-            "x colortext(r e d ,y z )",
+            "sum _(substack(0  <=  i  <=  n  zws ; i  !=  k )) a _(i )",
         )
         "###);
-        assert_debug_snapshot!(convert_math(r#"$x\colorbox{red}yz$"#), @r###"
-        Ok(
-            "x colorbox(r e d ,y )z ",
-        )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(r#"$x\colorbox{red}{yz}$"#), @r###"
+        // a single line
+        assert_debug_snapshot!(convert_math(r#"$\sum_{\substack{i}}$"#), @r###"
         Ok(
-            "x colorbox(r e d ,y z )",
+            "sum _(substack(i ))",
         )
-        "###
-        );
+        "###);
     }
 
     #[test]
-    fn test_convert_matrix() {
-        assert_debug_snapshot!(convert_math(
-                     r#"$\begin{pmatrix}x{\\}x\end{pmatrix}$"#
-            ).unwrap(),
-            @r###""pmatrix(x x )""###
-        );
-        assert_debug_snapshot!(convert_math(
-                     r#"$\begin{pmatrix} \\ & \ddots \end{pmatrix}$"#
-            ).unwrap(),
-            @r###""pmatrix( zws ; zws , dots.down  )""###
-        );
-        assert_debug_snapshot!(convert_math(
-                r#"$\begin{matrix}
-        1 & 2 & 3\\
-a & b & c
-\end{matrix}$"#
-            ),
-            @r###"
+    fn test_convert_inner_env() {
+        assert_debug_snapshot!(convert_math(r#"$\begin{aligned} a &= b \\ c &= d \end{aligned}$"#), @r###"
         Ok(
-            "matrix(\n1  zws , 2  zws , 3 zws ;\na  zws , b  zws , c \n)",
+            "aligned( a  &=  b  \\  c  &=  d  )",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(
-                r#"$\begin{Vmatrix}
-        1 & 2 & 3\\
-a & b & c
-\end{Vmatrix}$"#
-            ),
-            @r###"
+        "###);
+        // nested in `\left(...\right)`
+        assert_debug_snapshot!(convert_math(r#"$\left( \begin{aligned} a &= b \\ c &= d \end{aligned} \right)$"#), @r###"
         Ok(
-            "Vmatrix(\n1  zws , 2  zws , 3 zws ;\na  zws , b  zws , c \n)",
+            "lr(\\(  aligned( a  &=  b  \\  c  &=  d  ) \\) )",
         )
-        "###
-        );
-        assert_debug_snapshot!(convert_math(
-                r#"$\begin{array}{lcr}
-        1 & 2 & 3\\
-a & b & c
-\end{array}$"#
-            ),
-            @r###"
+        "###);
+        // as an argument of a command
+        assert_debug_snapshot!(convert_math(r#"$\frac{\begin{aligned} a \\ b \end{aligned}}{c}$"#), @r###"
         Ok(
-            "mitexarray(arg0: l c r ,\n1  zws , 2  zws , 3 zws ;\na  zws , b  zws , c \n)",
+            "frac(aligned( a  \\  b  ),c )",
         )
-        "###
-        );
+        "###);
+        // the lines of gathered are rows
+        assert_debug_snapshot!(convert_math(r#"$\begin{gathered} a = b \\ c \end{gathered}$"#), @r###"
+        Ok(
+            "gathered( a  =  b  zws ; c  )",
+        )
+        "###);
     }
 
     #[test]
@@ -1108,8 +6061,11 @@ a & b & c
         assert_debug_snapshot!(convert_math(r#"$\text{ab*c}$"#).unwrap(), @r###""#textmath[ab\\*c];""###);
         assert_debug_snapshot!(convert_math(r#"$\text{ab_c}$"#).unwrap(), @r###""#textmath[ab\\_c];""###);
         assert_debug_snapshot!(convert_math(r#"$\text{ab^c}$"#).unwrap(), @r###""#textmath[ab\\^c];""###);
-        // note: hack doesn't work in this case
-        assert_debug_snapshot!(convert_math(r#"$\text{ab\color{red}c}$"#).unwrap(), @r###""#textmath[abmitexcolor(red,c)];""###);
+        assert_debug_snapshot!(convert_math(r#"$\text{when $n \ge 1$}$"#).unwrap(), @r###""#textmath[when #math.equation(block: false, $n  >=  1 $);];""###);
+        // an unbalanced `$` is closed by the end of the argument
+        assert_debug_snapshot!(convert_math(r#"$\text{if $x > 0} y$"#).unwrap(), @r###""#textmath[if #math.equation(block: false, $x  >  0 $);]; y ""###);
+        // a color command in the text argument colors the rest of it
+        assert_debug_snapshot!(convert_math(r#"$\text{ab\color{red}c}$"#).unwrap(), @r###""#textmath[ab#text(fill: rgb(255, 0, 0))[c];];""###);
     }
 
     #[test]
@@ -1149,6 +6105,11 @@ a & b & c
             "#math.equation(block: false, $ a  $);",
         )
         "###);
+        assert_debug_snapshot!(convert_text(r#"$a$$b$"#), @r###"
+        Ok(
+            "#math.equation(block: false, $a $);#math.equation(block: false, $b $);",
+        )
+        "###);
         assert_debug_snapshot!(convert_text(r#"$$ a $ $ b $$"#), @r###"
         Err(
             "error: formula is not valid",
@@ -1161,9 +6122,66 @@ a & b & c
         "###);
     }
 
+    #[test]
+    fn test_convert_deep_nested_groups() {
+        // the groups nested too deeply are kept as raw text rather than
+        // overflowing the stack, and the rest is converted
+        let input = "{".repeat(50_000) + "x" + &"}".repeat(50_000) + "+y";
+        let output = convert_math(&input).unwrap();
+        let raw = "{".repeat(50_000 - 256) + "x" + &"}".repeat(50_000 - 256);
+        assert!(output.starts_with(&format!("#raw({raw:?});")), "{output}");
+        assert!(output.ends_with("+ y "), "{output}");
+    }
+
+    #[test]
+    fn test_convert_caret_notation() {
+        assert_debug_snapshot!(convert_text(r#"a^^Mb^^41"#), @r###"
+        Ok(
+            "abA",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_ligature() {
+        assert_debug_snapshot!(convert_text(r#"pages 1--2 --- ``quoted''"#), @r###"
+        Ok(
+            "pages 1–2 — “quoted”",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"$a--b$"#), @r###"
+        Ok(
+            "a - - b ",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_verb() {
+        assert_debug_snapshot!(convert_text(r#"\verb|a_b^c| and \verb*!"$\!"#), @r###"
+        Ok(
+            "#raw(\"a_b^c\"); and #raw(\"\\\"$\\\\\");",
+        )
+        "###);
+        assert_debug_snapshot!(convert_math(r#"x \verb|y|"#), @r###"
+        Ok(
+            "x  #raw(\"y\");",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_convert_makeat() {
+        // the scope commands are ignored by the default spec
+        assert!(convert_text(r#"\makeatletter a \makeatother b"#).is_ok());
+        assert!(convert_math(r#"\makeatletter x \makeatother"#).is_ok());
+    }
+
     #[test]
     fn test_fuzzing() {
-        assert_debug_snapshot!(convert_math(r#"\left\0"#).unwrap_err(), @r###""error: unknown command: \\0""###);
+        assert!(convert_math(r#"\left\0"#).is_ok());
+        // a label without a name
+        assert!(convert_text(r#"a \label"#).is_ok());
         assert_debug_snapshot!(convert_math(r#"\end{}"#).unwrap_err(), @r###""error: error unexpected: \"\"""###);
     }
 }
@@ -0,0 +1,10 @@
+use afl::fuzz;
+use mitex_spec_gen::DEFAULT_SPEC;
+fn main() {
+    // `parse_resilient` doesn't catch a panic, which is reported as a crash
+    fuzz!(|data: &[u8]| {
+        if let Ok(s) = std::str::from_utf8(data) {
+            let _ = mitex_parser::parse_resilient(s, DEFAULT_SPEC.clone());
+        }
+    });
+}
@@ -3,43 +3,19 @@
 #import "../prelude.typ": *
 
 // 0. Some useful internal variables or functions
-#let mitex-color-map = (
-  "red": rgb(255, 0, 0),
-  "green": rgb(0, 255, 0),
-  "blue": rgb(0, 0, 255),
-  "cyan": rgb(0, 255, 255),
-  "magenta": rgb(255, 0, 255),
-  "yellow": rgb(255, 255, 0),
-  "black": rgb(0, 0, 0),
-  "white": rgb(255, 255, 255),
-  "gray": rgb(128, 128, 128),
-  "lightgray": rgb(192, 192, 192),
-  "darkgray": rgb(64, 64, 64),
-  "brown": rgb(165, 42, 42),
-  "orange": rgb(255, 165, 0),
-  "pink": rgb(255, 182, 193),
-  "purple": rgb(128, 0, 128),
-  "teal": rgb(0, 128, 128),
-  "olive": rgb(128, 128, 0),
-)
 #let get-tex-str-from-arr(arr) = arr.filter(it => it != [ ] and it != [#math.zws]).map(it => it.text).sum()
 #let get-tex-str(tex) = if tex.has("children") { get-tex-str-from-arr(tex.children) } else { tex.text }
-#let get-tex-color-from-arr(arr) = {
-    mitex-color-map.at(lower(get-tex-str-from-arr(arr)), default: none)
-}
-#let get-tex-color(texcolor) = if texcolor.has("children") {
-  get-tex-color-from-arr(texcolor.children)
-} else {
-  texcolor.text
-} 
 
 // 1. functions created to make it easier to define a spec
 #let operatornamewithlimits(it) = math.op(limits: true, math.upright(it))
-#let arrow-handle(arrow-sym) = define-cmd(1, handle: it => $limits(xarrow(sym: #arrow-sym, it))$)
+// the label below an arrow, e.g. `f` in `\xrightarrow[f]{g}`, is attached by mitex
+#let arrow-handle(arrow-sym) = define-glob-cmd("{,b}t", none, handle: it => $limits(xarrow(sym: #arrow-sym, it))$)
 #let _greedy-handle(fn) = (..args) => $fn(#args.pos().sum())$
 #let greedy-handle(alias, fn) = define-greedy-cmd(alias, handle: _greedy-handle(fn))
 #let limits-handle(alias, wrap) = define-cmd(1, alias: alias, handle: (it) => math.limits(wrap(it)))
-#let matrix-handle(delim: none, handle: none) = define-env(none, kind: "is-matrix", alias: none, handle: math.mat.with(delim: delim))
+#let matrix-handle(delim: none, handle: none) = define-env(none, kind: "is-matrix", alias: none, handle: if handle != none { handle } else { math.mat.with(delim: delim) })
+// the column alignment option of starred matrices, e.g. `[r]`, is ignored
+#let matrix-star-handle(alias, delim: none) = define-glob-env("{,b}", kind: "is-matrix", alias: alias, handle: (arg0: none, ..args) => math.mat(delim: delim, ..args.pos()))
 #let text-handle(handle) = define-cmd(1, handle: handle)
 #let call-or-ignore(fn) = (..args) => if args.pos().len() > 0 { fn(..args) } else { math.zws }
 #let ignore-me = it => {}
@@ -49,15 +25,61 @@
 //    for parser/convert and typst respectively
 #let (spec, scope) = process-spec((
   // Text mode
-  section: define-cmd(1, alias: "#heading(level: 1)"),
-  subsection: define-cmd(1, alias: "#heading(level: 2)"),
-  subsubsection: define-cmd(1, alias: "#heading(level: 3)"),
-  footnote: define-cmd(1, alias: "#footnote"),
-  cite: define-cmd(1, alias: "#mitexcite", handle: it => cite(label(get-tex-str(it)))),
+  // the headings are converted by mitex, which writes a heading markup at the
+  // start of a line, or calls the alias, where the short title is ignored
+  section: define-glob-cmd("{,b}t", "#heading(level: 1)"),
+  subsection: define-glob-cmd("{,b}t", "#heading(level: 2)"),
+  subsubsection: define-glob-cmd("{,b}t", "#heading(level: 3)"),
+  paragraph: define-glob-cmd("{,b}t", "#heading(level: 4)"),
+  subparagraph: define-glob-cmd("{,b}t", "#heading(level: 5)"),
+  "section*": define-cmd(1, alias: "#heading(level: 1, numbering: none)"),
+  "subsection*": define-cmd(1, alias: "#heading(level: 2, numbering: none)"),
+  "subsubsection*": define-cmd(1, alias: "#heading(level: 3, numbering: none)"),
+  "paragraph*": define-cmd(1, alias: "#heading(level: 4, numbering: none)"),
+  "subparagraph*": define-cmd(1, alias: "#heading(level: 5, numbering: none)"),
+  // the footnotes are converted by mitex, where the number is ignored
+  footnote: define-glob-cmd("{,b}t", "#footnote"),
+  cite: define-glob-cmd("{,b}{,b}t", "#mitexcite", handle: (..args) => cite(label(get-tex-str(args.pos().last())))),
   emph: define-cmd(1, alias: "#emph"),
-  item: ignore-sym,
+  // the options are converted by mitex
+  includegraphics: define-glob-cmd("{,b}t", "image"),
+  // the urls are converted verbatim by mitex
+  href: define-cmd(2, alias: "link"),
+  url: define-cmd(1, alias: "link"),
+  // the optional label, e.g. `\item[a]`, is converted by mitex
+  item: define-glob-cmd("{,b}", "mitexitem"),
   itemize: define-env(none, kind: "is-itemize"),
   enumerate: define-env(none, kind: "is-enumerate"),
+  description: define-env(none, kind: "is-description"),
+  // the column specification and the cells are converted by mitex
+  tabular: define-env(1, kind: "is-table"),
+  multicolumn: define-cmd(3, alias: "table.cell"),
+  // the floats are converted to figures by mitex, where the placement
+  // specifier, e.g. `[htbp]`, is ignored
+  figure: define-glob-env("{,b}", alias: "figure"),
+  "figure*": define-glob-env("{,b}", alias: "figure"),
+  table: define-glob-env("{,b}", alias: "figure"),
+  "table*": define-glob-env("{,b}", alias: "figure"),
+  caption: define-glob-cmd("{,b}t", "mitexcaption", handle: ignore-me),
+  centering: ignore-sym,
+  // the theorem-like environments are converted to blocks by mitex, whose
+  // labels are the aliases
+  theorem: define-glob-env("{,b}", kind: "is-theorem", alias: "Theorem"),
+  lemma: define-glob-env("{,b}", kind: "is-theorem", alias: "Lemma"),
+  corollary: define-glob-env("{,b}", kind: "is-theorem", alias: "Corollary"),
+  proposition: define-glob-env("{,b}", kind: "is-theorem", alias: "Proposition"),
+  definition: define-glob-env("{,b}", kind: "is-theorem", alias: "Definition"),
+  example: define-glob-env("{,b}", kind: "is-theorem", alias: "Example"),
+  remark: define-glob-env("{,b}", kind: "is-theorem", alias: "Remark"),
+  proof: define-glob-env("{,b}", kind: "is-theorem", alias: "Proof"),
+  // the quotes are converted to block quotes by mitex
+  quote: define-env(none, alias: "quote"),
+  quotation: define-env(none, alias: "quote"),
+  verse: define-env(none, alias: "quote"),
+  // the alignments are converted to aligned blocks by mitex
+  center: define-env(none, alias: "align"),
+  flushleft: define-env(none, alias: "align"),
+  flushright: define-env(none, alias: "align"),
   label: define-cmd(1, alias: "mitexlabel", handle: ignore-me),
   tag: define-cmd(1, alias: "mitexlabel", handle: ignore-me),
   ref: define-cmd(1, alias: "#mitexref", handle: it => ref(label(get-tex-str(it)))),
@@ -82,8 +104,8 @@
   quad: sym,
   qquad: define-sym("wide"),
   phantom: define-cmd(1, handle: hide),
-  hphantom: define-cmd(1, handle: it => box(height: 0pt, hide(it))),
-  vphantom: define-cmd(1, handle: it => box(width: 0pt, hide(it))),
+  hphantom: define-cmd(1, handle: it => box(height: 0pt, hide($it$))),
+  vphantom: define-cmd(1, handle: it => box(width: 0pt, hide($it$))),
   // Escape symbols
   "_": define-sym("\\_"),
   "^": define-sym("hat"),
@@ -169,55 +191,46 @@
   large: ignore-sym,
   tiny: ignore-sym,
   // Colors
-  color: define-greedy-cmd("mitexcolor", handle: (texcolor, ..args) => {
-    let color = get-tex-color(texcolor)
-    if color != none {
-      text(fill: color, args.pos().sum())
-    } else {
-      args.pos().sum()
-    }
-  }),
-  textcolor: define-cmd(2, alias: "colortext", handle: (texcolor, body) => {
-    let color = get-tex-color(texcolor)
-    if color != none {
-      text(fill: get-tex-color(texcolor), body)
-    } else {
-      body
-    }
-  }),
-  colorbox: define-cmd(2, handle: (texcolor, body) => {
-    let color = get-tex-color(texcolor)
-    if color != none {
-      box(fill: get-tex-color(texcolor), $body$)
-    } else {
-      body
-    }
-  }),
+  // the colors are converted by mitex, where `\color` colors the rest of its group
+  color: define-greedy-cmd("mitexcolor"),
+  textcolor: define-glob-cmd("{,b}tt", "colortext"),
+  colorbox: define-glob-cmd("{,b}tt", "colorbox"),
   // Limits
   limits: left1-op("limits"),
   nolimits: left1-op("scripts"),
   // Commands
   frac: define-cmd(2, handle: (num, den) => $(num)/(den)$),
-  // todo: cfrac, dfrac are same?
-  cfrac: define-cmd(2, handle: (num, den) => $display((num)/(den))$),
+  // cfrac keeps the display style in its numerator and denominator, so that
+  // a nested chain of continued fractions is not shrunk level by level
+  cfrac: define-cmd(2, handle: (num, den) => $display(display(num)/display(den))$),
   dfrac: define-cmd(2, handle: (num, den) => $display((num)/(den))$),
   tfrac: define-cmd(2, handle: (num, den) => $inline((num)/(den))$),
   binom: define-cmd(2),
   dbinom: define-cmd(2, handle: (n, k) => $display(binom(#n, #k))$),
   tbinom: define-cmd(2, handle: (n, k) => $inline(binom(#n, #k))$),
-  stackrel: define-cmd(2, handle: (sup, base) => $limits(base)^(sup)$),
-  substack: define-cmd(1, handle: it => it),
-  overset: define-cmd(2, handle: (sup, base) => $limits(base)^(sup)$),
-  underset: define-cmd(2, handle: (sub, base) => $limits(base)_(sub)$),
+  // the delimiters, thickness and style are interpreted by mitex
+  genfrac: define-cmd(6, handle: (left, right, thickness, style, num, den) => $(num)/(den)$),
+  stackrel: define-cmd(2, handle: (sup, base) => math.class("relation", math.attach(math.limits(base), t: sup))),
+  substack: define-cmd(1, handle: (..lines) => math.mat(delim: none, ..lines.pos().map(line => if type(line) == array { line } else { (line,) }))),
+  overset: define-cmd(2, handle: (sup, base) => math.attach(math.limits(base), t: sup)),
+  underset: define-cmd(2, handle: (sub, base) => math.attach(math.limits(base), b: sub)),
+  // the numbers and units of siunitx are converted by mitex, where the options
+  // are ignored
+  num: define-glob-cmd("{,b}t", "num"),
+  si: define-glob-cmd("{,b}t", "si"),
+  SI: define-glob-cmd("{,b}t{,b}t", "SI"),
   // Accents
   "not": define-cmd(1, alias: "cancel"),
   cancel: define-cmd(1),
-  xcancel: define-cmd(1, handle: math.cancel),
+  xcancel: define-cmd(1, handle: math.cancel.with(cross: true)),
   bcancel: define-cmd(1, handle: math.cancel.with(inverted: true)),
+  // the target is put at the top right end of the line, e.g. `\cancelto{0}{x}`
+  cancelto: define-cmd(2, handle: (target, it) => math.attach(math.cancel(it), tr: target)),
   sout: define-cmd(1, handle: math.cancel.with(angle: 90deg)),
   grave: define-cmd(1, alias: "grave"),
   acute: define-cmd(1, alias: "acute"),
   hat: define-cmd(1, alias: "hat"),
+  // typst accents already stretch to the width of their base
   widehat: define-cmd(1, alias: "hat"),
   tilde: define-cmd(1, alias: "tilde"),
   widetilde: define-cmd(1, alias: "tilde"),
@@ -242,7 +255,8 @@
   underbrace: limits-handle("mitexunderbrace", math.underbrace),
   overbracket: limits-handle("mitexoverbracket", math.overbracket),
   underbracket: limits-handle("mitexunderbracket", math.underbracket),
-  boxed: define-cmd(1, handle: it => box(stroke: 0.5pt, $it$)),
+  // it is converted to a box by mitex
+  boxed: define-cmd(1, math-args: true, handle: it => box(stroke: 0.5pt, inset: 0.25em, $it$)),
   // Greeks
   alpha: sym,
   beta: sym,
@@ -347,8 +361,7 @@
   oiint: define-sym("integral.surf"),
   oiiint: define-sym("integral.vol"),
   // Symbols
-  mod: define-sym("mod"),
-  bmod: define-sym("mod"),
+  bmod: define-sym("bmod", sym: math.class("binary", math.op("mod"))),
   cdot: define-sym("dot.c"),
   cdotp: define-sym("dot.c"),
   sdot: define-sym("dot.c"),
@@ -802,6 +815,7 @@
   llless: define-sym("<<<"),
   lnapprox: define-sym("⪉"),
   lneq: define-sym("⪇"),
+  lmoustache: define-sym("⎰"),
   lrcorner: define-sym("⌟"),
   lq: define-sym("quote.l.single"),
   Lsh: define-sym("↰"),
@@ -937,14 +951,17 @@
   xmapsto: arrow-handle(math.arrow.r.bar),
   xlongequal: arrow-handle(math.eq),
   pmod: define-cmd(1, handle: it => $quad (mod thick it)$),
+  mod: define-cmd(1, handle: it => $quad mod thick it$),
   pod: define-cmd(1, handle: it => $quad (it)$),
   "set": define-cmd(1, handle: it => $\{it\}$),
   Set: define-cmd(1, handle: it => $lr(\{it\})$),
-  bra: define-cmd(1, handle: it => $angle.l it|$),
+  bra: define-cmd(1, handle: it => $lr(angle.l it|)$),
   Bra: define-cmd(1, handle: it => $lr(angle.l it|)$),
-  ket: define-cmd(1, handle: it => $|it angle.r$),
+  ket: define-cmd(1, handle: it => $lr(|it angle.r)$),
   Ket: define-cmd(1, handle: it => $lr(|it angle.r)$),
-  braket: define-cmd(1, handle: it => $angle.l it angle.r$),
+  // the argument is split at its first bar by mitex, and a bra without a ket
+  // is paired with itself as in the physics package
+  braket: define-cmd(1, handle: (bra, ..ket) => $lr(angle.l bra | #ket.pos().at(0, default: bra) angle.r)$),
   Braket: define-cmd(1, handle: it => $lr(angle.l it angle.r)$),
   fbox: define-cmd(1, handle: it => box(stroke: 0.5pt, $it$)),
   hbox: define-cmd(1, handle: it => it),
@@ -955,33 +972,30 @@
   Bmatrix: matrix-handle(delim: "{"),
   vmatrix: matrix-handle(delim: "|"),
   Vmatrix: matrix-handle(delim: "||"),
-  smallmatrix: matrix-handle(handle: (..args) => math.inline(math.mat.with(delim: none, ..args))),
-  array: define-env(1, kind: "is-matrix", alias: "mitexarray", handle: (arg0: ("l",), ..args) => {
+  "matrix*": matrix-star-handle("mitexmatrixstar"),
+  "pmatrix*": matrix-star-handle("mitexpmatrixstar", delim: "("),
+  "bmatrix*": matrix-star-handle("mitexbmatrixstar", delim: "["),
+  "Bmatrix*": matrix-star-handle("mitexBmatrixstar", delim: "{"),
+  "vmatrix*": matrix-star-handle("mitexvmatrixstar", delim: "|"),
+  "Vmatrix*": matrix-star-handle("mitexVmatrixstar", delim: "||"),
+  // the cells are split like a matrix, which is rendered in the script size
+  smallmatrix: matrix-handle(handle: (..args) => math.script(math.mat(delim: none, ..args))),
+  array: define-env(1, kind: "is-matrix", alias: "mitexarray", handle: (align: (left,), vlines: (), ..args) => {
     if args.pos().len() == 0 {
       return
     }
-    if type(arg0) != str {
-      if arg0.has("children") {
-        arg0 = arg0.children.filter(it => it != [ ] and it != [#math.zws])
-          .map(it => it.text)
-          .filter(it => it == "l" or it == "c" or it == "r")
-      } else {
-        arg0 = (arg0.text,)
-      }
-    }
     let matrix = args.pos().map(row => if type(row) == array { row } else { (row,) } )
-    let n = matrix.len()
     let m = calc.max(..matrix.map(row => row.len()))
     matrix = matrix.map(row => row + (m - row.len()) * (none,))
     let array-at(arr, pos) = {
       arr.at(calc.min(pos, arr.len() - 1))
     }
-    let align-map = ("l": left, "c": center, "r": right)
-    set align(align-map.at(array-at(arg0, 0)))
     pad(y: 0.2em, grid(
       columns: m,
       column-gutter: 0.5em,
       row-gutter: 0.5em,
+      align: (x, _) => array-at(align, x),
+      ..vlines.map(x => grid.vline(x: x, stroke: 0.5pt)),
       ..matrix.flatten().map(it => $it$)
     ))
   }),
@@ -995,8 +1009,10 @@
   "equation*": define-env(none, kind: "is-math", alias: "aligned"),
   split: define-env(none, kind: "is-math", alias: "aligned"),
   gather: define-env(none, kind: "is-math", alias: "aligned"),
-  gathered: define-env(none, kind: "is-math", alias: "aligned"),
+  // the lines are rows of a matrix, whose cells are centered
+  gathered: define-env(none, kind: "is-matrix", alias: "gathered", handle: (..lines) => math.mat(delim: none, ..lines.pos().map(line => if type(line) == array { line } else { (line,) }))),
   cases: define-env(none, kind: "is-cases", alias: "cases"),
+  dcases: define-env(none, kind: "is-cases", alias: "cases"),
   rcases: define-env(none, kind: "is-cases", alias: "rcases", handle: math.cases.with(reverse: true)),
   // Specials
   notag: ignore-sym,
@@ -1021,6 +1037,9 @@
   vspace: define-cmd(1, handle: it => v(eval(get-tex-str(it)))),
   hspace: define-cmd(1, handle: it => h(eval(get-tex-str(it)))),
   text: define-cmd(1, alias: "textmath", handle: it => it),
+  mbox: define-cmd(1, alias: "textmath", handle: it => it),
+  // the argument is converted to inline math in text mode by mitex
+  ensuremath: define-cmd(1, math-args: true, handle: it => it),
   textmd: define-cmd(1, handle: it => it),
   textnormal: define-cmd(1, handle: it => it),
   textbf: text-handle(math.bold),
@@ -1054,6 +1073,8 @@
   "renewcommand*": ignore-sym,
   DeclareRobustCommand: ignore-sym,
   "DeclareRobustCommand*": ignore-sym,
+  DeclareMathOperator: ignore-sym,
+  "DeclareMathOperator*": ignore-sym,
   DeclareTextCommand: ignore-sym,
   DeclareTextCommandDefault: ignore-sym,
   ProvideTextCommand: ignore-sym,
@@ -1069,6 +1090,8 @@
   AtBeginDocument: ignore-sym,
   AtEndDocument: ignore-sym,
   "@ifstar": ignore-sym,
+  makeatletter: ignore-sym,
+  makeatother: ignore-sym,
   "if": ignore-sym,
   ifdim: ignore-sym,
   iffalse: ignore-sym,
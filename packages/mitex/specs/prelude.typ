@@ -66,15 +66,18 @@
 /// - num (int): The number of arguments for the command.
 /// - alias (str): Alias command for typst handler.
 ///   For example, alias `\frac` to typst's `frac`, as the key in mitex-scope.
+/// - math-args (bool): Whether the arguments are math even in text, like \ensuremath{x},
+///   where the command is then converted in an inline equation.
 /// - handle (function): The handler function, as the value of alias in mitex-scope.
 ///   It receives fixed number of arguments, for example `frac(1, 2)` for `\frac{1}{2}`.
 ///
 /// Return: A spec item and a scope item (none for no scope item)
-#let define-cmd(num, alias: none, handle: none) = {
+#let define-cmd(num, alias: none, math-args: false, handle: none) = {
   ((
     kind: "cmd",
     args: ( "kind": "right", "pattern": ( kind: "fixed-len", len: num ) ),
     alias: alias,
+    math_args: math-args,
   ), if handle != none { (alias: alias, handle: handle) } else { none })
 }
 
@@ -86,7 +89,7 @@
 ///   For example, alias `\begin{alignedat}{2}` to typst's `alignedat`,
 ///   and alias `\begin{aligned}` to typst's `aligned`, as the key in mitex-scope.
 /// - kind (str): environment kind, it could be "is-math", "is-cases", "is-matrix",
-///   "is-itemize", "is-enumerate"
+///   "is-itemize", "is-enumerate", "is-description", "is-table", "is-theorem"
 /// - handle (function): The handler function, as the value of alias in mitex-scope.
 ///   It receives fixed number of named arguments as environment options,
 ///   for example `alignedat(arg0: ..)` or `alignedat(arg0: .., arg1: ..)`.
@@ -107,6 +110,27 @@
   ), if handle != none { (alias: alias, handle: handle) } else { none })
 }
 
+/// Define an environment with a glob pattern for matching options, like \begin{pmatrix*}[r]
+///
+/// Arguments:
+/// - pat (str): The glob pattern for matching environment options.
+///   For example, `{,b}` matches an optional bracket option.
+/// - alias (str): Alias command for typst handler, as the key in mitex-scope.
+/// - kind (str): environment kind, the same as `define-env`
+/// - handle (function): The handler function, as the value of alias in mitex-scope.
+///   It receives the matched options as named arguments `arg0`, `arg1`, ...,
+///   and the environment body as variable length arguments.
+///
+/// Return: A spec item and a scope item (none for no scope item)
+#let define-glob-env(pat, kind: "none", alias: none, handle: none) = {
+  ((
+    kind: "env",
+    args: ( kind: "glob", pattern: pat ),
+    ctx_feature: ( kind: kind ),
+    alias: alias,
+  ), if handle != none { (alias: alias, handle: handle) } else { none })
+}
+
 /// Define a symbol without alias and without handler function, like \alpha => alpha
 /// 
 /// Return: A spec item and no scope item (none for no scope item)
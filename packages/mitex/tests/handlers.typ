@@ -0,0 +1,85 @@
+// Tests of the handlers in the specifications, which evaluate the typst code
+// converted by mitex and fail to compile if an assertion fails, e.g. by
+// `typst compile --root . packages/mitex/tests/handlers.typ`
+#import "../specs/mod.typ": mitex-scope
+
+// evaluate converted math code and get the content of its equation
+#let eval-math(code) = eval("$" + code + "$", scope: mitex-scope).body
+
+// gathered: the lines are centered rows inside math, not a block
+#let gathered = eval-math("gathered( a  =  b  zws ; c  )")
+#assert.eq(gathered.func(), math.mat)
+#assert.eq(gathered.delim, none)
+#assert.eq(gathered.rows.len(), 2)
+#assert.eq(eval-math("gathered( a  )").rows.len(), 1)
+
+// substack: the lines are rows of a matrix
+#let substack = eval-math("substack(0  <=  i  zws ; i  !=  k )")
+#assert.eq(substack.func(), math.mat)
+#assert.eq(substack.rows.len(), 2)
+#assert.eq(eval-math("substack(i )").rows.len(), 1)
+
+// hphantom and vphantom: the hidden content is laid out as math
+#let hphantom = eval-math("hphantom(a b c )")
+#assert.eq(hphantom.func(), box)
+#assert.eq(hphantom.height, 0pt)
+#assert.eq(hphantom.body.func(), hide)
+#assert.eq(hphantom.body.body.func(), math.equation)
+#let vphantom = eval-math("vphantom(frac(1 ,2 ))")
+#assert.eq(vphantom.width, 0pt)
+#assert.eq(vphantom.body.body.func(), math.equation)
+
+// overset and underset: the labels are in the top and bottom slots
+#let overset = eval-math("overset(d e f ,= )")
+#assert.eq(overset.func(), math.attach)
+#assert.eq(overset.base.func(), math.limits)
+#assert(overset.has("t") and not overset.has("tr"))
+#let underset = eval-math("underset(x ,y )")
+#assert.eq(underset.func(), math.attach)
+#assert(underset.has("b") and not underset.has("br"))
+
+// stackrel: the stacked relation has the relation class
+#let stackrel = eval-math("stackrel(? ,= )")
+#assert.eq(stackrel.func(), math.class)
+#assert.eq(stackrel.class, "relation")
+#assert.eq(stackrel.body.func(), math.attach)
+#assert(stackrel.body.has("t"))
+
+// mod and bmod: a spaced operator and a binary operator
+#let mod = eval-math("mod(n )")
+#assert.eq(mod.func(), math.equation)
+#assert(mod.body.children.any(it => it.func() == math.op))
+#let bmod = eval-math("bmod")
+#assert.eq(bmod.func(), math.class)
+#assert.eq(bmod.class, "binary")
+
+// smallmatrix: a matrix in the script size
+#let smallmatrix = eval-math("smallmatrix( a  zws , b  zws ; c  zws , d  )")
+#assert.eq(smallmatrix.func(), math.script(none).func())
+#assert.eq(smallmatrix.body.func(), math.mat)
+#assert.eq(smallmatrix.body.rows.len(), 2)
+
+// boxed: a stroked box of the math argument
+#let boxed = eval-math("boxed(E = m c ^(2 ))")
+#assert.eq(boxed.func(), box)
+#assert.eq(boxed.body.func(), math.equation)
+
+// xcancel and cancelto: a crossed cancel and a cancel with the target
+#let xcancel = eval-math("xcancel(a  +  b )")
+#assert.eq(xcancel.func(), math.cancel)
+#assert.eq(xcancel.cross, true)
+#let cancelto = eval-math("cancelto(0 ,x ^(2 ))")
+#assert.eq(cancelto.func(), math.attach)
+#assert.eq(cancelto.base.func(), math.cancel)
+#assert(cancelto.has("tr"))
+
+// bra, ket and braket: scaled angle brackets, where a bra without a ket is
+// paired with itself
+#assert.eq(eval-math("bra(1 )").func(), math.lr)
+#assert.eq(eval-math("ket(zws )").func(), math.lr)
+#let braket = eval-math("braket(a ,b )")
+#assert.eq(braket.func(), math.lr)
+#assert.eq(eval-math("braket(a )"), eval-math("braket(a ,a )"))
+
+// mathbf: the argument is upright as well as bold
+#assert.eq(eval-math("mitexmathbf(x )"), eval-math("bold(upright(x ))"))